@@ -0,0 +1,11 @@
+//! Data shared by the criterion benches and `vicalc bench`.
+
+/// CSV text with a header and `rows` rows of text, numbers and a formula
+pub fn sample_csv(rows: usize) -> String {
+    let mut csv = String::from("Item,Qty,Price,Total\n");
+    for i in 0..rows {
+        let r = i + 2;
+        csv.push_str(&format!("Item {},{},{}.5,=B{}*C{}\n", i, i % 97, i % 1000, r, r));
+    }
+    csv
+}
@@ -0,0 +1,47 @@
+//! Benchmarks of the core operations: `cargo bench`.
+//!
+//! `vicalc bench` gives quick numbers for the same operations without
+//! criterion, plus the ones that need the terminal front end (drawing).
+
+mod common;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use vicalc::csv;
+use vicalc::formula;
+use vicalc::sheet::Sheet;
+
+fn formula(c: &mut Criterion) {
+    let mut sheet = Sheet::new();
+    for row in 0..1000 {
+        sheet.set_cell(0, row, row.to_string());
+    }
+    sheet.set_cell(1, 0, "=SUM(A1:A1000)".to_string());
+    c.bench_function("formula sum 1,000 cells", |b| b.iter(|| black_box(sheet.evaluate(1, 0))));
+
+    let mut sheet = Sheet::new();
+    sheet.set_cell(0, 0, "1".to_string());
+    for row in 1..100 {
+        sheet.set_cell(0, row, format!("={}+1", formula::cell_name(0, row - 1)));
+    }
+    c.bench_function("formula chain of 100 cells", |b| b.iter(|| black_box(sheet.evaluate(0, 99))));
+}
+
+fn sheet(c: &mut Criterion) {
+    // An undo snapshot is a clone of the sheet
+    let sheet = csv::read(&common::sample_csv(2500));
+    c.bench_function("undo snapshot 10k cells", |b| b.iter(|| black_box(sheet.clone())));
+
+    let sheet = csv::read(&common::sample_csv(1000));
+    let mut group = c.benchmark_group("slow");
+    group.sample_size(10);
+    group.bench_function("search 1k rows (no match)", |b| {
+        b.iter(|| black_box(sheet.find("no such text", 0, 0, true)))
+    });
+    let text = common::sample_csv(100_000);
+    group.bench_function("import csv 100k rows", |b| b.iter(|| black_box(csv::read(&text))));
+    group.finish();
+}
+
+criterion_group!(benches, formula, sheet);
+criterion_main!(benches);
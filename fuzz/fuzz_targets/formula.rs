@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::collections::HashMap;
+use vicalc::cell::{self, Cell};
+use vicalc::{engine, formula};
+
+// Feed arbitrary text through every entry point that sees raw cell input:
+// reference parsing, formula adjustment and evaluation (including evaluation
+// of a cell that refers to itself through the fuzzed text).
+fuzz_target!(|input: &str| {
+    let _ = formula::parse_cell_ref(input);
+    let _ = formula::adjust_formula(input, 1, -1);
+    let _ = formula::adjust_formula_for_row_insert(input, 0);
+    let _ = formula::adjust_formula_for_row_delete(input, 1);
+    let _ = formula::adjust_formula_for_col_insert(input, 0);
+    let _ = formula::adjust_formula_for_col_delete(input, 1);
+
+    let mut cells = HashMap::new();
+    cells.insert((0, 0), Cell::new(input.to_string(), cell::parse_input(input)));
+    let _ = engine::evaluate_input(&cells, input);
+    let _ = engine::Engine::new(&cells).evaluate_cell(0, 0);
+});
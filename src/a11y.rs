@@ -0,0 +1,125 @@
+//! Screen-reader output.
+//!
+//! With `:set a11y=on` the top line of the screen becomes a plain sentence
+//! about the cell under the cursor ("B3, Price, 12.5") in the terminal's
+//! own colors, and the terminal cursor is left at its start, where screen
+//! readers look. `:set a11ynotify=<command>` also sends each announcement
+//! to a program on its standard input, e.g. `spd-say -e` or
+//! `espeak --stdin`, whenever it changes.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::formula;
+use crate::App;
+
+#[derive(Default)]
+pub struct Announcer {
+    pub enabled: bool,
+    // Program the announcements are piped to
+    pub notify: Option<String>,
+    // Last announcement made, so each is made once
+    last: String,
+}
+
+impl Announcer {
+    /// The current announcement, for the top line
+    pub fn text(&self) -> &str {
+        &self.last
+    }
+}
+
+/// What to say about the cell under the cursor: its name, its column's
+/// header, its value and its formula
+pub fn announcement(app: &App) -> String {
+    let (col, row) = (app.cursor_col, app.cursor_row);
+    let mut parts = vec![formula::cell_name(col, row)];
+    if let Some(header) = app.sheet.header_row().filter(|&h| h < row) {
+        let name = app.sheet.evaluate(col, header);
+        if !name.is_empty() {
+            parts.push(name);
+        }
+    }
+    let cell = app.sheet.get_cell(col, row);
+    let value = app.sheet.evaluate(col, row);
+    parts.push(if value.is_empty() { "blank".to_string() } else { value });
+    if cell.raw_input.starts_with('=') {
+        parts.push(format!("formula {}", cell.raw_input));
+    }
+    parts.join(", ")
+}
+
+/// Announce the cell under the cursor if it is not what was said last.
+/// Returns whether there was something new to say.
+pub fn update(app: &mut App) -> bool {
+    if !app.a11y.enabled {
+        return false;
+    }
+    let text = announcement(app);
+    if text == app.a11y.last {
+        return false;
+    }
+    if let Some(command) = &app.a11y.notify {
+        if let Err(e) = notify(command, &text) {
+            let message = format!("a11ynotify: {}", e);
+            app.a11y.notify = None;
+            app.error(message);
+        }
+    }
+    app.a11y.last = text;
+    true
+}
+
+/// Pipe `text` to the notifier without waiting for it to finish speaking
+fn notify(command: &str, text: &str) -> std::io::Result<()> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let mut child = shell
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut stdin = child.stdin.take();
+    let text = format!("{}\n", text);
+    std::thread::spawn(move || {
+        if let Some(stdin) = stdin.as_mut() {
+            let _ = stdin.write_all(text.as_bytes());
+        }
+        drop(stdin);
+        let _ = child.wait();
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_announcement() {
+        let mut app = App::new();
+        app.sheet.set_cell(1, 0, "Price".to_string());
+        app.sheet.set_cell(1, 2, "=2*3".to_string());
+        app.move_cursor_to(1, 2);
+        assert_eq!(announcement(&app), "B3, Price, 6, formula =2*3");
+        app.move_cursor_to(1, 0);
+        assert_eq!(announcement(&app), "B1, Price");
+        app.move_cursor_to(0, 4);
+        assert_eq!(announcement(&app), "A5, blank");
+
+        // Each announcement is made once
+        assert!(!update(&mut app));
+        app.a11y.enabled = true;
+        assert!(update(&mut app));
+        assert_eq!(app.a11y.text(), "A5, blank");
+        assert!(!update(&mut app));
+    }
+}
@@ -0,0 +1,195 @@
+//! Calculation audit log.
+//!
+//! `:set auditlog=path` (or `auditlog = "path"` in the config file) appends
+//! a line to the file for every formula whose value changes, with the time,
+//! the cell, the old and new values and what caused it: the change made
+//! (`edit (B2)`, `:paste (5 cells in A1:C2)`), an undo or redo, or a
+//! recalculation with F9 or `:calc`. Fields are separated by tabs. Lines
+//! starting with `#` note when logging started and which file was open, so
+//! the log shows how the numbers evolved over a session. Values are logged
+//! once worked out, so with `:set calc=manual` at the next recalculation.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::formula::cell_name;
+use crate::App;
+
+pub struct AuditLog {
+    path: PathBuf,
+    out: BufWriter<File>,
+    // What changed the sheet since the last update
+    cause: Option<Cause>,
+    // Formula values as last logged, with the file and the sheet version
+    // they were taken from; None until the first update
+    values: Option<HashMap<(usize, usize), String>>,
+    file: Option<String>,
+    version: u64,
+}
+
+enum Cause {
+    // A change saved for undo, under its label
+    Change(String),
+    // Undo, redo or a recalculation
+    Other(String),
+}
+
+impl AuditLog {
+    /// Start logging to `path`, after what it already holds
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut out = BufWriter::new(file);
+        writeln!(out, "# vicalc {} audit log started {}", env!("CARGO_PKG_VERSION"), timestamp(SystemTime::now()))
+            .and_then(|_| out.flush())
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(AuditLog { path: path.to_path_buf(), out, cause: None, values: None, file: None, version: 0 })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Note a change about to be made, labeled as in the undo history
+    pub fn change(&mut self, label: &str) {
+        self.cause = Some(Cause::Change(label.to_string()));
+    }
+
+    /// Note an undo, a redo or a recalculation
+    pub fn other(&mut self, what: String) {
+        self.cause = Some(Cause::Other(what));
+    }
+}
+
+/// Every formula's value, by cell
+fn formula_values(app: &App) -> HashMap<(usize, usize), String> {
+    app.sheet
+        .cells()
+        .iter()
+        .filter(|(_, cell)| cell.raw_input.starts_with('='))
+        .map(|(&(col, row), _)| ((col, row), app.sheet.evaluate(col, row)))
+        .collect()
+}
+
+/// The formulas whose values differ, in reading order, as (cell, old, new)
+pub fn changes(
+    before: &HashMap<(usize, usize), String>,
+    after: &HashMap<(usize, usize), String>,
+) -> Vec<((usize, usize), String, String)> {
+    let value = |values: &HashMap<(usize, usize), String>, pos| values.get(pos).cloned().unwrap_or_default();
+    let mut changed: Vec<((usize, usize), String, String)> = before
+        .keys()
+        .chain(after.keys().filter(|pos| !before.contains_key(pos)))
+        .map(|pos| (*pos, value(before, pos), value(after, pos)))
+        .filter(|(_, old, new)| old != new)
+        .collect();
+    changed.sort_by_key(|&((col, row), _, _)| (row, col));
+    changed
+}
+
+/// Log the formulas changed since the last update. Waits while the values
+/// shown are out of date; a file opened since starts over without logging.
+pub fn update(app: &mut App) {
+    let Some(log) = &app.audit else {
+        return;
+    };
+    if app.sheet.is_stale() || (app.sheet.version() == log.version && log.cause.is_none() && log.values.is_some()) {
+        return;
+    }
+    let values = formula_values(app);
+    let opened = log.values.is_none() || log.file != app.current_file;
+    let cause = match &log.cause {
+        Some(Cause::Change(label)) => {
+            // What the change touched, against the sheet before it
+            match app.history.undo_entries().last() {
+                Some(entry) => format!("{} ({})", label, crate::history::summarize(&entry.sheet, &app.sheet)),
+                None => label.clone(),
+            }
+        }
+        Some(Cause::Other(what)) => what.clone(),
+        None => "recalculation".to_string(),
+    };
+    let file = app.current_file.clone();
+    let version = app.sheet.version();
+
+    let Some(log) = app.audit.as_mut() else {
+        return;
+    };
+    let result = if opened {
+        let name = file.as_deref().unwrap_or("(unnamed)");
+        writeln!(log.out, "# file: {}", name).and_then(|_| log.out.flush())
+    } else {
+        write_changes(log, &changes(log.values.as_ref().unwrap_or(&HashMap::new()), &values), &cause)
+    };
+    log.values = Some(values);
+    log.file = file;
+    log.version = version;
+    log.cause = None;
+    if let Err(e) = result {
+        let message = format!("auditlog: {}: {}", log.path.display(), e);
+        app.audit = None;
+        app.error(message);
+    }
+}
+
+fn write_changes(log: &mut AuditLog, changes: &[((usize, usize), String, String)], cause: &str) -> std::io::Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let time = timestamp(SystemTime::now());
+    // Tabs and line breaks in values would split the line
+    let field = |s: &str| s.replace(['\t', '\n', '\r'], " ");
+    for ((col, row), old, new) in changes {
+        writeln!(log.out, "{}\t{}\t{}\t{}\t{}", time, cell_name(*col, *row), field(old), field(new), field(cause))?;
+    }
+    log.out.flush()
+}
+
+/// A time as UTC in ISO 8601, as `2026-10-16T09:30:12Z`
+pub fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_changes_and_timestamp() {
+        let before: HashMap<(usize, usize), String> =
+            [((1, 2), "10".to_string()), ((0, 0), "1".to_string()), ((2, 0), "x".to_string())].into();
+        let after: HashMap<(usize, usize), String> =
+            [((1, 2), "12".to_string()), ((0, 0), "1".to_string()), ((0, 1), "3".to_string())].into();
+        let changed = changes(&before, &after);
+        assert_eq!(
+            changed,
+            vec![
+                ((2, 0), "x".to_string(), String::new()),
+                ((0, 1), String::new(), "3".to_string()),
+                ((1, 2), "10".to_string(), "12".to_string()),
+            ]
+        );
+
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(timestamp(UNIX_EPOCH + Duration::from_secs(1_709_210_096)), "2024-02-29T12:34:56Z");
+    }
+}
@@ -0,0 +1,136 @@
+//! `vicalc bench [filter]`: quick timings of the core operations.
+//!
+//! A dependency-free counterpart of the criterion benches in `benches/` that
+//! also covers what lives in the binary: undo snapshots, import and search
+//! through the command path, and drawing frames. Each case is repeated for
+//! about a second and the mean time per run is printed.
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::commands;
+use crate::formula;
+use crate::ui::UI;
+use crate::App;
+
+// The same sample data as the criterion benches
+#[path = "../benches/common/mod.rs"]
+mod common;
+
+const BUDGET: Duration = Duration::from_secs(1);
+
+fn app_with_csv(rows: usize) -> App {
+    let mut app = App::new();
+    app.term_size = (120, 40);
+    app.sheet = crate::csv::read(&common::sample_csv(rows));
+    app
+}
+
+/// Run `f` repeatedly for about `BUDGET` and print the mean time per run
+fn time<F: FnMut()>(name: &str, mut f: F) {
+    let start = Instant::now();
+    let mut runs = 0u32;
+    while runs == 0 || start.elapsed() < BUDGET {
+        f();
+        runs += 1;
+    }
+    let mean = start.elapsed() / runs;
+    println!("{:<32} {:>12} ({} runs)", name, format_duration(mean), runs);
+}
+
+fn format_duration(d: Duration) -> String {
+    let nanos = d.as_nanos();
+    if nanos < 10_000 {
+        format!("{} ns", nanos)
+    } else if nanos < 10_000_000 {
+        format!("{:.1} µs", nanos as f64 / 1e3)
+    } else {
+        format!("{:.1} ms", nanos as f64 / 1e6)
+    }
+}
+
+/// Entry point for `vicalc bench [filter]`. Returns the process exit code.
+pub fn run_cli(args: &[String]) -> i32 {
+    let filter = args.first().map(String::as_str).unwrap_or("");
+    let selected = |name: &str| name.contains(filter);
+
+    let name = "formula sum 1,000 cells";
+    if selected(name) {
+        let mut app = app_with_csv(0);
+        for row in 0..1000 {
+            app.sheet.set_cell(0, row, row.to_string());
+        }
+        app.sheet.set_cell(1, 0, "=SUM(A1:A1000)".to_string());
+        time(name, || {
+            std::hint::black_box(app.sheet.evaluate(1, 0));
+        });
+    }
+
+    let name = "formula chain of 100 cells";
+    if selected(name) {
+        let mut app = app_with_csv(0);
+        app.sheet.set_cell(0, 0, "1".to_string());
+        for row in 1..100 {
+            app.sheet.set_cell(0, row, format!("={}+1", formula::cell_name(0, row - 1)));
+        }
+        time(name, || {
+            std::hint::black_box(app.sheet.evaluate(0, 99));
+        });
+    }
+
+    let name = "undo snapshot 10k cells";
+    if selected(name) {
+        let mut app = app_with_csv(2500);
+        time(name, || {
+            app.save_undo();
+            app.undo();
+        });
+    }
+
+    let name = "import csv 100k rows";
+    if selected(name) {
+        let path = std::env::temp_dir().join(format!("vicalc-bench-{}.csv", std::process::id()));
+        if let Err(e) = std::fs::write(&path, common::sample_csv(100_000)) {
+            eprintln!("error: {}: {}", path.display(), e);
+            return 2;
+        }
+        let mut app = app_with_csv(0);
+        let cmd = format!("import {}", path.display());
+        time(name, || {
+            commands::execute_command(&mut app, &cmd);
+            app.history = Default::default();
+        });
+        let _ = std::fs::remove_file(&path);
+    }
+
+    let name = "search 1k rows (no match)";
+    if selected(name) {
+        let mut app = app_with_csv(1000);
+        app.last_search = "no such text".to_string();
+        time(name, || {
+            commands::search_forward(&mut app);
+        });
+    }
+
+    let (full, moved) = ("redraw full frame", "redraw after cursor move");
+    if selected(full) || selected(moved) {
+        let mut app = app_with_csv(1000);
+        let mut ui = UI::new(&app);
+        if selected(full) {
+            time(full, || {
+                ui.invalidate();
+                ui.draw_to(&app, &mut io::sink()).unwrap();
+            });
+        }
+        if selected(moved) {
+            let mut down = true;
+            time(moved, || {
+                app.cursor_row = if down { 1 } else { 0 };
+                down = !down;
+                ui.draw_to(&app, &mut io::sink()).unwrap();
+            });
+        }
+    }
+
+    0
+}
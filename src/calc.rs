@@ -0,0 +1,194 @@
+//! When formulas are worked out (`:set calc`).
+//!
+//! Normally a formula is worked out each time it is shown. On a sheet with
+//! many formulas that makes every keystroke wait for the whole screen to be
+//! evaluated, so from `BACKGROUND_FORMULAS` on the sheet keeps the values
+//! from its last recalculation and shows those, while a worker thread works
+//! out a copy of the changed cells; the status bar says "calculating…" until
+//! the new values arrive. With `:set calc=manual` the kept values are only
+//! worked out again with F9 or `:calc`.
+
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use vicalc::sheet::{self, Calculated};
+
+use crate::App;
+
+/// Formulas from which a sheet is recalculated in the background
+pub const BACKGROUND_FORMULAS: usize = 1000;
+
+/// How often a running recalculation is checked for its results
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Mode {
+    // As shown, or in the background for big sheets
+    #[default]
+    Auto,
+    // Only with F9 or :calc
+    Manual,
+}
+
+#[derive(Default)]
+pub struct Calc {
+    pub mode: Mode,
+    // Recalculation running on the worker thread
+    job: Option<Job>,
+    // Formulas in the sheet, with the version of the cells counted
+    formulas: Option<(u64, usize)>,
+    // Version of the cells whose recalculation failed, not to be tried again
+    failed: Option<u64>,
+}
+
+struct Job {
+    // Version of the cells being worked out
+    version: u64,
+    results: Receiver<Calculated>,
+}
+
+impl Calc {
+    /// Whether a recalculation is running in the background
+    pub fn busy(&self) -> bool {
+        self.job.is_some()
+    }
+
+    /// When to look for the results of a running recalculation
+    pub fn deadline(&self, now: Instant) -> Option<Instant> {
+        self.busy().then(|| now + POLL_INTERVAL)
+    }
+}
+
+/// Bring the sheet in line with the mode: take the results of a finished
+/// recalculation, and start one if cells have changed. Returns whether
+/// there is something new to show.
+pub fn update(app: &mut App) -> bool {
+    let mut redraw = false;
+    if let Some(job) = &app.calc.job {
+        match job.results.try_recv() {
+            Ok(calculated) => {
+                app.sheet.finish_recalculation(job.version, calculated);
+                app.calc.job = None;
+                redraw = true;
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => {
+                // The worker panicked; trying the same cells again would too
+                app.calc.failed = Some(job.version);
+                app.calc.job = None;
+                app.error("Recalculation failed; values shown may be out of date");
+                redraw = true;
+            }
+        }
+    }
+
+    let deferred = match app.calc.mode {
+        Mode::Manual => true,
+        Mode::Auto => formulas(app) >= BACKGROUND_FORMULAS,
+    };
+    app.sheet.set_deferred_calc(deferred);
+    let failed = app.calc.failed == Some(app.sheet.version());
+    if app.calc.mode == Mode::Auto && app.sheet.is_stale() && app.calc.job.is_none() && !failed {
+        start(app);
+        redraw = true;
+    }
+    redraw
+}
+
+/// Wait for a running recalculation to finish, for replays that should
+/// come out the same every time
+pub fn wait(app: &mut App) {
+    if let Some(job) = app.calc.job.take() {
+        if let Ok(calculated) = job.results.recv() {
+            app.sheet.finish_recalculation(job.version, calculated);
+        }
+    }
+}
+
+/// Make the values current before a command reads them: wait for the
+/// background recalculation, or if the cells have changed since it started
+/// work them out here. Manual calculation is left as it is.
+pub fn settle(app: &mut App) {
+    if app.calc.mode == Mode::Auto && app.sheet.is_stale() {
+        wait(app);
+        if app.sheet.is_stale() {
+            app.sheet.recalculate();
+        }
+    }
+}
+
+/// Work out a copy of the cells on a worker thread
+fn start(app: &mut App) {
+    let (version, cells) = (app.sheet.version(), app.sheet.cells().clone());
+    let (sender, results) = mpsc::channel();
+    let worker = std::thread::Builder::new()
+        .name("recalculate".to_string())
+        // Evaluation recurses as deep as on the main thread
+        .stack_size(crate::APP_STACK_SIZE)
+        .spawn(move || {
+            let _ = sender.send(sheet::calculate(&cells));
+        });
+    match worker {
+        Ok(_) => app.calc.job = Some(Job { version, results }),
+        // Without a thread, the work is done here
+        Err(_) => app.sheet.recalculate(),
+    }
+}
+
+/// Formulas in the sheet, counted again only once its cells have changed
+fn formulas(app: &mut App) -> usize {
+    let version = app.sheet.version();
+    match app.calc.formulas {
+        Some((counted, formulas)) if counted == version => formulas,
+        _ => {
+            let formulas = app.sheet.cells().values().filter(|cell| cell.raw_input.starts_with('=')).count();
+            app.calc.formulas = Some((version, formulas));
+            formulas
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_background_recalculation() {
+        let mut app = App::new();
+        for row in 0..BACKGROUND_FORMULAS {
+            app.sheet.set_cell(0, row, format!("={}", row));
+        }
+        app.sheet.set_cell(1, 0, "1".to_string());
+        app.sheet.set_cell(2, 0, "=B1*2".to_string());
+        update(&mut app);
+        assert!(app.sheet.deferred_calc() && !app.calc.busy());
+        assert_eq!(app.calc.formulas, Some((app.sheet.version(), BACKGROUND_FORMULAS + 1)));
+
+        // The old value shows until the worker is done
+        app.sheet.set_cell(1, 0, "5".to_string());
+        assert!(update(&mut app));
+        assert!(app.calc.busy());
+        assert_eq!(app.sheet.evaluate(2, 0), "2");
+        wait(&mut app);
+        assert_eq!(app.sheet.evaluate(2, 0), "10");
+        assert!(!app.sheet.is_stale());
+
+        // A worker that died is not started again on the same cells
+        app.sheet.set_cell(1, 0, "7".to_string());
+        let (sender, results) = mpsc::channel();
+        drop(sender);
+        app.calc.job = Some(Job { version: app.sheet.version(), results });
+        assert!(update(&mut app));
+        assert!(!app.calc.busy() && app.sheet.is_stale());
+        app.sheet.set_cell(1, 0, "8".to_string());
+        update(&mut app);
+        assert!(app.calc.busy());
+        wait(&mut app);
+        assert_eq!(app.sheet.evaluate(2, 0), "16");
+
+        // Small sheets are worked out as shown
+        app.sheet = vicalc::sheet::Sheet::new();
+        update(&mut app);
+        assert!(!app.sheet.deferred_calc());
+    }
+}
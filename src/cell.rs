@@ -20,6 +20,17 @@ pub enum CellError {
     Num,        // #NUM!
     NA,         // #N/A
     Cycle,      // Circular reference
+    Spill,      // #SPILL! (an array formula's result would cover other cells)
+}
+
+impl CellValue {
+    /// Bytes allocated on the heap by the value
+    pub fn heap_size(&self) -> usize {
+        match self {
+            CellValue::Text(s) | CellValue::Formula(s) => s.capacity(),
+            _ => 0,
+        }
+    }
 }
 
 impl CellError {
@@ -32,14 +43,17 @@ impl CellError {
             CellError::Num => "#NUM!",
             CellError::NA => "#N/A",
             CellError::Cycle => "#CYCLE!",
+            CellError::Spill => "#SPILL!",
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
 pub enum DisplayFormat {
+    #[default]
     General,
     Number(usize),      // decimal places
+    Grouped(usize),     // decimal places, thousands separated
     Currency(usize),
     Percent(usize),
     Scientific,
@@ -47,9 +61,223 @@ pub enum DisplayFormat {
     Text,
 }
 
-impl Default for DisplayFormat {
-    fn default() -> Self {
-        DisplayFormat::General
+impl DisplayFormat {
+    /// A format by name, with its decimal places where it has them
+    pub fn parse(name: &str, decimals: usize) -> Option<DisplayFormat> {
+        let format = match name.to_lowercase().as_str() {
+            "general" => DisplayFormat::General,
+            "number" => DisplayFormat::Number(decimals),
+            "grouped" => DisplayFormat::Grouped(decimals),
+            "currency" => DisplayFormat::Currency(decimals),
+            "percent" => DisplayFormat::Percent(decimals),
+            "scientific" => DisplayFormat::Scientific,
+            "date" => DisplayFormat::Date,
+            "text" => DisplayFormat::Text,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    pub fn is_general(&self) -> bool {
+        *self == DisplayFormat::General
+    }
+}
+
+/// How numbers are written, both shown and typed: `:set numfmt=grouped`
+/// separates the thousands of numbers without a format of their own, and
+/// `:set decimal=comma` writes 1.234.567,89 for 1,234,567.89. Cells always
+/// keep what was typed with a decimal point; the style only changes how
+/// numbers are shown and read from the user.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NumberStyle {
+    pub grouped: bool,
+    pub decimal_comma: bool,
+}
+
+impl NumberStyle {
+    /// Input typed in this style as a cell keeps it: "1,5", "$1.234,50" or
+    /// "12,5%" with a decimal comma becomes "1.5", "$1,234.50" or "12.5%".
+    /// Anything but a number, such as a formula or text, is kept as typed.
+    pub fn canonical_input(self, input: &str) -> String {
+        if !self.decimal_comma || input.trim_start().starts_with('=') {
+            return input.to_string();
+        }
+        match parse_typed(input, self).0 {
+            CellValue::Number(_) => swap_marks(input),
+            _ => input.to_string(),
+        }
+    }
+
+    /// Whether input typed in this style is text that would be a number
+    /// with a decimal point, such as "1.5" or "1.234" with a decimal comma.
+    /// Kept as it is, it would read as a number, so it is stored as text.
+    pub fn is_foreign_number(self, input: &str) -> bool {
+        self.decimal_comma
+            && !input.trim_start().starts_with('=')
+            && !matches!(parse_typed(input, self).0, CellValue::Number(_))
+            && matches!(parse_typed(input, NumberStyle::default()).0, CellValue::Number(_))
+    }
+
+    /// What a cell keeps, as typed in this style for editing it again: the
+    /// reverse of `canonical_input` for numbers; text stays as it is
+    pub fn typed_input(self, cell: &Cell) -> String {
+        if !self.decimal_comma || !matches!(cell.value, CellValue::Number(_)) {
+            return cell.raw_input.clone();
+        }
+        swap_marks(&cell.raw_input)
+    }
+
+    /// The thousands separator and the decimal mark
+    fn separators(self) -> (char, char) {
+        if self.decimal_comma { ('.', ',') } else { (',', '.') }
+    }
+
+    /// A number written plainly ("-1234.5") in this style: with the decimal
+    /// mark it uses and, if `grouped`, the thousands separated
+    pub fn localize(self, plain: &str, grouped: bool) -> String {
+        let (group, mark) = self.separators();
+        let (sign, rest) = plain.strip_prefix('-').map_or(("", plain), |rest| ("-", rest));
+        let (whole, fraction) = rest.split_once('.').map_or((rest, None), |(whole, fraction)| (whole, Some(fraction)));
+        let mut out = sign.to_string();
+        if grouped && whole.bytes().all(|b| b.is_ascii_digit()) {
+            for (i, c) in whole.chars().enumerate() {
+                if i > 0 && (whole.len() - i) % 3 == 0 {
+                    out.push(group);
+                }
+                out.push(c);
+            }
+        } else {
+            out.push_str(whole);
+        }
+        if let Some(fraction) = fraction {
+            out.push(mark);
+            out.push_str(fraction);
+        }
+        out
+    }
+
+    /// A number as typed in this style: "1234.5", or "1234,5" with a
+    /// decimal comma, when a point is not a decimal mark
+    pub fn parse_number(self, text: &str) -> Option<f64> {
+        if !self.decimal_comma {
+            return text.parse().ok();
+        }
+        if text.contains('.') {
+            return None;
+        }
+        text.replacen(',', ".", 1).parse().ok()
+    }
+
+    /// A number as typed in this style with its thousands separated or not
+    /// ("1,234.5", or "1.234,5" with a decimal comma). Separators must come
+    /// every three digits, so "1,5" is not 15.
+    pub fn parse_grouped(self, text: &str) -> Option<f64> {
+        let (group, mark) = self.separators();
+        let (whole, fraction) = text.split_once(mark).map_or((text, None), |(whole, fraction)| (whole, Some(fraction)));
+        let digits = whole.trim_start_matches(['-', '+']);
+        let groups: Vec<&str> = digits.split(group).collect();
+        let all_digits = |g: &&str| g.bytes().all(|b| b.is_ascii_digit());
+        if groups.len() > 1
+            && !((1..=3).contains(&groups[0].len()) && groups.iter().all(all_digits) && groups[1..].iter().all(|g| g.len() == 3))
+        {
+            return None;
+        }
+        let mut plain = format!("{}{}", &whole[..whole.len() - digits.len()], groups.concat());
+        if let Some(fraction) = fraction {
+            plain = format!("{}.{}", plain, fraction);
+        }
+        plain.parse().ok()
+    }
+
+    /// Decimal places of a number as typed in this style
+    fn decimals(self, text: &str) -> usize {
+        let (_, mark) = self.separators();
+        text.split_once(mark).map_or(0, |(_, fraction)| fraction.chars().take_while(char::is_ascii_digit).count())
+    }
+}
+
+/// Horizontal alignment set with :align. Without one, numbers go to the
+/// right and text to the left.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+impl Align {
+    pub fn parse(name: &str) -> Option<Align> {
+        match name.to_lowercase().as_str() {
+            "left" => Some(Align::Left),
+            "right" => Some(Align::Right),
+            "center" | "centre" => Some(Align::Center),
+            _ => None,
+        }
+    }
+}
+
+/// Text color, saved as "#rrggbb"
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(into = "String", try_from = "String")]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    /// A color name (red, green, yellow, blue, magenta, cyan, gray, white) or
+    /// "#rrggbb"
+    pub fn parse(text: &str) -> Option<Rgb> {
+        if let Some(hex) = text.strip_prefix('#') {
+            if hex.len() != 6 || !hex.is_ascii() {
+                return None;
+            }
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+            return Some(Rgb(channel(0)?, channel(2)?, channel(4)?));
+        }
+        let rgb = match text.to_lowercase().as_str() {
+            "red" => Rgb(0xe0, 0x40, 0x40),
+            "green" => Rgb(0x40, 0xb0, 0x40),
+            "yellow" => Rgb(0xd0, 0xb0, 0x20),
+            "blue" => Rgb(0x40, 0x80, 0xf0),
+            "magenta" => Rgb(0xc0, 0x50, 0xc0),
+            "cyan" => Rgb(0x30, 0xb0, 0xc0),
+            "gray" | "grey" => Rgb(0x80, 0x80, 0x80),
+            "white" => Rgb(0xff, 0xff, 0xff),
+            _ => return None,
+        };
+        Some(rgb)
+    }
+}
+
+impl From<Rgb> for String {
+    fn from(Rgb(r, g, b): Rgb) -> String {
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+impl TryFrom<String> for Rgb {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Rgb, String> {
+        Rgb::parse(&text).ok_or_else(|| format!("invalid color: {}", text))
+    }
+}
+
+/// How a cell is shown, set with :align and :style
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct CellStyle {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub align: Option<Align>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bold: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub underline: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<Rgb>,
+}
+
+impl CellStyle {
+    pub fn is_plain(&self) -> bool {
+        *self == CellStyle::default()
     }
 }
 
@@ -58,6 +286,8 @@ pub struct Cell {
     pub value: CellValue,
     pub raw_input: String,
     pub format: DisplayFormat,
+    #[serde(default)]
+    pub style: CellStyle,
 }
 
 impl Default for Cell {
@@ -66,6 +296,7 @@ impl Default for Cell {
             value: CellValue::Empty,
             raw_input: String::new(),
             format: DisplayFormat::General,
+            style: CellStyle::default(),
         }
     }
 }
@@ -76,6 +307,7 @@ impl Cell {
             value,
             raw_input: input,
             format: DisplayFormat::General,
+            style: CellStyle::default(),
         }
     }
 
@@ -83,6 +315,11 @@ impl Cell {
         matches!(self.value, CellValue::Empty)
     }
 
+    /// Bytes allocated on the heap by the cell, not counting the cell itself
+    pub fn heap_size(&self) -> usize {
+        self.raw_input.capacity() + self.value.heap_size()
+    }
+
     pub fn display(&self, width: usize) -> String {
         let text = match &self.value {
             CellValue::Empty => String::new(),
@@ -93,13 +330,13 @@ impl Cell {
             CellValue::Error(e) => e.to_string().to_string(),
         };
 
-        if text.len() > width {
+        if text.chars().count() > width {
             if matches!(self.value, CellValue::Number(_)) {
                 // Numbers show ### if too wide
                 "#".repeat(width)
             } else {
                 // Text gets truncated
-                text[..width].to_string()
+                text.chars().take(width).collect()
             }
         } else {
             text
@@ -107,30 +344,39 @@ impl Cell {
     }
 
     pub fn format_number(&self, n: f64) -> String {
+        self.format_number_in(n, NumberStyle::default())
+    }
+
+    /// `n` in the cell's format, written in `style`
+    pub fn format_number_in(&self, n: f64, style: NumberStyle) -> String {
         match &self.format {
             DisplayFormat::General => {
                 if n == n.floor() && n.abs() < 1e10 {
-                    format!("{:.0}", n)
+                    style.localize(&format!("{:.0}", n), style.grouped)
                 } else if n.abs() < 0.0001 || n.abs() >= 1e10 {
-                    format!("{:.2e}", n)
+                    style.localize(&format!("{:.2e}", n), false)
                 } else {
                     // Remove trailing zeros
                     let s = format!("{:.6}", n);
                     let s = s.trim_end_matches('0').trim_end_matches('.');
-                    s.to_string()
+                    style.localize(s, style.grouped)
                 }
             }
             DisplayFormat::Number(decimals) => {
-                format!("{:.prec$}", n, prec = decimals)
+                style.localize(&format!("{:.prec$}", n, prec = decimals), style.grouped)
+            }
+            DisplayFormat::Grouped(decimals) => {
+                style.localize(&format!("{:.prec$}", n, prec = decimals), true)
             }
             DisplayFormat::Currency(decimals) => {
-                format!("${:.prec$}", n, prec = decimals)
+                let amount = style.localize(&format!("{:.prec$}", n.abs(), prec = decimals), true);
+                format!("{}${}", if n < 0.0 { "-" } else { "" }, amount)
             }
             DisplayFormat::Percent(decimals) => {
-                format!("{:.prec$}%", n * 100.0, prec = decimals)
+                format!("{}%", style.localize(&format!("{:.prec$}", n * 100.0, prec = decimals), style.grouped))
             }
             DisplayFormat::Scientific => {
-                format!("{:.2e}", n)
+                style.localize(&format!("{:.2e}", n), false)
             }
             DisplayFormat::Date => {
                 // Excel serial date (days since 1900-01-01)
@@ -144,39 +390,138 @@ impl Cell {
     }
 }
 
-/// Parse raw input into CellValue
+/// Parse raw input into CellValue, numbers written with a decimal point
 pub fn parse_input(input: &str) -> CellValue {
+    parse_input_in(input, NumberStyle::default())
+}
+
+/// Parse input typed in a number style into CellValue
+pub fn parse_input_in(input: &str, style: NumberStyle) -> CellValue {
+    parse_typed(input, style).0
+}
+
+/// Points as commas and commas as points
+fn swap_marks(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '.' => ',',
+            ',' => '.',
+            c => c,
+        })
+        .collect()
+}
+
+/// Parse raw input into CellValue, with the format it was written in:
+/// `$1,234.50` is a number shown as currency with two decimals and `45%`
+/// one shown as a percentage. Other input has no format of its own.
+pub fn parse_typed(input: &str, style: NumberStyle) -> (CellValue, Option<DisplayFormat>) {
     let trimmed = input.trim();
     
     if trimmed.is_empty() {
-        return CellValue::Empty;
+        return (CellValue::Empty, None);
     }
 
     // Formula starts with =
     if trimmed.starts_with('=') {
-        return CellValue::Formula(trimmed.to_string());
+        return (CellValue::Formula(trimmed.to_string()), None);
     }
 
     // Boolean
     if trimmed.eq_ignore_ascii_case("true") {
-        return CellValue::Boolean(true);
+        return (CellValue::Boolean(true), None);
     }
     if trimmed.eq_ignore_ascii_case("false") {
-        return CellValue::Boolean(false);
+        return (CellValue::Boolean(false), None);
+    }
+
+    // Number, with a decimal point unless a decimal comma is in use
+    if let Some(n) = style.parse_number(trimmed) {
+        return (CellValue::Number(n), None);
     }
 
-    // Number
-    if let Ok(n) = trimmed.parse::<f64>() {
-        return CellValue::Number(n);
+    // Currency (e.g., "$1,234.50" or "-$5")
+    let (sign, unsigned) = trimmed.strip_prefix('-').map_or((1.0, trimmed), |rest| (-1.0, rest));
+    if let Some(amount) = unsigned.strip_prefix('$').filter(|a| a.starts_with(|c: char| c.is_ascii_digit())) {
+        if let Some(n) = style.parse_grouped(amount) {
+            return (CellValue::Number(sign * n), Some(DisplayFormat::Currency(style.decimals(amount))));
+        }
     }
 
     // Percentage (e.g., "50%")
-    if trimmed.ends_with('%') {
-        if let Ok(n) = trimmed[..trimmed.len()-1].trim().parse::<f64>() {
-            return CellValue::Number(n / 100.0);
+    if let Some(number) = trimmed.strip_suffix('%') {
+        if let Some(n) = style.parse_grouped(number.trim()) {
+            return (CellValue::Number(n / 100.0), Some(DisplayFormat::Percent(style.decimals(number))));
         }
     }
 
     // Text
-    CellValue::Text(trimmed.to_string())
+    (CellValue::Text(trimmed.to_string()), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_number_style() {
+        let point = NumberStyle::default();
+        let comma = NumberStyle { grouped: true, decimal_comma: true };
+        let grouped = Cell { format: DisplayFormat::Grouped(2), ..Cell::default() };
+        assert_eq!(grouped.format_number_in(1234567.891, point), "1,234,567.89");
+        assert_eq!(grouped.format_number_in(-999.5, point), "-999.50");
+        assert_eq!(grouped.format_number_in(1234567.891, comma), "1.234.567,89");
+        assert_eq!(Cell::default().format_number_in(1234567.5, comma), "1.234.567,5");
+        assert_eq!(Cell::default().format_number_in(1234567.5, point), "1234567.5");
+        let currency = Cell { format: DisplayFormat::Currency(2), ..Cell::default() };
+        assert_eq!(currency.format_number_in(-1234.5, point), "-$1,234.50");
+
+        assert_eq!(parse_input_in("1234.5", point), CellValue::Number(1234.5));
+        assert_eq!(parse_input_in("-2,5", comma), CellValue::Number(-2.5));
+        assert_eq!(parse_input_in("12,5%", comma), CellValue::Number(0.125));
+        assert_eq!(parse_input_in("1e3", comma), CellValue::Number(1000.0));
+        assert_eq!(parse_input_in("1,5", point), CellValue::Text("1,5".to_string()));
+        assert_eq!(parse_input_in("1.5", comma), CellValue::Text("1.5".to_string()));
+        assert_eq!(parse_input_in("1,2,3", comma), CellValue::Text("1,2,3".to_string()));
+
+        // Typed numbers are kept with a decimal point and edited as typed
+        assert_eq!(comma.canonical_input("-2,5"), "-2.5");
+        assert_eq!(comma.canonical_input("$1.234,50"), "$1,234.50");
+        assert_eq!(comma.canonical_input("12,5%"), "12.5%");
+        assert_eq!(comma.canonical_input("1.5"), "1.5");
+        assert_eq!(comma.canonical_input("=ROUND(A1,2)"), "=ROUND(A1,2)");
+        assert_eq!(comma.canonical_input("a,b"), "a,b");
+        assert_eq!(point.canonical_input("1,5"), "1,5");
+        let typed = |style: NumberStyle, raw: &str| style.typed_input(&Cell::new(raw.to_string(), parse_input(raw)));
+        assert_eq!(typed(comma, "$1,234.50"), "$1.234,50");
+        assert_eq!(typed(comma, "1,5"), "1,5");
+        assert_eq!(typed(point, "2.5"), "2.5");
+        assert_eq!(comma.typed_input(&Cell::new("1.5".to_string(), CellValue::Text("1.5".to_string()))), "1.5");
+
+        // Numbers only with a decimal point are text with a decimal comma
+        assert!(comma.is_foreign_number("1.5"));
+        assert!(comma.is_foreign_number("1.234"));
+        assert!(!comma.is_foreign_number("1,5"));
+        assert!(!comma.is_foreign_number("abc"));
+        assert!(!comma.is_foreign_number("=1.5"));
+        assert!(!point.is_foreign_number("1.5"));
+    }
+
+    #[test]
+    fn test_currency_and_percent_input() {
+        let point = NumberStyle::default();
+        let comma = NumberStyle { grouped: false, decimal_comma: true };
+        assert_eq!(parse_typed("$1,234.50", point), (CellValue::Number(1234.5), Some(DisplayFormat::Currency(2))));
+        assert_eq!(parse_typed("-$5", point), (CellValue::Number(-5.0), Some(DisplayFormat::Currency(0))));
+        assert_eq!(parse_typed("$1.234,5", comma), (CellValue::Number(1234.5), Some(DisplayFormat::Currency(1))));
+        assert_eq!(parse_typed("45%", point), (CellValue::Number(0.45), Some(DisplayFormat::Percent(0))));
+        assert_eq!(parse_typed("12.5 %", point), (CellValue::Number(0.125), Some(DisplayFormat::Percent(1))));
+        assert_eq!(parse_typed("42", point), (CellValue::Number(42.0), None));
+        // Separators every three digits only, and plain numbers keep none
+        assert_eq!(parse_typed("$1,23", point).0, CellValue::Text("$1,23".to_string()));
+        assert_eq!(parse_typed("$-5", point).0, CellValue::Text("$-5".to_string()));
+        assert_eq!(parse_typed("1,234", point).0, CellValue::Text("1,234".to_string()));
+
+        let cell = Cell { format: DisplayFormat::Currency(2), ..Cell::default() };
+        assert_eq!(cell.format_number_in(1234.5, point), "$1,234.50");
+    }
 }
@@ -0,0 +1,491 @@
+//! Terminal charts for `:chart` and `:hist`, and `:trend` sparklines.
+//!
+//! A range becomes one or more series: with a single column its values are
+//! plotted in order, with more the first column gives the x values (or
+//! labels) and every other column is a series. A first row of text is taken
+//! as the series names. Charts are drawn onto a grid of glyphs, bars from
+//! block elements in eighths and lines from braille dots (2x4 per
+//! character), and each glyph remembers the series that drew it so the UI
+//! can color it. A histogram is a bar chart of how many of a column's
+//! numbers fall in each of a number of equal bins.
+
+use crate::cell::CellValue;
+use crate::formula;
+use crate::sheet::Sheet;
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Bar,
+    Line,
+    Histogram,
+}
+
+impl Kind {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "bar" => Some(Kind::Bar),
+            "line" => Some(Kind::Line),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Bar => "bar",
+            Kind::Line => "line",
+            Kind::Histogram => "histogram",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Series {
+    pub name: String,
+    pub values: Vec<Option<f64>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chart {
+    pub kind: Kind,
+    /// The charted range in A1 notation
+    pub range: String,
+    /// Label of each point (x value or category)
+    pub labels: Vec<String>,
+    /// Numeric x values, when the x column is all numbers
+    pub xs: Option<Vec<f64>>,
+    pub series: Vec<Series>,
+}
+
+/// A character of the drawn chart; `series` is None for axes and labels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glyph {
+    pub ch: char,
+    pub series: Option<usize>,
+}
+
+impl Glyph {
+    const BLANK: Glyph = Glyph { ch: ' ', series: None };
+}
+
+fn number(sheet: &Sheet, col: usize, row: usize) -> Option<f64> {
+    match sheet.value(col, row) {
+        CellValue::Number(n) => Some(n),
+        _ => None,
+    }
+}
+
+/// Short axis label for a value
+fn format_value(n: f64) -> String {
+    if n == n.trunc() && n.abs() < 1e9 {
+        format!("{:.0}", n)
+    } else if n.abs() >= 1e9 || n.abs() < 0.01 {
+        format!("{:.1e}", n)
+    } else {
+        let s = format!("{:.2}", n);
+        s.trim_end_matches('0').trim_end_matches('.').to_string()
+    }
+}
+
+fn chars_of(s: &str, series: Option<usize>) -> Vec<Glyph> {
+    s.chars().map(|ch| Glyph { ch, series }).collect()
+}
+
+impl Chart {
+    /// Collect the data of `(start_col, start_row, end_col, end_row)`
+    pub fn from_range(sheet: &Sheet, kind: Kind, bounds: (usize, usize, usize, usize)) -> Result<Chart, String> {
+        let (c1, r1, c2, r2) = bounds;
+        let range = format!("{}:{}", formula::cell_name(c1, r1), formula::cell_name(c2, r2));
+        let x_col = if c2 > c1 { Some(c1) } else { None };
+        let series_cols: Vec<usize> = match x_col {
+            Some(x) => (x + 1..=c2).collect(),
+            None => vec![c1],
+        };
+
+        // A header row has text above the series
+        let header = series_cols.iter().any(|&col| matches!(sheet.value(col, r1), CellValue::Text(_)));
+        let first = if header { r1 + 1 } else { r1 };
+        if first > r2 {
+            return Err(format!("No data to chart in {}", range));
+        }
+        let rows = first..=r2;
+
+        let series: Vec<Series> = series_cols
+            .iter()
+            .map(|&col| Series {
+                name: if header { sheet.evaluate(col, r1) } else { formula::col_to_name(col) },
+                values: rows.clone().map(|row| number(sheet, col, row)).collect(),
+            })
+            .collect();
+        if series.iter().all(|s| s.values.iter().all(Option::is_none)) {
+            return Err(format!("No numbers to chart in {}", range));
+        }
+
+        let (labels, xs) = match x_col {
+            Some(x) => {
+                let labels = rows.clone().map(|row| sheet.evaluate(x, row)).collect();
+                let xs: Option<Vec<f64>> = rows.clone().map(|row| number(sheet, x, row)).collect();
+                (labels, xs)
+            }
+            None => (rows.clone().map(|row| (row + 1).to_string()).collect(), None),
+        };
+
+        Ok(Chart { kind, range, labels, xs, series })
+    }
+
+    /// Count the visible numbers of a column into `bins` equal bins (by
+    /// Sturges' rule when None), labelled by their lower edges
+    pub fn histogram(sheet: &Sheet, col: usize, bins: Option<usize>) -> Result<Chart, String> {
+        let name = formula::col_to_name(col);
+        let rows: Vec<usize> = (0..=sheet.max_row_in_col(col).unwrap_or(0))
+            .filter(|&row| !sheet.is_row_hidden(row) && number(sheet, col, row).is_some())
+            .collect();
+        let (Some(&first), Some(&last)) = (rows.first(), rows.last()) else {
+            return Err(format!("No numbers in column {}", name));
+        };
+        let values: Vec<f64> = rows.iter().filter_map(|&row| number(sheet, col, row)).collect();
+        let (min, max) = values.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &n| (lo.min(n), hi.max(n)));
+
+        let bins = match bins {
+            _ if max == min => 1,
+            Some(bins) => bins.max(1),
+            None => (values.len() as f64).log2().ceil() as usize + 1,
+        };
+        let width = (max - min) / bins as f64;
+        let mut counts = vec![0.0; bins];
+        for v in &values {
+            let bin = if width > 0.0 { ((v - min) / width) as usize } else { 0 };
+            counts[bin.min(bins - 1)] += 1.0;
+        }
+
+        // Name the series after the header above the numbers, if any
+        let header = sheet.header_row().filter(|&h| h < first && matches!(sheet.value(col, h), CellValue::Text(_)));
+        Ok(Chart {
+            kind: Kind::Histogram,
+            range: format!("{}:{}", formula::cell_name(col, first), formula::cell_name(col, last)),
+            labels: (0..bins).map(|i| format_value(min + width * i as f64)).collect(),
+            xs: None,
+            series: vec![Series {
+                name: header.map_or(name, |h| sheet.evaluate(col, h)),
+                values: counts.into_iter().map(Some).collect(),
+            }],
+        })
+    }
+
+    /// Lowest and highest value over all series; bars always include zero
+    fn value_range(&self) -> (f64, f64) {
+        let values = self.series.iter().flat_map(|s| s.values.iter().flatten().copied());
+        let (mut min, mut max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), n| (lo.min(n), hi.max(n)));
+        if self.kind != Kind::Line {
+            min = min.min(0.0);
+            max = max.max(0.0);
+        }
+        if max <= min {
+            max = min + 1.0;
+        }
+        (min, max)
+    }
+
+    /// Draw the chart, axes and labels into `width` x `height` glyphs
+    pub fn render(&self, width: usize, height: usize) -> Vec<Vec<Glyph>> {
+        let mut grid = vec![vec![Glyph::BLANK; width]; height];
+        if height < 3 || width < 8 {
+            return grid;
+        }
+        let (min, max) = self.value_range();
+        let plot_h = height - 2;
+
+        // Y axis labels at the top, middle and bottom
+        let mut y_labels = vec![(0, format_value(max)), (plot_h - 1, format_value(min))];
+        if plot_h >= 5 {
+            y_labels.push((plot_h / 2, format_value(max - (max - min) * (plot_h / 2) as f64 / (plot_h - 1) as f64)));
+        }
+        let label_w = y_labels.iter().map(|(_, s)| s.chars().count()).max().unwrap_or(0).min(width / 3);
+        for (row, label) in &y_labels {
+            let label: String = label.chars().take(label_w).collect();
+            let pad = label_w - label.chars().count();
+            for (i, g) in chars_of(&label, None).into_iter().enumerate() {
+                grid[*row][pad + i] = g;
+            }
+        }
+        for (row, line) in grid.iter_mut().enumerate().take(plot_h) {
+            let tick = y_labels.iter().any(|(r, _)| *r == row);
+            line[label_w] = Glyph { ch: if tick { '┤' } else { '│' }, series: None };
+        }
+        grid[plot_h][label_w] = Glyph { ch: '└', series: None };
+        for g in grid[plot_h].iter_mut().skip(label_w + 1) {
+            g.ch = '─';
+        }
+
+        let left = label_w + 1;
+        let plot_w = width - left;
+        match self.kind {
+            Kind::Bar | Kind::Histogram => self.render_bars(&mut grid, left, plot_w, plot_h, min, max),
+            Kind::Line => self.render_lines(&mut grid, left, plot_w, plot_h, min, max),
+        }
+        grid
+    }
+
+    fn render_bars(&self, grid: &mut [Vec<Glyph>], left: usize, plot_w: usize, plot_h: usize, min: f64, max: f64) {
+        let n_series = self.series.len();
+        let shown = self.labels.len().min(plot_w / n_series);
+        if shown == 0 {
+            return;
+        }
+        let slot = plot_w / shown;
+        // Leave a column between groups when there is room
+        let bar_w = if slot > n_series { ((slot - 1) / n_series).max(1) } else { 1 };
+
+        // Heights are in eighths of a row; the zero line sits on a row boundary
+        let eighths = (plot_h * 8) as f64;
+        let level = |v: f64| ((v - min) / (max - min) * eighths).round() as usize;
+        let zero = level(0.0) / 8 * 8;
+
+        for point in 0..shown {
+            let x0 = left + point * slot;
+            for (s, series) in self.series.iter().enumerate() {
+                let Some(value) = series.values[point] else { continue };
+                let v = level(value);
+                let (lo, hi, up) = if v >= zero { (zero, v, true) } else { (v, zero, false) };
+                for (row, line) in grid.iter_mut().enumerate().take(plot_h) {
+                    let bottom = (plot_h - 1 - row) * 8;
+                    let fill = hi.min(bottom + 8).saturating_sub(lo.max(bottom));
+                    let ch = match fill {
+                        0 => continue,
+                        8 => '█',
+                        n if up => BARS[n - 1],
+                        n if n >= 4 => '▀',
+                        _ => '▔',
+                    };
+                    let start = x0 + s * bar_w;
+                    for g in &mut line[start..(start + bar_w).min(left + plot_w)] {
+                        *g = Glyph { ch, series: Some(s) };
+                    }
+                }
+            }
+            // Category label under the group
+            if slot >= 2 {
+                let label: String = self.labels[point].chars().take(slot - 1).collect();
+                for (i, g) in chars_of(&label, None).into_iter().enumerate() {
+                    grid[plot_h + 1][x0 + i] = g;
+                }
+            }
+        }
+    }
+
+    fn render_lines(&self, grid: &mut [Vec<Glyph>], left: usize, plot_w: usize, plot_h: usize, min: f64, max: f64) {
+        // Dot bits of a braille character by (x, y) within its 2x4 cell
+        const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+        let (dots_w, dots_h) = (plot_w * 2, plot_h * 4);
+        let mut bits = vec![vec![(0u32, None); plot_w]; plot_h];
+        let n = self.labels.len();
+
+        let x_of = |i: usize| -> usize {
+            match &self.xs {
+                Some(xs) => {
+                    let (lo, hi) = xs.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(a, b), &x| (a.min(x), b.max(x)));
+                    if hi > lo { ((xs[i] - lo) / (hi - lo) * (dots_w - 1) as f64).round() as usize } else { 0 }
+                }
+                None if n > 1 => i * (dots_w - 1) / (n - 1),
+                None => 0,
+            }
+        };
+        let y_of = |v: f64| -> usize { dots_h - 1 - ((v - min) / (max - min) * (dots_h - 1) as f64).round() as usize };
+
+        for (s, series) in self.series.iter().enumerate() {
+            let mut points: Vec<(usize, usize)> = (0..n)
+                .filter_map(|i| series.values[i].map(|v| (x_of(i), y_of(v))))
+                .collect();
+            points.sort_by_key(|&(x, _)| x);
+
+            let mut plot = |x: usize, y: usize| {
+                let cell = &mut bits[y / 4][x / 2];
+                cell.0 |= DOTS[x % 2][y % 4];
+                cell.1 = Some(s);
+            };
+            if let [(x, y)] = points[..] {
+                plot(x, y);
+            }
+            for pair in points.windows(2) {
+                // Bresenham between consecutive points
+                let ((x0, y0), (x1, y1)) = ((pair[0].0 as isize, pair[0].1 as isize), (pair[1].0 as isize, pair[1].1 as isize));
+                let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+                let (sx, sy) = (if x0 < x1 { 1 } else { -1 }, if y0 < y1 { 1 } else { -1 });
+                let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+                loop {
+                    plot(x as usize, y as usize);
+                    if x == x1 && y == y1 {
+                        break;
+                    }
+                    let e2 = 2 * err;
+                    if e2 >= dy {
+                        err += dy;
+                        x += sx;
+                    }
+                    if e2 <= dx {
+                        err += dx;
+                        y += sy;
+                    }
+                }
+            }
+        }
+
+        for (row, line) in bits.iter().enumerate() {
+            for (col, &(b, series)) in line.iter().enumerate() {
+                if b != 0 {
+                    grid[row][left + col] = Glyph { ch: char::from_u32(0x2800 + b).unwrap_or(' '), series };
+                }
+            }
+        }
+
+        // First and last x label under the axis
+        if let (Some(first), Some(last)) = (self.labels.first(), self.labels.last()) {
+            let first: Vec<Glyph> = chars_of(first, None).into_iter().take(plot_w / 2).collect();
+            let last: Vec<Glyph> = chars_of(last, None).into_iter().take(plot_w / 2).collect();
+            for (i, g) in first.into_iter().enumerate() {
+                grid[plot_h + 1][left + i] = g;
+            }
+            let start = left + plot_w - last.len();
+            for (i, g) in last.into_iter().enumerate() {
+                grid[plot_h + 1][start + i] = g;
+            }
+        }
+    }
+}
+
+/// `:trend`: a one-line summary of a row or column, a sparkline of its
+/// numbers followed by their lowest, highest and last value. Text before
+/// the first number (a header) is left out, and other gaps stay blank. At
+/// most `width` bars are drawn, the last ones when there are more.
+pub fn trend(sheet: &Sheet, bounds: (usize, usize, usize, usize), width: usize) -> Result<String, String> {
+    let (c1, r1, c2, r2) = bounds;
+    let cells: Vec<(usize, usize)> = if c1 == c2 {
+        let last = sheet.max_row_in_col(c1).map_or(r1, |r| r.min(r2));
+        (r1..=last).filter(|&row| !sheet.is_row_hidden(row)).map(|row| (c1, row)).collect()
+    } else if r1 == r2 {
+        let last = sheet.max_col_in_row(r1).map_or(c1, |c| c.min(c2));
+        (c1..=last).map(|col| (col, r1)).collect()
+    } else {
+        return Err("Select a single row or column".to_string());
+    };
+    let values: Vec<Option<f64>> = cells.iter().map(|&(col, row)| number(sheet, col, row)).collect();
+    let (Some(first), Some(last)) = (values.iter().position(Option::is_some), values.iter().rposition(Option::is_some))
+    else {
+        return Err(format!("No numbers in {}:{}", formula::cell_name(c1, r1), formula::cell_name(c2, r2)));
+    };
+    let values = &values[first..=last];
+    let numbers: Vec<f64> = values.iter().flatten().copied().collect();
+    let (min, max) = numbers.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &n| (lo.min(n), hi.max(n)));
+
+    let shown = &values[values.len().saturating_sub(width.max(1))..];
+    let bars: String = shown
+        .iter()
+        .map(|v| match v {
+            Some(n) if max > min => BARS[((n - min) / (max - min) * (BARS.len() - 1) as f64).round() as usize],
+            Some(_) => BARS[BARS.len() / 2],
+            None => ' ',
+        })
+        .collect();
+    let (start, end) = (cells[first], cells[last]);
+    Ok(format!(
+        "{}:{} {}{} min {} max {} last {} ({} {})",
+        formula::cell_name(start.0, start.1),
+        formula::cell_name(end.0, end.1),
+        if shown.len() < values.len() { "…" } else { "" },
+        bars,
+        format_value(min),
+        format_value(max),
+        format_value(numbers[numbers.len() - 1]),
+        numbers.len(),
+        if numbers.len() == 1 { "value" } else { "values" }
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sheet(rows: &[&[&str]]) -> Sheet {
+        let mut sheet = Sheet::new();
+        for (r, row) in rows.iter().enumerate() {
+            for (c, value) in row.iter().enumerate() {
+                sheet.set_cell(c, r, value.to_string());
+            }
+        }
+        sheet
+    }
+
+    fn text(grid: &[Vec<Glyph>]) -> Vec<String> {
+        grid.iter().map(|line| line.iter().map(|g| g.ch).collect::<String>().trim_end().to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_range() {
+        let s = sheet(&[&["Month", "Sales", "Cost"], &["Jan", "10", "4"], &["Feb", "", "x"]]);
+        let chart = Chart::from_range(&s, Kind::Bar, (0, 0, 2, 2)).unwrap();
+        assert_eq!(chart.range, "A1:C3");
+        assert_eq!(chart.labels, vec!["Jan", "Feb"]);
+        assert_eq!(chart.xs, None);
+        assert_eq!(chart.series[0], Series { name: "Sales".into(), values: vec![Some(10.0), None] });
+        assert_eq!(chart.series[1].values, vec![Some(4.0), None]);
+
+        // A single column is plotted against its row numbers
+        let chart = Chart::from_range(&s, Kind::Line, (1, 1, 1, 2)).unwrap();
+        assert_eq!(chart.labels, vec!["2", "3"]);
+        assert_eq!(chart.series[0].name, "B");
+
+        assert!(Chart::from_range(&s, Kind::Bar, (0, 0, 0, 2)).is_err());
+    }
+
+    #[test]
+    fn test_render_bars() {
+        let s = sheet(&[&["a", "1"], &["b", "2"]]);
+        let chart = Chart::from_range(&s, Kind::Bar, (0, 0, 1, 1)).unwrap();
+        assert_eq!(
+            text(&chart.render(10, 4)),
+            vec!["2┤    ███", "0┤███ ███", " └────────", "  a   b"]
+        );
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut s = sheet(&[&["Score"]]);
+        for n in 1..=10 {
+            s.set_cell(0, n, n.to_string());
+        }
+        s.set_cell(0, 11, "n/a".to_string());
+
+        let chart = Chart::histogram(&s, 0, Some(3)).unwrap();
+        assert_eq!(chart.range, "A2:A11");
+        assert_eq!(chart.labels, vec!["1", "4", "7"]);
+        assert_eq!(chart.series[0], Series { name: "Score".into(), values: vec![Some(3.0), Some(3.0), Some(4.0)] });
+
+        // Sturges' rule: 10 values make 5 bins
+        assert_eq!(Chart::histogram(&s, 0, None).unwrap().labels.len(), 5);
+        assert!(Chart::histogram(&s, 1, None).is_err());
+    }
+
+    #[test]
+    fn test_trend() {
+        let s = sheet(&[&["Sales", "1", "", "5", "3", "x"], &["2"]]);
+        assert_eq!(trend(&s, (0, 0, 255, 0), 80).unwrap(), "B1:E1 ▁ █▅ min 1 max 5 last 3 (3 values)");
+        assert_eq!(trend(&s, (0, 0, 255, 0), 2).unwrap(), "B1:E1 …█▅ min 1 max 5 last 3 (3 values)");
+        assert_eq!(trend(&s, (0, 0, 0, 9999), 80).unwrap(), "A2:A2 ▅ min 2 max 2 last 2 (1 value)");
+        assert!(trend(&s, (0, 0, 1, 1), 80).is_err());
+        assert!(trend(&s, (2, 0, 2, 5), 80).is_err());
+    }
+
+    #[test]
+    fn test_render_line() {
+        let s = sheet(&[&["1"], &["2"], &["3"]]);
+        let chart = Chart::from_range(&s, Kind::Line, (0, 0, 0, 2)).unwrap();
+        let lines = text(&chart.render(10, 3));
+        assert_eq!(lines[1], " └────────");
+        assert_eq!(lines[2], "  1      3");
+        // A rising line starts at the bottom left and ends at the top right
+        let plot: Vec<char> = lines[0].chars().skip(2).collect();
+        assert_eq!(plot.len(), 8);
+        assert!(plot.iter().all(|c| ('\u{2800}'..='\u{28ff}').contains(c)));
+    }
+}
@@ -0,0 +1,180 @@
+//! Command-line history: the `:` commands and search patterns entered so
+//! far, recalled with Up and Down in Command mode. Only entries starting
+//! with what has been typed are offered, as in vim. With `:set savehist=on`
+//! the history is kept in `~/.vicalc_history` (or `$VICALC_HISTORY`)
+//! between sessions.
+
+use std::path::PathBuf;
+
+/// Most entries kept of each kind
+pub const MAX_ENTRIES: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Command,
+    // Patterns of `:/` and `:?`, kept without the leading character
+    Search,
+}
+
+#[derive(Default)]
+pub struct CmdHistory {
+    commands: Vec<String>,
+    searches: Vec<String>,
+    // While browsing: the history being browsed, the entry shown (its
+    // length when back at the typed text) and the text typed before
+    browse: Option<(Kind, usize, String)>,
+    // Whether the history is written out on exit
+    save: bool,
+}
+
+/// The history a command line belongs to, and the text to store for it
+pub fn classify(line: &str) -> (Kind, &str) {
+    match line.strip_prefix(['/', '?']) {
+        Some(pattern) => (Kind::Search, pattern),
+        None => (Kind::Command, line),
+    }
+}
+
+/// Where the history is saved: `$VICALC_HISTORY`, else `~/.vicalc_history`
+pub fn path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("VICALC_HISTORY") {
+        return Some(PathBuf::from(path));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".vicalc_history"))
+}
+
+impl CmdHistory {
+    fn entries_mut(&mut self, kind: Kind) -> &mut Vec<String> {
+        match kind {
+            Kind::Command => &mut self.commands,
+            Kind::Search => &mut self.searches,
+        }
+    }
+
+    /// Remember an entered line; an earlier copy moves to the end
+    pub fn add(&mut self, kind: Kind, entry: &str) {
+        self.browse = None;
+        let entry = entry.trim();
+        if entry.is_empty() || entry.contains('\n') {
+            return;
+        }
+        let entries = self.entries_mut(kind);
+        entries.retain(|e| e != entry);
+        entries.push(entry.to_string());
+        if entries.len() > MAX_ENTRIES {
+            entries.drain(..entries.len() - MAX_ENTRIES);
+        }
+    }
+
+    /// Stop browsing; the next recall starts from the newest entry again
+    pub fn reset(&mut self) {
+        self.browse = None;
+    }
+
+    /// The next older (or newer) entry of `kind` starting with the text
+    /// typed before browsing began. Going newer past the last entry gives
+    /// back the typed text; None when there is nothing further.
+    pub fn recall(&mut self, kind: Kind, typed: &str, older: bool) -> Option<String> {
+        let (kind, index, prefix) = match self.browse.take() {
+            Some(browse) if browse.0 == kind => browse,
+            _ => (kind, self.entries_mut(kind).len(), typed.to_string()),
+        };
+        let entries = self.entries_mut(kind);
+        let found = if older {
+            entries[..index].iter().rposition(|e| e.starts_with(&prefix))
+        } else {
+            entries.iter().skip(index + 1).position(|e| e.starts_with(&prefix)).map(|i| i + index + 1)
+        };
+        let result = match found {
+            Some(i) => Some((i, entries[i].clone())),
+            None if !older && index < entries.len() => Some((entries.len(), prefix.clone())),
+            None => None,
+        };
+        let index = result.as_ref().map_or(index, |&(i, _)| i);
+        self.browse = Some((kind, index, prefix));
+        result.map(|(_, text)| text)
+    }
+
+    /// Read a saved history: one entry per line, `:` for commands and `/`
+    /// for search patterns
+    pub fn parse(&mut self, text: &str) {
+        for line in text.lines() {
+            if let Some(command) = line.strip_prefix(':') {
+                self.add(Kind::Command, command);
+            } else if let Some(pattern) = line.strip_prefix('/') {
+                self.add(Kind::Search, pattern);
+            }
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let commands = self.commands.iter().map(|c| format!(":{}\n", c));
+        let searches = self.searches.iter().map(|s| format!("/{}\n", s));
+        commands.chain(searches).collect()
+    }
+
+    pub fn saving(&self) -> bool {
+        self.save
+    }
+
+    /// Turn saving on or off. Turning it on reads the saved history in,
+    /// ahead of what was entered this session.
+    pub fn set_saving(&mut self, save: bool) {
+        if save && !self.save {
+            let session = std::mem::take(self);
+            if let Some(text) = path().and_then(|path| std::fs::read_to_string(path).ok()) {
+                self.parse(&text);
+            }
+            self.parse(&session.to_text());
+        }
+        self.save = save;
+    }
+
+    /// Write the history out if saving is on
+    pub fn write(&self) -> Result<(), String> {
+        if !self.save {
+            return Ok(());
+        }
+        let path = path().ok_or("no home directory")?;
+        std::fs::write(&path, self.to_text()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recall_by_prefix() {
+        let mut history = CmdHistory::default();
+        for line in ["export a.csv", "w", "export b.csv", "w"] {
+            history.add(Kind::Command, line);
+        }
+        // The earlier "w" moved to the end
+        assert_eq!(history.commands, vec!["export a.csv", "export b.csv", "w"]);
+
+        assert_eq!(history.recall(Kind::Command, "ex", true).as_deref(), Some("export b.csv"));
+        assert_eq!(history.recall(Kind::Command, "ex", true).as_deref(), Some("export a.csv"));
+        assert_eq!(history.recall(Kind::Command, "ex", true), None);
+        assert_eq!(history.recall(Kind::Command, "ex", false).as_deref(), Some("export b.csv"));
+        assert_eq!(history.recall(Kind::Command, "ex", false).as_deref(), Some("ex"));
+        assert_eq!(history.recall(Kind::Command, "ex", false), None);
+
+        history.reset();
+        assert_eq!(history.recall(Kind::Search, "", true), None);
+    }
+
+    #[test]
+    fn test_parse_and_to_text() {
+        let mut history = CmdHistory::default();
+        history.add(Kind::Command, "export out.csv");
+        history.add(Kind::Search, "total");
+        let text = history.to_text();
+        assert_eq!(text, ":export out.csv\n/total\n");
+
+        let mut read = CmdHistory::default();
+        read.parse(&text);
+        assert_eq!(read.searches, vec!["total"]);
+        assert_eq!(read.to_text(), text);
+    }
+}
@@ -0,0 +1,198 @@
+//! Declared column types.
+//!
+//! A column can be declared to hold numbers, dates, text or booleans
+//! (`:coltype C date`). Input that does not fit is refused, values that stop
+//! fitting some other way (pasted, imported, computed by a formula) are
+//! flagged, and comparisons within the column (filter criteria, pivot group
+//! order) go by the declared type instead of guessing from the text.
+
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cell::CellValue;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColType {
+    Number,
+    /// Text in ISO form, YYYY-MM-DD (or with slashes)
+    Date,
+    /// Kept as typed: "007" stays "007" and sorts as text
+    Text,
+    Bool,
+}
+
+impl ColType {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "number" | "num" => Some(ColType::Number),
+            "date" => Some(ColType::Date),
+            "text" | "string" => Some(ColType::Text),
+            "bool" | "boolean" => Some(ColType::Bool),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColType::Number => "number",
+            ColType::Date => "date",
+            ColType::Text => "text",
+            ColType::Bool => "bool",
+        }
+    }
+
+    /// What a value has to look like, for messages
+    pub fn expected(self) -> &'static str {
+        match self {
+            ColType::Number => "a number",
+            ColType::Date => "a date (YYYY-MM-DD)",
+            ColType::Text => "text",
+            ColType::Bool => "TRUE or FALSE",
+        }
+    }
+
+    /// Whether a cell value fits the type. Empty cells always do.
+    pub fn check(self, value: &CellValue) -> bool {
+        match (self, value) {
+            (_, CellValue::Empty) | (ColType::Text, _) => true,
+            (ColType::Number, CellValue::Number(_)) => true,
+            (ColType::Date, CellValue::Text(s)) => parse_date(s).is_some(),
+            (ColType::Bool, CellValue::Boolean(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Order of two displayed values of the column. Values that do not fit
+    /// the type come after those that do, and blanks come last.
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        let (a, b) = (a.trim(), b.trim());
+        match (a.is_empty(), b.is_empty()) {
+            (true, true) => return Ordering::Equal,
+            (true, false) => return Ordering::Greater,
+            (false, true) => return Ordering::Less,
+            _ => {}
+        }
+        let text = || a.to_lowercase().cmp(&b.to_lowercase());
+        match self {
+            ColType::Number => compare_parsed(a.parse::<f64>().ok(), b.parse::<f64>().ok()).then_with(text),
+            ColType::Date => compare_parsed(parse_date(a), parse_date(b)).then_with(text),
+            ColType::Text => text(),
+            ColType::Bool => compare_parsed(parse_bool(a), parse_bool(b)).then_with(text),
+        }
+    }
+
+    /// Whether a displayed value meets filter criteria such as ">=2024-01-01",
+    /// or None when the type has no rules of its own for the criteria
+    pub fn matches(self, value: &str, criteria: &str) -> Option<bool> {
+        if self != ColType::Date {
+            return None;
+        }
+        let criteria = criteria.strip_prefix('=').unwrap_or(criteria);
+        let (op, target) = ["<>", "!=", ">=", "<=", ">", "<"]
+            .iter()
+            .find_map(|op| criteria.strip_prefix(op).map(|rest| (*op, rest)))
+            .unwrap_or(("=", criteria));
+        let target = parse_date(target.trim())?;
+        let Some(date) = parse_date(value.trim()) else {
+            return Some(false);
+        };
+        Some(match op {
+            ">=" => date >= target,
+            "<=" => date <= target,
+            ">" => date > target,
+            "<" => date < target,
+            "<>" | "!=" => date != target,
+            _ => date == target,
+        })
+    }
+}
+
+/// Parsed values in order, unparsable ones after them
+fn compare_parsed<T: PartialOrd>(a: Option<T>, b: Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    if s.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if s.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Day number of a YYYY-MM-DD or YYYY/MM/DD date, None if it is not one
+pub fn parse_date(s: &str) -> Option<i64> {
+    let sep = if s.contains('/') { '/' } else { '-' };
+    let mut parts = s.split(sep);
+    let (y, m, d) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() || y.len() != 4 || m.is_empty() || m.len() > 2 || d.is_empty() || d.len() > 2 {
+        return None;
+    }
+    let (y, m, d): (i64, i64, i64) = (y.parse().ok()?, m.parse().ok()?, d.parse().ok()?);
+    let leap = (y % 4 == 0 && y % 100 != 0) || y % 400 == 0;
+    let days_in_month = match m {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if d < 1 || d > days_in_month {
+        return None;
+    }
+    // Days since 0000-03-01, counting years from March so leap days come last
+    let (y, m) = if m <= 2 { (y - 1, m + 9) } else { (y, m - 3) };
+    Some(365 * y + y / 4 - y / 100 + y / 400 + (153 * m + 2) / 5 + d - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("2024-03-01").unwrap() - parse_date("2024-02-28").unwrap(), 2);
+        assert_eq!(parse_date("2023-03-01").unwrap() - parse_date("2023-02-28").unwrap(), 1);
+        assert_eq!(parse_date("2024/1/5"), parse_date("2024-01-05"));
+        assert!(parse_date("2024-02-30").is_none());
+        assert!(parse_date("2024-13-01").is_none());
+        assert!(parse_date("24-01-01").is_none());
+        assert!(parse_date("hello").is_none());
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(ColType::Number.check(&CellValue::Number(1.0)));
+        assert!(!ColType::Number.check(&CellValue::Text("x".into())));
+        assert!(ColType::Date.check(&CellValue::Text("2024-01-31".into())));
+        assert!(!ColType::Date.check(&CellValue::Number(45000.0)));
+        assert!(ColType::Bool.check(&CellValue::Boolean(false)));
+        assert!(ColType::Text.check(&CellValue::Number(7.0)));
+        assert!(ColType::Date.check(&CellValue::Empty));
+    }
+
+    #[test]
+    fn test_compare_and_matches() {
+        let mut dates = vec!["2024-10-01", "", "2024-9-30", "soon"];
+        dates.sort_by(|a, b| ColType::Date.compare(a, b));
+        assert_eq!(dates, vec!["2024-9-30", "2024-10-01", "soon", ""]);
+
+        // As text, "10" sorts before "9"
+        assert_eq!(ColType::Text.compare("10", "9"), Ordering::Less);
+        assert_eq!(ColType::Number.compare("10", "9"), Ordering::Greater);
+
+        assert_eq!(ColType::Date.matches("2024-02-01", ">2024-1-31"), Some(true));
+        assert_eq!(ColType::Date.matches("2024-01-31", "2024-01-31"), Some(true));
+        assert_eq!(ColType::Date.matches("n/a", "<2024-01-31"), Some(false));
+        assert_eq!(ColType::Number.matches("5", ">3"), None);
+    }
+}
@@ -1,6 +1,9 @@
 use crate::App;
-use crate::cell::CellValue;
-use std::collections::HashMap;
+use crate::cell::{Align, CellStyle, CellValue, DisplayFormat, NumberStyle, Rgb};
+use crate::coltype::ColType;
+use crate::sheet::{Pattern, SearchTarget, MAX_COL_WIDTH, MIN_COL_WIDTH};
+use crate::validation::{self, Action, Rule, Validation};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io::{Read, Write};
 use std::path::Path;
@@ -12,9 +15,166 @@ use unicode_width::UnicodeWidthStr;
 struct VicalcFile {
     version: String,
     name: String,
+    cells: HashMap<String, CellData>,
+    #[serde(flatten)]
+    view: ViewSettings,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    validations: Vec<ValidationData>,
+    // Ranges protected with :lock
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    locks: Vec<String>,
+    // Ranges shown as one cell with :merge
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    merges: Vec<String>,
+}
+
+/// How a file is shown: kept in a JSON file beside its cells, and for a
+/// CSV file in a `<file>.vicalc` next to it, so it opens as it was left
+#[derive(Default, Serialize, Deserialize)]
+struct ViewSettings {
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     col_widths: HashMap<String, usize>,
-    cells: HashMap<String, CellData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    filter: Option<FilterData>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    hidden_rows: Vec<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    hidden_cols: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    col_types: BTreeMap<String, ColType>,
+    // Column formulas (:colformula), by column name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    col_formulas: BTreeMap<String, String>,
+    // Cell tags (:tag), by cell name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    tags: BTreeMap<String, Vec<String>>,
+    // "grouped" and "comma" when numbers are shown so (:set numfmt,
+    // :set decimal)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    numfmt: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    decimal: Option<String>,
+    // "col" when editing down columns; rows are the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    axis: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
+}
+
+impl ViewSettings {
+    fn of(app: &App) -> Self {
+        ViewSettings {
+            col_widths: app.sheet.col_widths().map(|(col, width)| (crate::formula::col_to_name(col), width)).collect(),
+            filter: app.sheet.filter().map(|f| FilterData {
+                column: crate::formula::col_to_name(f.col),
+                criteria: f.criteria.clone(),
+            }),
+            hidden_rows: app.sheet.hidden_rows().map(|r| r + 1).collect(),
+            hidden_cols: app.sheet.hidden_cols().map(crate::formula::col_to_name).collect(),
+            col_types: app.sheet.col_types().map(|(col, ty)| (crate::formula::col_to_name(col), ty)).collect(),
+            col_formulas: app
+                .sheet
+                .col_formulas()
+                .map(|(col, template)| (crate::formula::col_to_name(col), template.to_string()))
+                .collect(),
+            tags: app
+                .sheet
+                .tagged_cells()
+                .into_iter()
+                .map(|((col, row), tags)| (crate::formula::cell_name(col, row), tags.to_vec()))
+                .collect(),
+            numfmt: app.number_style.grouped.then(|| "grouped".to_string()),
+            decimal: app.number_style.decimal_comma.then(|| "comma".to_string()),
+            axis: (app.axis == crate::EditAxis::Column).then(|| "col".to_string()),
+            cursor: Some(crate::formula::cell_name(app.cursor_col, app.cursor_row)).filter(|c| c != "A1"),
+        }
+    }
+
+    /// Show numbers as they were, before the cells are read
+    fn apply_numbers(&self, app: &mut App) {
+        if let Some(numfmt) = &self.numfmt {
+            app.set_number_style(NumberStyle { grouped: numfmt == "grouped", ..app.number_style });
+        }
+        if let Some(decimal) = &self.decimal {
+            app.set_number_style(NumberStyle { decimal_comma: decimal == "comma", ..app.number_style });
+        }
+    }
+
+    /// Declare the column types, before the cells are read
+    fn apply_types(&self, sheet: &mut crate::sheet::Sheet) {
+        for (col_name, &ty) in &self.col_types {
+            if let Some(col) = parse_col_name(col_name) {
+                sheet.set_col_type(col, Some(ty));
+            }
+        }
+    }
+
+    /// Set the widths, hidden rows and columns, tags and the filter of a sheet
+    /// whose cells are in place
+    fn apply_layout(&self, sheet: &mut crate::sheet::Sheet) {
+        for (col_name, &width) in &self.col_widths {
+            if let Some(col) = parse_col_name(col_name) {
+                sheet.set_col_width(col, width);
+            }
+        }
+        for &row in &self.hidden_rows {
+            if row > 0 {
+                sheet.set_rows_hidden(row - 1, row - 1, true);
+            }
+        }
+        for col_name in &self.hidden_cols {
+            if let Some(col) = parse_col_name(col_name) {
+                sheet.set_cols_hidden(col, col, true);
+            }
+        }
+        for (col_name, template) in &self.col_formulas {
+            if let Some(col) = parse_col_name(col_name) {
+                sheet.set_col_formula(col, Some(template.clone()));
+            }
+        }
+        for (name, tags) in &self.tags {
+            if let Some((col, row, _, _)) = crate::formula::parse_cell_ref(name) {
+                for tag in tags {
+                    sheet.add_tag(col, row, tag);
+                }
+            }
+        }
+        if let Some(filter) = &self.filter {
+            if let Some(col) = parse_col_name(&filter.column) {
+                let _ = sheet.set_filter(col, &filter.criteria);
+            }
+        }
+    }
+
+    /// Put the axis and cursor back, once the sheet is loaded
+    fn apply_position(&self, app: &mut App) {
+        if self.axis.as_deref() == Some("col") {
+            app.axis = crate::EditAxis::Column;
+        }
+        if let Some((col, row, _, _)) = self.cursor.as_deref().and_then(crate::formula::parse_cell_ref) {
+            app.move_cursor_to(col, row);
+        }
+    }
+}
+
+/// The settings file kept beside a CSV file
+fn sidecar_path(filename: &str) -> String {
+    format!("{}.vicalc", filename)
+}
+
+#[derive(Serialize, Deserialize)]
+struct FilterData {
+    column: String,
+    criteria: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ValidationData {
+    range: String,
+    #[serde(flatten)]
+    rule: Rule,
+    #[serde(default)]
+    action: Action,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -22,113 +182,264 @@ struct CellData {
     value: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     formula: Option<String>,
+    // Set with :align and :style
+    #[serde(default, skip_serializing_if = "CellStyle::is_plain")]
+    style: CellStyle,
+    // Set with :format
+    #[serde(default, skip_serializing_if = "DisplayFormat::is_general")]
+    format: DisplayFormat,
+}
+
+/// Target of :hide and :unhide
+enum Span {
+    Rows(usize, usize),
+    Cols(usize, usize),
+}
+
+/// Parse rows ("3", "3:7") or columns ("B", "B:D") into 0-based bounds
+fn parse_span(args: &str) -> Option<Span> {
+    let (start, end) = args.split_once(':').unwrap_or((args, args));
+    let (start, end) = (start.trim(), end.trim());
+    if let (Ok(a), Ok(b)) = (start.parse::<usize>(), end.parse::<usize>()) {
+        if a == 0 || b == 0 {
+            return None;
+        }
+        return Some(Span::Rows(a.min(b) - 1, a.max(b) - 1));
+    }
+    let (a, b) = (parse_col_name(start)?, parse_col_name(end)?);
+    Some(Span::Cols(a.min(b), a.max(b)))
 }
 
 pub fn execute_command(app: &mut App, cmd: &str) {
+    // Label undo entries with the command, and after a nested one (a macro,
+    // a script) go back to the outer command's label
+    let label: String = cmd.trim().chars().take(40).collect();
+    let outer = app.undo_label.replace(format!(":{}", label));
+    run_command(app, cmd);
+    app.undo_label = outer;
+}
+
+fn run_command(app: &mut App, cmd: &str) {
     let cmd = cmd.trim();
+    // Commands save, export and search the values as they are now
+    crate::calc::settle(app);
     
-    // Handle forward search :/pattern
-    if cmd.starts_with('/') {
-        let search_term = &cmd[1..];
-        if !search_term.is_empty() {
-            app.last_search = search_term.to_string();
-            app.search_forward = true;
-        }
+    // Handle forward search :/pattern[/offset]
+    if let Some(search_term) = cmd.strip_prefix('/') {
+        set_search(app, search_term, true);
         search_forward(app);
         return;
     }
     
-    // Handle backward search :?pattern
-    if cmd.starts_with('?') {
-        let search_term = &cmd[1..];
-        if !search_term.is_empty() {
-            app.last_search = search_term.to_string();
-            app.search_forward = false;
-        }
+    // Handle backward search :?pattern[?offset]
+    if let Some(search_term) = cmd.strip_prefix('?') {
+        set_search(app, search_term, false);
         search_backward(app);
         return;
     }
 
+    // A command typed from Visual mode applies to the selection ('<,'>)
+    let (range, cmd) = match cmd.strip_prefix("'<,'>") {
+        Some(rest) => (app.last_selection, rest.trim_start()),
+        None => (None, cmd),
+    };
+
+    // :'<,'>!sort filters the selection through a shell command
+    if let Some(command) = cmd.strip_prefix('!') {
+        filter_through(app, command.trim(), range);
+        return;
+    }
+
+    // Aliases from the config file stand for the start of a command line
+    let (name, rest) = cmd.split_once(' ').unwrap_or((cmd, ""));
+    let expanded;
+    let cmd = match app.aliases.get(&name.to_lowercase()) {
+        Some(expansion) => {
+            expanded = format!("{} {}", expansion, rest);
+            expanded.trim()
+        }
+        None => cmd,
+    };
+
     let parts: Vec<&str> = cmd.splitn(2, ' ').collect();
     let command = parts[0].to_lowercase();
     let args = if parts.len() > 1 { parts[1] } else { "" };
 
+    if range.is_some()
+        && !matches!(
+            command.as_str(),
+            "pivot" | "chart" | "trend" | "export" | "lock" | "unlock" | "merge" | "unmerge" | "align" | "style" | "format" | "grep" | "gr" | "select"
+                | "transpose" | "tag" | "untag"
+        )
+        && !app.plugins.contains_key(&command)
+    {
+        app.error(format!("No range allowed: {}", command));
+        return;
+    }
+
     match command.as_str() {
-        "q" | "quit" => {
-            app.running = false;
+        // With two windows, quitting closes the active one as in vim
+        "q" | "quit" | "q!" if app.split.is_some() => app.close_window(),
+        "q" | "quit" | "qa" | "qall" => {
+            if app.modified {
+                app.error("No write since last change (:q! to quit anyway, :wq to save and quit)");
+            } else {
+                app.running = false;
+            }
         }
-        "q!" => {
+        "q!" | "qa!" | "qall!" => {
             app.running = false;
         }
-        "w" | "write" => {
+        "w" | "write" | "w!" | "write!" => {
+            if args.is_empty() && ask_for_name(app, &command) {
+                return;
+            }
             let filename = if args.is_empty() {
                 app.current_file.clone().unwrap_or_else(|| "spreadsheet".to_string())
             } else {
                 args.to_string()
             };
+            if refuse_shared(app, &filename, command.ends_with('!')) {
+                return;
+            }
             match save_file(app, &filename) {
                 Ok(actual_filename) => {
+                    lock_saved(app, &actual_filename);
                     app.current_file = Some(actual_filename.clone());
-                    app.status_message = format!("Saved to {}", actual_filename);
+                    app.mark_saved();
+                    app.info(format!("Saved to {}", actual_filename));
                 }
                 Err(e) => {
-                    app.status_message = format!("Error saving: {}", e);
+                    app.error(format!("Error saving: {}", e));
                 }
             }
         }
-        "wq" => {
+        "wq" | "wq!" => {
+            if args.is_empty() && ask_for_name(app, &command) {
+                return;
+            }
             let filename = if args.is_empty() {
                 app.current_file.clone().unwrap_or_else(|| "spreadsheet".to_string())
             } else {
                 args.to_string()
             };
+            if refuse_shared(app, &filename, command.ends_with('!')) {
+                return;
+            }
             match save_file(app, &filename) {
                 Ok(actual_filename) => {
                     app.current_file = Some(actual_filename.clone());
-                    app.status_message = format!("Saved to {}", actual_filename);
+                    app.mark_saved();
+                    app.info(format!("Saved to {}", actual_filename));
                     app.running = false;
                 }
                 Err(e) => {
-                    app.status_message = format!("Error saving: {}", e);
+                    app.error(format!("Error saving: {}", e));
                 }
             }
         }
-        "e" | "edit" | "open" => {
+        "e" | "edit" | "open" | "e!" | "edit!" => {
             if args.is_empty() {
-                app.status_message = "Usage: :e <filename>".to_string();
-            } else if let Err(e) = load_file(app, args) {
-                app.status_message = format!("Error loading: {}", e);
+                app.warn("Usage: :e <filename>");
+            } else if app.modified && !command.ends_with('!') {
+                app.error("No write since last change (:e! to discard changes)");
             } else {
-                app.current_file = Some(args.to_string());
-                app.status_message = format!("Loaded {}", args);
+                // Where the file being left was, for when it is opened again
+                if let Err(e) = crate::session::remember(app) {
+                    app.warn(format!("Could not save the session: {}", e));
+                }
+                match load_file(app, args) {
+                    Err(e) => app.error(format!("Error loading: {}", e)),
+                    Ok(note) => {
+                        app.current_file = Some(args.to_string());
+                        crate::session::restore(app);
+                        app.template = None;
+                        app.mark_saved();
+                        let holder = app.lock.take(args);
+                        app.read_only = holder.is_some();
+                        match (holder, note) {
+                            (Some(holder), _) => app.warn(format!(
+                                "Loaded {} read-only: it is open in another vicalc, {} (:w! to write anyway)",
+                                args, holder
+                            )),
+                            (None, Some(note)) => app.warn(format!("Loaded {} ({})", args, note)),
+                            (None, None) => app.info(format!("Loaded {}", args)),
+                        }
+                    }
+                }
             }
         }
-        "export" => {
+        "newfrom" | "newfrom!" => {
             if args.is_empty() {
-                app.status_message = "Usage: :export <filename.csv>".to_string();
-            } else if let Err(e) = export_csv(app, args) {
-                app.status_message = format!("Error exporting: {}", e);
+                app.warn("Usage: :newfrom <template>");
+            } else if app.modified && !command.ends_with('!') {
+                app.error("No write since last change (:newfrom! to discard changes)");
             } else {
-                app.status_message = format!("Exported to {}", args);
+                if let Err(e) = crate::session::remember(app) {
+                    app.warn(format!("Could not save the session: {}", e));
+                }
+                match load_file(app, args) {
+                    Err(e) => app.error(format!("Error loading: {}", e)),
+                    Ok(_) => {
+                        // A new, unnamed sheet: the template itself is
+                        // neither locked nor written back
+                        app.current_file = None;
+                        app.template = Some(args.to_string());
+                        app.mark_saved();
+                        app.lock.release();
+                        app.read_only = false;
+                        app.info(format!("New sheet from {} (:w <file> to name it)", args));
+                    }
+                }
             }
         }
+        "export" => export(app, args, range),
         "import" => {
-            if args.is_empty() {
-                app.status_message = "Usage: :import <filename.csv>".to_string();
-            } else if let Err(e) = import_csv(app, args) {
-                app.status_message = format!("Error importing: {}", e);
+            // Declared column types carry over to the imported data
+            let types: Vec<(usize, ColType)> = app.sheet.col_types().collect();
+            let (filename, mut options) = split_import_options(args);
+            let records = options.contains(&"records");
+            options.retain(|&o| o != "records");
+            let mut dialect = crate::csv::Dialect::default();
+            if let Some(e) = options.iter().find_map(|o| dialect.set(o).err()) {
+                app.error(e);
+            } else if filename.is_empty() {
+                app.warn(
+                    "Usage: :import <filename.csv> [delim=tab] [skip=N] [noheader] [encoding=shift_jis] \
+                     or :import <filename.json> records",
+                );
             } else {
-                app.status_message = format!("Imported {}", args);
+                let result = if records { import_records(app, &filename) } else { import_csv(app, &filename, &dialect) };
+                match result {
+                    Err(e) => app.error(format!("Error importing: {}", e)),
+                    Ok(encoding) => {
+                        let invalid: usize =
+                            types.into_iter().map(|(col, ty)| app.sheet.set_col_type(col, Some(ty))).sum();
+                        let encoding = if encoding == "UTF-8" { String::new() } else { format!(" ({})", encoding) };
+                        if invalid > 0 {
+                            app.warn(format!(
+                                "Imported {}{}: {} values do not match their column type",
+                                filename, encoding, invalid
+                            ));
+                        } else {
+                            app.info(format!("Imported {}{}", filename, encoding));
+                        }
+                    }
+                }
             }
         }
+        "coltype" | "ct" => {
+            coltype(app, args);
+        }
+        "colformula" | "cf" => {
+            colformula(app, args);
+        }
         "goto" | "go" | "g" => {
             if let Some((col, row, _, _)) = crate::formula::parse_cell_ref(args) {
-                app.cursor_col = col;
-                app.cursor_row = row;
-                app.adjust_view();
-                app.status_message = format!("Moved to {}", crate::formula::cell_name(col, row));
+                app.jump_to(col, row);
+                app.info(format!("Moved to {}", crate::formula::cell_name(col, row)));
             } else {
-                app.status_message = "Invalid cell reference".to_string();
+                app.error("Invalid cell reference");
             }
         }
         "set" => {
@@ -140,90 +451,1502 @@ pub fn execute_command(app: &mut App, cmd: &str) {
             } else {
                 args.parse::<usize>().unwrap_or(app.cursor_row + 1).saturating_sub(1)
             };
+            if app.refuse_locked((0, row, 255, row)) {
+                return;
+            }
             app.save_undo();
-            app.sheet.adjust_formulas_for_row_delete(row);
             app.sheet.delete_row(row);
-            app.status_message = format!("Deleted row {}", row + 1);
+            app.info(format!("Deleted row {}", row + 1));
         }
         "delcol" | "dc" => {
             let col = if args.is_empty() {
                 app.cursor_col
             } else {
-                crate::formula::parse_cell_ref(&format!("{}1", args))
-                    .map(|(c, _, _, _)| c)
-                    .unwrap_or(app.cursor_col)
+                crate::formula::parse_cell_ref(&format!("{}1", args))
+                    .map(|(c, _, _, _)| c)
+                    .unwrap_or(app.cursor_col)
+            };
+            if app.refuse_locked((col, 0, col, 9999)) {
+                return;
+            }
+            app.save_undo();
+            app.sheet.delete_col(col);
+            app.info(format!("Deleted column {}", crate::formula::col_to_name(col)));
+        }
+        "moverow" | "mr" => {
+            let rows: Vec<Option<usize>> =
+                args.split_whitespace().map(|a| a.parse::<usize>().ok().filter(|&r| (1..=10000).contains(&r))).collect();
+            match rows.as_slice() {
+                [Some(to)] => app.move_row(app.cursor_row, to - 1),
+                [Some(from), Some(to)] => app.move_row(from - 1, to - 1),
+                _ => app.warn("Usage: :moverow [from] <to> (row numbers)"),
+            }
+        }
+        "movecol" | "mc" => {
+            let col = |name: &str| crate::formula::parse_cell_ref(&format!("{}1", name)).map(|(c, _, _, _)| c);
+            let cols: Vec<Option<usize>> = args.split_whitespace().map(col).collect();
+            match cols.as_slice() {
+                [Some(to)] => app.move_col(app.cursor_col, *to),
+                [Some(from), Some(to)] => app.move_col(*from, *to),
+                _ => app.warn("Usage: :movecol [from] <to> (column letters)"),
+            }
+        }
+        "insrow" | "ir" => {
+            let row = if args.is_empty() {
+                app.cursor_row
+            } else {
+                args.parse::<usize>().unwrap_or(app.cursor_row + 1).saturating_sub(1)
+            };
+            app.save_undo();
+            app.sheet.insert_row(row);
+            app.info(format!("Inserted row at {}", row + 1));
+        }
+        "inscol" | "ic" => {
+            let col = if args.is_empty() {
+                app.cursor_col
+            } else {
+                crate::formula::parse_cell_ref(&format!("{}1", args))
+                    .map(|(c, _, _, _)| c)
+                    .unwrap_or(app.cursor_col)
+            };
+            app.save_undo();
+            app.sheet.insert_col(col);
+            app.info(format!("Inserted column at {}", crate::formula::col_to_name(col)));
+        }
+        "clear" => {
+            let used = app.sheet.max_col().zip(app.sheet.max_row());
+            if used.is_some_and(|(col, row)| app.refuse_locked((0, 0, col, row))) {
+                return;
+            }
+            app.save_undo();
+            app.replace_sheet(crate::sheet::Sheet::new());
+            // The buffer no longer holds the file, so it is no longer locked
+            app.current_file = None;
+            app.lock.release();
+            app.read_only = false;
+            app.info("Sheet cleared");
+        }
+        "autowidth" | "aw" => {
+            app.modified = true;
+            autowidth(app, args);
+        }
+        "validate" => {
+            if args.is_empty() {
+                let message = match app.sheet.validation_at(app.cursor_col, app.cursor_row) {
+                    Some(v) => format!("Validation: {}", v.describe()),
+                    None => format!("No validation for {}", crate::formula::cell_name(app.cursor_col, app.cursor_row)),
+                };
+                app.info(message);
+            } else {
+                match validation::parse(args) {
+                    Ok(v) => {
+                        app.save_undo();
+                        let invalid = (v.start_row..=v.end_row)
+                            .flat_map(|row| (v.start_col..=v.end_col).map(move |col| (col, row)))
+                            .filter(|&(col, row)| app.sheet.get_cell_ref(col, row).is_some())
+                            .filter(|&(col, row)| !v.rule.check(&app.sheet.value(col, row)))
+                            .count();
+                        if invalid > 0 {
+                            app.warn(format!("Validation set: {} ({} invalid cells)", v.describe(), invalid));
+                        } else {
+                            app.info(format!("Validation set: {}", v.describe()));
+                        }
+                        app.sheet.add_validation(v);
+                    }
+                    Err(e) => app.error(e),
+                }
+            }
+        }
+        "novalidate" => {
+            let range = if args.is_empty() {
+                Some((app.cursor_col, app.cursor_row, app.cursor_col, app.cursor_row))
+            } else {
+                validation::parse_range(args)
+            };
+            match range {
+                Some((c1, r1, c2, r2)) => {
+                    app.save_undo();
+                    let removed = app.sheet.remove_validations(c1, r1, c2, r2);
+                    app.info(format!("Removed {} validation rule(s)", removed));
+                }
+                None => app.error(format!("Invalid range: {}", args)),
+            }
+        }
+        "filter" => {
+            if args.is_empty() {
+                // Re-apply the current filter to the edited data
+                match app.sheet.filter().cloned() {
+                    Some(f) => {
+                        app.save_undo();
+                        let hidden = app.sheet.set_filter(f.col, &f.criteria).unwrap_or(0);
+                        app.adjust_view();
+                        app.info(format!("Filter {} {} re-applied: {} rows hidden", crate::formula::col_to_name(f.col), f.criteria, hidden));
+                    }
+                    None => app.info("No filter"),
+                }
+            } else {
+                let (col_str, criteria) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                match parse_col_name(col_str) {
+                    Some(col) if !criteria.trim().is_empty() => {
+                        let before = app.sheet.clone();
+                        match app.sheet.set_filter(col, criteria) {
+                            Ok(hidden) => {
+                                let label = app.undo_label();
+                                app.history.push(before, app.current_file.clone(), &label);
+                                app.modified = true;
+                                app.adjust_view();
+                                app.info(format!("Filtered on {} {}: {} rows hidden", col_str.to_uppercase(), criteria.trim(), hidden));
+                            }
+                            Err(e) => app.error(e),
+                        }
+                    }
+                    _ => app.warn("Usage: :filter <column> <criteria> (e.g. :filter B >100)"),
+                }
+            }
+        }
+        "nofilter" => {
+            app.save_undo();
+            let shown = app.sheet.clear_filter();
+            app.adjust_view();
+            app.info(format!("Filter cleared: {} rows shown", shown));
+        }
+        "hide" => {
+            if args.is_empty() {
+                app.hide_structure();
+            } else {
+                match parse_span(args) {
+                    Some(span) => {
+                        app.save_undo();
+                        match span {
+                            Span::Rows(start, end) => {
+                                let n = app.sheet.set_rows_hidden(start, end, true);
+                                app.info(format!("{} row(s) hidden", n));
+                            }
+                            Span::Cols(start, end) => {
+                                let n = app.sheet.set_cols_hidden(start, end, true);
+                                app.info(format!("{} column(s) hidden", n));
+                            }
+                        }
+                        app.adjust_view();
+                    }
+                    None => app.error(format!("Invalid rows or columns: {}", args)),
+                }
+            }
+        }
+        "unhide" => {
+            if args.is_empty() {
+                app.save_undo();
+                let (rows, cols) = app.sheet.unhide_all();
+                app.info(format!("{} row(s) and {} column(s) shown", rows, cols));
+            } else {
+                match parse_span(args) {
+                    Some(span) => {
+                        app.save_undo();
+                        match span {
+                            Span::Rows(start, end) => {
+                                let n = app.sheet.set_rows_hidden(start, end, false);
+                                app.info(format!("{} row(s) shown", n));
+                            }
+                            Span::Cols(start, end) => {
+                                let n = app.sheet.set_cols_hidden(start, end, false);
+                                app.info(format!("{} column(s) shown", n));
+                            }
+                        }
+                    }
+                    None => app.error(format!("Invalid rows or columns: {}", args)),
+                }
+            }
+        }
+        "pivot" => {
+            pivot(app, args, range);
+        }
+        "chart" => {
+            chart(app, args, range);
+        }
+        "hist" | "histogram" => histogram(app, args),
+        "sample" => sample_rows(app, args, true),
+        "shuffle" => sample_rows(app, args, false),
+        "transpose" => transpose(app, range),
+        "regress" => regress(app, args),
+        "trend" => trend(app, args, range),
+        "lock" => lock(app, args, range, true),
+        "unlock" => lock(app, args, range, false),
+        "merge" => merge(app, args, range, true),
+        "align" => align(app, args, range),
+        "style" => style(app, args, range),
+        "format" => format(app, args, range),
+        "unmerge" => merge(app, args, range, false),
+        "memory" | "mem" => {
+            memory(app);
+        }
+        "undolist" | "undol" => {
+            undolist(app);
+        }
+        "messages" | "mes" => messages(app, args),
+        "check" => check(app),
+        "precedents" | "prec" => trace(app, false),
+        "dependents" | "dep" => trace(app, true),
+        "errors" => errors(app),
+        "grep" | "gr" => grep(app, args, range),
+        "rhai" if args.is_empty() => app.warn("Usage: :rhai <code> (e.g. :rhai set(\"A1\", 42))"),
+        "rhai" => crate::scripting::run(app, args, "rhai"),
+        "rhaifile" if args.is_empty() => app.warn("Usage: :rhaifile <file.rhai>"),
+        "rhaifile" => crate::scripting::run_file(app, args),
+        "copen" | "cope" => crate::quickfix::show(app, true),
+        "cclose" | "ccl" => crate::quickfix::show(app, false),
+        "cnext" | "cn" => crate::quickfix::step(app, args.parse::<isize>().unwrap_or(1)),
+        "cprevious" | "cprev" | "cp" => crate::quickfix::step(app, -args.parse::<isize>().unwrap_or(1)),
+        "cc" => match args.parse::<usize>() {
+            Ok(n) if n > 0 => crate::quickfix::go(app, n - 1),
+            _ if args.is_empty() => {
+                let current = app.quickfix.as_ref().map_or(0, |qf| qf.current);
+                crate::quickfix::go(app, current);
+            }
+            _ => app.error(format!("Invalid entry number: {}", args)),
+        },
+        "colorscheme" | "colo" => {
+            if args.is_empty() {
+                app.info(format!("Color schemes: {} (now {})", crate::theme::NAMES.join(", "), app.theme.name));
+            } else {
+                match set_option(app, "theme", args.trim()) {
+                    Ok(message) => app.info(message),
+                    Err(e) => app.error(e),
+                }
+            }
+        }
+        "calc" => app.recalculate(),
+        "nohlsearch" | "noh" => app.hlsearch = None,
+        "macro" => crate::macros::command(app, args),
+        "select" => crate::select::command(app, args, range),
+        "snapshot" | "snap" => crate::snapshot::take(app, args),
+        "snapshots" => crate::snapshot::list(app),
+        "diff" => crate::snapshot::diff(app, args),
+        "changed" => crate::snapshot::changed(app),
+        "tag" => crate::tags::tag(app, args, range),
+        "untag" => crate::tags::untag(app, args, range),
+        "tags" => crate::tags::list(app, args),
+        "split" | "sp" => app.split_window(crate::SplitDir::Horizontal),
+        "vsplit" | "vs" => app.split_window(crate::SplitDir::Vertical),
+        "close" | "clo" => app.close_window(),
+        "only" | "on" => app.only_window(),
+        "help" | "h" => {
+            app.info("Commands: :w :q :wq :e :newfrom :export :import :goto :set :autowidth :validate :lock :unlock :merge :unmerge :align :style :format :filter :hide :pivot :regress :chart :trend :hist :sample :shuffle :transpose :moverow :movecol :coltype :colformula :check :errors :select :snapshot :snapshots :diff :changed :tag :untag :tags :precedents :dependents :copen :messages :memory :calc :colorscheme :macro :noh :undolist :rhai :rhaifile :split :vsplit :help");
+        }
+        "" => {}
+        _ => {
+            // Commands from the config file come after the built-in ones
+            if !crate::plugin::run(app, &command, args, range) {
+                app.error(format!("Unknown command: {}", command));
+            }
+        }
+    }
+}
+
+/// Show a full-screen chart of the selection or the range given
+fn chart(app: &mut App, args: &str, range: Option<(usize, usize, usize, usize)>) {
+    const USAGE: &str = "Usage: :chart bar|line <range> (or :'<,'>chart bar|line)";
+
+    let (kind, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    let Some(kind) = crate::chart::Kind::parse(kind) else {
+        app.warn(USAGE);
+        return;
+    };
+    let bounds = match (rest.trim(), range) {
+        ("", Some(range)) => range,
+        ("", None) => {
+            app.warn(USAGE);
+            return;
+        }
+        (rest, _) => match validation::parse_range(rest) {
+            Some(bounds) => bounds,
+            None => {
+                app.error(format!("Invalid range: {}", rest));
+                return;
+            }
+        },
+    };
+    match crate::chart::Chart::from_range(&app.sheet, kind, bounds) {
+        Ok(chart) => {
+            app.chart = Some(chart);
+            app.mode = crate::Mode::Chart;
+        }
+        Err(e) => app.error(e),
+    }
+}
+
+/// `:trend [row|col|<range>]`: sparkline and summary of the selected row
+/// or column, or of the column (or row) under the cursor
+fn trend(app: &mut App, args: &str, range: Option<(usize, usize, usize, usize)>) {
+    let (col, row) = (app.cursor_col, app.cursor_row);
+    let bounds = match (args.trim().to_lowercase().as_str(), range) {
+        ("", Some(range)) => range,
+        ("" | "col" | "column", None) => (col, 0, col, 9999),
+        ("row", None) => (0, row, 255, row),
+        (_, _) => match validation::parse_range(args.trim()) {
+            Some(bounds) => bounds,
+            None => {
+                app.warn("Usage: :trend [row|col|<range>] (or :'<,'>trend)");
+                return;
+            }
+        },
+    };
+    // Leave room for the range and the summary after the bars
+    let width = (app.term_size.0 as usize).saturating_sub(50);
+    match crate::chart::trend(&app.sheet, bounds, width) {
+        Ok(summary) => app.info(summary),
+        Err(e) => app.error(e),
+    }
+}
+
+/// `:regress y=C x=B [to <cell>]`: fit a line to the data rows of two
+/// columns and write its coefficients, as formulas that stay up to date,
+/// next to the data or at the `to` cell
+fn regress(app: &mut App, args: &str) {
+    const USAGE: &str = "Usage: :regress y=<column> x=<column> [to <cell>]";
+
+    let mut words: Vec<&str> = args.split_whitespace().collect();
+    let mut target = None;
+    if words.len() >= 2 && words[words.len() - 2].eq_ignore_ascii_case("to") {
+        let cell = words[words.len() - 1];
+        match crate::formula::parse_cell_ref(cell) {
+            Some((col, row, _, _)) => target = Some((col, row)),
+            None => {
+                app.error(format!("Invalid cell: {}", cell));
+                return;
+            }
+        }
+        words.truncate(words.len() - 2);
+    }
+    let (mut y, mut x) = (None, None);
+    for word in words {
+        match word.split_once('=') {
+            Some((name, col)) if name.eq_ignore_ascii_case("y") => y = parse_col_name(col),
+            Some((name, col)) if name.eq_ignore_ascii_case("x") => x = parse_col_name(col),
+            _ => {
+                app.warn(USAGE);
+                return;
+            }
+        }
+    }
+    let (Some(y), Some(x)) = (y, x) else {
+        app.warn(USAGE);
+        return;
+    };
+
+    // The data rows: from the first row, or the one below it when it holds
+    // the column names, to the last value in either column
+    let first = app.sheet.header_row().unwrap_or(0);
+    let has_header = [x, y].iter().any(|&col| matches!(app.sheet.value(col, first), CellValue::Text(_)));
+    let first = if has_header { first + 1 } else { first };
+    let last = app.sheet.max_row_in_col(x).max(app.sheet.max_row_in_col(y)).unwrap_or(0);
+    if last <= first {
+        app.warn("Nothing to fit: the columns need at least two rows of data");
+        return;
+    }
+    let ys = validation::area_name((y, first, y, last));
+    let xs = validation::area_name((x, first, x, last));
+    let (y_name, x_name) = (crate::formula::col_to_name(y), crate::formula::col_to_name(x));
+    let lines = [
+        ["Regression".to_string(), format!("{} on {}", y_name, x_name)],
+        ["Slope".to_string(), format!("=SLOPE({},{})", ys, xs)],
+        ["Intercept".to_string(), format!("=INTERCEPT({},{})", ys, xs)],
+        ["R²".to_string(), format!("=CORREL({},{})^2", ys, xs)],
+    ];
+
+    let (target_col, target_row) = target.unwrap_or((app.sheet.max_col().unwrap_or(0) + 2, first.saturating_sub(1)));
+    let (last_col, last_row) = (target_col + 1, target_row + lines.len() - 1);
+    if last_col > 255 || last_row > 9999 {
+        app.error("Regression results do not fit in the sheet");
+        return;
+    }
+    let area = (target_col, target_row, last_col, last_row);
+    let occupied = (target_row..=last_row)
+        .any(|row| (target_col..=last_col).any(|col| app.sheet.get_cell_ref(col, row).is_some()));
+    if occupied {
+        app.error(format!(
+            "{} is not empty (choose another place with \"to <cell>\")",
+            validation::area_name(area)
+        ));
+        return;
+    }
+    if app.refuse_locked(area) {
+        return;
+    }
+
+    app.save_undo();
+    for (r, line) in lines.iter().enumerate() {
+        for (c, input) in line.iter().enumerate() {
+            app.sheet.set_cell(target_col + c, target_row + r, input.clone());
+        }
+    }
+    let value = |row: usize| app.sheet.evaluate(target_col + 1, target_row + row);
+    let message = format!(
+        "{} on {}: slope {}, intercept {}, R² {} at {}",
+        y_name,
+        x_name,
+        value(1),
+        value(2),
+        value(3),
+        crate::formula::cell_name(target_col, target_row)
+    );
+    app.info(message);
+}
+
+/// `:lock A1:D1` / `:unlock A1:D1`: protect cells from edits, or stop
+/// protecting them. The selection or the cursor cell without a range.
+fn lock(app: &mut App, args: &str, range: Option<(usize, usize, usize, usize)>, lock: bool) {
+    let area = match (args.trim(), range) {
+        ("", Some(range)) => range,
+        ("", None) => (app.cursor_col, app.cursor_row, app.cursor_col, app.cursor_row),
+        (args, _) => match validation::parse_range(args) {
+            Some(area) => area,
+            None => {
+                app.error(format!("Invalid range: {}", args));
+                return;
+            }
+        },
+    };
+    app.save_undo();
+    if lock {
+        app.sheet.lock(area);
+        app.info(format!("Locked {}", validation::area_name(area)));
+    } else {
+        let unlocked = app.sheet.unlock(area);
+        app.info(format!("Unlocked {} cell(s) in {}", unlocked, validation::area_name(area)));
+    }
+}
+
+/// `:merge A1:D1` / `:unmerge A1`: show an area as one cell holding the
+/// content of its top-left cell, for headers over several columns. Without
+/// a range they work on the selection or, for `:unmerge`, the cursor cell.
+fn merge(app: &mut App, args: &str, range: Option<(usize, usize, usize, usize)>, merge: bool) {
+    let area = match (args.trim(), range) {
+        ("", Some(range)) => range,
+        ("", None) if !merge => (app.cursor_col, app.cursor_row, app.cursor_col, app.cursor_row),
+        ("", None) => {
+            app.warn("Usage: :merge <range>, or select the cells and type :merge");
+            return;
+        }
+        (args, _) => match validation::parse_range(args) {
+            Some(area) => area,
+            None => {
+                app.error(format!("Invalid range: {}", args));
+                return;
+            }
+        },
+    };
+    if !merge {
+        app.save_undo();
+        let count = app.sheet.unmerge(area);
+        app.info(format!("Unmerged {} area(s) in {}", count, validation::area_name(area)));
+        return;
+    }
+
+    let (c1, r1, c2, r2) = area;
+    if (c1, r1) == (c2, r2) {
+        app.warn("Select more than one cell to merge");
+        return;
+    }
+    // Only the top-left cell is shown, so the others must be empty
+    let hidden = (r1..=r2)
+        .flat_map(|row| (c1..=c2).map(move |col| (col, row)))
+        .find(|&(col, row)| (col, row) != (c1, r1) && app.sheet.get_cell_ref(col, row).is_some());
+    if let Some((col, row)) = hidden {
+        app.error(format!(
+            "Merging would hide {} (clear it first)",
+            crate::formula::cell_name(col, row)
+        ));
+        return;
+    }
+    app.save_undo();
+    app.sheet.merge(area);
+    app.move_cursor_to(c1, r1);
+    app.info(format!("Merged {}", validation::area_name(area)));
+}
+
+/// `:!command` sends the selection (without one, the data rows under the
+/// header) to a shell command as tab-separated lines and puts what it
+/// writes back in their place, as `:'<,'>!sort -k2 -n` does in vim. Values
+/// go out as shown, so formulas in the area are replaced by what the
+/// command returns.
+fn filter_through(app: &mut App, command: &str, range: Option<(usize, usize, usize, usize)>) {
+    if command.is_empty() {
+        app.warn("Usage: :!<command> (e.g. :'<,'>!sort -k2 -n)");
+        return;
+    }
+    let area = match range {
+        Some(area) => area,
+        None => {
+            let (Some(header), Some(last)) = (app.sheet.header_row(), app.sheet.max_row()) else {
+                app.warn("No data rows");
+                return;
+            };
+            if last <= header {
+                app.warn("No data rows");
+                return;
+            }
+            (0, header + 1, app.sheet.max_col().unwrap_or(0), last)
+        }
+    };
+    let input = crate::csv::write_with(&app.sheet, area, '\t', false);
+
+    let output = match run_filter(command, input) {
+        Ok(output) => output,
+        Err(e) => {
+            app.error(format!("Filter failed: {}", e));
+            return;
+        }
+    };
+    let rows = crate::csv::parse_records(&output, '\t');
+    let (c1, r1, c2, r2) = area;
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let (c_end, r_end) = ((c1 + width).max(c2 + 1) - 1, (r1 + rows.len()).max(r2 + 1) - 1);
+    if c_end > 255 || r_end > 9999 {
+        app.error("Filter output does not fit in the sheet");
+        return;
+    }
+    if app.refuse_locked((c1, r1, c_end, r_end)) {
+        return;
+    }
+
+    app.save_undo();
+    for row in r1..=r2 {
+        for col in c1..=c2 {
+            app.sheet.set_cell(col, row, String::new());
+        }
+    }
+    for (r_offset, cells) in rows.iter().enumerate() {
+        for (c_offset, value) in cells.iter().enumerate() {
+            app.sheet.set_typed(c1 + c_offset, r1 + r_offset, value);
+        }
+    }
+    app.info(format!("{} rows from !{}", rows.len(), command));
+}
+
+/// Run a shell command with `input` on its standard input, returning what
+/// it wrote, or the first line of its error output if it failed
+pub fn run_filter(command: &str, input: String) -> Result<String, String> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let mut child = shell
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    // Written from another thread so a command that starts writing before
+    // it has read everything cannot block on a full pipe
+    let mut stdin = child.stdin.take().ok_or("no input pipe")?;
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    // A command that does not read its input closes the pipe early
+    let _ = writer.join();
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(stderr.lines().next().map_or_else(|| output.status.to_string(), str::to_string));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Turn the selection, or the whole sheet, over about its top-left cell:
+/// rows become columns and references to the cells follow them
+fn transpose(app: &mut App, range: Option<(usize, usize, usize, usize)>) {
+    let area = match (range, app.sheet.max_col(), app.sheet.max_row()) {
+        (Some(area), _, _) => area,
+        (None, Some(col), Some(row)) => (0, 0, col, row),
+        _ => {
+            app.warn("Nothing to transpose");
+            return;
+        }
+    };
+    if app.refuse_locked(area) {
+        return;
+    }
+    let mut sheet = app.sheet.clone();
+    match sheet.transpose(area) {
+        Ok(turned) => {
+            if app.refuse_locked(turned) {
+                return;
+            }
+            app.save_undo();
+            app.sheet = sheet;
+            app.info(format!("Transposed {} to {}", validation::area_name(area), validation::area_name(turned)));
+        }
+        Err(e) => app.error(e),
+    }
+}
+
+/// `:sample N [seed]` keeps N data rows picked at random, in their order;
+/// `:shuffle [seed]` puts the data rows in a random order. The header row
+/// stays where it is, and a seed gives the same result every time.
+fn sample_rows(app: &mut App, args: &str, sample: bool) {
+    let usage = if sample { "Usage: :sample <rows> [seed]" } else { "Usage: :shuffle [seed]" };
+    let mut words = args.split_whitespace();
+    let count = if sample {
+        match words.next().map(str::parse::<usize>) {
+            Some(Ok(count)) if count > 0 => Some(count),
+            _ => {
+                app.warn(usage);
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    let mut rng = match words.next().map(str::parse::<u64>) {
+        None => crate::sample::Rng::from_time(),
+        Some(Ok(seed)) => crate::sample::Rng::new(seed),
+        Some(Err(_)) => {
+            app.warn(usage);
+            return;
+        }
+    };
+
+    if app.sheet.filter().is_some() {
+        app.warn("Remove the filter first (:nofilter)");
+        return;
+    }
+    let (Some(header), Some(last)) = (app.sheet.header_row(), app.sheet.max_row()) else {
+        app.warn("No data rows");
+        return;
+    };
+    let first = header + 1;
+    if last < first {
+        app.warn("No data rows");
+        return;
+    }
+    let area = (0, first, app.sheet.max_col().unwrap_or(0), last);
+    if app.refuse_locked(area) {
+        return;
+    }
+
+    let rows: Vec<usize> = (first..=last).collect();
+    let total = rows.len();
+    let order = match count {
+        Some(count) => crate::sample::sample(rows, count, &mut rng),
+        None => {
+            let mut rows = rows;
+            crate::sample::shuffle(&mut rows, &mut rng);
+            rows
+        }
+    };
+    app.save_undo();
+    app.sheet.arrange_rows(first, last, &order);
+    app.adjust_view();
+    match count {
+        Some(_) => app.info(format!("Kept {} of {} rows", order.len(), total)),
+        None => app.info(format!("Shuffled {} rows", total)),
+    }
+}
+
+/// `:align left|right|center|auto` on the selection or the cursor cell;
+/// `auto` puts numbers to the right and text to the left again
+fn align(app: &mut App, args: &str, range: Option<(usize, usize, usize, usize)>) {
+    let align = match args.trim() {
+        "auto" => None,
+        name => match Align::parse(name) {
+            Some(align) => Some(align),
+            None => {
+                app.warn("Usage: :align left|right|center|auto");
+                return;
+            }
+        },
+    };
+    restyle(app, range, |style| style.align = align);
+}
+
+/// `:style bold underline color=red` on the selection or the cursor cell.
+/// `nobold`, `nounderline` and `nocolor` take a setting off, `none` all of
+/// them but the alignment.
+fn style(app: &mut App, args: &str, range: Option<(usize, usize, usize, usize)>) {
+    const USAGE: &str = "Usage: :style [no]bold [no]underline color=<name|#rrggbb> nocolor none";
+    type Change = Box<dyn Fn(&mut CellStyle)>;
+    let mut changes: Vec<Change> = Vec::new();
+    for word in args.split_whitespace() {
+        let change: Change = match word.to_lowercase().as_str() {
+            "bold" => Box::new(|s| s.bold = true),
+            "nobold" => Box::new(|s| s.bold = false),
+            "underline" => Box::new(|s| s.underline = true),
+            "nounderline" => Box::new(|s| s.underline = false),
+            "nocolor" => Box::new(|s| s.color = None),
+            "none" => Box::new(|s| *s = CellStyle { align: s.align, ..CellStyle::default() }),
+            word => match word.strip_prefix("color=") {
+                Some(name) => match Rgb::parse(name) {
+                    Some(color) => Box::new(move |s| s.color = Some(color)),
+                    None => {
+                        app.error(format!("Unknown color: {} (a name such as red, or #rrggbb)", name));
+                        return;
+                    }
+                },
+                None => {
+                    app.warn(USAGE);
+                    return;
+                }
+            },
+        };
+        changes.push(change);
+    }
+    if changes.is_empty() {
+        app.warn(USAGE);
+        return;
+    }
+    restyle(app, range, |style| changes.iter().for_each(|change| change(style)));
+}
+
+/// Apply a style change to the cells with content in the selection or,
+/// without one, the cursor cell
+fn restyle(app: &mut App, range: Option<(usize, usize, usize, usize)>, change: impl Fn(&mut CellStyle)) {
+    let area = range.unwrap_or((app.cursor_col, app.cursor_row, app.cursor_col, app.cursor_row));
+    if !app.sheet.cells().keys().any(|&(col, row)| (area.0..=area.2).contains(&col) && (area.1..=area.3).contains(&row)) {
+        app.warn(format!("No cells with content in {}", validation::area_name(area)));
+        return;
+    }
+    app.save_undo();
+    let count = app.sheet.style_cells(area, change);
+    app.info(format!("Styled {} cell(s) in {}", count, validation::area_name(area)));
+}
+
+/// `:format grouped 2` on the selection or the cursor cell: how its numbers
+/// are shown. Without decimals, 2 (none for percentages).
+fn format(app: &mut App, args: &str, range: Option<(usize, usize, usize, usize)>) {
+    const USAGE: &str = "Usage: :format general|number|grouped|currency|percent|scientific|date|text [decimals]";
+    let mut words = args.split_whitespace();
+    let Some(name) = words.next() else {
+        app.warn(USAGE);
+        return;
+    };
+    let decimals = match words.next().map(str::parse::<usize>) {
+        None if name.eq_ignore_ascii_case("percent") => 0,
+        None => 2,
+        Some(Ok(decimals)) if decimals <= 10 => decimals,
+        Some(_) => {
+            app.error("Decimals must be a number from 0 to 10");
+            return;
+        }
+    };
+    let Some(format) = DisplayFormat::parse(name, decimals) else {
+        app.warn(USAGE);
+        return;
+    };
+    let area = range.unwrap_or((app.cursor_col, app.cursor_row, app.cursor_col, app.cursor_row));
+    if !app.sheet.cells().keys().any(|&(col, row)| (area.0..=area.2).contains(&col) && (area.1..=area.3).contains(&row)) {
+        app.warn(format!("No cells with content in {}", validation::area_name(area)));
+        return;
+    }
+    app.save_undo();
+    let count = app.sheet.format_cells(area, &format);
+    app.info(format!("Formatted {} cell(s) in {}", count, validation::area_name(area)));
+}
+
+/// `:hist C [bins]`: histogram of a column's numbers
+fn histogram(app: &mut App, args: &str) {
+    let mut words = args.split_whitespace();
+    let Some(col) = words.next().and_then(parse_col_name) else {
+        app.warn("Usage: :hist <column> [bins]");
+        return;
+    };
+    let bins = match words.next().map(str::parse::<usize>) {
+        None => None,
+        Some(Ok(bins)) if (1..=100).contains(&bins) => Some(bins),
+        Some(_) => {
+            app.error("Bins must be a number from 1 to 100");
+            return;
+        }
+    };
+    match crate::chart::Chart::histogram(&app.sheet, col, bins) {
+        Ok(chart) => {
+            app.chart = Some(chart);
+            app.mode = crate::Mode::Chart;
+        }
+        Err(e) => app.error(e),
+    }
+}
+
+/// Summarize the selection (or all data) with a group-by and write the result
+/// next to the data or at the `to` cell
+fn pivot(app: &mut App, args: &str, range: Option<(usize, usize, usize, usize)>) {
+    let p = match crate::pivot::parse(args) {
+        Ok(p) => p,
+        Err(e) => {
+            app.error(e);
+            return;
+        }
+    };
+    let (start_col, start_row, end_col, end_row) = match range {
+        Some(range) => range,
+        None => {
+            let start_row = app.sheet.cells().keys().map(|&(_, r)| r).min().unwrap_or(0);
+            (0, start_row, app.sheet.max_col().unwrap_or(0), app.sheet.max_row().unwrap_or(0))
+        }
+    };
+    let columns = [Some(p.row_key), p.col_key, Some(p.value_col)];
+    if let Some(col) = columns.into_iter().flatten().find(|c| !(start_col..=end_col).contains(c)) {
+        app.error(format!("Column {} is outside the data range", crate::formula::col_to_name(col)));
+        return;
+    }
+    if end_row <= start_row {
+        app.warn("Nothing to summarize: the range needs a header row and data");
+        return;
+    }
+
+    let grid = p.compute(&app.sheet, start_row, end_row);
+    let (target_col, target_row) = p.target.unwrap_or((end_col + 2, start_row));
+    let width = grid[0].len();
+    let (last_col, last_row) = (target_col + width - 1, target_row + grid.len() - 1);
+    if last_col > 255 || last_row > 9999 {
+        app.error("Pivot table does not fit in the sheet");
+        return;
+    }
+    let occupied = (target_row..=last_row)
+        .any(|row| (target_col..=last_col).any(|col| app.sheet.get_cell_ref(col, row).is_some()));
+    if occupied {
+        app.error(format!(
+            "{}:{} is not empty (choose another place with \"to <cell>\")",
+            crate::formula::cell_name(target_col, target_row),
+            crate::formula::cell_name(last_col, last_row)
+        ));
+        return;
+    }
+    if app.refuse_locked((target_col, target_row, last_col, last_row)) {
+        return;
+    }
+
+    app.save_undo();
+    for (r, line) in grid.iter().enumerate() {
+        for (c, value) in line.iter().enumerate() {
+            app.sheet.set_value(target_col + c, target_row + r, value.clone());
+        }
+    }
+    app.info(format!(
+        "Pivot: {} group(s) written to {}",
+        grid.len() - 2,
+        crate::formula::cell_name(target_col, target_row)
+    ));
+}
+
+/// Human-readable size (1536 -> "1.5 KB")
+fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Parse a size with an optional K, M or G suffix ("64M", "512k", "1GB")
+fn parse_size(s: &str) -> Option<usize> {
+    let upper = s.trim().to_uppercase();
+    let digits = upper.strip_suffix('B').unwrap_or(&upper);
+    let (number, multiplier) = match digits.chars().last()? {
+        'K' => (&digits[..digits.len() - 1], 1024),
+        'M' => (&digits[..digits.len() - 1], 1024 * 1024),
+        'G' => (&digits[..digits.len() - 1], 1024 * 1024 * 1024),
+        _ => (digits, 1),
+    };
+    number.trim().parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+/// Report how much memory the sheet, the undo history and the clipboard use
+fn memory(app: &mut App) {
+    let sheet = app.sheet.heap_size();
+    let (undo, redo) = app.history.memory();
+    let (clip_cells, clip) = match &app.clipboard {
+        Some(c) => (c.width * c.height, c.heap_size()),
+        None => (0, 0),
+    };
+    app.info(format!(
+        "Memory: {} cells ~{} | undo {} ~{} | redo {} ~{} | clipboard {} cells ~{} | total ~{}",
+        app.sheet.cells().len(),
+        format_bytes(sheet),
+        app.history.undo_entries().len(),
+        format_bytes(undo),
+        app.history.redo_entries().len(),
+        format_bytes(redo),
+        clip_cells,
+        format_bytes(clip),
+        format_bytes(sheet + undo + redo + clip)
+    ));
+}
+
+fn handle_set(app: &mut App, args: &str) {
+    let parts: Vec<&str> = args.splitn(2, '=').collect();
+    if parts.len() != 2 {
+        // `:set option` shows the current value, `:set` all of them
+        let option = parts[0].trim().to_lowercase();
+        if option.is_empty() {
+            let values: Vec<String> = OPTIONS.iter().filter_map(|name| get_option(app, name)).collect();
+            app.info(values.join("  "));
+            return;
+        }
+        match get_option(app, &option) {
+            Some(value) => app.info(value),
+            None => app.error(format!("Unknown option: {}", option)),
+        }
+        return;
+    }
+
+    match set_option(app, parts[0].trim(), parts[1].trim()) {
+        Ok(message) => app.info(message),
+        Err(e) => app.error(e),
+    }
+}
+
+/// Options listed by a bare `:set`
+pub const OPTIONS: [&str; 24] = [
+    "name", "axis", "colwidth", "theme", "autosave", "undomemory", "savehist", "keylog", "wheelscroll", "wheeldrag",
+    "searchtarget", "regex", "funcmenu", "stats", "a11y", "a11ynotify", "calc", "relativenumber", "autoextend",
+    "changemarks", "scrollbind", "auditlog", "numfmt", "decimal",
+];
+
+/// `option=value` for `:set option`, None if there is no such option
+fn get_option(app: &App, option: &str) -> Option<String> {
+    let value = match option {
+        "name" | "sheet" => app.sheet.name.clone(),
+        "axis" => match app.axis {
+            crate::EditAxis::Row => "row".to_string(),
+            crate::EditAxis::Column => "col".to_string(),
+        },
+        "colwidth" | "cw" => app.sheet.default_col_width().to_string(),
+        "theme" => app.theme.name.to_string(),
+        "autosave" | "as" => app.swap.update_time().map_or(0, |t| t.as_secs()).to_string(),
+        "undomemory" | "um" => format_bytes(app.history.budget()),
+        "savehist" | "sh" => if app.cmd_history.saving() { "on" } else { "off" }.to_string(),
+        "keylog" => app.keylog.as_ref().map_or("off".to_string(), |log| log.path().display().to_string()),
+        "auditlog" => app.audit.as_ref().map_or("off".to_string(), |log| log.path().display().to_string()),
+        "wheelscroll" | "ws" => app.wheel_scroll.to_string(),
+        "wheeldrag" => if app.wheel_drag { "on" } else { "off" }.to_string(),
+        "funcmenu" | "fm" => if app.func_menu_enabled { "on" } else { "off" }.to_string(),
+        "stats" => if app.stats.shown { "on" } else { "off" }.to_string(),
+        "regex" => if app.search_regex { "on" } else { "off" }.to_string(),
+        "relativenumber" | "rnu" => if app.relative_number { "on" } else { "off" }.to_string(),
+        "autoextend" => if app.auto_extend { "on" } else { "off" }.to_string(),
+        "changemarks" | "cm" => if app.change_marks { "on" } else { "off" }.to_string(),
+        "numfmt" => if app.number_style.grouped { "grouped" } else { "general" }.to_string(),
+        "decimal" => if app.number_style.decimal_comma { "comma" } else { "point" }.to_string(),
+        "scrollbind" | "scb" => if app.scroll_bind { "on" } else { "off" }.to_string(),
+        "a11y" => if app.a11y.enabled { "on" } else { "off" }.to_string(),
+        "calc" => match app.calc.mode {
+            crate::calc::Mode::Auto => "auto".to_string(),
+            crate::calc::Mode::Manual => "manual".to_string(),
+        },
+        "a11ynotify" => app.a11y.notify.clone().unwrap_or_default(),
+        "searchtarget" | "st" => match app.search_target {
+            SearchTarget::Value => "value".to_string(),
+            SearchTarget::Formula => "formula".to_string(),
+        },
+        _ => return None,
+    };
+    Some(format!("{}={}", option, value))
+}
+
+/// Set an option, as `:set option=value` and the config file do. Returns
+/// the message to show.
+pub fn set_option(app: &mut App, option: &str, value: &str) -> Result<String, String> {
+    match option.to_lowercase().as_str() {
+        "name" | "sheet" => {
+            app.sheet.name = value.to_string();
+            app.modified = true;
+            Ok(format!("Sheet name set to '{}'", value))
+        }
+        "axis" => {
+            app.axis = match value.to_lowercase().as_str() {
+                "row" | "r" => crate::EditAxis::Row,
+                "col" | "column" | "c" => crate::EditAxis::Column,
+                _ => return Err(format!("Invalid axis: {} (row or col)", value)),
+            };
+            Ok(format!("Axis set to {}", value.to_lowercase()))
+        }
+        "colwidth" | "cw" => {
+            let width = value
+                .parse::<usize>()
+                .ok()
+                .filter(|w| (MIN_COL_WIDTH..=MAX_COL_WIDTH).contains(w))
+                .ok_or_else(|| format!("Invalid width: {} ({}-{})", value, MIN_COL_WIDTH, MAX_COL_WIDTH))?;
+            app.colwidth = width;
+            app.sheet.set_default_col_width(width);
+            Ok(format!("Default column width set to {}", width))
+        }
+        "theme" => {
+            app.theme = crate::theme::Theme::named(value)
+                .ok_or_else(|| format!("Unknown theme: {} ({})", value, crate::theme::NAMES.join(", ")))?;
+            Ok(format!("Theme set to {}", app.theme.name))
+        }
+        "autosave" | "as" => {
+            let secs = value
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid interval: {} (seconds, 0 for none)", value))?;
+            app.swap.set_update_time(secs);
+            if secs == 0 {
+                Ok("Swap file disabled".to_string())
+            } else {
+                Ok(format!("Swap file written {}s after a change", secs))
+            }
+        }
+        "undomemory" | "um" => {
+            let budget = parse_size(value).ok_or_else(|| format!("Invalid size: {} (e.g. 64M, 512K)", value))?;
+            let dropped = app.history.set_budget(budget);
+            Ok(format!("Undo memory set to {} ({} old entries dropped)", format_bytes(budget), dropped))
+        }
+        "savehist" | "sh" => {
+            let save = match value.to_lowercase().as_str() {
+                "on" | "true" | "yes" | "1" => true,
+                "off" | "false" | "no" | "0" => false,
+                _ => return Err(format!("Invalid value: {} (on or off)", value)),
+            };
+            app.cmd_history.set_saving(save);
+            if save {
+                Ok("Command history saved on exit".to_string())
+            } else {
+                Ok("Command history not saved".to_string())
+            }
+        }
+        "keylog" => {
+            if value.is_empty() || value.eq_ignore_ascii_case("off") {
+                app.keylog = None;
+                return Ok("Key log stopped".to_string());
+            }
+            let log = crate::keylog::KeyLog::create(Path::new(value))?;
+            app.keylog = Some(log);
+            Ok(format!("Logging keys to {}", value))
+        }
+        "auditlog" => {
+            if value.is_empty() || value.eq_ignore_ascii_case("off") {
+                app.audit = None;
+                return Ok("Audit log stopped".to_string());
+            }
+            let log = crate::audit::AuditLog::create(Path::new(value))?;
+            app.audit = Some(log);
+            Ok(format!("Logging formula changes to {}", value))
+        }
+        "wheelscroll" | "ws" => {
+            let rows = value
+                .parse::<usize>()
+                .ok()
+                .filter(|n| (1..=100).contains(n))
+                .ok_or_else(|| format!("Invalid step: {} (1-100 rows)", value))?;
+            app.wheel_scroll = rows;
+            Ok(format!("Mouse wheel scrolls {} row(s)", rows))
+        }
+        "wheeldrag" => {
+            app.wheel_drag = match value.to_lowercase().as_str() {
+                "on" | "true" | "yes" | "1" => true,
+                "off" | "false" | "no" | "0" => false,
+                _ => return Err(format!("Invalid value: {} (on or off)", value)),
+            };
+            if app.wheel_drag {
+                Ok("Mouse wheel moves the cursor with the view".to_string())
+            } else {
+                Ok("Mouse wheel leaves the cursor in place".to_string())
+            }
+        }
+        "funcmenu" | "fm" => {
+            app.func_menu_enabled = match value.to_lowercase().as_str() {
+                "on" | "true" | "yes" | "1" => true,
+                "off" | "false" | "no" | "0" => false,
+                _ => return Err(format!("Invalid value: {} (on or off)", value)),
+            };
+            if app.func_menu_enabled {
+                Ok("= on an empty cell opens the function menu".to_string())
+            } else {
+                Ok("Function menu off".to_string())
+            }
+        }
+        "stats" => {
+            app.stats.shown = match value.to_lowercase().as_str() {
+                "on" | "true" | "yes" | "1" => true,
+                "off" | "false" | "no" | "0" => false,
+                _ => return Err(format!("Invalid value: {} (on or off)", value)),
+            };
+            if app.stats.shown {
+                Ok("Session time and cells entered shown in the status bar".to_string())
+            } else {
+                Ok("Session stats hidden".to_string())
+            }
+        }
+        "regex" => {
+            app.search_regex = match value.to_lowercase().as_str() {
+                "on" | "true" | "yes" | "1" => true,
+                "off" | "false" | "no" | "0" => false,
+                _ => return Err(format!("Invalid value: {} (on or off)", value)),
+            };
+            if app.search_regex {
+                Ok("Search patterns are regular expressions".to_string())
+            } else {
+                Ok("Search patterns are plain text (\\v for a regular expression)".to_string())
+            }
+        }
+        "relativenumber" | "rnu" => {
+            app.relative_number = match value.to_lowercase().as_str() {
+                "on" | "true" | "yes" | "1" => true,
+                "off" | "false" | "no" | "0" => false,
+                _ => return Err(format!("Invalid value: {} (on or off)", value)),
+            };
+            if app.relative_number {
+                Ok("Row numbers count from the cursor row".to_string())
+            } else {
+                Ok("Row numbers shown as they are".to_string())
+            }
+        }
+        "autoextend" => {
+            app.auto_extend = match value.to_lowercase().as_str() {
+                "on" | "true" | "yes" | "1" => true,
+                "off" | "false" | "no" | "0" => false,
+                _ => return Err(format!("Invalid value: {} (on or off)", value)),
+            };
+            if app.auto_extend {
+                Ok("Rows typed below a table take its formats, validation and formulas".to_string())
+            } else {
+                Ok("Rows typed below a table are left as typed".to_string())
+            }
+        }
+        "changemarks" | "cm" => {
+            app.change_marks = match value.to_lowercase().as_str() {
+                "on" | "true" | "yes" | "1" => true,
+                "off" | "false" | "no" | "0" => false,
+                _ => return Err(format!("Invalid value: {} (on or off)", value)),
+            };
+            if app.change_marks {
+                Ok("Cells changed since the last save are marked".to_string())
+            } else {
+                Ok("Cells changed since the last save are not marked".to_string())
+            }
+        }
+        "scrollbind" | "scb" => {
+            app.scroll_bind = match value.to_lowercase().as_str() {
+                "on" | "true" | "yes" | "1" => true,
+                "off" | "false" | "no" | "0" => false,
+                _ => return Err(format!("Invalid value: {} (on or off)", value)),
             };
-            app.save_undo();
-            app.sheet.adjust_formulas_for_col_delete(col);
-            app.sheet.delete_col(col);
-            app.status_message = format!("Deleted column {}", crate::formula::col_to_name(col));
+            if app.scroll_bind {
+                Ok("Split windows scroll together".to_string())
+            } else {
+                Ok("Split windows scroll on their own".to_string())
+            }
         }
-        "insrow" | "ir" => {
-            let row = if args.is_empty() {
-                app.cursor_row
+        "numfmt" => {
+            let grouped = match value.to_lowercase().as_str() {
+                "grouped" => true,
+                "general" => false,
+                _ => return Err(format!("Invalid value: {} (general or grouped)", value)),
+            };
+            app.set_number_style(NumberStyle { grouped, ..app.number_style });
+            if grouped {
+                Ok("Thousands separated in numbers without a format".to_string())
             } else {
-                args.parse::<usize>().unwrap_or(app.cursor_row + 1).saturating_sub(1)
+                Ok("Numbers without a format shown plainly".to_string())
+            }
+        }
+        "decimal" => {
+            let decimal_comma = match value.to_lowercase().as_str() {
+                "comma" => true,
+                "point" => false,
+                _ => return Err(format!("Invalid value: {} (point or comma)", value)),
             };
-            app.save_undo();
-            app.sheet.adjust_formulas_for_row_insert(row);
-            app.sheet.insert_row(row);
-            app.status_message = format!("Inserted row at {}", row + 1);
+            app.set_number_style(NumberStyle { decimal_comma, ..app.number_style });
+            if decimal_comma {
+                Ok("Numbers written as 1.234,5".to_string())
+            } else {
+                Ok("Numbers written as 1,234.5".to_string())
+            }
         }
-        "inscol" | "ic" => {
-            let col = if args.is_empty() {
-                app.cursor_col
+        "calc" => {
+            app.calc.mode = match value.to_lowercase().as_str() {
+                "manual" => crate::calc::Mode::Manual,
+                "auto" | "automatic" => crate::calc::Mode::Auto,
+                _ => return Err(format!("Invalid value: {} (auto or manual)", value)),
+            };
+            crate::calc::update(app);
+            if app.calc.mode == crate::calc::Mode::Manual {
+                Ok("Formulas recalculated only with F9 or :calc".to_string())
             } else {
-                crate::formula::parse_cell_ref(&format!("{}1", args))
-                    .map(|(c, _, _, _)| c)
-                    .unwrap_or(app.cursor_col)
+                Ok("Formulas recalculated automatically".to_string())
+            }
+        }
+        "a11y" => {
+            app.a11y.enabled = match value.to_lowercase().as_str() {
+                "on" | "true" | "yes" | "1" => true,
+                "off" | "false" | "no" | "0" => false,
+                _ => return Err(format!("Invalid value: {} (on or off)", value)),
             };
-            app.save_undo();
-            app.sheet.adjust_formulas_for_col_insert(col);
-            app.sheet.insert_col(col);
-            app.status_message = format!("Inserted column at {}", crate::formula::col_to_name(col));
+            if app.a11y.enabled {
+                Ok("The top line describes the cell under the cursor".to_string())
+            } else {
+                Ok("Screen-reader line off".to_string())
+            }
         }
-        "clear" => {
-            app.save_undo();
-            app.sheet = crate::sheet::Sheet::new();
-            app.cursor_col = 0;
-            app.cursor_row = 0;
-            app.view_col = 0;
-            app.view_row = 0;
-            app.current_file = None;
-            app.status_message = "Sheet cleared".to_string();
+        "a11ynotify" => {
+            if value.is_empty() || value.eq_ignore_ascii_case("off") {
+                app.a11y.notify = None;
+                return Ok("Announcements not sent to a program".to_string());
+            }
+            app.a11y.notify = Some(value.to_string());
+            Ok(format!("Announcements piped to {}", value))
         }
-        "autowidth" | "aw" => {
-            autowidth(app, args);
+        "searchtarget" | "st" => {
+            app.search_target = match value.to_lowercase().as_str() {
+                "value" | "values" => SearchTarget::Value,
+                "formula" | "formulas" => SearchTarget::Formula,
+                _ => return Err(format!("Invalid search target: {} (value or formula)", value)),
+            };
+            match app.search_target {
+                SearchTarget::Value => Ok("Searches match cell values".to_string()),
+                SearchTarget::Formula => Ok("Searches match formulas as written".to_string()),
+            }
         }
-        "help" | "h" => {
-            app.status_message = "Commands: :w :q :wq :e :export :import :goto :set :autowidth :help".to_string();
+        other => Err(format!("Unknown option: {}", other)),
+    }
+}
+
+/// `:coltype C date` declares a column type, `:coltype C none` removes it,
+/// `:coltype C` shows it and `:coltype` lists all of them
+fn coltype(app: &mut App, args: &str) {
+    let mut words = args.split_whitespace();
+    let (Some(col_name), type_name) = (words.next(), words.next()) else {
+        let types: Vec<String> = app
+            .sheet
+            .col_types()
+            .map(|(col, ty)| format!("{} {}", crate::formula::col_to_name(col), ty.name()))
+            .collect();
+        if types.is_empty() {
+            app.info("No column types (:coltype <column> number|date|text|bool)");
+        } else {
+            app.info(format!("Column types: {}", types.join(", ")));
         }
-        "" => {}
-        _ => {
-            app.status_message = format!("Unknown command: {}", command);
+        return;
+    };
+    let Some(col) = parse_col_name(col_name) else {
+        app.error(format!("Invalid column: {}", col_name));
+        return;
+    };
+    let name = crate::formula::col_to_name(col);
+
+    let ty = match type_name.map(str::to_lowercase).as_deref() {
+        None => {
+            match app.sheet.col_type(col) {
+                Some(ty) => app.info(format!("Column {} is {}", name, ty.name())),
+                None => app.info(format!("Column {} has no type", name)),
+            }
+            return;
         }
+        Some("none" | "any") => None,
+        Some(other) => match ColType::parse(other) {
+            Some(ty) => Some(ty),
+            None => {
+                app.error(format!("Unknown column type: {} (number, date, text, bool or none)", other));
+                return;
+            }
+        },
+    };
+
+    app.save_undo();
+    let invalid = app.sheet.set_col_type(col, ty);
+    match ty {
+        None => app.info(format!("Column {} type removed", name)),
+        Some(ty) if invalid > 0 => app.warn(format!("Column {} is {} ({} values do not fit)", name, ty.name(), invalid)),
+        Some(ty) => app.info(format!("Column {} is {}", name, ty.name())),
     }
 }
 
-fn handle_set(app: &mut App, args: &str) {
-    let parts: Vec<&str> = args.splitn(2, '=').collect();
-    if parts.len() != 2 {
-        app.status_message = "Usage: :set option=value".to_string();
+/// `:colformula [column [formula|none]]`: a formula every data row of the
+/// column gets, `@` standing for the row (`=B@*C@`)
+fn colformula(app: &mut App, args: &str) {
+    let (col_name, template) = match args.split_once(char::is_whitespace) {
+        Some((col_name, template)) => (col_name, Some(template.trim().trim_matches(|c| c == '"' || c == '\'').trim())),
+        None => (args, None),
+    };
+    if col_name.is_empty() {
+        let formulas: Vec<String> = app
+            .sheet
+            .col_formulas()
+            .map(|(col, template)| format!("{} {}", crate::formula::col_to_name(col), template))
+            .collect();
+        if formulas.is_empty() {
+            app.info("No column formulas (:colformula <column> =B@*C@)");
+        } else {
+            app.info(format!("Column formulas: {}", formulas.join(", ")));
+        }
         return;
     }
+    let Some(col) = parse_col_name(col_name) else {
+        app.error(format!("Invalid column: {}", col_name));
+        return;
+    };
+    let name = crate::formula::col_to_name(col);
 
-    let option = parts[0].trim().to_lowercase();
-    let _value = parts[1].trim();
-
-    match option.as_str() {
-        "name" | "sheet" => {
-            app.sheet.name = _value.to_string();
-            app.status_message = format!("Sheet name set to '{}'", _value);
+    let template = match template.map(str::to_lowercase).as_deref() {
+        None => {
+            match app.sheet.col_formula(col) {
+                Some(template) => app.info(format!("Column {}: {}", name, template)),
+                None => app.info(format!("Column {} has no formula", name)),
+            }
+            return;
         }
-        _ => {
-            app.status_message = format!("Unknown option: {}", option);
+        Some("none" | "off") => None,
+        Some(_) => {
+            let template = template.unwrap_or_default();
+            if !template.starts_with('=') || !template.contains('@') {
+                app.error("A column formula starts with = and uses @ for the row, as in =B@*C@");
+                return;
+            }
+            Some(template.to_string())
+        }
+    };
+
+    let Some(template) = template else {
+        if app.sheet.col_formula(col).is_some() {
+            app.save_undo();
+            app.sheet.set_col_formula(col, None);
         }
+        app.info(format!("Column {} formula removed (its cells are kept)", name));
+        return;
+    };
+    if app.refuse_locked((col, 0, col, 9999)) {
+        return;
+    }
+    app.save_undo();
+    app.sheet.set_col_formula(col, Some(template.clone()));
+    let filled = app.sheet.fill_col_formula(col);
+    let plural = if filled == 1 { "" } else { "s" };
+    app.info(format!("Column {} is {}: filled {} row{}", name, template, filled, plural));
+}
+
+/// Show recent status line messages, oldest first, in List mode
+fn messages(app: &mut App, args: &str) {
+    if args == "clear" {
+        app.messages.clear_history();
+        return;
+    }
+    let lines: Vec<String> = app
+        .messages
+        .history()
+        .map(|m| format!("{:>8}  {:<7}  {}", crate::history::age(m.logged), m.severity.name(), m.text))
+        .collect();
+    if lines.is_empty() {
+        app.info("No messages");
+        return;
+    }
+    // Start at the bottom, with the latest
+    let page = (app.term_size.1 as usize).saturating_sub(2);
+    let scroll = lines.len().saturating_sub(page);
+    let title = format!("Messages: {}", lines.len());
+    app.list = Some(crate::ListView { title, lines, scroll });
+    app.mode = crate::Mode::List;
+}
+
+/// Highlight the cells the cursor's cell depends on (or that depend on it)
+/// and list them in the quickfix panel, nearest first
+fn trace(app: &mut App, dependents: bool) {
+    let (col, row) = (app.cursor_col, app.cursor_row);
+    let name = crate::formula::cell_name(col, row);
+    let (found, what) = if dependents {
+        (app.sheet.dependents(col, row), "Dependents")
+    } else {
+        (app.sheet.precedents(col, row), "Precedents")
+    };
+    if found.is_empty() {
+        app.trace = None;
+        app.info(format!("No {} of {}", what.to_lowercase(), name));
+        return;
+    }
+    let entries = found
+        .iter()
+        .map(|&((c, r), depth)| {
+            let raw = app.sheet.get_cell(c, r).raw_input;
+            let raw = if raw.is_empty() { "(empty)".to_string() } else { raw };
+            crate::quickfix::Entry { col: c, row: r, text: format!("[{}] {}", depth, raw) }
+        })
+        .collect();
+    app.trace = Some(crate::Trace { dependents, cells: found.iter().map(|&(pos, _)| pos).collect() });
+    crate::quickfix::set(app, format!("{} of {}: {} cells", what, name, found.len()), entries);
+}
+
+/// Collect likely mistakes in the sheet into the quickfix list
+fn check(app: &mut App) {
+    let issues = crate::lint::check(&app.sheet);
+    if issues.is_empty() {
+        app.info("No problems found");
     }
+    let title = format!("Check: {} problem{}", issues.len(), if issues.len() == 1 { "" } else { "s" });
+    set_quickfix(app, title, issues);
+}
+
+/// Collect the cells whose value is an error into the quickfix list
+fn errors(app: &mut App) {
+    let issues = crate::lint::errors(&app.sheet);
+    if issues.is_empty() {
+        app.info("No error values");
+    }
+    let title = format!("Errors: {} cell{}", issues.len(), if issues.len() == 1 { "" } else { "s" });
+    set_quickfix(app, title, issues);
+}
+
+fn set_quickfix(app: &mut App, title: String, issues: Vec<crate::lint::Issue>) {
+    let entries = issues
+        .into_iter()
+        .map(|issue| crate::quickfix::Entry { col: issue.col, row: issue.row, text: issue.message })
+        .collect();
+    crate::quickfix::set(app, title, entries);
+}
+
+/// Show the undo history, newest first, in List mode
+fn undolist(app: &mut App) {
+    let entries = app.history.undo_entries();
+    if entries.is_empty() {
+        app.info("Undo history is empty");
+        return;
+    }
+
+    let mut lines = vec![format!("{:>5}  {:>8}  {:>9}  {:<24}  {}", "undo", "taken", "size", "change", "cells")];
+    for (i, entry) in entries.iter().enumerate().rev() {
+        // Each entry is the state before the change that led to the next one
+        let (after, after_file) = entries
+            .get(i + 1)
+            .map_or((&app.sheet, &app.current_file), |e| (&e.sheet, &e.file));
+        // Undoing past :e goes back to the other file
+        let changes = if entry.file != *after_file {
+            format!("back to {}", entry.file.as_deref().unwrap_or("an unnamed sheet"))
+        } else {
+            crate::history::summarize(&entry.sheet, after)
+        };
+        lines.push(format!(
+            "{:>5}  {:>8}  {:>9}  {:<24}  {}",
+            entries.len() - i,
+            crate::history::age(entry.time),
+            format_bytes(entry.size),
+            entry.label,
+            changes
+        ));
+    }
+
+    let (undo, _) = app.history.memory();
+    let title = format!(
+        "Undo history: {} entries, {} of {} | {} redo",
+        entries.len(),
+        format_bytes(undo),
+        format_bytes(app.history.budget()),
+        app.history.redo_entries().len()
+    );
+    app.list = Some(crate::ListView { title, lines, scroll: 0 });
+    app.mode = crate::Mode::List;
 }
 
 /// Auto-adjust column widths to fit content
@@ -232,6 +1955,7 @@ fn autowidth(app: &mut App, args: &str) {
     const MAX_WIDTH: usize = 50;
     
     let max_row = app.sheet.max_row().unwrap_or(0);
+    app.save_undo();
     
     if args.is_empty() {
         // Adjust all columns with data
@@ -244,7 +1968,7 @@ fn autowidth(app: &mut App, args: &str) {
             adjusted += 1;
         }
         
-        app.status_message = format!("Auto-adjusted {} columns", adjusted);
+        app.info(format!("Auto-adjusted {} columns", adjusted));
     } else {
         // Parse column range (e.g., "A", "A:C", "B:D")
         let args_upper = args.to_uppercase();
@@ -259,18 +1983,18 @@ fn autowidth(app: &mut App, args: &str) {
                     let width = calc_column_width(app, col, max_row, MIN_WIDTH, MAX_WIDTH);
                     app.sheet.set_col_width(col, width);
                 }
-                app.status_message = format!("Auto-adjusted columns {}:{}", start_str, end_str);
+                app.info(format!("Auto-adjusted columns {}:{}", start_str, end_str));
             } else {
-                app.status_message = "Invalid column range".to_string();
+                app.error("Invalid column range");
             }
         } else {
             // Single column: A
             if let Some(col) = parse_col_name(&args_upper) {
                 let width = calc_column_width(app, col, max_row, MIN_WIDTH, MAX_WIDTH);
                 app.sheet.set_col_width(col, width);
-                app.status_message = format!("Column {} width set to {}", args_upper, width);
+                app.info(format!("Column {} width set to {}", args_upper, width));
             } else {
-                app.status_message = "Invalid column name".to_string();
+                app.error("Invalid column name");
             }
         }
     }
@@ -301,123 +2025,240 @@ fn parse_col_name(name: &str) -> Option<usize> {
         return None;
     }
     
-    let mut col = 0usize;
-    for c in name.chars() {
-        if !c.is_ascii_alphabetic() {
-            return None;
-        }
-        col = col * 26 + (c.to_ascii_uppercase() as usize - 'A' as usize + 1);
+    if !name.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
     }
-    
-    Some(col.saturating_sub(1))
+    crate::formula::col_from_letters(&name.to_ascii_uppercase()).filter(|&col| col <= 255)
 }
 
-/// Search forward from current position
-pub fn search_forward(app: &mut App) {
-    if app.last_search.is_empty() {
-        app.status_message = "No search pattern".to_string();
-        return;
+/// Where a search puts the cursor relative to the match: `:/total/A` on
+/// column A of the matching row, `:/total/+1` on the row below it,
+/// `:/total/A+1` both
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SearchOffset {
+    pub col: Option<usize>,
+    pub rows: isize,
+}
+
+/// Parse a search offset: an optional column name, then +N or -N rows
+/// (+ and - alone for one)
+fn parse_search_offset(text: &str) -> Option<SearchOffset> {
+    let letters = text.chars().take_while(|c| c.is_ascii_alphabetic()).count();
+    let (name, rows) = text.split_at(letters);
+    let col = if name.is_empty() { None } else { Some(parse_col_name(name)?) };
+    let rows = match rows {
+        "" => 0,
+        "+" => 1,
+        "-" => -1,
+        _ if rows.starts_with(['+', '-']) => rows.parse().ok()?,
+        _ => return None,
+    };
+    Some(SearchOffset { col, rows })
+}
+
+/// The area a search is limited to: `col` or `row` for the cursor's, a
+/// span of columns (`B:B`) or rows (`3:7`), or a range (`A1:D20`)
+fn parse_search_scope(app: &App, text: &str) -> Option<(usize, usize, usize, usize)> {
+    match text {
+        "col" | "column" => Some((app.cursor_col, 0, app.cursor_col, 9999)),
+        "row" => Some((0, app.cursor_row, 255, app.cursor_row)),
+        _ => match parse_span(text) {
+            Some(Span::Cols(a, b)) => Some((a, 0, b, 9999)),
+            Some(Span::Rows(a, b)) => Some((0, a, 255, b)),
+            None => crate::validation::parse_range(text),
+        },
     }
+}
 
-    let term = app.last_search.clone();
-    let term_upper = term.to_uppercase();
-    let start_col = app.cursor_col;
-    let start_row = app.cursor_row;
-
-    // Search from current position forward
-    for row in start_row..10000 {
-        let col_start = if row == start_row { start_col + 1 } else { 0 };
-        for col in col_start..256 {
-            let value = app.sheet.evaluate(col, row);
-            if value.to_uppercase().contains(&term_upper) {
-                app.cursor_col = col;
-                app.cursor_row = row;
-                app.adjust_view();
-                app.status_message = format!("/{} -> {}", term, crate::formula::cell_name(col, row));
-                return;
-            }
-        }
+/// A search scope as it would be typed
+fn scope_name((c1, r1, c2, r2): (usize, usize, usize, usize)) -> String {
+    if (r1, r2) == (0, 9999) {
+        format!("{}:{}", crate::formula::col_to_name(c1), crate::formula::col_to_name(c2))
+    } else if (c1, c2) == (0, 255) {
+        format!("{}:{}", r1 + 1, r2 + 1)
+    } else {
+        crate::validation::area_name((c1, r1, c2, r2))
     }
+}
 
-    // Wrap around
-    for row in 0..=start_row {
-        let col_end = if row == start_row { start_col } else { 256 };
-        for col in 0..col_end {
-            let value = app.sheet.evaluate(col, row);
-            if value.to_uppercase().contains(&term_upper) {
-                app.cursor_col = col;
-                app.cursor_row = row;
-                app.adjust_view();
-                app.status_message = format!("/{} -> {} (wrapped)", term, crate::formula::cell_name(col, row));
-                return;
-            }
+/// Split `pattern in B:B` into the pattern and the area searched
+fn split_search_scope<'a>(app: &App, text: &'a str) -> (&'a str, Option<(usize, usize, usize, usize)>) {
+    match text.rsplit_once(" in ") {
+        Some((pattern, scope)) if !pattern.is_empty() => match parse_search_scope(app, scope.trim()) {
+            Some(area) => (pattern, Some(area)),
+            None => (text, None),
+        },
+        _ => (text, None),
+    }
+}
+
+/// `:grep pattern [in B:B]` lists every matching cell in the quickfix
+/// panel, by its column title when there is a header row. From Visual
+/// mode only the selection is searched.
+fn grep(app: &mut App, args: &str, range: Option<(usize, usize, usize, usize)>) {
+    let (pattern, scope) = split_search_scope(app, args);
+    if pattern.is_empty() {
+        app.warn("Usage: :grep <pattern> [in B:B]");
+        return;
+    }
+    let area = range.or(scope).unwrap_or((0, 0, 255, 9999));
+    let found = match search_pattern(app, pattern) {
+        Ok(compiled) => app.sheet.find_all(&compiled, area, app.search_target),
+        Err(e) => {
+            app.error(e);
+            return;
         }
+    };
+    if found.is_empty() {
+        app.quickfix = None;
+        app.warn(format!("Pattern not found: {}", pattern));
+        return;
+    }
+    let header = app.sheet.header_row();
+    let entries = found
+        .iter()
+        .map(|&(col, row)| {
+            let text = match app.search_target {
+                SearchTarget::Value => app.sheet.evaluate(col, row),
+                SearchTarget::Formula => app.sheet.get_cell(col, row).raw_input,
+            };
+            let title = header.filter(|&h| h != row).map(|h| app.sheet.evaluate(col, h)).unwrap_or_default();
+            let text = if title.is_empty() { text } else { format!("{}: {}", title, text) };
+            crate::quickfix::Entry { col, row, text }
+        })
+        .collect();
+    let title = format!("Grep {}: {} cell{}", pattern, found.len(), if found.len() == 1 { "" } else { "s" });
+    crate::quickfix::set(app, title, entries);
+}
+
+/// A search pattern as typed: a regular expression with `:set regex=on`
+/// or after `\v`, else plain text
+fn search_pattern(app: &App, text: &str) -> Result<Pattern, String> {
+    match text.strip_prefix("\\v") {
+        Some(expr) => Pattern::regex(expr),
+        None if app.search_regex => Pattern::regex(text),
+        None => Ok(Pattern::text(text)),
     }
+    .map_err(|e| format!("Invalid pattern: {}: {}", text, e))
+}
+
+/// Take a new search pattern, with the offset after a second `/` (`?`
+/// searching backward) and the scope after ` in `. An empty pattern keeps
+/// the last one, its offset and its scope.
+fn set_search(app: &mut App, text: &str, forward: bool) {
+    if text.is_empty() {
+        return;
+    }
+    let (text, scope) = split_search_scope(app, text);
+    app.search_scope = scope;
+    let separator = if forward { '/' } else { '?' };
+    let (pattern, offset) = match text.rsplit_once(separator) {
+        Some((pattern, offset)) if !pattern.is_empty() => match parse_search_offset(offset) {
+            Some(offset) => (pattern, Some(offset).filter(|o| *o != SearchOffset::default())),
+            None => (text, None),
+        },
+        _ => (text, None),
+    };
+    app.last_search = pattern.to_string();
+    app.search_offset = offset;
+    app.search_forward = forward;
+}
 
-    app.status_message = format!("Pattern not found: {}", term);
+/// Search forward from current position
+pub fn search_forward(app: &mut App) {
+    search(app, true, 1, false);
 }
 
 /// Search backward from current position
 pub fn search_backward(app: &mut App) {
+    search(app, false, 1, false);
+}
+
+/// Go to the count'th match, then apply the search offset. Repeating a
+/// search (n, N) goes on from the last match rather than the cursor.
+fn search(app: &mut App, forward: bool, count: usize, repeat: bool) {
     if app.last_search.is_empty() {
-        app.status_message = "No search pattern".to_string();
+        app.warn("No search pattern");
         return;
     }
 
     let term = app.last_search.clone();
-    let term_upper = term.to_uppercase();
-    let start_col = app.cursor_col;
-    let start_row = app.cursor_row;
-
-    // Search backward from current position
-    for row in (0..=start_row).rev() {
-        let col_end = if row == start_row { start_col } else { 256 };
-        for col in (0..col_end).rev() {
-            let value = app.sheet.evaluate(col, row);
-            if value.to_uppercase().contains(&term_upper) {
-                app.cursor_col = col;
-                app.cursor_row = row;
-                app.adjust_view();
-                app.status_message = format!("?{} -> {}", term, crate::formula::cell_name(col, row));
-                return;
-            }
+    let pattern = match search_pattern(app, &term) {
+        Ok(pattern) => pattern,
+        Err(e) => {
+            app.error(e);
+            return;
         }
+    };
+    // Every match shows until :noh, or the next search
+    app.hlsearch = Some(pattern.clone());
+    let prefix = if forward { '/' } else { '?' };
+    let offset = app.search_offset.unwrap_or_default();
+    let (mut col, mut row) = (app.cursor_col, app.cursor_row);
+    if repeat {
+        // The row the offset came from, and the end of it (the start,
+        // backward) when the offset changed the column
+        row = (row as isize - offset.rows).clamp(0, 9999) as usize;
+        col = match offset.col {
+            Some(_) if forward => 255,
+            Some(_) => 0,
+            None => col,
+        };
     }
-
-    // Wrap around from end
-    for row in (start_row..10000).rev() {
-        let col_start = if row == start_row { start_col + 1 } else { 0 };
-        for col in (col_start..256).rev() {
-            let value = app.sheet.evaluate(col, row);
-            if value.to_uppercase().contains(&term_upper) {
-                app.cursor_col = col;
-                app.cursor_row = row;
-                app.adjust_view();
-                app.status_message = format!("?{} -> {} (wrapped)", term, crate::formula::cell_name(col, row));
+    let mut wrapped = false;
+    for _ in 0..count.max(1) {
+        let area = app.search_scope.unwrap_or((0, 0, 255, 9999));
+        match app.sheet.find_in(&pattern, col, row, forward, area, app.search_target) {
+            Some((c, r, w)) => {
+                (col, row) = (c, r);
+                wrapped |= w;
+            }
+            None => {
+                let scope = app.search_scope.map_or(String::new(), |area| format!(" in {}", scope_name(area)));
+                app.warn(format!("Pattern not found: {}{}{}", term, scope, target_note(app)));
                 return;
             }
         }
     }
 
-    app.status_message = format!("Pattern not found: {}", term);
+    let target_col = offset.col.unwrap_or(col);
+    let target_row = (row as isize + offset.rows).clamp(0, 9999) as usize;
+    app.jump_to(target_col, target_row);
+    let wrapped = if wrapped { " (wrapped)" } else { "" };
+    let cell = crate::formula::cell_name(target_col, target_row);
+    app.info(format!("{}{} -> {}{}{}", prefix, term, cell, wrapped, target_note(app)));
 }
 
-/// Search next (n key) - same direction as last search
-pub fn search_next(app: &mut App) {
-    if app.search_forward {
-        search_forward(app);
-    } else {
-        search_backward(app);
+/// A sheet made from a template has no name yet: rather than write it
+/// under a default name, open the command line at `:w ` for one. Returns
+/// whether it did.
+fn ask_for_name(app: &mut App, command: &str) -> bool {
+    let Some(template) = app.template.clone().filter(|_| app.current_file.is_none()) else {
+        return false;
+    };
+    app.mode = crate::Mode::Command;
+    app.command_buffer = format!("{} ", command);
+    app.error(format!("The sheet is new from {}: give it a file name", template));
+    true
+}
+
+/// Reminder in search messages that formulas are being searched, since
+/// the values shown may not contain what was found
+fn target_note(app: &App) -> &'static str {
+    match app.search_target {
+        SearchTarget::Value => "",
+        SearchTarget::Formula => " (in formulas)",
     }
 }
 
+/// Search next (n key) - same direction as last search
+pub fn search_next(app: &mut App, count: usize) {
+    search(app, app.search_forward, count, true);
+}
+
 /// Search previous (N key) - opposite direction
-pub fn search_prev(app: &mut App) {
-    if app.search_forward {
-        search_backward(app);
-    } else {
-        search_forward(app);
-    }
+pub fn search_prev(app: &mut App, count: usize) {
+    search(app, !app.search_forward, count, true);
 }
 
 /// Save file. Returns the actual filename used.
@@ -443,8 +2284,10 @@ fn save_file(app: &App, filename: &str) -> Result<String, String> {
     match ext.as_str() {
         "csv" => {
             export_csv(app, &filename).map_err(|e| e.to_string())?;
+            save_sidecar(app, &filename).map_err(|e| e.to_string())?;
             Ok(filename)
         }
+        "parquet" => Err("Parquet files are read-only (:w file.json or file.csv)".to_string()),
         _ => {
             // Default to JSON
             save_json(app, &filename).map_err(|e| e.to_string())?;
@@ -453,8 +2296,26 @@ fn save_file(app: &App, filename: &str) -> Result<String, String> {
     }
 }
 
-/// Load file
-fn load_file(app: &mut App, filename: &str) -> Result<(), String> {
+/// Refuse to write a file another vicalc has open, unless forced with `!`
+fn refuse_shared(app: &mut App, filename: &str, force: bool) -> bool {
+    let open_here = app.current_file.as_deref() == Some(filename);
+    if force || !(open_here && app.read_only || crate::lockfile::holder(filename).is_some()) {
+        return false;
+    }
+    app.error(format!("{} is open in another vicalc (add ! to write anyway)", filename));
+    true
+}
+
+/// Hold the lock of a file saved under a new name
+fn lock_saved(app: &mut App, filename: &str) {
+    if app.current_file.as_deref() != Some(filename) {
+        app.read_only = app.lock.take(filename).is_some();
+    }
+}
+
+/// Open a file in place of the sheet. Returns a note to show after
+/// "Loaded", if part of the file was left out.
+fn load_file(app: &mut App, filename: &str) -> Result<Option<String>, String> {
     let path = Path::new(filename);
     let ext = path.extension()
         .and_then(|e| e.to_str())
@@ -462,29 +2323,48 @@ fn load_file(app: &mut App, filename: &str) -> Result<(), String> {
         .unwrap_or_default();
 
     match ext.as_str() {
-        "csv" => {
-            import_csv(app, filename).map_err(|e| e.to_string())
+        "csv" | "tsv" => {
+            // Settings saved beside the file are optional; a damaged one is ignored
+            let settings = fs::read_to_string(sidecar_path(filename)).ok();
+            let view = settings.and_then(|s| serde_json::from_str::<ViewSettings>(&s).ok());
+            if let Some(view) = &view {
+                view.apply_numbers(app);
+            }
+            import_csv(app, filename, &crate::csv::Dialect::default())?;
+            if let Some(view) = view {
+                view.apply_types(&mut app.sheet);
+                view.apply_layout(&mut app.sheet);
+                view.apply_position(app);
+            }
+            Ok(None)
+        }
+        "parquet" => {
+            let (sheet, total) = crate::parquet::read(path)?;
+            app.save_undo();
+            app.replace_sheet(sheet);
+            Ok((total > 9999).then(|| format!("first 9999 of {} rows", total)))
         }
         _ => {
             // Default to JSON
-            load_json(app, filename).map_err(|e| e.to_string())
+            load_json(app, filename).map(|_| None).map_err(|e| e.to_string())
         }
     }
 }
 
-fn save_json(app: &App, filename: &str) -> std::io::Result<()> {
-    use crate::sheet::DEFAULT_COL_WIDTH;
-    
-    // Build col_widths map (only non-default widths)
-    let mut col_widths = HashMap::new();
-    for col in 0..=255 {
-        let width = app.sheet.get_col_width(col);
-        if width != DEFAULT_COL_WIDTH {
-            let col_name = crate::formula::col_to_name(col);
-            col_widths.insert(col_name, width);
-        }
+/// Write the sheet to a crash-recovery file after a panic.
+/// Returns the path written, or None if there was nothing worth saving.
+pub fn emergency_save(app: &App) -> Option<String> {
+    if app.sheet.cells().is_empty() {
+        return None;
     }
-    
+    let path = match &app.current_file {
+        Some(file) => format!("{}.crash.json", file),
+        None => "vicalc-crash.json".to_string(),
+    };
+    save_json(app, &path).ok().map(|_| path)
+}
+
+pub fn save_json(app: &App, filename: &str) -> std::io::Result<()> {
     // Build cells map
     let mut cells = HashMap::new();
     for ((col, row), cell) in app.sheet.cells().iter() {
@@ -498,12 +2378,16 @@ fn save_json(app: &App, filename: &str) -> std::io::Result<()> {
                 CellData {
                     value: evaluated,
                     formula: Some(cell.raw_input.clone()),
+                    style: cell.style,
+                    format: cell.format.clone(),
                 }
             }
             _ => {
                 CellData {
                     value: cell.raw_input.clone(),
                     formula: None,
+                    style: cell.style,
+                    format: cell.format.clone(),
                 }
             }
         };
@@ -511,40 +2395,50 @@ fn save_json(app: &App, filename: &str) -> std::io::Result<()> {
         cells.insert(cell_name, cell_data);
     }
     
+    let validations = app.sheet.validations()
+        .iter()
+        .map(|v| ValidationData {
+            range: v.range_name(),
+            rule: v.rule.clone(),
+            action: v.action,
+        })
+        .collect();
+
     let file_data = VicalcFile {
         version: "1.0".to_string(),
         name: app.sheet.name.clone(),
-        col_widths,
         cells,
+        view: ViewSettings::of(app),
+        validations,
+        locks: app.sheet.locks().iter().map(|&area| validation::area_name(area)).collect(),
+        merges: app.sheet.merges().iter().map(|&area| validation::area_name(area)).collect(),
     };
     
     let json = serde_json::to_string_pretty(&file_data)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        .map_err(std::io::Error::other)?;
     let mut file = fs::File::create(filename)?;
     file.write_all(json.as_bytes())?;
     Ok(())
 }
 
-fn load_json(app: &mut App, filename: &str) -> std::io::Result<()> {
+pub fn load_json(app: &mut App, filename: &str) -> std::io::Result<()> {
     let mut file = fs::File::open(filename)?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
     
     let file_data: VicalcFile = serde_json::from_str(&contents)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        .map_err(std::io::Error::other)?;
     
     app.save_undo();
     
     // Create new sheet
     let mut sheet = crate::sheet::Sheet::new();
     sheet.name = file_data.name;
-    
-    // Restore col_widths
-    for (col_name, width) in file_data.col_widths {
-        if let Some((col, _, _, _)) = crate::formula::parse_cell_ref(&format!("{}1", col_name)) {
-            sheet.set_col_width(col, width);
-        }
-    }
+    sheet.set_default_col_width(app.colwidth);
+
+    // Column types first, so text columns keep their values as text
+    file_data.view.apply_numbers(app);
+    file_data.view.apply_types(&mut sheet);
     
     // Restore cells
     for (cell_name, cell_data) in file_data.cells {
@@ -552,84 +2446,240 @@ fn load_json(app: &mut App, filename: &str) -> std::io::Result<()> {
             // If formula exists, use formula; otherwise use value
             let input = cell_data.formula.unwrap_or(cell_data.value);
             sheet.set_cell(col, row, input);
+            let style = cell_data.style;
+            sheet.style_cells((col, row, col, row), |s| *s = style);
+            sheet.format_cells((col, row, col, row), &cell_data.format);
+        }
+    }
+    
+    // Restore validation rules
+    for data in file_data.validations {
+        if let Some((start_col, start_row, end_col, end_row)) = validation::parse_range(&data.range) {
+            sheet.add_validation(Validation {
+                start_col,
+                start_row,
+                end_col,
+                end_row,
+                rule: data.rule,
+                action: data.action,
+            });
         }
     }
     
-    app.sheet = sheet;
-    app.cursor_col = 0;
-    app.cursor_row = 0;
-    app.view_col = 0;
-    app.view_row = 0;
+    for range in file_data.locks {
+        if let Some(area) = validation::parse_range(&range) {
+            sheet.lock(area);
+        }
+    }
+    for range in file_data.merges {
+        if let Some(area) = validation::parse_range(&range) {
+            sheet.merge(area);
+        }
+    }
+
+    // Widths, hidden rows and the filter, re-applied to the loaded data
+    file_data.view.apply_layout(&mut sheet);
+    
+    app.replace_sheet(sheet);
+    file_data.view.apply_position(app);
     Ok(())
 }
 
-fn export_csv(app: &App, filename: &str) -> std::io::Result<()> {
-    let max_col = app.sheet.max_col().unwrap_or(0);
-    let max_row = app.sheet.max_row().unwrap_or(0);
+/// `:export file.csv`, or `:export file.txt --box` (`--ascii`) for a drawn
+/// table of the selection or the used area
+fn export(app: &mut App, args: &str, range: Option<(usize, usize, usize, usize)>) {
+    const USAGE: &str = "Usage: :export <filename.csv> [range=A1:D20] [formulas=true] [delim=tab], \
+                         :export <filename.json> records or :export <filename.txt> --box|--ascii";
+    let mut border = None;
+    let mut records = false;
+    let mut range = range;
+    let mut formulas = false;
+    let mut delimiter = None;
+    let mut words = Vec::new();
+    for word in args.split_whitespace() {
+        match word.split_once('=') {
+            Some(("range", area)) => match crate::validation::parse_range(area) {
+                Some(area) => range = Some(area),
+                None => {
+                    app.error(format!("Invalid range: {}", area));
+                    return;
+                }
+            },
+            Some(("formulas", value)) => match value {
+                "true" | "on" | "yes" => formulas = true,
+                "false" | "off" | "no" => formulas = false,
+                _ => {
+                    app.error(format!("Invalid value: {} (true or false)", value));
+                    return;
+                }
+            },
+            Some(("delim" | "delimiter", name)) => match crate::csv::parse_delimiter(name) {
+                Ok(c) => delimiter = Some(c),
+                Err(e) => {
+                    app.error(e);
+                    return;
+                }
+            },
+            _ => match word {
+                "--box" => border = Some(crate::textable::Border::Unicode),
+                "--ascii" => border = Some(crate::textable::Border::Ascii),
+                "records" => records = true,
+                flag if flag.starts_with("--") => {
+                    app.error(format!("Unknown option: {} ({})", flag, USAGE));
+                    return;
+                }
+                _ => words.push(word),
+            },
+        }
+    }
+    let filename = words.join(" ");
+    if filename.is_empty() {
+        app.warn(USAGE);
+        return;
+    }
 
-    let mut csv = String::new();
-    for row in 0..=max_row {
-        let mut row_values = Vec::new();
-        for col in 0..=max_col {
-            let value = app.sheet.evaluate(col, row);
-            // Escape quotes and wrap in quotes if needed
-            if value.contains(',') || value.contains('"') || value.contains('\n') {
-                row_values.push(format!("\"{}\"", value.replace('"', "\"\"")));
-            } else {
-                row_values.push(value);
-            }
+    let bounds = range.unwrap_or((0, 0, app.sheet.max_col().unwrap_or(0), app.sheet.max_row().unwrap_or(0)));
+    let text = match border {
+        Some(border) => crate::textable::render(&app.sheet, bounds, border),
+        // Field names from the header row unless a range says otherwise
+        None if records => {
+            let (c1, r1, c2, r2) = bounds;
+            let first = if range.is_some() { r1 } else { app.sheet.header_row().unwrap_or(r1) };
+            crate::records::write(&app.sheet, (c1, first, c2, r2))
+        }
+        None => {
+            // .tsv files are tab-separated unless told otherwise
+            let tsv = Path::new(&filename).extension().is_some_and(|e| e.eq_ignore_ascii_case("tsv"));
+            let delimiter = delimiter.unwrap_or(if tsv { '\t' } else { ',' });
+            crate::csv::write_with(&app.sheet, bounds, delimiter, formulas)
+        }
+    };
+    match fs::write(&filename, text) {
+        Ok(()) if range.is_some() => {
+            app.info(format!("Exported {} to {}", crate::validation::area_name(bounds), filename))
         }
-        csv.push_str(&row_values.join(","));
-        csv.push('\n');
+        Ok(()) => app.info(format!("Exported to {}", filename)),
+        Err(e) => app.error(format!("Error exporting: {}", e)),
     }
+}
 
+fn export_csv(app: &App, filename: &str) -> std::io::Result<()> {
     let mut file = fs::File::create(filename)?;
-    file.write_all(csv.as_bytes())?;
+    file.write_all(crate::csv::write(&app.sheet).as_bytes())?;
     Ok(())
 }
 
-fn import_csv(app: &mut App, filename: &str) -> std::io::Result<()> {
-    let mut file = fs::File::open(filename)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
+/// Keep the view settings of a saved CSV file beside it; with none to keep,
+/// remove an old settings file
+fn save_sidecar(app: &App, filename: &str) -> std::io::Result<()> {
+    let path = sidecar_path(filename);
+    let view = ViewSettings::of(app);
+    let json = serde_json::to_string_pretty(&view).map_err(std::io::Error::other)?;
+    if json == "{}" {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+    }
+    fs::write(path, json + "\n")
+}
 
+/// Read a delimited file into the sheet; returns the encoding it was in
+fn import_csv(app: &mut App, filename: &str, dialect: &crate::csv::Dialect) -> Result<&'static str, String> {
+    let bytes = fs::read(filename).map_err(|e| e.to_string())?;
+    let (contents, encoding) = crate::csv::decode(&bytes, dialect.encoding);
+
+    let dialect = crate::csv::Dialect { decimal_comma: app.number_style.decimal_comma, ..dialect.clone() };
     app.save_undo();
-    app.sheet = crate::sheet::Sheet::new();
-
-    for (row, line) in contents.lines().enumerate() {
-        let mut col = 0;
-        let mut current = String::new();
-        let mut in_quotes = false;
-        let mut chars = line.chars().peekable();
-
-        while let Some(c) = chars.next() {
-            if c == '"' {
-                if in_quotes && chars.peek() == Some(&'"') {
-                    // Escaped quote
-                    current.push('"');
-                    chars.next();
-                } else {
-                    in_quotes = !in_quotes;
-                }
-            } else if c == ',' && !in_quotes {
-                if !current.is_empty() {
-                    app.sheet.set_cell(col, row, current.clone());
-                }
-                current.clear();
-                col += 1;
-            } else {
-                current.push(c);
-            }
+    app.replace_sheet(crate::csv::read_with(&contents, &dialect));
+    Ok(encoding)
+}
+
+/// Read a JSON array of records into the sheet
+fn import_records(app: &mut App, filename: &str) -> Result<&'static str, String> {
+    let contents = fs::read_to_string(filename).map_err(|e| e.to_string())?;
+    let sheet = crate::records::read(&contents)?;
+
+    app.save_undo();
+    app.replace_sheet(sheet);
+    Ok("UTF-8")
+}
+
+/// Split `:import` arguments into the file name and the options after it
+/// (delim=, skip=, encoding=, header, noheader, records)
+fn split_import_options(args: &str) -> (String, Vec<&str>) {
+    let mut words: Vec<&str> = args.split_whitespace().collect();
+    let mut options = Vec::new();
+    while words.len() > 1 {
+        let last = words[words.len() - 1];
+        let key = last.split_once('=').map_or(last, |(key, _)| key);
+        if !matches!(key, "delim" | "delimiter" | "skip" | "encoding" | "enc" | "header" | "noheader" | "records") {
+            break;
         }
-        
-        if !current.is_empty() {
-            app.sheet.set_cell(col, row, current);
+        options.insert(0, last);
+        words.pop();
+    }
+    (words.join(" "), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_filter_through() {
+        let column = |app: &App, col: usize, rows: usize| -> Vec<String> {
+            (0..rows).map(|row| app.sheet.evaluate(col, row)).collect()
+        };
+        let mut app = App::new();
+        for (row, (name, n)) in [("c", "3"), ("a", "1"), ("b", "2")].into_iter().enumerate() {
+            app.sheet.set_cell(0, row, name.to_string());
+            app.sheet.set_cell(1, row, n.to_string());
         }
+        let area = Some((0, 0, 1, 2));
+        filter_through(&mut app, "LC_ALL=C sort", area);
+        assert_eq!(column(&app, 0, 3), vec!["a", "b", "c"]);
+        assert_eq!(column(&app, 1, 3), vec!["1", "2", "3"]);
+
+        // Output longer than the area runs on below it
+        filter_through(&mut app, "LC_ALL=C sort -r; printf 'z\\t26\\n'", area);
+        assert_eq!(column(&app, 0, 4), vec!["c", "b", "a", "z"]);
+        assert_eq!(app.sheet.evaluate(1, 3), "26");
+
+        // Shorter output leaves the rest of the area empty
+        filter_through(&mut app, "head -n 1", Some((0, 0, 1, 3)));
+        assert_eq!(column(&app, 0, 4), vec!["c", "", "", ""]);
+        assert_eq!(column(&app, 1, 4), vec!["3", "", "", ""]);
+
+        // A failing command changes nothing
+        let undo = app.history.undo_entries().len();
+        filter_through(&mut app, "exit 3", area);
+        assert_eq!(app.sheet.evaluate(0, 0), "c");
+        assert_eq!(app.history.undo_entries().len(), undo);
     }
 
-    app.cursor_col = 0;
-    app.cursor_row = 0;
-    app.view_col = 0;
-    app.view_row = 0;
-    Ok(())
+    #[test]
+    fn test_clear() {
+        let mut app = App::new();
+        let file = std::env::temp_dir().join(format!("vicalc-clear-{}.csv", std::process::id()));
+        let file = file.to_string_lossy().into_owned();
+        assert!(app.lock.take(&file).is_none());
+        assert!(crate::lockfile::path_for(&file).exists());
+        app.current_file = Some(file.clone());
+        app.sheet.set_cell(0, 0, "keep".to_string());
+        app.sheet.set_cell(2, 3, "x".to_string());
+
+        // Locked cells are not wiped
+        execute_command(&mut app, "lock B2");
+        execute_command(&mut app, "clear");
+        assert_eq!(app.sheet.evaluate(0, 0), "keep");
+        assert!(app.messages.current().unwrap().text.contains("B2 is locked"));
+
+        execute_command(&mut app, "unlock B2");
+        execute_command(&mut app, "clear");
+        assert!(app.sheet.cells().is_empty());
+        assert_eq!(app.current_file, None);
+        assert!(!crate::lockfile::path_for(&file).exists());
+    }
 }
@@ -0,0 +1,237 @@
+//! Tab completion on the command line: command names, their subcommands,
+//! `:set` options and values, and file names for `:e`, `:w` and the like.
+//!
+//! The first Tab completes as far as all the matches agree and lists them;
+//! each further Tab puts the next match in place (Shift-Tab the previous
+//! one), coming back round to the text as typed.
+
+use crate::commands::OPTIONS;
+use crate::App;
+
+/// Command names offered for completion, without their short forms
+pub const COMMANDS: &[&str] = &[
+    "align", "autowidth", "calc", "cc", "cclose", "changed", "chart", "check", "clear", "close", "cnext",
+    "colformula", "colorscheme", "coltype", "copen", "cprevious", "delcol", "delrow", "dependents",
+    "diff", "edit", "errors", "export", "filter", "format", "goto", "grep", "help", "hide", "hist",
+    "import", "inscol", "insrow", "lock", "macro", "mc", "memory", "merge", "messages", "movecol",
+    "moverow", "mr", "newfrom", "nofilter", "nohlsearch", "novalidate", "only", "pivot", "precedents",
+    "quit", "regress", "rhai", "rhaifile", "sample", "select", "set", "shuffle", "snap", "snapshot",
+    "snapshots", "split", "tag", "tags", "transpose", "trend", "undolist", "unhide", "unlock", "unmerge",
+    "untag", "validate", "vsplit", "wq", "write",
+];
+
+/// Matches being cycled through with repeated Tabs
+pub struct CommandCompletion {
+    // Byte offset in the command line of the word being completed
+    start: usize,
+    typed: String,
+    matches: Vec<String>,
+    index: Option<usize>,
+}
+
+/// Complete the command line, or go to the next (previous) match
+pub fn complete(app: &mut App, forward: bool) {
+    if let Some(mut completion) = app.cmd_completion.take() {
+        let count = completion.matches.len();
+        completion.index = match (completion.index, forward) {
+            (None, true) => Some(0),
+            (None, false) => Some(count - 1),
+            (Some(i), true) => (i + 1 < count).then_some(i + 1),
+            (Some(i), false) => i.checked_sub(1),
+        };
+        let word = completion.index.map_or(&completion.typed, |i| &completion.matches[i]);
+        app.command_buffer = format!("{}{}", &app.command_buffer[..completion.start], word);
+        let position = completion.index.map_or(String::new(), |i| format!("({} of {}) ", i + 1, count));
+        app.info(format!("{}{}", position, list(&completion.matches)));
+        app.cmd_completion = Some(completion);
+        return;
+    }
+
+    let (start, matches) = candidates(app, &app.command_buffer);
+    let typed = &app.command_buffer[start..];
+    match matches.len() {
+        0 => {
+            if is_file_command(&app.command_buffer) && !typed.is_empty() {
+                app.info(format!("{} (new file)", typed));
+            }
+        }
+        1 => app.command_buffer = format!("{}{}", &app.command_buffer[..start], matches[0]),
+        _ => {
+            let common = common_prefix(&matches);
+            let typed = if common.chars().count() > typed.chars().count() { common } else { typed.to_string() };
+            app.command_buffer = format!("{}{}", &app.command_buffer[..start], typed);
+            app.info(list(&matches));
+            app.cmd_completion = Some(CommandCompletion { start, typed, matches, index: None });
+        }
+    }
+}
+
+/// Up to five matches, and how many more there are
+fn list(matches: &[String]) -> String {
+    let shown: Vec<&str> = matches.iter().take(5).map(String::as_str).collect();
+    let more = if matches.len() > 5 { format!(" +{}", matches.len() - 5) } else { String::new() };
+    format!("[{}{}]", shown.join(" "), more)
+}
+
+/// Whether the command line's command takes a file name
+fn is_file_command(line: &str) -> bool {
+    let command = line.split(' ').next().unwrap_or("").trim_end_matches('!');
+    matches!(command, "e" | "edit" | "open" | "w" | "write" | "export" | "import" | "rhaifile")
+}
+
+/// Where the word being completed starts, and what it could be
+pub fn candidates(app: &App, line: &str) -> (usize, Vec<String>) {
+    let offset = if line.starts_with("'<,'>") { "'<,'>".len() } else { 0 };
+    let line = &line[offset..];
+    let Some((command, args)) = line.split_once(' ') else {
+        let typed = line.to_lowercase();
+        let mut names: Vec<String> = COMMANDS
+            .iter()
+            .map(|c| c.to_string())
+            .chain(app.aliases.keys().cloned())
+            .chain(app.plugins.keys().cloned())
+            .filter(|c| c.starts_with(&typed) && *c != typed)
+            .collect();
+        names.sort();
+        names.dedup();
+        return (offset, names);
+    };
+
+    // The word being typed: after the last space, or after `=` in `:set`
+    let word_start = args.rfind([' ', '=']).map_or(0, |i| i + 1);
+    let word = &args[word_start..];
+    let start = offset + command.len() + 1 + word_start;
+    let previous: Vec<&str> = args[..word_start].split([' ', '=']).filter(|w| !w.is_empty()).collect();
+
+    let choices: Vec<String> = match (command.to_lowercase().trim_end_matches('!'), previous.as_slice()) {
+        ("e" | "edit" | "open" | "newfrom" | "w" | "write" | "export" | "import" | "rhaifile", []) => return (start, files(word)),
+        ("set", []) => OPTIONS.iter().map(|o| o.to_string()).collect(),
+        ("set", [option]) if args[..word_start].ends_with('=') => match option.to_lowercase().as_str() {
+            "name" | "sheet" => vec![app.sheet.name.clone()],
+            "axis" => vec!["row".to_string(), "col".to_string()],
+            "calc" => vec!["auto".to_string(), "manual".to_string()],
+            "searchtarget" | "st" => vec!["formula".to_string(), "value".to_string()],
+            "theme" => crate::theme::NAMES.iter().map(|n| n.to_string()).collect(),
+            "savehist" | "sh" | "wheeldrag" | "funcmenu" | "fm" | "stats" | "a11y" | "regex" | "relativenumber" | "rnu"
+            | "autoextend" | "changemarks" | "cm" | "scrollbind" | "scb" => {
+                vec!["on".to_string(), "off".to_string()]
+            }
+            "numfmt" => vec!["general".to_string(), "grouped".to_string()],
+            "decimal" => vec!["comma".to_string(), "point".to_string()],
+            "keylog" | "auditlog" => return (start, files(word)),
+            _ => Vec::new(),
+        },
+        ("import" | "export", [.., "delim"]) => ["comma", "pipe", "semicolon", "space", "tab"].map(String::from).to_vec(),
+        ("export", [.., "formulas"]) => vec!["false".to_string(), "true".to_string()],
+        ("export", [_, ..]) => ["--ascii", "--box", "delim=", "formulas=", "range=", "records"].map(String::from).to_vec(),
+        ("import", [_, ..]) => ["delim=", "encoding=", "header", "noheader", "records", "skip="].map(String::from).to_vec(),
+        ("align", []) => ["auto", "center", "left", "right"].map(String::from).to_vec(),
+        ("style", _) => ["bold", "color=", "nobold", "nocolor", "none", "nounderline", "underline"].map(String::from).to_vec(),
+        ("chart", []) => vec!["bar".to_string(), "line".to_string()],
+        ("colorscheme" | "colo", []) => crate::theme::NAMES.iter().map(|n| n.to_string()).collect(),
+        ("format", []) => {
+            ["currency", "date", "general", "grouped", "number", "percent", "scientific", "text"].map(String::from).to_vec()
+        }
+        ("coltype" | "ct", [_]) => ["bool", "date", "none", "number", "text"].map(String::from).to_vec(),
+        ("messages" | "mes", []) => vec!["clear".to_string()],
+        ("select", [.., "type"]) if args[..word_start].ends_with('=') => ["boolean", "error", "formula", "number", "text"].map(String::from).to_vec(),
+        ("select", [.., "format"]) if args[..word_start].ends_with('=') => {
+            ["currency", "date", "general", "grouped", "number", "percent", "scientific", "text"].map(String::from).to_vec()
+        }
+        ("select", [.., "align"]) if args[..word_start].ends_with('=') => ["center", "left", "right"].map(String::from).to_vec(),
+        ("select", _) => ["align=", "bold", "color", "format=", "locked", "type=", "underline"].map(String::from).to_vec(),
+        ("macro", []) => ["delete", "list", "run", "save"].map(String::from).to_vec(),
+        ("macro", ["run" | "delete"]) => app.macros.named.keys().cloned().collect(),
+        ("diff" | "snapshot" | "snap", []) => app.snapshots.iter().map(|s| s.name.clone()).collect(),
+        ("tag", _) | ("tags" | "untag", []) => {
+            let names: std::collections::BTreeSet<&String> =
+                app.sheet.tagged_cells().into_iter().flat_map(|(_, tags)| tags).collect();
+            names.into_iter().cloned().collect()
+        }
+        _ => Vec::new(),
+    };
+    let lower = word.to_lowercase();
+    let matches = choices.into_iter().filter(|c| c.to_lowercase().starts_with(&lower) && *c != word).collect();
+    (start, matches)
+}
+
+/// Files and directories starting with `partial`; with a partial name only
+/// the kinds vicalc opens (.json, .csv, .tsv, .parquet, .rhai) and directories
+fn files(partial: &str) -> Vec<String> {
+    let (dir, file_prefix) = match partial.rfind(['/', '\\']) {
+        Some(i) => (&partial[..i.max(1)], &partial[i + 1..]),
+        None => (".", partial),
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let lower_prefix = file_prefix.to_lowercase();
+    let mut matches: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            if !name.to_lowercase().starts_with(&lower_prefix) {
+                return None;
+            }
+            let is_dir = e.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            let ext = std::path::Path::new(&name)
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            if !(is_dir || matches!(ext.as_str(), "json" | "csv" | "tsv" | "parquet" | "rhai") || file_prefix.is_empty()) {
+                return None;
+            }
+            Some(if dir == "." && !partial.starts_with("./") { name } else { format!("{}/{}", dir.trim_end_matches('/'), name) })
+        })
+        .collect();
+    matches.sort();
+    matches
+}
+
+/// Longest prefix shared by all of `strings`, ignoring case
+fn common_prefix(strings: &[String]) -> String {
+    let Some(first) = strings.first() else {
+        return String::new();
+    };
+    let mut prefix_len = first.chars().count();
+    for s in &strings[1..] {
+        prefix_len = first
+            .chars()
+            .zip(s.chars())
+            .take_while(|(a, b)| a.to_lowercase().eq(b.to_lowercase()))
+            .count()
+            .min(prefix_len);
+    }
+    first.chars().take(prefix_len).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(app: &App, line: &str) -> (usize, Vec<String>) {
+        candidates(app, line)
+    }
+
+    #[test]
+    fn test_candidates() {
+        let mut app = App::new();
+        assert_eq!(words(&app, "ex"), (0, vec!["export".to_string()]));
+        assert_eq!(words(&app, "'<,'>pi"), (5, vec!["pivot".to_string()]));
+        assert_eq!(words(&app, "set th"), (4, vec!["theme".to_string()]));
+        assert_eq!(words(&app, "set theme=").1, vec!["dark", "light", "monochrome", "deuteranopia", "highcontrast"]);
+        assert_eq!(words(&app, "set theme=l"), (10, vec!["light".to_string()]));
+        assert_eq!(words(&app, "coltype B d").1, vec!["date"]);
+        assert!(words(&app, "coltype d").1.is_empty());
+
+        app.aliases.insert("exp".to_string(), "export out.csv".to_string());
+        assert_eq!(words(&app, "ex").1, vec!["exp", "export"]);
+    }
+
+    #[test]
+    fn test_common_prefix() {
+        let strings = ["check".to_string(), "chart".to_string(), "Chop".to_string()];
+        assert_eq!(common_prefix(&strings), "ch");
+        assert_eq!(common_prefix(&[]), "");
+    }
+}
@@ -0,0 +1,370 @@
+//! Startup configuration, read from `~/.vicalcrc` (or `$VICALCRC`).
+//!
+//! The file is TOML. Top-level keys are options, applied as if given to
+//! `:set`; tables remap Normal mode keys, define command aliases, change the
+//! interface colors and define snippets for Edit mode:
+//!
+//! ```toml
+//! colwidth = 12
+//! axis = "col"
+//! autosave = 10
+//!
+//! [keymap]
+//! H = "0"
+//! "<C-s>" = ":w<CR>"
+//!
+//! [aliases]
+//! W = "w"
+//!
+//! [colors]
+//! text = "#00aa00"
+//! frame = "grey"
+//!
+//! [snippets]
+//! ";vl" = "=VLOOKUP(|, |, |, FALSE)"
+//! ```
+//!
+//! `scripts = ["~/.vicalc/functions.rhai"]` runs Rhai files at startup, for
+//! the formula functions they define (see `scripting`), and a `[commands]`
+//! table adds `:` commands run by programs or scripts (see `plugin`).
+//! `[macros]` holds the keyboard macros saved with `:macro save` (see
+//! `macros`).
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+
+use crate::commands;
+use crate::App;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub keymap: BTreeMap<String, String>,
+    pub aliases: BTreeMap<String, String>,
+    pub colors: BTreeMap<String, String>,
+    pub snippets: BTreeMap<String, String>,
+    // New commands: a program, or a Rhai file ending in .rhai
+    pub commands: BTreeMap<String, String>,
+    // Keyboard macros saved with :macro save
+    pub macros: BTreeMap<String, String>,
+    // Rhai files run at startup, for the functions they define
+    pub scripts: Vec<String>,
+    // Everything else is an option
+    #[serde(flatten)]
+    pub options: BTreeMap<String, toml::Value>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.message().to_string())
+    }
+}
+
+/// Where the config file is looked for: `$VICALCRC`, else `~/.vicalcrc`
+pub fn path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("VICALCRC") {
+        return Some(PathBuf::from(path));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".vicalcrc"))
+}
+
+/// Read the config file, if there is one, and apply it. Problems are
+/// reported as errors; the settings that are fine still take effect.
+pub fn load(app: &mut App) {
+    let Some(path) = path() else {
+        return;
+    };
+    let Ok(text) = std::fs::read_to_string(&path) else {
+        return;
+    };
+    let config = match Config::parse(&text) {
+        Ok(config) => config,
+        Err(e) => {
+            app.error(format!("{}: {}", path.display(), e.trim_end()));
+            return;
+        }
+    };
+    let problems = apply(app, config);
+    if let Some(first) = problems.first() {
+        let more = if problems.len() > 1 { format!(" (+{} more)", problems.len() - 1) } else { String::new() };
+        app.error(format!("{}: {}{}", path.display(), first, more));
+    }
+}
+
+/// Apply a parsed config, returning what could not be applied
+pub fn apply(app: &mut App, config: Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for (name, value) in &config.options {
+        let value = match value {
+            toml::Value::String(s) => s.clone(),
+            toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => value.to_string(),
+            _ => {
+                problems.push(format!("{}: expected a string, number or boolean", name));
+                continue;
+            }
+        };
+        if let Err(e) = commands::set_option(app, name, &value) {
+            problems.push(e);
+        }
+    }
+
+    for (from, to) in &config.keymap {
+        match (parse_keys(from), parse_keys(to)) {
+            (Ok(key), Ok(keys)) if key.len() == 1 => app.keymap.insert(key[0], keys),
+            (Ok(_), Ok(_)) => problems.push(format!("keymap: {} is not a single key", from)),
+            (Err(e), _) | (_, Err(e)) => problems.push(format!("keymap: {}", e)),
+        }
+    }
+
+    for (name, expansion) in config.aliases {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            problems.push(format!("aliases: invalid command name '{}'", name));
+        } else {
+            app.aliases.insert(name.to_lowercase(), expansion);
+        }
+    }
+
+    for (name, color) in &config.colors {
+        if let Err(e) = app.theme.set(name, color) {
+            problems.push(format!("colors: {}", e));
+        }
+    }
+
+    for (trigger, text) in config.snippets {
+        if trigger.is_empty() || trigger.contains(char::is_whitespace) {
+            problems.push(format!("snippets: invalid trigger '{}'", trigger));
+        } else {
+            app.snippets.insert(trigger, text);
+        }
+    }
+
+    for (name, program) in config.commands {
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+            problems.push(format!("commands: invalid command name '{}'", name));
+        } else if program.ends_with(".rhai") {
+            app.plugins.insert(name.to_lowercase(), expand_home(&program));
+        } else {
+            app.plugins.insert(name.to_lowercase(), program);
+        }
+    }
+
+    for (name, keys) in &config.macros {
+        match parse_keys(keys) {
+            Ok(keys) if crate::macros::valid_name(name) => {
+                app.macros.named.insert(name.clone(), keys);
+            }
+            Ok(_) => problems.push(format!("macros: invalid macro name '{}'", name)),
+            Err(e) => problems.push(format!("macros: {}: {}", name, e)),
+        }
+    }
+
+    for path in &config.scripts {
+        if let Err(e) = crate::scripting::load(app, &expand_home(path)) {
+            problems.push(format!("scripts: {}", e));
+        }
+    }
+
+    problems
+}
+
+/// A path with a leading `~/` made relative to the home directory
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest).display().to_string(),
+        _ => path.to_string(),
+    }
+}
+
+/// Normal mode key remappings. Keys are matched on code and modifiers only;
+/// Shift is left out for characters, which already carry it as their case.
+#[derive(Default)]
+pub struct Keymap(HashMap<(KeyCode, KeyModifiers), Vec<KeyEvent>>);
+
+impl Keymap {
+    fn index(key: &KeyEvent) -> (KeyCode, KeyModifiers) {
+        match key.code {
+            KeyCode::Char(_) => (key.code, key.modifiers - KeyModifiers::SHIFT),
+            _ => (key.code, key.modifiers),
+        }
+    }
+
+    pub fn insert(&mut self, key: KeyEvent, keys: Vec<KeyEvent>) {
+        self.0.insert(Self::index(&key), keys);
+    }
+
+    /// Keys `key` is mapped to, if it is mapped
+    pub fn get(&self, key: &KeyEvent) -> Option<&[KeyEvent]> {
+        self.0.get(&Self::index(key)).map(Vec::as_slice)
+    }
+}
+
+/// Parse a key sequence in vim notation: plain characters stand for
+/// themselves, and `<C-x>`, `<A-x>`, `<S-Tab>`, `<CR>`, `<Esc>`, `<Space>`,
+/// `<lt>` and so on for the rest
+pub fn parse_keys(s: &str) -> Result<Vec<KeyEvent>, String> {
+    let mut keys = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let special = (c == '<').then(|| rest.find('>')).flatten().filter(|&end| end > 1);
+        match special {
+            Some(end) => {
+                let name = &rest[1..end];
+                keys.push(parse_key_name(name).ok_or_else(|| format!("unknown key <{}>", name))?);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                keys.push(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    if keys.is_empty() {
+        return Err("empty key sequence".to_string());
+    }
+    Ok(keys)
+}
+
+/// Write a key in the notation `parse_keys` reads; None for keys it has no
+/// name for
+pub fn key_to_string(key: &KeyEvent) -> Option<String> {
+    let mut modifiers = String::new();
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        modifiers.push_str("C-");
+    }
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        modifiers.push_str("A-");
+    }
+    // Characters carry Shift as their case
+    if key.modifiers.contains(KeyModifiers::SHIFT) && !matches!(key.code, KeyCode::Char(_) | KeyCode::BackTab) {
+        modifiers.push_str("S-");
+    }
+    let name = match key.code {
+        KeyCode::Char('<') => "lt".to_string(),
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) if modifiers.is_empty() => return Some(c.to_string()),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Enter => "CR".to_string(),
+        KeyCode::Esc => "Esc".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::BackTab => "S-Tab".to_string(),
+        KeyCode::Backspace => "BS".to_string(),
+        KeyCode::Delete => "Del".to_string(),
+        KeyCode::Up => "Up".to_string(),
+        KeyCode::Down => "Down".to_string(),
+        KeyCode::Left => "Left".to_string(),
+        KeyCode::Right => "Right".to_string(),
+        KeyCode::Home => "Home".to_string(),
+        KeyCode::End => "End".to_string(),
+        KeyCode::PageUp => "PageUp".to_string(),
+        KeyCode::PageDown => "PageDown".to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        _ => return None,
+    };
+    Some(format!("<{}{}>", modifiers, name))
+}
+
+/// A key name between angle brackets, with optional C-, A-/M- and S- prefixes
+fn parse_key_name(name: &str) -> Option<KeyEvent> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut name = name;
+    while name.len() > 2 && name.as_bytes()[1] == b'-' {
+        modifiers |= match name.as_bytes()[0].to_ascii_uppercase() {
+            b'C' => KeyModifiers::CONTROL,
+            b'A' | b'M' => KeyModifiers::ALT,
+            b'S' => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+        name = &name[2..];
+    }
+
+    let code = match name.to_lowercase().as_str() {
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "tab" if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+        "tab" => KeyCode::Tab,
+        "space" => KeyCode::Char(' '),
+        "bs" | "backspace" => KeyCode::Backspace,
+        "del" | "delete" => KeyCode::Delete,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "lt" => KeyCode::Char('<'),
+        lower => {
+            if let Some(n) = lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                KeyCode::F(n)
+            } else {
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    // Ctrl-letters arrive lowercase whatever the case typed
+                    (Some(c), None) if modifiers.contains(KeyModifiers::CONTROL) => KeyCode::Char(c.to_ascii_lowercase()),
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return None,
+                }
+            }
+        }
+    };
+    Some(KeyEvent::new(code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keys() {
+        let keys = parse_keys(":w<CR>").unwrap();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys[0].code, KeyCode::Char(':'));
+        assert_eq!(keys[2].code, KeyCode::Enter);
+
+        let ctrl_s = parse_keys("<C-S>").unwrap();
+        assert_eq!(ctrl_s, vec![KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL)]);
+        assert_eq!(parse_keys("<S-Tab>").unwrap()[0].code, KeyCode::BackTab);
+        assert_eq!(parse_keys("<lt>").unwrap()[0].code, KeyCode::Char('<'));
+        // A lone < is just the character
+        assert_eq!(parse_keys("<").unwrap()[0].code, KeyCode::Char('<'));
+        assert!(parse_keys("<nosuchkey>").is_err());
+        assert!(parse_keys("").is_err());
+    }
+
+    #[test]
+    fn test_key_to_string() {
+        for keys in ["a", "<C-s>", "<CR>", "<S-Tab>", "<lt>", "<Space>", "<A-x>", "<S-Up>", "<F5>", "A"] {
+            let key = parse_keys(keys).unwrap()[0];
+            assert_eq!(key_to_string(&key).as_deref(), Some(keys));
+        }
+        let shifted = KeyEvent::new(KeyCode::Char('A'), KeyModifiers::SHIFT);
+        assert_eq!(key_to_string(&shifted).as_deref(), Some("A"));
+    }
+
+    #[test]
+    fn test_keymap_ignores_shift_on_characters() {
+        let mut keymap = Keymap::default();
+        keymap.insert(parse_keys("H").unwrap()[0], parse_keys("0").unwrap());
+        let typed = KeyEvent::new(KeyCode::Char('H'), KeyModifiers::SHIFT);
+        assert_eq!(keymap.get(&typed).map(|k| k.len()), Some(1));
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(
+            "colwidth = 12\naxis = \"col\"\n[keymap]\nH = \"0\"\n[aliases]\nW = \"w\"\n[colors]\nframe = \"grey\"\n[snippets]\n\";s\" = \"=SUM(|)\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.options.get("colwidth"), Some(&toml::Value::Integer(12)));
+        assert_eq!(config.keymap.get("H").map(String::as_str), Some("0"));
+        assert_eq!(config.aliases.get("W").map(String::as_str), Some("w"));
+        assert_eq!(config.colors.len(), 1);
+        assert_eq!(config.snippets.get(";s").map(String::as_str), Some("=SUM(|)"));
+        assert!(Config::parse("colwidth = ").is_err());
+    }
+}
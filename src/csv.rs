@@ -0,0 +1,309 @@
+//! CSV reading and writing.
+//!
+//! Fields are separated by commas and may be wrapped in double quotes, with
+//! `""` standing for a literal quote inside a quoted field. Values are
+//! imported as if typed into the cell, so numbers and formulas keep working;
+//! exports contain the displayed (evaluated) values.
+//!
+//! `:import` also takes files that are not quite CSV: another delimiter
+//! (found by looking at the first lines, or given), lines to skip above the
+//! table, no header line, and text in another encoding than UTF-8.
+
+use encoding_rs::{Encoding, SHIFT_JIS, UTF_8, WINDOWS_1252};
+
+use crate::cell::{Cell, CellValue, NumberStyle};
+use crate::sheet::Sheet;
+
+/// How to read a delimited file: `:import data.tsv delim=tab skip=2`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Dialect {
+    // None to detect it from the first lines
+    pub delimiter: Option<char>,
+    // Lines above the table to leave out, such as a title
+    pub skip: usize,
+    // The file has no header line; one naming the columns is added
+    pub no_header: bool,
+    // None for UTF-8, falling back to Shift_JIS or Windows-1252
+    pub encoding: Option<&'static Encoding>,
+    // Numbers are written with a decimal comma (:set decimal=comma)
+    pub decimal_comma: bool,
+}
+
+impl Dialect {
+    /// Apply one option: delim=, skip=, header, noheader or encoding=
+    pub fn set(&mut self, option: &str) -> Result<(), String> {
+        match option.split_once('=') {
+            Some(("delim" | "delimiter", name)) => self.delimiter = Some(parse_delimiter(name)?),
+            Some(("skip", n)) => self.skip = n.parse().map_err(|_| format!("Invalid line count: {}", n))?,
+            Some(("encoding" | "enc", label)) => {
+                let encoding = Encoding::for_label(label.as_bytes()).ok_or(format!("Unknown encoding: {}", label))?;
+                self.encoding = Some(encoding);
+            }
+            None if option == "header" => self.no_header = false,
+            None if option == "noheader" => self.no_header = true,
+            _ => return Err(format!("Unknown import option: {}", option)),
+        }
+        Ok(())
+    }
+}
+
+/// A delimiter given by name (tab, comma, semicolon, pipe, space) or as
+/// the character itself
+pub fn parse_delimiter(name: &str) -> Result<char, String> {
+    Ok(match name {
+        "tab" | "\\t" => '\t',
+        "comma" => ',',
+        "semicolon" => ';',
+        "pipe" => '|',
+        "space" => ' ',
+        _ if name.chars().count() == 1 => name.chars().next().unwrap(),
+        _ => return Err(format!("Invalid delimiter: {} (tab, comma, semicolon, pipe, space or one character)", name)),
+    })
+}
+
+/// Text of a file's bytes and the name of its encoding. Without one given,
+/// a byte order mark decides, then UTF-8 if the bytes are valid UTF-8,
+/// then Shift_JIS, then Windows-1252, which takes any bytes.
+pub fn decode(bytes: &[u8], encoding: Option<&'static Encoding>) -> (String, &'static str) {
+    let encoding = encoding.or_else(|| Encoding::for_bom(bytes).map(|(e, _)| e)).unwrap_or_else(|| {
+        if std::str::from_utf8(bytes).is_ok() {
+            UTF_8
+        } else if SHIFT_JIS.decode_without_bom_handling_and_without_replacement(bytes).is_some() {
+            SHIFT_JIS
+        } else {
+            WINDOWS_1252
+        }
+    });
+    let (text, used, _) = encoding.decode(bytes);
+    (text.into_owned(), used.name())
+}
+
+/// The delimiter of delimited text: of comma, tab, semicolon and pipe, the
+/// one splitting the first lines into the same number of fields, the most
+/// fields if several do. Comma if none does.
+pub fn detect_delimiter(text: &str) -> char {
+    let sample: String = text.lines().take(20).collect::<Vec<_>>().join("\n");
+    let mut best = (',', 1);
+    for delimiter in [',', '\t', ';', '|'] {
+        let records = parse_records(&sample, delimiter);
+        let fields = records.first().map_or(0, Vec::len);
+        if fields > best.1 && records.iter().all(|r| r.len() == fields) {
+            best = (delimiter, fields);
+        }
+    }
+    best.0
+}
+
+/// Split one line into its fields
+pub fn parse_line(line: &str) -> Vec<String> {
+    parse_records(line, ',').pop().unwrap_or_else(|| vec![String::new()])
+}
+
+/// Split text into records of fields. A quoted field may hold the
+/// delimiter and line breaks; a formula keeps the delimiters inside its
+/// parentheses, so `=SUM(A1,B1)` stays one field without quotes.
+pub fn parse_records(text: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    // Inside a formula: its open parentheses and whether in a string literal
+    let mut depth = 0usize;
+    let mut in_literal = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if current.starts_with('=') && !in_quotes {
+            match c {
+                '"' => in_literal = !in_literal,
+                '(' if !in_literal => depth += 1,
+                ')' if !in_literal => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            if in_literal || depth > 0 || c == '"' {
+                current.push(c);
+                continue;
+            }
+        }
+        if c == '"' {
+            if in_quotes && chars.peek() == Some(&'"') {
+                // Escaped quote
+                current.push('"');
+                chars.next();
+            } else {
+                in_quotes = !in_quotes;
+            }
+        } else if in_quotes {
+            current.push(c);
+        } else if c == delimiter {
+            fields.push(std::mem::take(&mut current));
+        } else if c == '\n' || c == '\r' {
+            if c == '\r' && chars.peek() == Some(&'\n') {
+                chars.next();
+            }
+            fields.push(std::mem::take(&mut current));
+            records.push(std::mem::take(&mut fields));
+        } else {
+            current.push(c);
+        }
+        if current.is_empty() {
+            depth = 0;
+            in_literal = false;
+        }
+    }
+    if !current.is_empty() || !fields.is_empty() {
+        fields.push(current);
+        records.push(fields);
+    }
+    records
+}
+
+/// Build a sheet from CSV text
+pub fn read(contents: &str) -> Sheet {
+    let mut sheet = Sheet::new();
+    for (row, record) in parse_records(contents, ',').into_iter().enumerate() {
+        for (col, field) in record.into_iter().enumerate() {
+            if !field.is_empty() {
+                sheet.set_cell(col, row, field);
+            }
+        }
+    }
+    sheet
+}
+
+/// Build a sheet from delimited text read the dialect's way
+pub fn read_with(contents: &str, dialect: &Dialect) -> Sheet {
+    let text = contents.splitn(dialect.skip + 1, '\n').nth(dialect.skip).unwrap_or("");
+    let delimiter = dialect.delimiter.unwrap_or_else(|| detect_delimiter(text));
+    let records = parse_records(text, delimiter);
+    let first = usize::from(dialect.no_header);
+    let style = NumberStyle { grouped: false, decimal_comma: dialect.decimal_comma };
+
+    let mut sheet = Sheet::new();
+    sheet.set_number_style(style);
+    if dialect.no_header {
+        let columns = records.iter().map(Vec::len).max().unwrap_or(0);
+        for col in 0..columns {
+            sheet.set_cell(col, 0, format!("Column {}", col + 1));
+        }
+    }
+    for (row, record) in records.into_iter().enumerate() {
+        for (col, field) in record.into_iter().enumerate() {
+            if !field.is_empty() {
+                sheet.set_typed(col, first + row, &field);
+            }
+        }
+    }
+    sheet
+}
+
+/// Displayed values of the used area as CSV text
+pub fn write(sheet: &Sheet) -> String {
+    let area = (0, 0, sheet.max_col().unwrap_or(0), sheet.max_row().unwrap_or(0));
+    write_with(sheet, area, ',', false)
+}
+
+/// The (min_col, min_row, max_col, max_row) area as delimited text: the
+/// displayed values, or what was typed (formulas included) with `formulas`.
+/// Numbers are written without their format or thousands separators, with
+/// the decimal mark in use, so they read back as numbers.
+pub fn write_with(sheet: &Sheet, (c1, r1, c2, r2): (usize, usize, usize, usize), delimiter: char, formulas: bool) -> String {
+    let plain = NumberStyle { grouped: false, ..sheet.number_style() };
+    let mut csv = String::new();
+    for row in r1..=r2 {
+        let mut row_values = Vec::new();
+        for col in c1..=c2 {
+            let value = if formulas {
+                sheet.get_cell_ref(col, row).map(|cell| plain.typed_input(cell)).unwrap_or_default()
+            } else {
+                match sheet.value(col, row) {
+                    CellValue::Number(n) => Cell::default().format_number_in(n, plain),
+                    _ => sheet.evaluate(col, row),
+                }
+            };
+            // Escape quotes and wrap in quotes if needed
+            if value.contains([delimiter, '"', '\n', '\r']) {
+                row_values.push(format!("\"{}\"", value.replace('"', "\"\"")));
+            } else {
+                row_values.push(value);
+            }
+        }
+        csv.push_str(&row_values.join(&delimiter.to_string()));
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line("a,b,,c"), vec!["a", "b", "", "c"]);
+        assert_eq!(parse_line("\"x, y\",\"say \"\"hi\"\"\""), vec!["x, y", "say \"hi\""]);
+        assert_eq!(parse_line(""), vec![""]);
+        assert_eq!(parse_line("=SUM(A1,B1),x"), vec!["=SUM(A1,B1)", "x"]);
+        assert_eq!(parse_line("=IF(A1=\"a,b\",1,2)"), vec!["=IF(A1=\"a,b\",1,2)"]);
+    }
+
+    #[test]
+    fn test_parse_records() {
+        let records = parse_records("a\t\"two\nlines\"\r\nb\t\"x\ty\"\n", '\t');
+        assert_eq!(records, vec![vec!["a", "two\nlines"], vec!["b", "x\ty"]]);
+        assert_eq!(parse_records("a,b\n\nc", ','), vec![vec!["a", "b"], vec![""], vec!["c"]]);
+        assert!(parse_records("", ',').is_empty());
+    }
+
+    #[test]
+    fn test_dialects() {
+        assert_eq!(detect_delimiter("a;b;c\n1;2,5;3\n"), ';');
+        assert_eq!(detect_delimiter("a\tb\n1\t2\n"), '\t');
+        assert_eq!(detect_delimiter("just text\n"), ',');
+
+        let mut dialect = Dialect::default();
+        for option in ["delim=pipe", "skip=2", "noheader"] {
+            dialect.set(option).unwrap();
+        }
+        assert!(dialect.set("delim=ab").is_err());
+        assert!(dialect.set("encoding=nonesuch").is_err());
+        let sheet = read_with("Report\n\nx|1\ny|2\n", &dialect);
+        assert_eq!(sheet.evaluate(0, 0), "Column 1");
+        assert_eq!(sheet.evaluate(1, 0), "Column 2");
+        assert_eq!(sheet.evaluate(0, 2), "y");
+        assert_eq!(sheet.evaluate(1, 1), "1");
+    }
+
+    #[test]
+    fn test_decode() {
+        // "表" in Shift_JIS
+        assert_eq!(decode(&[0x95, 0x5C], None), ("表".to_string(), "Shift_JIS"));
+        assert_eq!(decode("é".as_bytes(), None), ("é".to_string(), "UTF-8"));
+        assert_eq!(decode(&[0xEF, 0xBB, 0xBF, b'a'], None).0, "a");
+        assert_eq!(decode(&[0xE9], Encoding::for_label(b"latin1")), ("é".to_string(), "windows-1252"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sheet = read("Name,Amount\n\"Smith, J\",10\nLee,=B2*2\n");
+        assert_eq!(sheet.evaluate(0, 1), "Smith, J");
+        assert_eq!(sheet.evaluate(1, 2), "20");
+        assert_eq!(write(&sheet), "Name,Amount\n\"Smith, J\",10\nLee,20\n");
+        assert_eq!(write_with(&sheet, (0, 1, 1, 2), ';', true), "Smith, J;10\nLee;=B2*2\n");
+        assert_eq!(write_with(&sheet, (1, 0, 1, 0), '\t', false), "Amount\n");
+
+        // With a decimal comma the cells keep a decimal point
+        let dialect = Dialect { delimiter: Some(';'), decimal_comma: true, ..Dialect::default() };
+        let mut sheet = read_with("x;2,5\ny;=B1*2\n", &dialect);
+        assert_eq!(sheet.get_cell(1, 0).raw_input, "2.5");
+        assert_eq!(sheet.evaluate(1, 1), "5");
+        sheet.set_number_style(NumberStyle { grouped: true, decimal_comma: true });
+        assert_eq!(write_with(&sheet, (0, 0, 1, 1), ';', false), "x;2,5\ny;5\n");
+        assert_eq!(write_with(&sheet, (1, 0, 1, 0), ';', true), "2,5\n");
+
+        // A number with a decimal point is text there
+        let sheet = read_with("z;1.234\n", &dialect);
+        assert_eq!(sheet.value(1, 0), CellValue::Text("1.234".to_string()));
+        assert_eq!(write_with(&sheet, (1, 0, 1, 0), ';', true), "1.234\n");
+    }
+}
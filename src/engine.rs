@@ -1,15 +1,187 @@
-use std::collections::{HashMap, HashSet};
-use crate::cell::{Cell, CellValue, CellError};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use crate::cell::{self, Cell, CellValue, CellError};
 use crate::formula;
 
+/// Maximum nesting of sub-expressions and cell references followed during one
+/// evaluation. Deeper formulas evaluate to #NUM! instead of overflowing the stack.
+/// Each level costs a few KB of stack in debug builds, so callers evaluating
+/// untrusted sheets should run on a thread with a large stack (the TUI uses 64MB).
+pub const MAX_EVAL_DEPTH: usize = 4096;
+
+/// Largest range (in cells) a single range argument may cover
+pub const MAX_RANGE_CELLS: usize = 256 * 10000;
+
+/// Parse `input` as if it were typed into a cell and evaluate it against `cells`.
+///
+/// This is the panic-free entry point for arbitrary user input: malformed
+/// formulas yield an `Err` with the error text instead of aborting.
+pub fn evaluate_input(cells: &HashMap<(usize, usize), Cell>, input: &str) -> Result<CellValue, String> {
+    match cell::parse_input(input) {
+        CellValue::Formula(f) => Engine::new(cells).evaluate_formula(&f),
+        value => Ok(value),
+    }
+}
+
+/// Functions whose result is an array. A formula that is just a call to one
+/// of them spills: its cell shows the first value and the rest fill the
+/// empty cells to the right and below, or the cell shows #SPILL! when any
+/// of those cells has content.
+const ARRAY_FUNCTIONS: [&str; 4] = ["FILTER", "UNIQUE", "SORT", "TREND"];
+
+/// A formula function defined outside the engine, by a script. It gets
+/// the values of each argument (a range's row by row) and returns its
+/// result, or an error such as "#VALUE!".
+pub type UserFunction = Arc<dyn Fn(&[Vec<CellValue>]) -> Result<CellValue, String> + Send + Sync>;
+
+// Functions added with register_function, by upper-case name
+static USER_FUNCTIONS: RwLock<BTreeMap<String, UserFunction>> = RwLock::new(BTreeMap::new());
+
+/// Make a function usable in formulas, replacing one of the same name.
+/// Built-in functions cannot be replaced.
+pub fn register_function(name: &str, function: UserFunction) {
+    if let Ok(mut functions) = USER_FUNCTIONS.write() {
+        functions.insert(name.to_uppercase(), function);
+    }
+}
+
+/// Names of the functions added with register_function
+pub fn user_functions() -> Vec<String> {
+    USER_FUNCTIONS.read().map(|functions| functions.keys().cloned().collect()).unwrap_or_default()
+}
+
+fn user_function(name: &str) -> Option<UserFunction> {
+    USER_FUNCTIONS.read().ok()?.get(name).cloned()
+}
+
+/// Values of a range or array function result, row by row
+type Array = Vec<Vec<CellValue>>;
+
+/// Whether a formula spills its result (see `ARRAY_FUNCTIONS`)
+pub fn is_array_formula(formula: &str) -> bool {
+    formula.trim().strip_prefix('=').and_then(|expr| array_call(expr.trim())).is_some()
+}
+
+/// The name and arguments of an array function call making up all of `expr`
+fn array_call(expr: &str) -> Option<(String, &str)> {
+    let paren = expr.find('(')?;
+    let name = expr[..paren].trim().to_uppercase();
+    if !ARRAY_FUNCTIONS.contains(&name.as_str()) || find_matching_paren(expr, paren)? != expr.len() - 1 {
+        return None;
+    }
+    Some((name, &expr[paren + 1..expr.len() - 1]))
+}
+
 pub struct Engine<'a> {
     cells: &'a HashMap<(usize, usize), Cell>,
     eval_stack: HashSet<(usize, usize)>,
+    depth: usize,
+    // Cells holding array formulas, found on first need
+    anchors: Option<Vec<(usize, usize)>>,
+    // Results of the array formulas evaluated so far, or the error they show
+    arrays: HashMap<(usize, usize), Result<Array, CellValue>>,
+    // Spilled values worked out beforehand, used instead of the two above
+    spills: Option<&'a HashMap<(usize, usize), CellValue>>,
 }
 
 impl<'a> Engine<'a> {
     pub fn new(cells: &'a HashMap<(usize, usize), Cell>) -> Self {
-        Engine { cells, eval_stack: HashSet::new() }
+        Engine { cells, eval_stack: HashSet::new(), depth: 0, anchors: None, arrays: HashMap::new(), spills: None }
+    }
+
+    /// Use the values from an earlier `spills()` for array formulas and the
+    /// cells they spill into
+    pub fn with_spills(mut self, spills: &'a HashMap<(usize, usize), CellValue>) -> Self {
+        self.spills = Some(spills);
+        self
+    }
+
+    /// Values of every array formula's cell and the cells it spills into
+    pub fn spills(&mut self) -> HashMap<(usize, usize), CellValue> {
+        let mut spills = HashMap::new();
+        for (col, row) in self.anchors() {
+            if let Err(error) = self.spill(col, row) {
+                spills.insert((col, row), error);
+            } else if let Some(Ok(array)) = self.arrays.get(&(col, row)) {
+                for (i, values) in array.iter().enumerate() {
+                    for (j, value) in values.iter().enumerate() {
+                        // Where two spills overlap the first one wins
+                        spills.entry((col + j, row + i)).or_insert_with(|| value.clone());
+                    }
+                }
+            }
+        }
+        spills
+    }
+
+    fn anchors(&mut self) -> Vec<(usize, usize)> {
+        let cells = self.cells;
+        self.anchors
+            .get_or_insert_with(|| {
+                let mut anchors: Vec<(usize, usize)> = cells
+                    .iter()
+                    .filter(|(_, cell)| matches!(&cell.value, CellValue::Formula(f) if is_array_formula(f)))
+                    .map(|(&pos, _)| pos)
+                    .collect();
+                anchors.sort();
+                anchors
+            })
+            .clone()
+    }
+
+    /// Evaluate the array formula in a cell once, and return the size of its
+    /// result as (rows, columns)
+    fn spill(&mut self, col: usize, row: usize) -> Result<(usize, usize), CellValue> {
+        if !self.arrays.contains_key(&(col, row)) {
+            let cells = self.cells;
+            let Some(CellValue::Formula(f)) = cells.get(&(col, row)).map(|cell| &cell.value) else {
+                return Err(CellValue::Empty);
+            };
+            self.eval_stack.insert((col, row));
+            let result = self.evaluate_array(f.trim().trim_start_matches('='));
+            self.eval_stack.remove(&(col, row));
+            let result = match result {
+                Ok(array) if array.is_empty() || array[0].is_empty() => Err(CellValue::Error(CellError::NA)),
+                Ok(array) => {
+                    let blocked = array.iter().enumerate().any(|(i, values)| {
+                        (0..values.len()).any(|j| {
+                            (i, j) != (0, 0)
+                                && cells.get(&(col + j, row + i)).is_some_and(|cell| cell.value != CellValue::Empty)
+                        })
+                    });
+                    if blocked { Err(CellValue::Error(CellError::Spill)) } else { Ok(array) }
+                }
+                Err(e) => Err(error_value(&e)),
+            };
+            self.arrays.insert((col, row), result);
+        }
+        match &self.arrays[&(col, row)] {
+            Ok(array) => Ok((array.len(), array[0].len())),
+            Err(error) => Err(error.clone()),
+        }
+    }
+
+    /// Value of an array formula's cell or a cell it spills into
+    fn spilled(&mut self, col: usize, row: usize) -> Option<CellValue> {
+        if let Some(spills) = self.spills {
+            return spills.get(&(col, row)).cloned();
+        }
+        for (c, r) in self.anchors() {
+            if c > col || r > row || self.eval_stack.contains(&(c, r)) {
+                continue;
+            }
+            match self.spill(c, r) {
+                Ok((rows, cols)) if row - r < rows && col - c < cols => {
+                    if let Some(Ok(array)) = self.arrays.get(&(c, r)) {
+                        return Some(array[row - r][col - c].clone());
+                    }
+                }
+                Err(error) if (c, r) == (col, row) => return Some(error),
+                _ => {}
+            }
+        }
+        None
     }
 
     pub fn evaluate_formula(&mut self, formula_str: &str) -> Result<CellValue, String> {
@@ -26,13 +198,17 @@ impl<'a> Engine<'a> {
         }
         let cell = self.cells.get(&(col, row));
         match cell {
-            None => Ok(CellValue::Number(0.0)),
+            None => match self.spilled(col, row) {
+                None | Some(CellValue::Empty) => Ok(CellValue::Number(0.0)),
+                Some(value) => Ok(value),
+            },
             Some(cell) => match &cell.value {
                 CellValue::Empty => Ok(CellValue::Number(0.0)),
                 CellValue::Number(n) => Ok(CellValue::Number(*n)),
                 CellValue::Text(s) => Ok(CellValue::Text(s.clone())),
                 CellValue::Boolean(b) => Ok(CellValue::Boolean(*b)),
                 CellValue::Error(e) => Ok(CellValue::Error(e.clone())),
+                CellValue::Formula(f) if is_array_formula(f) => Ok(self.spilled(col, row).unwrap_or(CellValue::Empty)),
                 CellValue::Formula(f) => {
                     self.eval_stack.insert((col, row));
                     let result = self.evaluate_formula(f);
@@ -44,6 +220,16 @@ impl<'a> Engine<'a> {
     }
 
     fn evaluate_expr(&mut self, expr: &str) -> Result<CellValue, String> {
+        if self.depth >= MAX_EVAL_DEPTH {
+            return Err("#NUM!".to_string());
+        }
+        self.depth += 1;
+        let result = self.evaluate_expr_inner(expr);
+        self.depth -= 1;
+        result
+    }
+
+    fn evaluate_expr_inner(&mut self, expr: &str) -> Result<CellValue, String> {
         let expr = expr.trim();
         if let Some(result) = self.try_function(expr)? { return Ok(result); }
         if expr.starts_with('(') {
@@ -67,14 +253,14 @@ impl<'a> Engine<'a> {
             if pos > 0 {
                 let left = self.evaluate_expr(&expr[..pos])?;
                 let right = self.evaluate_expr(&expr[pos + 1..])?;
-                let op = expr.chars().nth(pos).unwrap();
+                let op = expr.as_bytes()[pos] as char;
                 return arithmetic(left, right, op);
             }
         }
         if let Some(pos) = find_operator_rtl(expr, &['*', '/']) {
             let left = self.evaluate_expr(&expr[..pos])?;
             let right = self.evaluate_expr(&expr[pos + 1..])?;
-            let op = expr.chars().nth(pos).unwrap();
+            let op = expr.as_bytes()[pos] as char;
             return arithmetic(left, right, op);
         }
         if let Some(pos) = find_operator_rtl(expr, &['^']) {
@@ -82,8 +268,8 @@ impl<'a> Engine<'a> {
             let right = self.evaluate_expr(&expr[pos + 1..])?;
             return power(left, right);
         }
-        if expr.starts_with('-') {
-            let val = self.evaluate_expr(&expr[1..])?;
+        if let Some(operand) = expr.strip_prefix('-') {
+            let val = self.evaluate_expr(operand)?;
             return match val {
                 CellValue::Number(n) => Ok(CellValue::Number(-n)),
                 _ => Err("#VALUE!".to_string()),
@@ -142,7 +328,26 @@ impl<'a> Engine<'a> {
             "ISBLANK" => self.func_isblank(args_str)?,
             "ISNUMBER" => self.func_isnumber(args_str)?,
             "ISTEXT" => self.func_istext(args_str)?,
-            _ => return Ok(None),
+            "SPARKLINE" => self.func_sparkline(args_str)?,
+            "CORREL" => self.func_correl(args_str)?,
+            "SLOPE" => self.func_slope(args_str)?,
+            "INTERCEPT" => self.func_intercept(args_str)?,
+            "FORECAST.LINEAR" | "FORECAST" => self.func_forecast(args_str)?,
+            // Inside a larger formula an array stands for its first value
+            "FILTER" | "UNIQUE" | "SORT" | "TREND" => {
+                let array = self.array_function(&func_name, args_str)?;
+                array.first().and_then(|values| values.first()).cloned().unwrap_or(CellValue::Error(CellError::NA))
+            }
+            _ => match user_function(&func_name) {
+                Some(function) => {
+                    let mut args = Vec::new();
+                    for arg in split_args(args_str).iter().filter(|a| !a.is_empty()) {
+                        args.push(self.evaluate_array(arg)?.into_iter().flatten().collect());
+                    }
+                    function(&args)?
+                }
+                None => return Ok(None),
+            },
         };
         Ok(Some(result))
     }
@@ -152,6 +357,8 @@ impl<'a> Engine<'a> {
         if parts.len() == 2 {
             let (sc, sr, _, _) = formula::parse_cell_ref(parts[0]).ok_or("Invalid range")?;
             let (ec, er, _, _) = formula::parse_cell_ref(parts[1]).ok_or("Invalid range")?;
+            let size = (ec + 1).saturating_sub(sc).saturating_mul((er + 1).saturating_sub(sr));
+            if size > MAX_RANGE_CELLS { return Err("#REF!".to_string()); }
             let mut cells = Vec::new();
             for row in sr..=er { for col in sc..=ec { cells.push((col, row)); } }
             Ok(cells)
@@ -161,6 +368,52 @@ impl<'a> Engine<'a> {
         } else { Err("Invalid range".to_string()) }
     }
 
+    /// Values of a range, an array function call or a single value. Empty
+    /// cells stay empty.
+    fn evaluate_array(&mut self, expr: &str) -> Result<Array, String> {
+        let expr = expr.trim();
+        if let Some((name, args)) = array_call(expr) {
+            return self.array_function(&name, args);
+        }
+        let (start, end) = expr.split_once(':').unwrap_or((expr, expr));
+        let Some(((sc, sr, _, _), (ec, er, _, _))) =
+            formula::parse_cell_ref(start.trim()).zip(formula::parse_cell_ref(end.trim()))
+        else {
+            // A single value
+            return Ok(vec![vec![self.evaluate_expr(expr)?]]);
+        };
+        let size = (ec + 1).saturating_sub(sc).saturating_mul((er + 1).saturating_sub(sr));
+        if size > MAX_RANGE_CELLS { return Err("#REF!".to_string()); }
+        let mut array = Vec::new();
+        for row in sr..=er {
+            let mut values = Vec::new();
+            for col in sc..=ec {
+                values.push(match self.cells.get(&(col, row)) {
+                    None => self.spilled(col, row).unwrap_or(CellValue::Empty),
+                    Some(cell) if cell.value == CellValue::Empty => CellValue::Empty,
+                    Some(_) => self.evaluate_cell(col, row)?,
+                });
+            }
+            array.push(values);
+        }
+        Ok(array)
+    }
+
+    fn array_function(&mut self, name: &str, args_str: &str) -> Result<Array, String> {
+        let args = split_args(args_str);
+        let Some(first) = args.first() else { return Err("#VALUE!".to_string()) };
+        if name == "TREND" {
+            return self.func_trend(&args);
+        }
+        let array = self.evaluate_array(first)?;
+        match name {
+            "FILTER" => self.func_filter(array, &args[1..]),
+            "UNIQUE" if args.len() == 1 => Ok(func_unique(array)),
+            "SORT" => self.func_sort(array, &args[1..]),
+            _ => Err("#VALUE!".to_string()),
+        }
+    }
+
     fn get_numeric_values(&mut self, args_str: &str) -> Result<Vec<f64>, String> {
         let mut values = Vec::new();
         for arg in split_args(args_str) {
@@ -175,11 +428,14 @@ impl<'a> Engine<'a> {
         Ok(values)
     }
 
-    fn matches_criteria(&mut self, col: usize, row: usize, criteria: &str) -> Result<bool, String> {
+    /// Whether a cell satisfies a COUNTIF-style criteria string
+    /// (">100", "<>0", "=Done", "Done", "42")
+    pub fn matches_criteria(&mut self, col: usize, row: usize, criteria: &str) -> Result<bool, String> {
         let val = self.evaluate_cell(col, row)?;
+        let criteria = criteria.strip_prefix('=').unwrap_or(criteria);
         for op in [">=", "<=", "<>", "!=", ">", "<"] {
-            if criteria.starts_with(op) {
-                let target: f64 = criteria[op.len()..].trim().parse().map_err(|_| "#VALUE!")?;
+            if let Some(rest) = criteria.strip_prefix(op) {
+                let target: f64 = rest.trim().parse().map_err(|_| "#VALUE!")?;
                 if let Ok(n) = to_number(&val) {
                     return Ok(match op {
                         ">=" => n >= target, "<=" => n <= target,
@@ -322,7 +578,7 @@ impl<'a> Engine<'a> {
         for row in min_row..=max_row {
             let cell_val = self.evaluate_cell(min_col, row)?;
             let matches = if exact { cell_eq(&lookup_val, &cell_val) } else { cell_lte(&cell_val, &lookup_val) };
-            if matches { return self.evaluate_cell(min_col + col_idx - 1, row); }
+            if matches { return self.evaluate_cell(offset(min_col, col_idx)?, row); }
         }
         Ok(CellValue::Error(CellError::NA))
     }
@@ -342,7 +598,7 @@ impl<'a> Engine<'a> {
         for col in min_col..=max_col {
             let cell_val = self.evaluate_cell(col, min_row)?;
             let matches = if exact { cell_eq(&lookup_val, &cell_val) } else { cell_lte(&cell_val, &lookup_val) };
-            if matches { return self.evaluate_cell(col, min_row + row_idx - 1); }
+            if matches { return self.evaluate_cell(col, offset(min_row, row_idx)?); }
         }
         Ok(CellValue::Error(CellError::NA))
     }
@@ -357,7 +613,7 @@ impl<'a> Engine<'a> {
         let min_col = range.iter().map(|(c, _)| *c).min().unwrap_or(0);
         let min_row = range.iter().map(|(_, r)| *r).min().unwrap_or(0);
         if row_num == 0 || col_num == 0 { return Ok(CellValue::Error(CellError::Value)); }
-        self.evaluate_cell(min_col + col_num - 1, min_row + row_num - 1)
+        self.evaluate_cell(offset(min_col, col_num)?, offset(min_row, row_num)?)
     }
 
     fn func_match(&mut self, args_str: &str) -> Result<CellValue, String> {
@@ -432,7 +688,7 @@ impl<'a> Engine<'a> {
 
     fn func_round(&mut self, args_str: &str) -> Result<CellValue, String> {
         let args = split_args(args_str);
-        let val = self.evaluate_expr(&args[0])?;
+        let val = self.evaluate_expr(args.first().ok_or("#VALUE!")?)?;
         let n = to_number(&val)?;
         let decimals = if args.len() > 1 { let v = self.evaluate_expr(&args[1])?; to_number(&v)? as i32 } else { 0 };
         let factor = 10f64.powi(decimals);
@@ -520,6 +776,221 @@ impl<'a> Engine<'a> {
     fn func_istext(&mut self, args_str: &str) -> Result<CellValue, String> {
         Ok(CellValue::Boolean(matches!(self.evaluate_expr(args_str)?, CellValue::Text(_))))
     }
+
+    /// SPARKLINE(range, ["bar"|"line"]): a mini-chart of the range as text.
+    /// Empty and non-numeric cells leave a gap.
+    fn func_sparkline(&mut self, args_str: &str) -> Result<CellValue, String> {
+        let args = split_args(args_str);
+        if args.is_empty() || args.len() > 2 { return Err("#VALUE!".to_string()); }
+        let mut values = Vec::new();
+        for (col, row) in self.parse_range(&args[0])? {
+            let value = if self.cells.contains_key(&(col, row)) { self.evaluate_cell(col, row)? } else { CellValue::Empty };
+            values.push(match value { CellValue::Number(n) => Some(n), _ => None });
+        }
+        let style = match args.get(1) {
+            Some(arg) => to_string(&self.evaluate_expr(arg)?).to_lowercase(),
+            None => "bar".to_string(),
+        };
+        match style.as_str() {
+            "bar" => Ok(CellValue::Text(sparkline_bars(&values))),
+            "line" => Ok(CellValue::Text(sparkline_line(&values))),
+            _ => Err("#VALUE!".to_string()),
+        }
+    }
+
+    /// Pairs of numbers at the same places in two ranges of the same size;
+    /// places where either is not a number are skipped
+    fn number_pairs(&mut self, first: &str, second: &str) -> Result<Vec<(f64, f64)>, String> {
+        let first: Vec<CellValue> = self.evaluate_array(first)?.into_iter().flatten().collect();
+        let second: Vec<CellValue> = self.evaluate_array(second)?.into_iter().flatten().collect();
+        if first.len() != second.len() { return Err("#N/A".to_string()); }
+        Ok(first.iter().zip(&second).filter_map(|pair| match pair {
+            (CellValue::Number(a), CellValue::Number(b)) => Some((*a, *b)),
+            _ => None,
+        }).collect())
+    }
+
+    /// Least-squares line through known_y and known_x as (slope, intercept)
+    fn fit_line(&mut self, known_y: &str, known_x: &str) -> Result<Option<(f64, f64)>, String> {
+        Ok(linear_fit(&self.number_pairs(known_x, known_y)?))
+    }
+
+    /// CORREL(range1, range2): Pearson correlation coefficient
+    fn func_correl(&mut self, args_str: &str) -> Result<CellValue, String> {
+        let args = split_args(args_str);
+        if args.len() != 2 { return Err("#VALUE!".to_string()); }
+        let pairs = self.number_pairs(&args[0], &args[1])?;
+        let n = pairs.len() as f64;
+        let (mean_a, mean_b) = (pairs.iter().map(|p| p.0).sum::<f64>() / n, pairs.iter().map(|p| p.1).sum::<f64>() / n);
+        let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+        for (a, b) in &pairs {
+            cov += (a - mean_a) * (b - mean_b);
+            var_a += (a - mean_a).powi(2);
+            var_b += (b - mean_b).powi(2);
+        }
+        if pairs.len() < 2 || var_a == 0.0 || var_b == 0.0 { return Ok(CellValue::Error(CellError::DivZero)); }
+        Ok(CellValue::Number(cov / (var_a * var_b).sqrt()))
+    }
+
+    /// SLOPE(known_y, known_x)
+    fn func_slope(&mut self, args_str: &str) -> Result<CellValue, String> {
+        let args = split_args(args_str);
+        if args.len() != 2 { return Err("#VALUE!".to_string()); }
+        Ok(match self.fit_line(&args[0], &args[1])? {
+            Some((slope, _)) => CellValue::Number(slope),
+            None => CellValue::Error(CellError::DivZero),
+        })
+    }
+
+    /// INTERCEPT(known_y, known_x)
+    fn func_intercept(&mut self, args_str: &str) -> Result<CellValue, String> {
+        let args = split_args(args_str);
+        if args.len() != 2 { return Err("#VALUE!".to_string()); }
+        Ok(match self.fit_line(&args[0], &args[1])? {
+            Some((_, intercept)) => CellValue::Number(intercept),
+            None => CellValue::Error(CellError::DivZero),
+        })
+    }
+
+    /// FORECAST.LINEAR(x, known_y, known_x): the fitted line's value at x
+    fn func_forecast(&mut self, args_str: &str) -> Result<CellValue, String> {
+        let args = split_args(args_str);
+        if args.len() != 3 { return Err("#VALUE!".to_string()); }
+        let x = to_number(&self.evaluate_expr(&args[0])?)?;
+        Ok(match self.fit_line(&args[1], &args[2])? {
+            Some((slope, intercept)) => CellValue::Number(intercept + slope * x),
+            None => CellValue::Error(CellError::DivZero),
+        })
+    }
+
+    /// TREND(known_y, known_x, [new_x]): the fitted line's values at new_x
+    /// (known_x by default), in new_x's shape
+    fn func_trend(&mut self, args: &[String]) -> Result<Array, String> {
+        if args.len() < 2 || args.len() > 3 { return Err("#VALUE!".to_string()); }
+        let (slope, intercept) = self.fit_line(&args[0], &args[1])?.ok_or("#DIV/0!")?;
+        let new_x = self.evaluate_array(args.get(2).unwrap_or(&args[1]))?;
+        Ok(new_x.into_iter().map(|values| values.into_iter().map(|x| match x {
+            CellValue::Number(x) => CellValue::Number(intercept + slope * x),
+            CellValue::Empty => CellValue::Empty,
+            _ => CellValue::Error(CellError::Value),
+        }).collect()).collect())
+    }
+
+    /// FILTER(range, condition, [if_empty]): the rows of the range where the
+    /// condition holds. The condition compares a column with a value, as in
+    /// `B2:B10>100`, or is a column of TRUE and FALSE.
+    fn func_filter(&mut self, array: Array, args: &[String]) -> Result<Array, String> {
+        if args.is_empty() || args.len() > 2 { return Err("#VALUE!".to_string()); }
+        let condition = args[0].trim();
+        let comparison = [">=", "<=", "<>", "!=", "=", ">", "<"]
+            .into_iter()
+            .find_map(|op| find_operator(condition, op).map(|pos| (pos, op)));
+        let (column, test) = match comparison {
+            Some((pos, op)) => (self.evaluate_array(&condition[..pos])?, Some((self.evaluate_expr(&condition[pos + op.len()..])?, op))),
+            None => (self.evaluate_array(condition)?, None),
+        };
+        if column.len() != array.len() || column.iter().any(|values| values.len() != 1) {
+            return Err("#VALUE!".to_string());
+        }
+        let keep = column.into_iter().map(|mut values| {
+            let value = values.remove(0);
+            match &test {
+                Some((target, op)) => matches!(compare(value, target.clone(), op), Ok(CellValue::Boolean(true))),
+                None => to_bool(&value).unwrap_or(false),
+            }
+        });
+        let rows: Array = array.into_iter().zip(keep).filter(|(_, keep)| *keep).map(|(values, _)| values).collect();
+        match args.get(1) {
+            Some(if_empty) if rows.is_empty() => Ok(vec![vec![self.evaluate_expr(if_empty)?]]),
+            _ if rows.is_empty() => Err("#N/A".to_string()),
+            _ => Ok(rows),
+        }
+    }
+
+    /// SORT(range, [column], [ascending]): the rows of the range ordered by
+    /// their value in `column` (1 by default), ascending unless FALSE or -1
+    /// is given. Blanks go last either way.
+    fn func_sort(&mut self, mut array: Array, args: &[String]) -> Result<Array, String> {
+        if args.len() > 2 { return Err("#VALUE!".to_string()); }
+        let index = match args.first() {
+            Some(arg) => to_number(&self.evaluate_expr(arg)?)?,
+            None => 1.0,
+        };
+        let width = array.first().map_or(0, Vec::len);
+        if index < 1.0 || index as usize > width { return Err("#VALUE!".to_string()); }
+        let index = index as usize - 1;
+        let ascending = match args.get(1).map(|arg| self.evaluate_expr(arg)).transpose()? {
+            Some(CellValue::Number(n)) => n > 0.0,
+            Some(value) => to_bool(&value)?,
+            None => true,
+        };
+        array.sort_by(|a, b| {
+            let (a, b) = (&a[index], &b[index]);
+            match (*a == CellValue::Empty, *b == CellValue::Empty) {
+                (false, false) if ascending => sort_order(a, b),
+                (false, false) => sort_order(b, a),
+                (a_empty, b_empty) => a_empty.cmp(&b_empty),
+            }
+        });
+        Ok(array)
+    }
+}
+
+/// UNIQUE(range): the distinct rows of the range in order of first
+/// appearance, comparing text without regard to case
+fn func_unique(array: Array) -> Array {
+    let mut seen = HashSet::new();
+    array
+        .into_iter()
+        .filter(|values| {
+            let key: Vec<String> = values
+                .iter()
+                .map(|value| match value {
+                    CellValue::Text(s) => format!("t{}", s.to_uppercase()),
+                    value => format!("v{}", to_string(value)),
+                })
+                .collect();
+            seen.insert(key)
+        })
+        .collect()
+}
+
+/// Least-squares line through (x, y) points as (slope, intercept); None
+/// with fewer than two points or no spread in x
+fn linear_fit(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let sxx: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    let sxy: f64 = points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    if points.len() < 2 || sxx == 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    Some((slope, mean_y - slope * mean_x))
+}
+
+/// Order of values in SORT: numbers, then text, then booleans, then errors
+fn sort_order(a: &CellValue, b: &CellValue) -> Ordering {
+    let rank = |value: &CellValue| match value {
+        CellValue::Number(_) => 0,
+        CellValue::Text(_) => 1,
+        CellValue::Boolean(_) => 2,
+        _ => 3,
+    };
+    match (a, b) {
+        (CellValue::Number(l), CellValue::Number(r)) => l.total_cmp(r),
+        (CellValue::Text(l), CellValue::Text(r)) => l.to_lowercase().cmp(&r.to_lowercase()),
+        (CellValue::Boolean(l), CellValue::Boolean(r)) => l.cmp(r),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// The error value for an evaluation error such as "#VALUE!"
+fn error_value(message: &str) -> CellValue {
+    use CellError::*;
+    let error = [DivZero, Value, Ref, Name, Num, NA, Cycle, Spill].into_iter().find(|e| e.to_string() == message);
+    CellValue::Error(error.unwrap_or(Value))
 }
 
 // Free functions
@@ -541,6 +1012,36 @@ fn split_args(args_str: &str) -> Vec<String> {
     args
 }
 
+/// Scale values to `0..levels` between their minimum and maximum (the middle
+/// level when they are all equal)
+fn sparkline_levels(values: &[Option<f64>], levels: usize) -> Vec<Option<usize>> {
+    let numbers = values.iter().flatten().copied();
+    let min = numbers.clone().fold(f64::INFINITY, f64::min);
+    let max = numbers.fold(f64::NEG_INFINITY, f64::max);
+    let top = (levels - 1) as f64;
+    values.iter().map(|v| v.map(|n| {
+        if max > min { ((n - min) / (max - min) * top).round() as usize } else { levels / 2 }
+    })).collect()
+}
+
+/// One block per value, from ▁ (minimum) to █ (maximum)
+fn sparkline_bars(values: &[Option<f64>]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    sparkline_levels(values, BARS.len()).iter().map(|l| l.map_or(' ', |l| BARS[l])).collect()
+}
+
+/// Braille dots, two values per character with four heights each
+fn sparkline_line(values: &[Option<f64>]) -> String {
+    // Dot bits of the left and right column, bottom row first
+    const LEFT: [u32; 4] = [0x40, 0x04, 0x02, 0x01];
+    const RIGHT: [u32; 4] = [0x80, 0x20, 0x10, 0x08];
+    sparkline_levels(values, 4).chunks(2).map(|pair| {
+        let mut bits = pair[0].map_or(0, |l| LEFT[l]);
+        if let Some(Some(l)) = pair.get(1) { bits |= RIGHT[*l]; }
+        char::from_u32(0x2800 + bits).unwrap_or(' ')
+    }).collect()
+}
+
 fn to_number(val: &CellValue) -> Result<f64, String> {
     match val {
         CellValue::Number(n) => Ok(*n),
@@ -591,20 +1092,23 @@ fn cell_lte(a: &CellValue, b: &CellValue) -> bool {
     }
 }
 
+/// `base + index - 1` for 1-based lookup offsets, failing with #REF! on overflow
+fn offset(base: usize, index: usize) -> Result<usize, String> {
+    base.checked_add(index - 1).ok_or_else(|| "#REF!".to_string())
+}
+
+// Operator scanners return byte offsets so callers can slice `expr` directly.
+
 fn find_operator(expr: &str, op: &str) -> Option<usize> {
     let mut depth = 0;
     let mut in_string = false;
-    let chars: Vec<char> = expr.chars().collect();
-    let op_chars: Vec<char> = op.chars().collect();
-    for i in 0..chars.len() {
-        if chars[i] == '"' { in_string = !in_string; }
+    for (i, c) in expr.char_indices() {
+        if c == '"' { in_string = !in_string; }
         else if !in_string {
-            if chars[i] == '(' { depth += 1; }
-            else if chars[i] == ')' { depth -= 1; }
-            else if depth == 0 && i + op_chars.len() <= chars.len() {
-                if chars[i..i + op_chars.len()].iter().zip(op_chars.iter()).all(|(a, b)| a == b) {
-                    return Some(i);
-                }
+            if c == '(' { depth += 1; }
+            else if c == ')' { depth -= 1; }
+            else if depth == 0 && expr[i..].starts_with(op) {
+                return Some(i);
             }
         }
     }
@@ -614,15 +1118,16 @@ fn find_operator(expr: &str, op: &str) -> Option<usize> {
 fn find_operator_rtl(expr: &str, ops: &[char]) -> Option<usize> {
     let mut depth = 0;
     let mut in_string = false;
-    let chars: Vec<char> = expr.chars().collect();
-    for i in (0..chars.len()).rev() {
-        if chars[i] == '"' { in_string = !in_string; }
+    let chars: Vec<(usize, char)> = expr.char_indices().collect();
+    for k in (0..chars.len()).rev() {
+        let (i, c) = chars[k];
+        if c == '"' { in_string = !in_string; }
         else if !in_string {
-            if chars[i] == ')' { depth += 1; }
-            else if chars[i] == '(' { depth -= 1; }
-            else if depth == 0 && ops.contains(&chars[i]) {
-                if (chars[i] == '+' || chars[i] == '-') && i > 0 && chars[i - 1].to_ascii_uppercase() == 'E' { continue; }
-                if chars[i] == '-' && i == 0 { continue; }
+            if c == ')' { depth += 1; }
+            else if c == '(' { depth -= 1; }
+            else if depth == 0 && ops.contains(&c) {
+                if (c == '+' || c == '-') && k > 0 && chars[k - 1].1.eq_ignore_ascii_case(&'E') { continue; }
+                if c == '-' && i == 0 { continue; }
                 return Some(i);
             }
         }
@@ -632,7 +1137,7 @@ fn find_operator_rtl(expr: &str, ops: &[char]) -> Option<usize> {
 
 fn find_matching_paren(expr: &str, start: usize) -> Option<usize> {
     let mut depth = 0;
-    for (i, c) in expr.chars().enumerate().skip(start) {
+    for (i, c) in expr.char_indices().skip_while(|(i, _)| *i < start) {
         if c == '(' { depth += 1; }
         else if c == ')' { depth -= 1; if depth == 0 { return Some(i); } }
     }
@@ -675,3 +1180,101 @@ fn compare(left: CellValue, right: CellValue, op: &str) -> Result<CellValue, Str
     };
     Ok(CellValue::Boolean(result))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str) -> Result<CellValue, String> {
+        evaluate_input(&HashMap::new(), input)
+    }
+
+    #[test]
+    fn test_multibyte_operands() {
+        assert_eq!(eval("=数量*2"), Err("#NAME?".to_string()));
+        assert_eq!(eval("=\"数量\"&\"個\""), Ok(CellValue::Text("数量個".to_string())));
+        assert_eq!(eval("=LEN(\"日本語\")+1"), Ok(CellValue::Number(4.0)));
+        assert_eq!(eval("=(\"é\"&\"ß\")"), Ok(CellValue::Text("éß".to_string())));
+    }
+
+    #[test]
+    fn test_deep_nesting_is_an_error() {
+        // MAX_EVAL_DEPTH assumes a generous stack, like the one the TUI thread gets
+        let deep = std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(|| eval(&format!("={}1{}", "(".repeat(5000), ")".repeat(5000))))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(deep, Err("#NUM!".to_string()));
+        let shallow = format!("={}1{}", "(".repeat(50), ")".repeat(50));
+        assert_eq!(eval(&shallow), Ok(CellValue::Number(1.0)));
+    }
+
+    #[test]
+    fn test_long_reference_chain() {
+        let mut cells = HashMap::new();
+        cells.insert((0, 0), Cell::new("1".to_string(), CellValue::Number(1.0)));
+        for row in 1..100 {
+            let input = format!("=A{}+1", row);
+            cells.insert((0, row), Cell::new(input.clone(), CellValue::Formula(input)));
+        }
+        assert_eq!(Engine::new(&cells).evaluate_cell(0, 99), Ok(CellValue::Number(100.0)));
+    }
+
+    #[test]
+    fn test_regression_functions() {
+        // y = 2x + 1, with a blank and a text cell that are skipped
+        let mut cells = HashMap::new();
+        for (row, (x, y)) in [("1", "3"), ("2", "5"), ("3", "7"), ("", "9"), ("4", "x"), ("5", "11")].iter().enumerate() {
+            for (col, input) in [(0, x), (1, y)] {
+                if !input.is_empty() {
+                    cells.insert((col, row), Cell::new(input.to_string(), cell::parse_input(input)));
+                }
+            }
+        }
+        let eval = |formula: &str| Engine::new(&cells).evaluate_formula(formula);
+        assert_eq!(eval("=SLOPE(B1:B6, A1:A6)"), Ok(CellValue::Number(2.0)));
+        assert_eq!(eval("=INTERCEPT(B1:B6, A1:A6)"), Ok(CellValue::Number(1.0)));
+        assert_eq!(eval("=FORECAST.LINEAR(10, B1:B6, A1:A6)"), Ok(CellValue::Number(21.0)));
+        assert_eq!(eval("=ROUND(CORREL(A1:A6, B1:B6), 6)"), Ok(CellValue::Number(1.0)));
+        assert_eq!(eval("=TREND(B1:B6, A1:A6, 7)"), Ok(CellValue::Number(15.0)));
+        assert_eq!(eval("=SLOPE(B1:B6, A1:A5)"), Err("#N/A".to_string()));
+        assert_eq!(eval("=CORREL(A1:A1, B1:B1)"), Ok(CellValue::Error(CellError::DivZero)));
+    }
+
+    #[test]
+    fn test_user_function() {
+        register_function("test_total", Arc::new(|args| {
+            let total = args.iter().flatten().filter_map(|v| if let CellValue::Number(n) = v { Some(*n) } else { None }).sum();
+            Ok(CellValue::Number(total))
+        }));
+        let mut cells = HashMap::new();
+        for (row, input) in ["2", "x", "5"].iter().enumerate() {
+            cells.insert((0, row), Cell::new(input.to_string(), cell::parse_input(input)));
+        }
+        let eval = |formula: &str| Engine::new(&cells).evaluate_formula(formula);
+        assert_eq!(eval("=Test_Total(A1:A3, 10)*2"), Ok(CellValue::Number(34.0)));
+        assert_eq!(eval("=TEST_TOTAL()"), Ok(CellValue::Number(0.0)));
+        assert!(user_functions().contains(&"TEST_TOTAL".to_string()));
+        assert_eq!(eval("=NO_SUCH_FUNCTION(1)"), Err("#NAME?".to_string()));
+    }
+
+    #[test]
+    fn test_hostile_input_does_not_panic() {
+        let inputs = [
+            "=", "==", "=(", "=)", "=()", "=)(", "=\"", "=\"\"\"", "=-", "=--1", "=1+", "=*2",
+            "=SUM(", "=SUM()", "=ROUND()", "=MID(\"a\",0,1)", "=INDEX(A1:B2,1e300,1e300)",
+            "=VLOOKUP(1,A1:B2,1e300)", "=HLOOKUP(1,A1:B2,1e300)", "=SUM(A1:XFD1048576)",
+            "=ABCDEFGHIJKLMNOPQRSTUVWXYZ1", "=A99999999999999999999999", "=$", "=$$A$$1",
+            "=数量*2", "=１２３+１", "=🙂+🙂", "=\u{301}", "=IF(,,)", "=A1:A", "=:", "=1e", "=1e-",
+        ];
+        for input in inputs {
+            let _ = eval(input);
+            let _ = formula::adjust_formula(input, 3, -3);
+            let _ = formula::adjust_formula_for_row_insert(input, 0);
+            let _ = formula::adjust_formula_for_col_delete(input, 0);
+            let _ = formula::parse_cell_ref(input);
+        }
+    }
+}
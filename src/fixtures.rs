@@ -0,0 +1,179 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::formula;
+use crate::sheet::Sheet;
+
+/// Default location of the golden fixtures, relative to the repository root
+pub const DEFAULT_FIXTURE_DIR: &str = "tests/fixtures";
+
+/// Engine fixture file format
+///
+/// `cells` holds raw inputs exactly as typed into the sheet, `expect` holds
+/// the displayed (evaluated) value of each listed cell.
+#[derive(Deserialize)]
+pub struct Fixture {
+    #[serde(default)]
+    pub description: String,
+    pub cells: BTreeMap<String, String>,
+    pub expect: BTreeMap<String, String>,
+}
+
+/// A single cell whose evaluated value differs from the expectation
+pub struct Mismatch {
+    pub cell: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Outcome of running one fixture file
+pub struct FixtureResult {
+    pub path: PathBuf,
+    pub description: String,
+    pub checked: usize,
+    pub mismatches: Vec<Mismatch>,
+    pub error: Option<String>,
+}
+
+impl FixtureResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.mismatches.is_empty()
+    }
+}
+
+impl Fixture {
+    pub fn load(path: &Path) -> Result<Fixture, String> {
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&contents).map_err(|e| e.to_string())
+    }
+
+    /// Build a sheet from the fixture's cells
+    pub fn to_sheet(&self) -> Result<Sheet, String> {
+        let mut sheet = Sheet::new();
+        for (name, input) in &self.cells {
+            let (col, row, _, _) = formula::parse_cell_ref(name)
+                .ok_or_else(|| format!("invalid cell reference: {}", name))?;
+            sheet.set_cell(col, row, input.clone());
+        }
+        Ok(sheet)
+    }
+
+    /// Evaluate every expected cell and collect the mismatches
+    pub fn check(&self) -> Result<Vec<Mismatch>, String> {
+        let sheet = self.to_sheet()?;
+        let mut mismatches = Vec::new();
+        for (name, expected) in &self.expect {
+            let (col, row, _, _) = formula::parse_cell_ref(name)
+                .ok_or_else(|| format!("invalid cell reference: {}", name))?;
+            let actual = sheet.evaluate(col, row);
+            if &actual != expected {
+                mismatches.push(Mismatch {
+                    cell: name.clone(),
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+/// Run a single fixture file
+pub fn run_fixture(path: &Path) -> FixtureResult {
+    match Fixture::load(path) {
+        Ok(fixture) => {
+            let checked = fixture.expect.len();
+            let (mismatches, error) = match fixture.check() {
+                Ok(m) => (m, None),
+                Err(e) => (Vec::new(), Some(e)),
+            };
+            FixtureResult {
+                path: path.to_path_buf(),
+                description: fixture.description,
+                checked,
+                mismatches,
+                error,
+            }
+        }
+        Err(e) => FixtureResult {
+            path: path.to_path_buf(),
+            description: String::new(),
+            checked: 0,
+            mismatches: Vec::new(),
+            error: Some(e),
+        },
+    }
+}
+
+/// Run every `.json` fixture in `dir`, sorted by file name
+pub fn run_dir(dir: &Path) -> Result<Vec<FixtureResult>, String> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| format!("{}: {}", dir.display(), e))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().map(|e| e == "json").unwrap_or(false))
+        .collect();
+    paths.sort();
+    Ok(paths.iter().map(|p| run_fixture(p)).collect())
+}
+
+/// Entry point for `vicalc test-fixtures [dir]`. Returns the process exit code.
+pub fn run_cli(args: &[String]) -> i32 {
+    let dir = args.first().map(String::as_str).unwrap_or(DEFAULT_FIXTURE_DIR);
+    let results = match run_dir(Path::new(dir)) {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 2;
+        }
+    };
+
+    let mut failed = 0;
+    for result in &results {
+        let name = result.path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if result.passed() {
+            println!("ok    {} ({} cells)", name, result.checked);
+            continue;
+        }
+        failed += 1;
+        println!("FAIL  {}", name);
+        if !result.description.is_empty() {
+            println!("      {}", result.description);
+        }
+        if let Some(e) = &result.error {
+            println!("      error: {}", e);
+        }
+        for m in &result.mismatches {
+            println!("      {}: expected {:?}, got {:?}", m.cell, m.expected, m.actual);
+        }
+    }
+
+    println!("{} fixtures, {} passed, {} failed", results.len(), results.len() - failed, failed);
+    if failed > 0 { 1 } else { 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn golden_fixtures() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(DEFAULT_FIXTURE_DIR);
+        let results = run_dir(&dir).expect("fixture directory");
+        assert!(!results.is_empty(), "no fixtures found in {}", dir.display());
+
+        let mut report = String::new();
+        for result in results.iter().filter(|r| !r.passed()) {
+            report.push_str(&format!("{}:\n", result.path.display()));
+            if let Some(e) = &result.error {
+                report.push_str(&format!("  error: {}\n", e));
+            }
+            for m in &result.mismatches {
+                report.push_str(&format!("  {}: expected {:?}, got {:?}\n", m.cell, m.expected, m.actual));
+            }
+        }
+        assert!(report.is_empty(), "fixture failures:\n{}", report);
+    }
+}
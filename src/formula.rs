@@ -31,10 +31,7 @@ pub fn parse_cell_ref(cell_ref: &str) -> Option<(usize, usize, bool, bool)> {
         return None;
     }
 
-    // Convert column letters to number
-    let col = col_str.chars().fold(0usize, |acc, c| {
-        acc * 26 + (c as usize - 'A' as usize + 1)
-    }) - 1;
+    let col = col_from_letters(&col_str)?;
 
     let row: usize = row_str.parse().ok()?;
     if row == 0 {
@@ -44,6 +41,20 @@ pub fn parse_cell_ref(cell_ref: &str) -> Option<(usize, usize, bool, bool)> {
     Some((col, row - 1, col_abs, row_abs))
 }
 
+/// Convert upper-case column letters to a 0-indexed column (A -> 0, AA -> 26).
+/// Returns None instead of overflowing on absurdly long letter runs.
+pub fn col_from_letters(letters: &str) -> Option<usize> {
+    if letters.is_empty() {
+        return None;
+    }
+    let mut col = 0usize;
+    for c in letters.chars() {
+        let digit = (c as usize).checked_sub('A' as usize)? + 1;
+        col = col.checked_mul(26)?.checked_add(digit)?;
+    }
+    Some(col - 1)
+}
+
 /// Convert column index to letters (0 -> A, 25 -> Z, 26 -> AA)
 pub fn col_to_name(col: usize) -> String {
     let mut result = String::new();
@@ -70,6 +81,96 @@ pub fn cell_name_with_abs(col: usize, row: usize, col_abs: bool, row_abs: bool)
     format!("{}{}{}{}", col_prefix, col_to_name(col), row_prefix, row + 1)
 }
 
+/// A cell or range reference found in formula text
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormulaRef {
+    /// Referenced area, 0-indexed and inclusive: (min_col, min_row, max_col, max_row)
+    pub area: (usize, usize, usize, usize),
+    /// Byte range of the reference text within the formula
+    pub span: std::ops::Range<usize>,
+}
+
+impl FormulaRef {
+    pub fn contains(&self, col: usize, row: usize) -> bool {
+        let (c1, r1, c2, r2) = self.area;
+        col >= c1 && col <= c2 && row >= r1 && row <= r2
+    }
+}
+
+/// Scan a single reference (`A1`, `$A$1`) starting at byte `start`.
+/// Returns (col, row, end) with `end` the byte just past the reference.
+fn scan_ref_at(bytes: &[u8], start: usize) -> Option<(usize, usize, usize)> {
+    let mut i = start;
+    if bytes.get(i) == Some(&b'$') {
+        i += 1;
+    }
+    let col_start = i;
+    while i < bytes.len() && bytes[i].is_ascii_alphabetic() {
+        i += 1;
+    }
+    let letters = std::str::from_utf8(&bytes[col_start..i]).ok()?.to_ascii_uppercase();
+    if bytes.get(i) == Some(&b'$') {
+        i += 1;
+    }
+    let row_start = i;
+    while i < bytes.len() && bytes[i].is_ascii_digit() {
+        i += 1;
+    }
+    let row: usize = std::str::from_utf8(&bytes[row_start..i]).ok()?.parse().ok()?;
+    if row == 0 {
+        return None;
+    }
+    // A1( is a function call, A1B is some other identifier
+    if bytes.get(i).map(|b| b.is_ascii_alphanumeric() || *b == b'(' || *b == b'_').unwrap_or(false) {
+        return None;
+    }
+    Some((col_from_letters(&letters)?, row - 1, i))
+}
+
+/// Find every cell and range reference in a formula, in order of appearance.
+/// String literals, function names and numeric literals like `1E5` are skipped.
+pub fn find_references(formula: &str) -> Vec<FormulaRef> {
+    let bytes = formula.as_bytes();
+    let mut refs = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b == b'"' {
+            i += 1;
+            while i < bytes.len() && bytes[i] != b'"' {
+                i += 1;
+            }
+            i += 1;
+            continue;
+        }
+
+        if b == b'$' || b.is_ascii_alphabetic() {
+            let inside_word = i > 0 && (bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'.' || bytes[i - 1] == b'_');
+            if !inside_word {
+                if let Some((c1, r1, end)) = scan_ref_at(bytes, i) {
+                    let range_end = if bytes.get(end) == Some(&b':') { scan_ref_at(bytes, end + 1) } else { None };
+                    let (area, end) = match range_end {
+                        Some((c2, r2, end2)) => ((c1.min(c2), r1.min(r2), c1.max(c2), r1.max(r2)), end2),
+                        None => ((c1, r1, c1, r1), end),
+                    };
+                    refs.push(FormulaRef { area, span: i..end });
+                    i = end;
+                    continue;
+                }
+            }
+            // Skip the rest of this identifier
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'$' || bytes[i] == b'_' || bytes[i] == b'.') {
+                i += 1;
+            }
+            continue;
+        }
+        i += 1;
+    }
+
+    refs
+}
+
 /// Adjust a formula when copying/pasting
 #[allow(dead_code)]
 pub fn adjust_formula(formula: &str, col_offset: isize, row_offset: isize) -> String {
@@ -127,20 +228,20 @@ pub fn adjust_formula(formula: &str, col_offset: isize, row_offset: isize) -> St
         // Check if we found a valid cell reference
         if !col_str.is_empty() && !row_str.is_empty() {
             if let (Some(col), Some(row)) = (
-                Some(col_str.chars().fold(0usize, |acc, c| acc * 26 + (c as usize - 'A' as usize + 1)) - 1),
+                col_from_letters(&col_str),
                 row_str.parse::<usize>().ok().map(|r| r.saturating_sub(1))
             ) {
                 // Adjust reference
                 let new_col = if col_abs {
                     col
                 } else {
-                    ((col as isize) + col_offset).max(0) as usize
+                    (col as isize).saturating_add(col_offset).max(0) as usize
                 };
 
                 let new_row = if row_abs {
                     row
                 } else {
-                    ((row as isize) + row_offset).max(0) as usize
+                    (row as isize).saturating_add(row_offset).max(0) as usize
                 };
 
                 // Build adjusted reference
@@ -151,15 +252,13 @@ pub fn adjust_formula(formula: &str, col_offset: isize, row_offset: isize) -> St
                 if row_abs {
                     result.push('$');
                 }
-                result.push_str(&(new_row + 1).to_string());
+                result.push_str(&new_row.saturating_add(1).to_string());
                 continue;
             }
         }
 
         // Not a cell reference, output original characters
-        for j in ref_start..i {
-            result.push(chars[j]);
-        }
+        result.extend(&chars[ref_start..i]);
 
         if i < chars.len() && i == ref_start {
             result.push(chars[i]);
@@ -170,38 +269,212 @@ pub fn adjust_formula(formula: &str, col_offset: isize, row_offset: isize) -> St
     result
 }
 
+/// Adjust a formula for the cells of `area` moved by (col_offset,
+/// row_offset): a reference lying wholly inside the area follows the cells,
+/// absolute or not, and the rest are left as they are
+pub fn adjust_formula_for_move(
+    formula: &str,
+    area: (usize, usize, usize, usize),
+    col_offset: isize,
+    row_offset: isize,
+) -> String {
+    remap_references(formula, area, |col, row| {
+        ((col as isize + col_offset).max(0) as usize, (row as isize + row_offset).max(0) as usize)
+    })
+}
+
+/// Adjust a formula for the cells of `area` transposed about its top-left
+/// cell, as `adjust_formula_for_move` does for a move
+pub fn adjust_formula_for_transpose(formula: &str, area: (usize, usize, usize, usize)) -> String {
+    let (c1, r1, _, _) = area;
+    remap_references(formula, area, |col, row| (c1 + (row - r1), r1 + (col - c1)))
+}
+
+/// Rewrite the references lying wholly inside `area`, each end through `to`
+fn remap_references(
+    formula: &str,
+    (c1, r1, c2, r2): (usize, usize, usize, usize),
+    to: impl Fn(usize, usize) -> (usize, usize),
+) -> String {
+    let mut result = String::new();
+    let mut last = 0;
+    for reference in find_references(formula) {
+        let (rc1, rr1, rc2, rr2) = reference.area;
+        if rc1 < c1 || rc2 > c2 || rr1 < r1 || rr2 > r2 {
+            continue;
+        }
+        result.push_str(&formula[last..reference.span.start]);
+        let moved: Vec<String> = formula[reference.span.clone()]
+            .split(':')
+            .map(|part| {
+                let Some((col, row, _)) = scan_ref_at(part.as_bytes(), 0) else {
+                    return part.to_string();
+                };
+                let col_abs = part.starts_with('$');
+                let row_abs = part.trim_start_matches('$').contains('$');
+                let (col, row) = to(col, row);
+                cell_name_with_abs(col, row, col_abs, row_abs)
+            })
+            .collect();
+        result.push_str(&moved.join(":"));
+        last = reference.span.end;
+    }
+    result.push_str(&formula[last..]);
+    result
+}
+
 /// Adjust formula when a row is inserted
 /// All references at or below inserted_row are shifted down by 1
 pub fn adjust_formula_for_row_insert(formula: &str, inserted_row: usize) -> String {
-    adjust_formula_for_structure_change(formula, StructureChange::RowInsert(inserted_row))
+    adjust_formula_for_structure_change(formula, StructureChange::RowInsert(inserted_row, 1))
 }
 
 /// Adjust formula when a row is deleted
 /// References to deleted_row become #REF!, references below are shifted up
 pub fn adjust_formula_for_row_delete(formula: &str, deleted_row: usize) -> String {
-    adjust_formula_for_structure_change(formula, StructureChange::RowDelete(deleted_row))
+    adjust_formula_for_structure_change(formula, StructureChange::RowDelete(deleted_row, 1))
 }
 
 /// Adjust formula when a column is inserted
 /// All references at or to the right of inserted_col are shifted right by 1
 pub fn adjust_formula_for_col_insert(formula: &str, inserted_col: usize) -> String {
-    adjust_formula_for_structure_change(formula, StructureChange::ColInsert(inserted_col))
+    adjust_formula_for_structure_change(formula, StructureChange::ColInsert(inserted_col, 1))
 }
 
 /// Adjust formula when a column is deleted
 /// References to deleted_col become #REF!, references to the right are shifted left
 pub fn adjust_formula_for_col_delete(formula: &str, deleted_col: usize) -> String {
-    adjust_formula_for_structure_change(formula, StructureChange::ColDelete(deleted_col))
+    adjust_formula_for_structure_change(formula, StructureChange::ColDelete(deleted_col, 1))
+}
+
+/// Rows or columns inserted or deleted at an index, as (index, count), or
+/// one moved from one index to another (the ones in between closing up).
+/// Everything in a sheet that is keyed by position follows it through the
+/// methods below.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StructureChange {
+    RowInsert(usize, usize),
+    RowDelete(usize, usize),
+    ColInsert(usize, usize),
+    ColDelete(usize, usize),
+    RowMove(usize, usize),
+    ColMove(usize, usize),
+}
+
+impl StructureChange {
+    /// New index of a row, None if it was deleted
+    pub fn row(self, row: usize) -> Option<usize> {
+        match self {
+            StructureChange::RowInsert(at, count) => Some(if row >= at { row.saturating_add(count) } else { row }),
+            StructureChange::RowDelete(at, count) => shift_deleted(row, at, count),
+            StructureChange::RowMove(from, to) => Some(shift_moved(row, from, to)),
+            _ => Some(row),
+        }
+    }
+
+    /// New index of a column, None if it was deleted
+    pub fn col(self, col: usize) -> Option<usize> {
+        match self {
+            StructureChange::ColInsert(at, count) => Some(if col >= at { col.saturating_add(count) } else { col }),
+            StructureChange::ColDelete(at, count) => shift_deleted(col, at, count),
+            StructureChange::ColMove(from, to) => Some(shift_moved(col, from, to)),
+            _ => Some(col),
+        }
+    }
+
+    /// New bounds of the rows start..=end: a span grows when rows are
+    /// inserted inside it and shrinks when some of it is deleted. None if
+    /// every row of it was deleted. A row moved within a span leaves it as it is;
+    /// one moved out of or into it shrinks or grows it as deleting and
+    /// inserting it would.
+    pub fn rows(self, start: usize, end: usize) -> Option<(usize, usize)> {
+        match self {
+            StructureChange::RowDelete(at, count) => shrink_span(start, end, at, count),
+            StructureChange::RowMove(from, to) => move_span(start, end, from, to),
+            _ => Some((self.row(start)?, self.row(end)?)),
+        }
+    }
+
+    /// New bounds of the columns start..=end (see `rows`)
+    pub fn cols(self, start: usize, end: usize) -> Option<(usize, usize)> {
+        match self {
+            StructureChange::ColDelete(at, count) => shrink_span(start, end, at, count),
+            StructureChange::ColMove(from, to) => move_span(start, end, from, to),
+            _ => Some((self.col(start)?, self.col(end)?)),
+        }
+    }
 }
 
-enum StructureChange {
-    RowInsert(usize),
-    RowDelete(usize),
-    ColInsert(usize),
-    ColDelete(usize),
+fn shift_deleted(index: usize, at: usize, count: usize) -> Option<usize> {
+    if index < at {
+        Some(index)
+    } else if index - at < count {
+        None
+    } else {
+        Some(index - count)
+    }
 }
 
-fn adjust_formula_for_structure_change(formula: &str, change: StructureChange) -> String {
+fn shift_moved(index: usize, from: usize, to: usize) -> usize {
+    if index == from {
+        to
+    } else if from < to && (from + 1..=to).contains(&index) {
+        index - 1
+    } else if to < from && (to..from).contains(&index) {
+        index + 1
+    } else {
+        index
+    }
+}
+
+fn move_span(start: usize, end: usize, from: usize, to: usize) -> Option<(usize, usize)> {
+    let span = start..=end;
+    if span.contains(&from) && span.contains(&to) {
+        return Some((start, end));
+    }
+    // Deleted from where it was, then inserted where it goes; a span of
+    // just the moved row follows it
+    let Some((start, end)) = shrink_span(start, end, from, 1) else {
+        return Some((to, to));
+    };
+    let insert = |index: usize| if index >= to { index + 1 } else { index };
+    Some((insert(start), insert(end)))
+}
+
+fn shrink_span(start: usize, end: usize, at: usize, count: usize) -> Option<(usize, usize)> {
+    let deleted = at..at.saturating_add(count);
+    if deleted.contains(&start) && deleted.contains(&end) {
+        return None;
+    }
+    // An end among the deleted ones goes to the nearest one kept
+    let start = shift_deleted(start, at, count).unwrap_or(at);
+    let end = shift_deleted(end, at, count).unwrap_or(at.saturating_sub(1));
+    Some((start, end))
+}
+
+/// The formula of a column formula template for one row: `@` stands for
+/// the row's number, as in `=B@*C@`
+pub fn for_row(template: &str, row: usize) -> String {
+    let mut result = String::new();
+    let mut in_string = false;
+    for c in template.chars() {
+        match c {
+            '"' => {
+                in_string = !in_string;
+                result.push(c);
+            }
+            '@' if !in_string => result.push_str(&(row + 1).to_string()),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Adjust the references in a formula for an inserted or deleted row or
+/// column; references to a deleted one become #REF!. In a column formula
+/// (`:colformula`), a reference to the current row such as `B@` only has
+/// its column adjusted.
+pub fn adjust_formula_for_structure_change(formula: &str, change: StructureChange) -> String {
     let mut result = String::new();
     let mut i = 0;
     let chars: Vec<char> = formula.chars().collect();
@@ -222,6 +495,14 @@ fn adjust_formula_for_structure_change(formula: &str, change: StructureChange) -
             continue;
         }
 
+        // A range moved among keeps its bounds, one a row or column moves
+        // out of or into shrinks or grows
+        if let Some((range, next)) = moved_range(&chars, i, change) {
+            result.push_str(&range);
+            i = next;
+            continue;
+        }
+
         // Try to parse cell reference
         let ref_start = i;
         let mut col_abs = false;
@@ -241,6 +522,23 @@ fn adjust_formula_for_structure_change(formula: &str, change: StructureChange) -
             i += 1;
         }
 
+        // The current row of a column formula
+        if !col_str.is_empty() && chars.get(i) == Some(&'@') {
+            i += 1;
+            match col_from_letters(&col_str).map(|col| change.col(col)) {
+                Some(Some(new_col)) => {
+                    if col_abs {
+                        result.push('$');
+                    }
+                    result.push_str(&col_to_name(new_col));
+                    result.push('@');
+                }
+                Some(None) => result.push_str("#REF!"),
+                None => result.extend(&chars[ref_start..i]),
+            }
+            continue;
+        }
+
         // Handle $ for row
         if i < chars.len() && chars[i] == '$' {
             row_abs = true;
@@ -255,59 +553,24 @@ fn adjust_formula_for_structure_change(formula: &str, change: StructureChange) -
 
         // Check if we found a valid cell reference
         if !col_str.is_empty() && !row_str.is_empty() {
-            let col = col_str.chars().fold(0usize, |acc, c| acc * 26 + (c as usize - 'A' as usize + 1)) - 1;
-            if let Ok(row_1based) = row_str.parse::<usize>() {
+            if let (Some(col), Ok(row_1based)) = (col_from_letters(&col_str), row_str.parse::<usize>()) {
                 if row_1based > 0 {
                     let row = row_1based - 1;
                     
                     // Apply structure change
-                    let (new_col, new_row, is_ref_error) = match change {
-                        StructureChange::RowInsert(inserted_row) => {
-                            if row >= inserted_row {
-                                (col, row + 1, false)
-                            } else {
-                                (col, row, false)
-                            }
-                        }
-                        StructureChange::RowDelete(deleted_row) => {
-                            if row == deleted_row {
-                                (col, row, true)  // #REF!
-                            } else if row > deleted_row {
-                                (col, row - 1, false)
-                            } else {
-                                (col, row, false)
+                    match (change.col(col), change.row(row)) {
+                        (Some(new_col), Some(new_row)) => {
+                            // Build adjusted reference preserving $ markers
+                            if col_abs {
+                                result.push('$');
                             }
-                        }
-                        StructureChange::ColInsert(inserted_col) => {
-                            if col >= inserted_col {
-                                (col + 1, row, false)
-                            } else {
-                                (col, row, false)
+                            result.push_str(&col_to_name(new_col));
+                            if row_abs {
+                                result.push('$');
                             }
+                            result.push_str(&new_row.saturating_add(1).to_string());
                         }
-                        StructureChange::ColDelete(deleted_col) => {
-                            if col == deleted_col {
-                                (col, row, true)  // #REF!
-                            } else if col > deleted_col {
-                                (col - 1, row, false)
-                            } else {
-                                (col, row, false)
-                            }
-                        }
-                    };
-
-                    if is_ref_error {
-                        result.push_str("#REF!");
-                    } else {
-                        // Build adjusted reference preserving $ markers
-                        if col_abs {
-                            result.push('$');
-                        }
-                        result.push_str(&col_to_name(new_col));
-                        if row_abs {
-                            result.push('$');
-                        }
-                        result.push_str(&(new_row + 1).to_string());
+                        _ => result.push_str("#REF!"),
                     }
                     continue;
                 }
@@ -315,9 +578,7 @@ fn adjust_formula_for_structure_change(formula: &str, change: StructureChange) -
         }
 
         // Not a valid cell reference, output original characters
-        for j in ref_start..i {
-            result.push(chars[j]);
-        }
+        result.extend(&chars[ref_start..i]);
 
         if i < chars.len() && i == ref_start {
             result.push(chars[i]);
@@ -328,6 +589,48 @@ fn adjust_formula_for_structure_change(formula: &str, change: StructureChange) -
     result
 }
 
+/// A plain cell reference starting at `i`: column, row, whether each is
+/// absolute, and where it ends
+fn reference_at(chars: &[char], i: usize) -> Option<(usize, usize, bool, bool, usize)> {
+    let mut j = i;
+    let col_abs = chars.get(j) == Some(&'$');
+    j += usize::from(col_abs);
+    let col_start = j;
+    while chars.get(j).is_some_and(char::is_ascii_alphabetic) {
+        j += 1;
+    }
+    let col = col_from_letters(&chars[col_start..j].iter().collect::<String>().to_ascii_uppercase())?;
+    let row_abs = chars.get(j) == Some(&'$');
+    j += usize::from(row_abs);
+    let row_start = j;
+    while chars.get(j).is_some_and(char::is_ascii_digit) {
+        j += 1;
+    }
+    let row = chars[row_start..j].iter().collect::<String>().parse::<usize>().ok().filter(|&r| r > 0)?;
+    Some((col, row - 1, col_abs, row_abs, j))
+}
+
+/// The range starting at `i` written again for a moved row or column, and
+/// where it ends; None for anything else
+fn moved_range(chars: &[char], i: usize, change: StructureChange) -> Option<(String, usize)> {
+    if !matches!(change, StructureChange::RowMove(..) | StructureChange::ColMove(..)) {
+        return None;
+    }
+    let (c1, r1, c1_abs, r1_abs, colon) = reference_at(chars, i)?;
+    if chars.get(colon) != Some(&':') {
+        return None;
+    }
+    let (c2, r2, c2_abs, r2_abs, next) = reference_at(chars, colon + 1)?;
+    if c1 > c2 || r1 > r2 {
+        return None;
+    }
+    let ((c1, c2), (r1, r2)) = (change.cols(c1, c2)?, change.rows(r1, r2)?);
+    let name = |col: usize, row: usize, col_abs: bool, row_abs: bool| {
+        format!("{}{}{}{}", if col_abs { "$" } else { "" }, col_to_name(col), if row_abs { "$" } else { "" }, row + 1)
+    };
+    Some((format!("{}:{}", name(c1, r1, c1_abs, r1_abs), name(c2, r2, c2_abs, r2_abs)), next))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -355,6 +658,18 @@ mod tests {
         assert_eq!(adjust_formula("=$A$1+B1", 1, 1), "=$A$1+C2");
     }
 
+    #[test]
+    fn test_find_references() {
+        let refs = find_references("=SUM(A1:B3)+$C$2*\"D4\"+LOG10(2)+1E5");
+        assert_eq!(refs.len(), 2);
+        assert_eq!(refs[0].area, (0, 0, 1, 2));
+        assert_eq!(refs[0].span, 5..10);
+        assert_eq!(refs[1].area, (2, 1, 2, 1));
+        assert_eq!(find_references("=B3:A1")[0].area, (0, 0, 1, 2));
+        assert_eq!(find_references("=数量*A2")[0].area, (0, 1, 0, 1));
+        assert!(find_references("=\"A1").is_empty());
+    }
+
     #[test]
     fn test_row_insert() {
         // Insert at row 2 (0-indexed)
@@ -389,4 +704,59 @@ mod tests {
         assert_eq!(adjust_formula_for_col_delete("=B1", 1), "=#REF!"); // col 1 == 1
         assert_eq!(adjust_formula_for_col_delete("=C1", 1), "=B1");  // col 2 > 1
     }
+
+    #[test]
+    fn test_column_formula_template() {
+        assert_eq!(for_row("=B@*C@&\"@\"", 4), "=B5*C5&\"@\"");
+        let template = "=B@*C@*$F$1";
+        assert_eq!(adjust_formula_for_structure_change(template, StructureChange::ColInsert(2, 1)), "=B@*D@*$G$1");
+        assert_eq!(adjust_formula_for_structure_change(template, StructureChange::RowInsert(0, 1)), "=B@*C@*$F$2");
+        assert_eq!(adjust_formula_for_structure_change(template, StructureChange::ColDelete(1, 1)), "=#REF!*B@*$E$1");
+    }
+
+    #[test]
+    fn test_move_and_transpose() {
+        let area = (1, 1, 2, 2);
+        assert_eq!(adjust_formula_for_move("=B2+$C$3*A1", area, 2, 1), "=D3+$E$4*A1");
+        assert_eq!(adjust_formula_for_move("=SUM(B2:C3)+SUM(B2:D3)", area, 0, 5), "=SUM(B7:C8)+SUM(B2:D3)");
+        assert_eq!(adjust_formula_for_move("=\"B2\"&B2", area, -1, 0), "=\"B2\"&A2");
+        // B2:C3 turned over: C2 and B3 change places
+        assert_eq!(adjust_formula_for_transpose("=C2+B3+SUM(B2:C2)+D2", area), "=B3+C2+SUM(B2:B3)+D2");
+    }
+
+    #[test]
+    fn test_structure_change_spans() {
+        // Insert row 3 (index 2): spans below move, spans across it grow
+        assert_eq!(StructureChange::RowInsert(2, 1).rows(0, 1), Some((0, 1)));
+        assert_eq!(StructureChange::RowInsert(2, 1).rows(1, 4), Some((1, 5)));
+        assert_eq!(StructureChange::RowInsert(2, 1).rows(2, 4), Some((3, 5)));
+        // Delete row 3: spans shrink, a span of just that row disappears
+        assert_eq!(StructureChange::RowDelete(2, 1).rows(1, 4), Some((1, 3)));
+        assert_eq!(StructureChange::RowDelete(2, 1).rows(2, 4), Some((2, 3)));
+        assert_eq!(StructureChange::RowDelete(2, 1).rows(0, 2), Some((0, 1)));
+        assert_eq!(StructureChange::RowDelete(2, 1).rows(2, 2), None);
+        // Several rows at once
+        assert_eq!(StructureChange::RowInsert(2, 3).rows(1, 4), Some((1, 7)));
+        assert_eq!(StructureChange::RowDelete(2, 3).rows(0, 9), Some((0, 6)));
+        assert_eq!(StructureChange::RowDelete(2, 3).rows(3, 9), Some((2, 6)));
+        assert_eq!(StructureChange::RowDelete(2, 3).rows(0, 3), Some((0, 1)));
+        assert_eq!(StructureChange::RowDelete(2, 3).rows(2, 4), None);
+        assert_eq!(StructureChange::RowDelete(2, 3).row(5), Some(2));
+        assert_eq!(adjust_formula_for_structure_change("=A2+A4+A6", StructureChange::RowDelete(2, 3)), "=A2+#REF!+A3");
+        // Row changes leave columns alone and the other way round
+        assert_eq!(StructureChange::RowDelete(2, 1).cols(2, 2), Some((2, 2)));
+        assert_eq!(StructureChange::ColDelete(0, 1).cols(0, 3), Some((0, 2)));
+        assert_eq!(StructureChange::ColDelete(0, 1).row(0), Some(0));
+        assert_eq!(StructureChange::ColDelete(0, 1).col(0), None);
+        // Move row 2 to row 5: rows 3 to 5 close up
+        let change = StructureChange::RowMove(1, 4);
+        assert_eq!((1..6).map(|r| change.row(r).unwrap()).collect::<Vec<_>>(), vec![4, 1, 2, 3, 5]);
+        assert_eq!(StructureChange::RowMove(4, 1).row(1), Some(2));
+        assert_eq!(adjust_formula_for_structure_change("=A2+SUM(B1:B5)", change), "=A5+SUM(B1:B5)");
+        // Moved out of a span it shrinks, moved into one it grows
+        assert_eq!(change.rows(0, 2), Some((0, 1)));
+        assert_eq!(StructureChange::RowMove(0, 3).rows(2, 4), Some((1, 4)));
+        assert_eq!(change.rows(1, 1), Some((4, 4)));
+        assert_eq!(StructureChange::ColMove(3, 0).cols(0, 4), Some((0, 4)));
+    }
 }
@@ -0,0 +1,112 @@
+//! Function menu for formula entry. With `:set funcmenu=on`, `=` on an
+//! empty cell opens a small menu of functions by category; Enter puts the
+//! chosen one in the edit buffer with a place to fill in for each argument,
+//! which Tab goes through as it does for snippets.
+//!
+//! Left and Right (Tab, Shift-Tab) change the category, Up and Down the
+//! function. Esc closes the menu and leaves the `=` to type a formula by
+//! hand; so does any other key, which is then typed as usual.
+
+use crossterm::event::{KeyCode, KeyEvent};
+
+use crate::App;
+
+/// A function: its name, the template inserted (`|` marks each place to
+/// fill in) and what it does
+pub type Function = (&'static str, &'static str, &'static str);
+
+pub const CATEGORIES: &[(&str, &[Function])] = &[
+    (
+        "Math",
+        &[
+            ("SUM", "=SUM(|)", "Total of a range"),
+            ("AVERAGE", "=AVERAGE(|)", "Mean of a range"),
+            ("COUNT", "=COUNT(|)", "How many numbers"),
+            ("MIN", "=MIN(|)", "Smallest value"),
+            ("MAX", "=MAX(|)", "Largest value"),
+            ("ROUND", "=ROUND(|, |)", "Round to some digits"),
+            ("ABS", "=ABS(|)", "Value without its sign"),
+            ("SUMIF", "=SUMIF(|, |, |)", "Total where a condition holds"),
+        ],
+    ),
+    (
+        "Text",
+        &[
+            ("LEFT", "=LEFT(|, |)", "First characters"),
+            ("RIGHT", "=RIGHT(|, |)", "Last characters"),
+            ("MID", "=MID(|, |, |)", "Characters from a position"),
+            ("LEN", "=LEN(|)", "Number of characters"),
+            ("TRIM", "=TRIM(|)", "Without extra spaces"),
+            ("UPPER", "=UPPER(|)", "In capitals"),
+            ("LOWER", "=LOWER(|)", "In small letters"),
+            ("CONCAT", "=CONCAT(|, |)", "Texts joined together"),
+        ],
+    ),
+    (
+        "Lookup",
+        &[
+            ("VLOOKUP", "=VLOOKUP(|, |, |, FALSE)", "Find a row, take a column"),
+            ("HLOOKUP", "=HLOOKUP(|, |, |, FALSE)", "Find a column, take a row"),
+            ("INDEX", "=INDEX(|, |, |)", "Value at a row and column"),
+            ("MATCH", "=MATCH(|, |, 0)", "Position of a value"),
+            ("FILTER", "=FILTER(|, |)", "Rows where a condition holds"),
+            ("UNIQUE", "=UNIQUE(|)", "Distinct rows"),
+        ],
+    ),
+    (
+        "Logic",
+        &[
+            ("IF", "=IF(|, |, |)", "One value or another"),
+            ("AND", "=AND(|, |)", "TRUE if all are"),
+            ("OR", "=OR(|, |)", "TRUE if any is"),
+            ("NOT", "=NOT(|)", "The opposite"),
+            ("IFERROR", "=IFERROR(|, |)", "A fallback for an error"),
+            ("ISBLANK", "=ISBLANK(|)", "TRUE if empty"),
+        ],
+    ),
+];
+
+/// The open menu: the category shown and the function picked in it
+#[derive(Clone, Copy, Default)]
+pub struct FuncMenu {
+    pub category: usize,
+    pub index: usize,
+}
+
+impl FuncMenu {
+    pub fn functions(&self) -> &'static [Function] {
+        CATEGORIES[self.category].1
+    }
+}
+
+/// Handle a key while the menu is open. False if it closed the menu
+/// without using the key, which then goes to the edit buffer.
+pub fn handle_key(app: &mut App, key: KeyEvent) -> bool {
+    let Some(menu) = app.func_menu.as_mut() else {
+        return false;
+    };
+    let count = CATEGORIES.len();
+    match key.code {
+        KeyCode::Right | KeyCode::Tab => {
+            menu.category = (menu.category + 1) % count;
+            menu.index = 0;
+        }
+        KeyCode::Left | KeyCode::BackTab => {
+            menu.category = (menu.category + count - 1) % count;
+            menu.index = 0;
+        }
+        KeyCode::Down => menu.index = (menu.index + 1) % menu.functions().len(),
+        KeyCode::Up => menu.index = (menu.index + menu.functions().len() - 1) % menu.functions().len(),
+        KeyCode::Enter => {
+            let (_, template, _) = menu.functions()[menu.index];
+            app.func_menu = None;
+            app.insert_template(String::new(), template, String::new());
+        }
+        KeyCode::Esc => app.func_menu = None,
+        _ => {
+            app.func_menu = None;
+            return false;
+        }
+    }
+    true
+}
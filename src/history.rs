@@ -0,0 +1,251 @@
+//! Undo and redo history.
+//!
+//! Every undoable change pushes a snapshot of the sheet taken before it.
+//! Instead of a fixed number of entries, the oldest snapshots are dropped
+//! once the undo history outgrows a memory budget (`:set undomemory=64M`),
+//! so a huge sheet keeps fewer levels than a small one.
+//!
+//! Each entry is labeled with the change that followed it (`:e data.csv`,
+//! `paste`) for `:undolist`, and remembers the file the sheet was open as:
+//! undoing past `:e` or `:clear` goes back to the previous file's contents
+//! under its own name, so `:w` writes them where they came from.
+
+use std::time::SystemTime;
+
+use crate::formula;
+use crate::sheet::Sheet;
+
+/// Default for `undomemory`
+pub const DEFAULT_BUDGET: usize = 64 * 1024 * 1024;
+
+pub struct Entry {
+    pub sheet: Sheet,
+    /// The file the sheet was open as
+    pub file: Option<String>,
+    /// The change made from here, as `:e data.csv` or `paste`
+    pub label: String,
+    /// When the snapshot was taken
+    pub time: SystemTime,
+    /// Estimated memory use in bytes
+    pub size: usize,
+}
+
+impl Entry {
+    fn new(sheet: Sheet, file: Option<String>, label: String) -> Self {
+        let size = std::mem::size_of::<Sheet>() + sheet.heap_size();
+        Entry { sheet, file, label, time: SystemTime::now(), size }
+    }
+}
+
+pub struct History {
+    undo: Vec<Entry>,
+    redo: Vec<Entry>,
+    budget: usize,
+    // Bumped on every change, undo and redo
+    generation: u64,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History { undo: Vec::new(), redo: Vec::new(), budget: DEFAULT_BUDGET, generation: 0 }
+    }
+}
+
+impl History {
+    /// Record the state before a change, open as `file`, and what the
+    /// change is; clears the redo history
+    pub fn push(&mut self, sheet: Sheet, file: Option<String>, label: &str) {
+        self.undo.push(Entry::new(sheet, file, label.to_string()));
+        self.redo.clear();
+        self.generation += 1;
+        self.enforce_budget();
+    }
+
+    /// Step back from `current`, returning the entry to restore; its label
+    /// names the change undone
+    pub fn undo(&mut self, current: Sheet, file: Option<String>) -> Option<Entry> {
+        let entry = self.undo.pop()?;
+        self.redo.push(Entry::new(current, file, entry.label.clone()));
+        self.generation += 1;
+        Some(entry)
+    }
+
+    /// Step forward from `current`, returning the entry to restore; its
+    /// label names the change redone
+    pub fn redo(&mut self, current: Sheet, file: Option<String>) -> Option<Entry> {
+        let entry = self.redo.pop()?;
+        self.undo.push(Entry::new(current, file, entry.label.clone()));
+        self.generation += 1;
+        Some(entry)
+    }
+
+    /// Changes to the sheet so far; differs from an earlier value once the
+    /// sheet has been edited since
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Undo entries, oldest first
+    pub fn undo_entries(&self) -> &[Entry] {
+        &self.undo
+    }
+
+    /// Redo entries, the next one to redo last
+    pub fn redo_entries(&self) -> &[Entry] {
+        &self.redo
+    }
+
+    /// Estimated memory use of the undo and redo history
+    pub fn memory(&self) -> (usize, usize) {
+        let total = |entries: &[Entry]| entries.iter().map(|e| e.size).sum();
+        (total(&self.undo), total(&self.redo))
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Change the memory budget, dropping old entries that no longer fit.
+    /// Returns how many were dropped.
+    pub fn set_budget(&mut self, budget: usize) -> usize {
+        self.budget = budget;
+        self.enforce_budget()
+    }
+
+    /// Drop the oldest entries while over budget, always keeping the latest
+    fn enforce_budget(&mut self) -> usize {
+        let mut total: usize = self.undo.iter().map(|e| e.size).sum();
+        let mut dropped = 0;
+        while total > self.budget && self.undo.len() - dropped > 1 {
+            total -= self.undo[dropped].size;
+            dropped += 1;
+        }
+        self.undo.drain(..dropped);
+        dropped
+    }
+}
+
+/// What changed between two sheets: "B2", "5 cells in A1:C2", or for a
+/// change to how the sheet is shown "widths of 3 columns", "filter" and the
+/// like ("no cell changes" if it is none of those)
+pub fn summarize(before: &Sheet, after: &Sheet) -> String {
+    let mut changed: Vec<(usize, usize)> = before
+        .cells()
+        .iter()
+        .filter(|(pos, cell)| after.get_cell_ref(pos.0, pos.1).map(|c| &c.raw_input) != Some(&cell.raw_input))
+        .map(|(pos, _)| *pos)
+        .collect();
+    changed.extend(after.cells().keys().filter(|pos| !before.cells().contains_key(pos)));
+
+    match changed.as_slice() {
+        [] => summarize_view(before, after).unwrap_or_else(|| "no cell changes".to_string()),
+        [(col, row)] => formula::cell_name(*col, *row),
+        _ => {
+            let min_col = changed.iter().map(|p| p.0).min().unwrap_or(0);
+            let max_col = changed.iter().map(|p| p.0).max().unwrap_or(0);
+            let min_row = changed.iter().map(|p| p.1).min().unwrap_or(0);
+            let max_row = changed.iter().map(|p| p.1).max().unwrap_or(0);
+            format!(
+                "{} cells in {}:{}",
+                changed.len(),
+                formula::cell_name(min_col, min_row),
+                formula::cell_name(max_col, max_row)
+            )
+        }
+    }
+}
+
+/// The view settings that differ between two sheets: column widths, the
+/// filter and hidden rows or columns
+fn summarize_view(before: &Sheet, after: &Sheet) -> Option<String> {
+    let mut parts = Vec::new();
+    let widths: Vec<usize> = (0..256).filter(|&c| before.get_col_width(c) != after.get_col_width(c)).collect();
+    match widths.as_slice() {
+        [] => {}
+        [col] => parts.push(format!("width of {}", formula::col_to_name(*col))),
+        _ => parts.push(format!("widths of {} columns", widths.len())),
+    }
+    if before.filter() != after.filter() {
+        parts.push("filter".to_string());
+    }
+    if !before.hidden_rows().eq(after.hidden_rows()) {
+        parts.push("hidden rows".to_string());
+    }
+    if !before.hidden_cols().eq(after.hidden_cols()) {
+        parts.push("hidden columns".to_string());
+    }
+    (!parts.is_empty()).then(|| parts.join(", "))
+}
+
+/// Age of a timestamp for display ("5s ago", "3m ago", "2h ago")
+pub fn age(time: SystemTime) -> String {
+    let secs = SystemTime::now().duration_since(time).map(|d| d.as_secs()).unwrap_or(0);
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sheet_with(cells: &[(usize, usize, &str)]) -> Sheet {
+        let mut sheet = Sheet::new();
+        for &(col, row, input) in cells {
+            sheet.set_cell(col, row, input.to_string());
+        }
+        sheet
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut history = History::default();
+        let a = sheet_with(&[(0, 0, "a")]);
+        let b = sheet_with(&[(0, 0, "b")]);
+        history.push(a, Some("a.csv".to_string()), ":e b.csv");
+        let restored = history.undo(b, Some("b.csv".to_string())).unwrap();
+        assert_eq!(restored.sheet.evaluate(0, 0), "a");
+        assert_eq!((restored.file.as_deref(), restored.label.as_str()), (Some("a.csv"), ":e b.csv"));
+        let restored = history.redo(restored.sheet, restored.file).unwrap();
+        assert_eq!(restored.sheet.evaluate(0, 0), "b");
+        assert_eq!((restored.file.as_deref(), restored.label.as_str()), (Some("b.csv"), ":e b.csv"));
+        assert!(history.redo(restored.sheet, None).is_none());
+    }
+
+    #[test]
+    fn test_budget_drops_oldest_entries() {
+        let mut history = History::default();
+        for i in 0..10 {
+            history.push(sheet_with(&[(0, 0, &"x".repeat(1000 * (i + 1)))]), None, "edit");
+        }
+        assert_eq!(history.undo_entries().len(), 10);
+
+        let newest = history.undo_entries()[9].size;
+        let dropped = history.set_budget(newest + 1);
+        assert_eq!(dropped, 9);
+        assert_eq!(history.undo_entries()[0].sheet.evaluate(0, 0).len(), 10000);
+
+        // The latest entry is kept even when it alone is over budget
+        history.set_budget(0);
+        history.push(sheet_with(&[(0, 0, "y")]), None, "edit");
+        assert_eq!(history.undo_entries().len(), 1);
+    }
+
+    #[test]
+    fn test_summarize() {
+        let before = sheet_with(&[(0, 0, "1"), (1, 1, "2")]);
+        assert_eq!(summarize(&before, &before.clone()), "no cell changes");
+        assert_eq!(summarize(&before, &sheet_with(&[(0, 0, "1"), (1, 1, "3")])), "B2");
+        assert_eq!(summarize(&before, &sheet_with(&[(1, 1, "2"), (2, 3, "x")])), "2 cells in A1:C4");
+
+        let mut after = before.clone();
+        after.set_col_width(1, 20);
+        assert_eq!(summarize(&before, &after), "width of B");
+        after.set_col_width(2, 20);
+        after.set_rows_hidden(3, 4, true);
+        assert_eq!(summarize(&before, &after), "widths of 2 columns, hidden rows");
+    }
+}
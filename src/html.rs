@@ -0,0 +1,175 @@
+//! Tables copied from web pages and other spreadsheets, which put an HTML
+//! version on the clipboard beside the plain text.
+//!
+//! Only the first `<table>` is read. Cells spanning several columns are
+//! followed by empty ones, tags inside a cell are dropped and entities
+//! decoded. Excel marks a cell's formula (`x:fmla`) and unformatted number
+//! (`x:num`) in attributes; those are used in place of the shown text.
+
+/// The rows of the first table in the HTML, or None if it has none
+pub fn parse_table(html: &str) -> Option<Vec<Vec<String>>> {
+    let lower = html.to_ascii_lowercase();
+    let start = find_tag(&lower, "table", 0)?;
+    let end = lower[start..].find("</table").map_or(html.len(), |i| start + i);
+
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut pos = start;
+    while let Some(tag_start) = lower[pos..end].find('<').map(|i| pos + i) {
+        let tag_end = lower[tag_start..end].find('>').map_or(end, |i| tag_start + i);
+        let tag = &html[tag_start + 1..tag_end];
+        pos = tag_end;
+        match tag_name(tag).as_str() {
+            "tr" => rows.push(Vec::new()),
+            "td" | "th" => {
+                let content_end = cell_end(&lower, tag_end + 1, end);
+                let value = attribute(tag, "x:fmla")
+                    .or_else(|| attribute(tag, "x:num"))
+                    .unwrap_or_else(|| text(&html[(tag_end + 1).min(content_end)..content_end]));
+                if rows.is_empty() {
+                    rows.push(Vec::new());
+                }
+                let row = rows.last_mut().unwrap();
+                row.push(value);
+                let span = attribute(tag, "colspan").and_then(|s| s.parse::<usize>().ok()).unwrap_or(1);
+                row.extend(std::iter::repeat_n(String::new(), span.clamp(1, 256) - 1));
+                pos = content_end;
+            }
+            _ => {}
+        }
+    }
+    rows.retain(|row| !row.is_empty());
+    (!rows.is_empty()).then_some(rows)
+}
+
+/// Position of the first `<name` tag at or after `from` in lowercased HTML
+fn find_tag(lower: &str, name: &str, from: usize) -> Option<usize> {
+    let mut pos = from;
+    while let Some(i) = lower[pos..].find('<').map(|i| pos + i) {
+        let rest = &lower[i + 1..];
+        if rest.starts_with(name) && rest[name.len()..].starts_with(|c: char| c == '>' || c.is_whitespace()) {
+            return Some(i);
+        }
+        pos = i + 1;
+    }
+    None
+}
+
+/// Where a cell's content ends: its closing tag, or the next cell or row
+fn cell_end(lower: &str, from: usize, end: usize) -> usize {
+    let (lower, from) = (&lower[..end], from.min(end));
+    let closes = ["</td", "</th", "</tr"].iter().filter_map(|t| lower[from..].find(t).map(|i| from + i));
+    let opens = ["td", "th", "tr"].iter().filter_map(|t| find_tag(lower, t, from));
+    closes.chain(opens).min().unwrap_or(end)
+}
+
+/// The element name of a tag's inside, lowercased; "/tr" for a closing tag
+fn tag_name(tag: &str) -> String {
+    tag.split(|c: char| c.is_whitespace() || c == '/' && !tag.starts_with('/'))
+        .next()
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+/// The value of an attribute in a tag's inside
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut pos = 0;
+    while let Some(i) = lower[pos..].find(name).map(|i| pos + i) {
+        pos = i + name.len();
+        let before_ok = lower[..i].ends_with(|c: char| c.is_whitespace());
+        let rest = lower[pos..].trim_start();
+        if !before_ok || !rest.starts_with('=') {
+            continue;
+        }
+        let value_start = tag.len() - rest.len() + 1;
+        let value = tag[value_start..].trim_start();
+        let raw = match value.chars().next() {
+            Some(q @ ('"' | '\'')) => value[1..].split(q).next().unwrap_or(""),
+            _ => value.split(|c: char| c.is_whitespace() || c == '>').next().unwrap_or(""),
+        };
+        return Some(decode_entities(raw));
+    }
+    None
+}
+
+/// The text of a cell's HTML: tags dropped, line breaks and runs of space
+/// made single spaces
+fn text(html: &str) -> String {
+    let mut out = String::new();
+    let mut rest = html;
+    while let Some(i) = rest.find('<') {
+        out.push_str(&rest[..i]);
+        let end = rest[i..].find('>').map_or(rest.len(), |j| i + j + 1);
+        if tag_name(rest[i + 1..end].trim_end_matches('>')) == "br" {
+            out.push(' ');
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    decode_entities(&out).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn decode_entities(s: &str) -> String {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let entity = rest[1..].find(';').filter(|&j| j <= 10).map(|j| &rest[1..j + 1]);
+        let decoded = entity.and_then(|e| match e {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => {
+                let code = e.strip_prefix("#x").or_else(|| e.strip_prefix("#X"));
+                let code = match code {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => e.strip_prefix('#').and_then(|d| d.parse().ok()),
+                };
+                code.and_then(char::from_u32)
+            }
+        });
+        match (entity, decoded) {
+            (Some(e), Some(c)) => {
+                out.push(c);
+                rest = &rest[e.len() + 2..];
+            }
+            _ => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_table() {
+        let html = "<html><body><p>Sales</p><TABLE border=1>\
+            <tr><th>Item</th><th colspan=\"2\">Q1 &amp; Q2</th></tr>\
+            <tr><td><b>Apple</b></td><td x:num=\"1234.5\">1,234.50</td><td x:fmla=\"=B2*2\">2469</td></tr>\
+            <tr><td>two<br>lines</td><td>&lt;5&gt;&#160;</td></tr>\
+            </TABLE><table><tr><td>ignored</td></tr></table></body></html>";
+        let rows = parse_table(html).unwrap();
+        assert_eq!(rows[0], vec!["Item", "Q1 & Q2", ""]);
+        assert_eq!(rows[1], vec!["Apple", "1234.5", "=B2*2"]);
+        assert_eq!(rows[2], vec!["two lines", "<5>"]);
+        assert_eq!(rows.len(), 3);
+    }
+
+    #[test]
+    fn test_unclosed_cells() {
+        let rows = parse_table("<table><tr><td>a<td>b<tr><td>c</table>").unwrap();
+        assert_eq!(rows, vec![vec!["a", "b"], vec!["c"]]);
+        assert!(parse_table("<p>no table</p>").is_none());
+        assert!(parse_table("<tablet>").is_none());
+    }
+}
@@ -0,0 +1,79 @@
+//! Jump list: cells the cursor jumped away from (`gg`, `G`, `:goto`,
+//! searches, `gd`, quickfix entries), gone back through with Ctrl-O and
+//! forward again with Ctrl-I (Tab), as in vim.
+
+/// Most positions remembered
+const MAX_JUMPS: usize = 100;
+
+#[derive(Default)]
+pub struct JumpList {
+    jumps: Vec<(usize, usize)>,
+    // Position in `jumps` while going back and forth; its length otherwise
+    index: usize,
+}
+
+impl JumpList {
+    /// Remember the position jumped away from. Going back and then jumping
+    /// somewhere new forgets the positions that were ahead.
+    pub fn push(&mut self, pos: (usize, usize)) {
+        self.jumps.truncate(self.index);
+        self.jumps.retain(|&p| p != pos);
+        self.jumps.push(pos);
+        if self.jumps.len() > MAX_JUMPS {
+            self.jumps.remove(0);
+        }
+        self.index = self.jumps.len();
+    }
+
+    /// The position before the current one; `current` is kept to come
+    /// forward to again
+    pub fn back(&mut self, current: (usize, usize)) -> Option<(usize, usize)> {
+        if self.index == 0 {
+            return None;
+        }
+        if self.index == self.jumps.len() {
+            self.jumps.retain(|&p| p != current);
+            self.jumps.push(current);
+            self.index = self.jumps.len() - 1;
+            if self.index == 0 {
+                return None;
+            }
+        }
+        self.index -= 1;
+        Some(self.jumps[self.index])
+    }
+
+    pub fn forward(&mut self) -> Option<(usize, usize)> {
+        if self.index + 1 >= self.jumps.len() {
+            return None;
+        }
+        self.index += 1;
+        Some(self.jumps[self.index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_back_and_forward() {
+        let mut jumps = JumpList::default();
+        assert_eq!(jumps.back((0, 0)), None);
+
+        jumps.push((0, 0));
+        jumps.push((1, 5));
+        assert_eq!(jumps.back((2, 9)), Some((1, 5)));
+        assert_eq!(jumps.back((1, 5)), Some((0, 0)));
+        assert_eq!(jumps.back((0, 0)), None);
+        assert_eq!(jumps.forward(), Some((1, 5)));
+        assert_eq!(jumps.forward(), Some((2, 9)));
+        assert_eq!(jumps.forward(), None);
+
+        // A new jump after going back drops what was ahead
+        jumps.back((2, 9));
+        jumps.push((1, 5));
+        assert_eq!(jumps.forward(), None);
+        assert_eq!(jumps.back((3, 3)), Some((1, 5)));
+    }
+}
@@ -0,0 +1,156 @@
+//! Keystroke log for bug reports.
+//!
+//! `:set keylog=path` (or `keylog = "path"` in the config file) writes every
+//! key pressed to a file, one per line in the notation of the config file's
+//! `[keymap]`, after a header naming the file that was open and the size
+//! of the terminal. `vicalc --replay path [file]` feeds the keys to vicalc
+//! without a terminal, starting from that file (or the one given), and
+//! prints the messages shown and the resulting sheet, so a report can be
+//! reproduced from the log and a fixture file. Files written by the keys
+//! go to a scratch directory that is removed afterwards.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crossterm::event::KeyEvent;
+
+use crate::config;
+use crate::App;
+
+pub struct KeyLog {
+    path: PathBuf,
+    out: BufWriter<File>,
+    // Whether the header has been written; it waits for the first key so
+    // that a log started from the config file names the file opened after
+    header_written: bool,
+}
+
+impl KeyLog {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(KeyLog { path: path.to_path_buf(), out: BufWriter::new(file), header_written: false })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a key. Each key is flushed, so the log survives a crash.
+    pub fn record(&mut self, key: &KeyEvent, file: Option<&str>, size: (u16, u16)) {
+        let Some(name) = config::key_to_string(key) else {
+            return;
+        };
+        if !self.header_written {
+            self.header_written = true;
+            let _ = writeln!(self.out, "# vicalc {} keylog", env!("CARGO_PKG_VERSION"));
+            if let Some(file) = file {
+                let _ = writeln!(self.out, "# file: {}", file);
+            }
+            let _ = writeln!(self.out, "# size: {}x{}", size.0, size.1);
+        }
+        let _ = writeln!(self.out, "{}", name);
+        let _ = self.out.flush();
+    }
+}
+
+/// A log read back: the header and the keys
+#[derive(Debug, Default)]
+pub struct Replay {
+    pub file: Option<String>,
+    pub size: Option<(u16, u16)>,
+    pub keys: Vec<KeyEvent>,
+}
+
+/// Read a log. Lines starting with "# " are the header or comments; every
+/// other line holds keys.
+pub fn parse(text: &str) -> Result<Replay, String> {
+    let mut replay = Replay::default();
+    for (i, line) in text.lines().enumerate() {
+        if let Some(comment) = line.strip_prefix("# ") {
+            if let Some(file) = comment.strip_prefix("file: ") {
+                replay.file = Some(file.to_string());
+            } else if let Some((w, h)) = comment.strip_prefix("size: ").and_then(|s| s.split_once('x')) {
+                replay.size = w.parse().ok().zip(h.parse().ok());
+            }
+        } else if !line.is_empty() {
+            let keys = config::parse_keys(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            replay.keys.extend(keys);
+        }
+    }
+    Ok(replay)
+}
+
+/// `vicalc --replay <log> [file]`
+pub fn run_cli(args: &[String]) -> i32 {
+    let Some(log_path) = args.first() else {
+        eprintln!("usage: vicalc --replay <keylog> [file]");
+        return 2;
+    };
+    let replay = match std::fs::read_to_string(log_path).map_err(|e| e.to_string()).and_then(|text| parse(&text)) {
+        Ok(replay) => replay,
+        Err(e) => {
+            eprintln!("error: {}: {}", log_path, e);
+            return 2;
+        }
+    };
+
+    let mut app = App::new();
+    app.term_size = replay.size.unwrap_or((100, 30));
+    app.swap.set_update_time(0);
+    if let Some(file) = args.get(1).or(replay.file.as_ref()) {
+        let path = std::fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file));
+        crate::commands::execute_command(&mut app, &format!("e {}", path.display()));
+        // Keep :w in the log from writing over the fixture
+        app.current_file = None;
+    }
+
+    // Files the keys write (:w, :export) go to a scratch directory
+    let scratch = std::env::temp_dir().join(format!("vicalc-replay-{}", std::process::id()));
+    if let Err(e) = std::fs::create_dir_all(&scratch).and_then(|_| std::env::set_current_dir(&scratch)) {
+        eprintln!("error: {}: {}", scratch.display(), e);
+        return 2;
+    }
+
+    let mut replayed = 0;
+    for key in replay.keys {
+        if !app.running {
+            break;
+        }
+        crate::handle_key(&mut app, key);
+        crate::calc::update(&mut app);
+        crate::calc::wait(&mut app);
+        crate::a11y::update(&mut app);
+        crate::audit::update(&mut app);
+        replayed += 1;
+    }
+
+    for message in app.messages.history() {
+        println!("{:<7}  {}", message.severity.name(), message.text);
+    }
+    println!(
+        "-- {} keys, cursor at {}, {} --",
+        replayed,
+        crate::formula::cell_name(app.cursor_col, app.cursor_row),
+        crate::ui::mode_name(app.mode),
+    );
+    print!("{}", crate::csv::write(&app.sheet));
+    let _ = std::fs::remove_dir_all(&scratch);
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyCode;
+
+    #[test]
+    fn test_parse() {
+        let replay = parse("# vicalc 0.1.0 keylog\n# file: data.csv\n# size: 80x24\nj\n<CR>\n#\n:w<CR>\n").unwrap();
+        assert_eq!(replay.file.as_deref(), Some("data.csv"));
+        assert_eq!(replay.size, Some((80, 24)));
+        let codes: Vec<KeyCode> = replay.keys.iter().map(|k| k.code).collect();
+        assert_eq!(codes, vec![KeyCode::Char('j'), KeyCode::Enter, KeyCode::Char('#'), KeyCode::Char(':'), KeyCode::Char('w'), KeyCode::Enter]);
+        assert!(parse("<nosuchkey>\n").is_err());
+    }
+}
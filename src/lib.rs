@@ -0,0 +1,22 @@
+//! Core spreadsheet model and formula engine of vicalc.
+//!
+//! The terminal front end lives in the `vicalc` binary; this library holds the
+//! pieces that do not depend on a terminal so they can be fuzzed, benchmarked
+//! and embedded.
+
+pub mod cell;
+pub mod chart;
+pub mod coltype;
+pub mod csv;
+pub mod engine;
+pub mod formula;
+pub mod html;
+pub mod lint;
+pub mod parquet;
+pub mod pivot;
+pub mod records;
+pub mod render;
+pub mod sample;
+pub mod sheet;
+pub mod textable;
+pub mod validation;
@@ -0,0 +1,204 @@
+//! Checks for likely mistakes in a sheet (`:check`).
+//!
+//! None of these are errors as such: a formula may be written ahead of its
+//! data, and a column may mix formulas with a deliberate override. They are
+//! the things worth a second look before the numbers are trusted.
+
+use std::collections::HashMap;
+
+use crate::cell::{CellError, CellValue};
+use crate::coltype::ColType;
+use crate::formula;
+use crate::sheet::Sheet;
+
+/// A cell that looks wrong, and why
+#[derive(Clone, Debug, PartialEq)]
+pub struct Issue {
+    pub col: usize,
+    pub row: usize,
+    pub message: String,
+}
+
+/// Every issue in the sheet, in reading order (by row, then column)
+pub fn check(sheet: &Sheet) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut add = |col: usize, row: usize, message: String| issues.push(Issue { col, row, message });
+
+    for (&(col, row), cell) in sheet.cells() {
+        match &cell.value {
+            CellValue::Formula(f) if f.contains("#REF!") => add(col, row, "refers to a deleted cell (#REF!)".to_string()),
+            CellValue::Formula(f) => {
+                if sheet.evaluate(col, row) == "#REF!" {
+                    add(col, row, "result is #REF!".to_string());
+                }
+                for r in formula::find_references(f) {
+                    if !r.contains(col, row) && area_is_empty(sheet, r.area) {
+                        add(col, row, format!("refers to empty {}", &f[r.span]));
+                    }
+                }
+            }
+            CellValue::Text(s) if sheet.col_type(col) != Some(ColType::Text) && looks_like_number(s) => {
+                add(col, row, format!("number stored as text: {}", s));
+            }
+            _ => {}
+        }
+    }
+
+    let max_col = sheet.max_col().unwrap_or(0);
+    for col in 0..=max_col {
+        for (row, message) in inconsistent_formulas(sheet, col) {
+            add(col, row, message);
+        }
+    }
+
+    issues.sort_by_key(|issue| (issue.row, issue.col));
+    issues
+}
+
+/// Formula cells whose value is an error such as #DIV/0!, in reading order
+pub fn errors(sheet: &Sheet) -> Vec<Issue> {
+    use CellError::*;
+    let errors = [DivZero, Value, Ref, Name, Num, NA, Cycle, Spill].map(|e| e.to_string());
+    let mut issues: Vec<Issue> = sheet
+        .cells()
+        .iter()
+        .filter(|(_, cell)| matches!(cell.value, CellValue::Formula(_)))
+        .filter_map(|(&(col, row), cell)| {
+            let value = sheet.evaluate(col, row);
+            errors
+                .contains(&value.as_str())
+                .then(|| Issue { col, row, message: format!("{} from {}", value, cell.raw_input) })
+        })
+        .collect();
+    issues.sort_by_key(|issue| (issue.row, issue.col));
+    issues
+}
+
+/// Whether no cell in the (min_col, min_row, max_col, max_row) area has content
+fn area_is_empty(sheet: &Sheet, (c1, r1, c2, r2): (usize, usize, usize, usize)) -> bool {
+    let size = (c2 - c1 + 1).saturating_mul(r2 - r1 + 1);
+    if size <= sheet.cells().len() {
+        (r1..=r2).all(|row| (c1..=c2).all(|col| sheet.get_cell_ref(col, row).is_none_or(|c| c.is_empty())))
+    } else {
+        !sheet.cells().iter().any(|(&(col, row), cell)| {
+            (c1..=c2).contains(&col) && (r1..=r2).contains(&row) && !cell.is_empty()
+        })
+    }
+}
+
+/// Text that would be a number without its thousands separators or currency
+/// sign ("1,234", "€5"), or was kept as text some other way
+fn looks_like_number(s: &str) -> bool {
+    let s = s.trim().trim_start_matches(['$', '¥', '€', '£']);
+    let digits: String = s.chars().filter(|&c| c != ',').collect();
+    !digits.is_empty() && digits.parse::<f64>().is_ok_and(f64::is_finite)
+}
+
+/// Cells that break the pattern of a column of formulas: a hard-coded value
+/// or a differently shaped formula between the first and last formula of
+/// the column, when most of the cells there share one formula
+fn inconsistent_formulas(sheet: &Sheet, col: usize) -> Vec<(usize, String)> {
+    let header = sheet.header_row();
+    let mut cells: Vec<(usize, &CellValue)> = sheet
+        .cells()
+        .iter()
+        .filter(|(&(c, r), cell)| c == col && Some(r) != header && !cell.is_empty())
+        .map(|(&(_, r), cell)| (r, &cell.value))
+        .collect();
+    cells.sort_by_key(|&(row, _)| row);
+
+    let formula_rows: Vec<usize> = cells.iter().filter(|(_, v)| matches!(v, CellValue::Formula(_))).map(|&(r, _)| r).collect();
+    let (Some(&first), Some(&last)) = (formula_rows.first(), formula_rows.last()) else {
+        return Vec::new();
+    };
+    cells.retain(|&(row, _)| row >= first && row <= last);
+
+    // Each formula moved up to the first formula row, so that copies of one
+    // formula down the column come out the same
+    let shape = |row: usize, f: &str| formula::adjust_formula(f, 0, first as isize - row as isize);
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for &(row, value) in &cells {
+        if let CellValue::Formula(f) = value {
+            *counts.entry(shape(row, f)).or_default() += 1;
+        }
+    }
+    let Some((common, count)) = counts.into_iter().max_by_key(|(_, n)| *n) else {
+        return Vec::new();
+    };
+    if count < 2 || count * 2 <= cells.len() {
+        return Vec::new();
+    }
+
+    let name = formula::col_to_name(col);
+    cells
+        .into_iter()
+        .filter_map(|(row, value)| match value {
+            CellValue::Formula(f) if shape(row, f) == common => None,
+            CellValue::Formula(_) => Some((row, format!("formula differs from the rest of column {}", name))),
+            _ => Some((row, format!("value in a column of formulas ({})", name))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sheet(cells: &[(&str, &str)]) -> Sheet {
+        let mut sheet = Sheet::new();
+        for (name, input) in cells {
+            let (col, row, _, _) = formula::parse_cell_ref(name).unwrap();
+            sheet.set_cell(col, row, input.to_string());
+        }
+        sheet
+    }
+
+    fn found(issues: &[Issue]) -> Vec<String> {
+        issues.iter().map(|i| formula::cell_name(i.col, i.row)).collect()
+    }
+
+    #[test]
+    fn test_empty_references_and_ref_errors() {
+        let sheet = sheet(&[("A1", "1"), ("B1", "=SUM(A1:A3)"), ("B2", "=SUM(C1:C9)"), ("B3", "=#REF!+1")]);
+        let issues = check(&sheet);
+        assert_eq!(found(&issues), vec!["B2", "B3"]);
+        assert_eq!(issues[0].message, "refers to empty C1:C9");
+    }
+
+    #[test]
+    fn test_errors() {
+        let sheet = sheet(&[("A1", "0"), ("A2", "=1/A1"), ("A3", "#DIV/0!"), ("B1", "=A2+1"), ("B2", "=A1+1")]);
+        let issues = errors(&sheet);
+        assert_eq!(found(&issues), vec!["B1", "A2"]);
+        assert_eq!(issues[1].message, "#DIV/0! from =1/A1");
+    }
+
+    #[test]
+    fn test_numbers_stored_as_text() {
+        assert!(looks_like_number("1,234"));
+        assert!(looks_like_number("$5.50"));
+        assert!(!looks_like_number("12 kg"));
+        assert!(!looks_like_number("N/A"));
+
+        let mut sheet = sheet(&[("A1", "1,234"), ("B1", "12 kg")]);
+        assert_eq!(found(&check(&sheet)), vec!["A1"]);
+        // A text column holds such values on purpose
+        sheet.set_col_type(0, Some(ColType::Text));
+        assert!(check(&sheet).is_empty());
+    }
+
+    #[test]
+    fn test_inconsistent_formulas() {
+        let sheet = sheet(&[
+            ("A1", "Price"), ("B1", "Total"),
+            ("A2", "1"), ("B2", "=A2*2"),
+            ("A3", "2"), ("B3", "=A3*2"),
+            ("A4", "3"), ("B4", "6"),
+            ("A5", "4"), ("B5", "=A5*3"),
+            ("A6", "5"), ("B6", "=A6*2"),
+        ]);
+        let issues = check(&sheet);
+        assert_eq!(found(&issues), vec!["B4", "B5"]);
+        assert_eq!(issues[0].message, "value in a column of formulas (B)");
+    }
+}
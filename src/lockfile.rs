@@ -0,0 +1,266 @@
+//! Lock files, so two vicalc sessions do not edit the same file unawares.
+//!
+//! Opening a file writes `.name.lock` next to it with the process, host
+//! and user holding it; quitting or opening another file removes it. A
+//! session that finds another's lock opens the file read-only, so `:w`
+//! cannot silently overwrite the other session's work (`:w!` writes
+//! anyway). A lock left by a process that is no longer running on this
+//! host is stale and taken over; one from another host, as on a shared
+//! drive, is always respected, though a session there that crashed can
+//! still be recovered from its swap file.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::history;
+
+/// Lock file of `file`: `.name.lock` in the same directory
+pub fn path_for(file: &str) -> PathBuf {
+    let path = Path::new(file);
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    path.with_file_name(format!(".{}.lock", name))
+}
+
+/// The session a lock file belongs to
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Holder {
+    pub pid: u32,
+    pub host: String,
+    pub user: String,
+}
+
+impl Holder {
+    fn this_session() -> Self {
+        let user = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_default();
+        Holder { pid: std::process::id(), host: host_name(), user }
+    }
+
+    /// Whether the session may still be running: certainly not if it was
+    /// on this host and its process is gone
+    fn is_live(&self) -> bool {
+        self.host != host_name() || process_exists(self.pid)
+    }
+}
+
+impl std::fmt::Display for Holder {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "process {} on {}", self.pid, self.host)?;
+        if !self.user.is_empty() {
+            write!(f, " ({})", self.user)?;
+        }
+        Ok(())
+    }
+}
+
+/// Another session holding `file`, if there is one still running
+pub fn holder(file: &str) -> Option<Holder> {
+    let text = fs::read_to_string(path_for(file)).ok()?;
+    let holder: Holder = serde_json::from_str(&text).ok()?;
+    (holder != Holder::this_session() && holder.is_live()).then_some(holder)
+}
+
+/// Write this session's lock file at `path`, failing if there is one
+/// already, so two sessions cannot both create it
+fn create(path: &Path) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    let json = serde_json::to_string(&Holder::this_session()).unwrap_or_default();
+    file.write_all((json + "\n").as_bytes())
+}
+
+/// The session written in a lock file. One just created may not be
+/// written yet, so an empty or partly written file is read again briefly.
+fn read_holder(path: &Path) -> Option<Holder> {
+    for attempt in 0..3 {
+        if attempt > 0 {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        let text = fs::read_to_string(path).ok()?;
+        if let Ok(holder) = serde_json::from_str(&text) {
+            return Some(holder);
+        }
+    }
+    None
+}
+
+/// The lock this session holds, removed when it is dropped
+#[derive(Default)]
+pub struct FileLock {
+    held: Option<PathBuf>,
+}
+
+impl FileLock {
+    /// Lock `file` in place of the file locked before. Returns the other
+    /// session holding it instead, if any, leaving it unlocked by this one.
+    pub fn take(&mut self, file: &str) -> Option<Holder> {
+        self.release();
+        let path = path_for(file);
+        // A stale lock is removed and the lock created again, once
+        for _ in 0..2 {
+            match create(&path) {
+                Ok(()) => {
+                    self.held = Some(path);
+                    return None;
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                // A directory that cannot be written to just goes without a lock
+                Err(_) => return None,
+            }
+            let found = read_holder(&path);
+            if found.as_ref().is_some_and(|h| *h != Holder::this_session() && h.is_live()) {
+                return found;
+            }
+            // Stale: removed only if it still names the same session, so a
+            // lock another session took over meanwhile is left alone
+            if read_holder(&path) == found {
+                let _ = fs::remove_file(&path);
+            }
+        }
+        None
+    }
+
+    /// Lock `file` whoever holds it
+    pub fn force(&mut self, file: &str) {
+        self.release();
+        let path = path_for(file);
+        let _ = fs::remove_file(&path);
+        if create(&path).is_ok() {
+            self.held = Some(path);
+        }
+    }
+
+    /// Remove the lock, unless another session has taken it over since
+    pub fn release(&mut self) {
+        if let Some(path) = self.held.take() {
+            let text = fs::read_to_string(&path).unwrap_or_default();
+            if serde_json::from_str::<Holder>(&text).is_ok_and(|h| h == Holder::this_session()) {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// What to do about a file another session has open
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Choice {
+    ReadOnly,
+    // Take the file over with what the swap file holds
+    Recover,
+    Override,
+    Quit,
+}
+
+/// Tell the user who has `file` open and ask what to do, offering to
+/// recover from `swap` when there is a swap file, for a session that is
+/// gone. Runs before the terminal is put into raw mode.
+pub fn ask(file: &str, holder: &Holder, swap: Option<&Path>) -> Choice {
+    eprintln!("{} is open in another vicalc: {}", file, holder);
+    if let Ok(time) = fs::metadata(path_for(file)).and_then(|m| m.modified()) {
+        eprintln!("  since {}", history::age(time));
+    }
+    eprintln!("Saving here could overwrite changes made there.");
+    if let Some(swap) = swap {
+        eprintln!("If that vicalc has crashed, its unsaved changes can be recovered from {}.", swap.display());
+    }
+
+    let prompt = match swap {
+        Some(_) => "[o]pen read-only, [r]ecover, [e]dit anyway, [q]uit: ",
+        None => "[o]pen read-only, [e]dit anyway, [q]uit: ",
+    };
+    let stdin = io::stdin();
+    loop {
+        eprint!("{}", prompt);
+        let _ = io::stderr().flush();
+        let mut answer = String::new();
+        if stdin.lock().read_line(&mut answer).unwrap_or(0) == 0 {
+            return Choice::Quit;
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "o" => return Choice::ReadOnly,
+            "r" if swap.is_some() => return Choice::Recover,
+            "e" => return Choice::Override,
+            "q" => return Choice::Quit,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(unix)]
+fn host_name() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its length, which is passed along
+    let result = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if result != 0 {
+        return String::new();
+    }
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn host_name() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks that the process exists
+    unsafe { libc::kill(pid, 0) == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM) }
+}
+
+#[cfg(not(unix))]
+fn process_exists(_pid: u32) -> bool {
+    // Without a way to tell, a lock is taken to be in use
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_holder() {
+        let dir = std::env::temp_dir().join(format!("vicalc-lock-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("data.csv").display().to_string();
+        assert_eq!(path_for(&file), dir.join(".data.csv.lock"));
+
+        // This session's own lock is not in the way
+        let mut lock = FileLock::default();
+        assert!(lock.take(&file).is_none());
+        assert!(path_for(&file).exists());
+        assert!(holder(&file).is_none());
+
+        // A live session elsewhere is; a dead one here is not
+        let write = |holder: &Holder| std::fs::write(path_for(&file), serde_json::to_string(holder).unwrap()).unwrap();
+        let other = Holder { pid: 1, host: "elsewhere".to_string(), user: "ann".to_string() };
+        write(&other);
+        assert_eq!(lock.take(&file), Some(other));
+        assert!(std::fs::read_to_string(path_for(&file)).unwrap().contains("elsewhere"));
+        // Creating the lock fails while another is there
+        assert_eq!(create(&path_for(&file)).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        write(&Holder { pid: i32::MAX as u32, host: host_name(), user: String::new() });
+        assert!(lock.take(&file).is_none());
+        assert_eq!(read_holder(&path_for(&file)), Some(Holder::this_session()));
+        // One that cannot be read is stale too
+        std::fs::write(path_for(&file), "").unwrap();
+        assert!(lock.take(&file).is_none());
+        assert!(holder(&file).is_none() && path_for(&file).exists());
+
+        drop(lock);
+        assert!(!path_for(&file).exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,259 @@
+//! Keyboard macros.
+//!
+//! `qa` starts recording the keys typed into register `a` (any of a-z) and
+//! `q` stops; `@a` types them again, `3@a` three times, and `@@` repeats
+//! the last macro played. A register only lasts for the session, so
+//! `:macro save <name> <register>` keeps it under a name in the `[macros]`
+//! table of the config file, in the notation of `[keymap]`:
+//!
+//! ```toml
+//! [macros]
+//! monthly_cleanup = "gg:autowidth<CR>"
+//! ```
+//!
+//! `:macro run <name>` plays a saved macro, `:macro delete <name>` removes
+//! it from the config file and `:macro` lists them.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crossterm::event::KeyEvent;
+
+use crate::config;
+use crate::App;
+
+/// How deep macros may play each other before it is taken for a loop
+const MAX_DEPTH: usize = 20;
+
+#[derive(Default)]
+pub struct Macros {
+    registers: HashMap<char, Vec<KeyEvent>>,
+    // The register being recorded into and the keys so far
+    recording: Option<(char, Vec<KeyEvent>)>,
+    // Register played last, for @@
+    last_played: Option<char>,
+    // Macros being played, one inside the other
+    depth: usize,
+    // Saved under names in the config file
+    pub named: BTreeMap<String, Vec<KeyEvent>>,
+}
+
+impl Macros {
+    /// The register being recorded into, for the status bar
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|(register, _)| *register)
+    }
+
+    /// Note a key typed while recording; keys a macro plays are not
+    /// recorded again
+    pub fn record(&mut self, key: KeyEvent) {
+        if self.depth == 0 {
+            if let Some((_, keys)) = &mut self.recording {
+                keys.push(key);
+            }
+        }
+    }
+
+    pub fn start(&mut self, register: char) {
+        self.recording = Some((register, Vec::new()));
+    }
+
+    /// Stop recording, leaving out the `q` that stopped it. Returns the
+    /// register and how many keys went into it.
+    pub fn stop(&mut self) -> Option<(char, usize)> {
+        let (register, mut keys) = self.recording.take()?;
+        keys.pop();
+        let count = keys.len();
+        self.registers.insert(register, keys);
+        Some((register, count))
+    }
+}
+
+/// Whether `c` names a macro register
+pub fn is_register(c: char) -> bool {
+    c.is_ascii_lowercase()
+}
+
+/// `@{register}` (`@@` for the last one), `count` times
+pub fn play_register(app: &mut App, register: char, count: usize) {
+    let register = if register == '@' { app.macros.last_played } else { Some(register) };
+    let Some(register) = register else {
+        app.warn("No macro played yet");
+        return;
+    };
+    let Some(keys) = app.macros.registers.get(&register).cloned() else {
+        app.warn(format!("Register {} is empty", register));
+        return;
+    };
+    app.macros.last_played = Some(register);
+    play(app, &keys, count);
+}
+
+/// `:macro [list|save|run|delete] ...`
+pub fn command(app: &mut App, args: &str) {
+    const USAGE: &str = "Usage: :macro save <name> <register> | run <name> [count] | delete <name> | list";
+
+    let words: Vec<&str> = args.split_whitespace().collect();
+    match words.as_slice() {
+        [] | ["list"] => {
+            if app.macros.named.is_empty() {
+                app.info("No saved macros (:macro save <name> <register>)");
+            } else {
+                let names: Vec<&str> = app.macros.named.keys().map(String::as_str).collect();
+                app.info(format!("Macros: {}", names.join(", ")));
+            }
+        }
+        ["save", name, register] => {
+            let mut chars = register.trim_start_matches('@').chars();
+            let (Some(register), None) = (chars.next(), chars.next()) else {
+                app.warn(USAGE);
+                return;
+            };
+            if !is_register(register) {
+                app.error(format!("Not a macro register: {} (a-z)", register));
+                return;
+            }
+            save(app, name, register);
+        }
+        ["run", name] | ["run", name, _] => {
+            let count = match words.get(2).map(|n| n.parse::<usize>()) {
+                None => 1,
+                Some(Ok(n)) if n > 0 => n,
+                Some(_) => {
+                    app.error(format!("Invalid count: {}", words[2]));
+                    return;
+                }
+            };
+            match app.macros.named.get(*name).cloned() {
+                Some(keys) => play(app, &keys, count),
+                None => app.error(format!("No macro named {}", name)),
+            }
+        }
+        ["delete", name] => {
+            if !app.macros.named.contains_key(*name) {
+                app.error(format!("No macro named {}", name));
+                return;
+            }
+            match write_config(name, None) {
+                Ok(path) => {
+                    app.macros.named.remove(*name);
+                    app.info(format!("Macro {} removed from {}", name, path));
+                }
+                Err(e) => app.error(e),
+            }
+        }
+        _ => app.warn(USAGE),
+    }
+}
+
+fn save(app: &mut App, name: &str, register: char) {
+    if !valid_name(name) {
+        app.error(format!("Invalid macro name: {}", name));
+        return;
+    }
+    let Some(keys) = app.macros.registers.get(&register).filter(|keys| !keys.is_empty()).cloned() else {
+        app.error(format!("Register {} is empty (record it with q{})", register, register));
+        return;
+    };
+    let Some(text) = keys.iter().map(config::key_to_string).collect::<Option<String>>() else {
+        app.error(format!("Register {} holds a key the config file cannot name", register));
+        return;
+    };
+    match write_config(name, Some(&text)) {
+        Ok(path) => {
+            app.macros.named.insert(name.to_string(), keys);
+            app.info(format!("Macro {} saved to {}", name, path));
+        }
+        Err(e) => app.error(e),
+    }
+}
+
+/// Macro names are bare TOML keys, so they read back as written
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Type `keys` `count` times, stopping at a macro that plays itself
+fn play(app: &mut App, keys: &[KeyEvent], count: usize) {
+    if app.macros.depth >= MAX_DEPTH {
+        app.error("Macros nested too deeply (one plays itself?)");
+        return;
+    }
+    app.macros.depth += 1;
+    for _ in 0..count {
+        for &key in keys {
+            crate::handle_key(app, key);
+        }
+    }
+    app.macros.depth -= 1;
+}
+
+/// Set (or with None remove) a macro in the `[macros]` table of the config
+/// file, keeping the rest of the file as it is. Returns the file's path.
+fn write_config(name: &str, keys: Option<&str>) -> Result<String, String> {
+    let path = config::path().ok_or("No config file: $HOME is not set")?;
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let text = set_macro(&text, name, keys).map_err(|e| format!("{}: {}", path.display(), e))?;
+    std::fs::write(&path, text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(path.display().to_string())
+}
+
+fn set_macro(text: &str, name: &str, keys: Option<&str>) -> Result<String, String> {
+    let mut doc: toml_edit::DocumentMut = text.parse().map_err(|e: toml_edit::TomlError| e.message().to_string())?;
+    let macros = doc
+        .entry("macros")
+        .or_insert_with(toml_edit::table)
+        .as_table_mut()
+        .ok_or("macros is not a table")?;
+    match keys {
+        Some(keys) => {
+            macros.insert(name, toml_edit::value(keys));
+        }
+        None => {
+            macros.remove(name);
+        }
+    }
+    Ok(doc.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyModifiers};
+
+    fn key(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_record_and_play() {
+        let mut app = App::new();
+        for c in "qajq".chars() {
+            crate::handle_key(&mut app, key(c));
+        }
+        assert_eq!(app.macros.recording(), None);
+        assert_eq!(app.macros.registers[&'a'], vec![key('j')]);
+        for c in "2@a@@".chars() {
+            crate::handle_key(&mut app, key(c));
+        }
+        assert_eq!(app.cursor_row, 4);
+
+        // A macro that plays itself stops
+        app.macros.registers.insert('b', vec![key('@'), key('b')]);
+        play_register(&mut app, 'b', 1);
+        assert_eq!(app.macros.depth, 0);
+    }
+
+    #[test]
+    fn test_set_macro() {
+        let text = "# my settings\ncolwidth = 12\n";
+        let text = set_macro(text, "cleanup", Some("gg:w<CR>")).unwrap();
+        assert_eq!(text, "# my settings\ncolwidth = 12\n\n[macros]\ncleanup = \"gg:w<CR>\"\n");
+        let config = config::Config::parse(&text).unwrap();
+        assert_eq!(config.macros["cleanup"], "gg:w<CR>");
+        assert_eq!(set_macro(&text, "cleanup", None).unwrap(), "# my settings\ncolwidth = 12\n\n[macros]\n");
+    }
+}
@@ -1,9 +1,33 @@
-mod cell;
-mod engine;
-mod formula;
-mod sheet;
 mod ui;
+mod a11y;
+mod audit;
+mod bench;
+mod calc;
+mod cmdhistory;
 mod commands;
+mod complete;
+mod config;
+mod fixtures;
+mod funcmenu;
+mod history;
+mod jumplist;
+mod keylog;
+mod lockfile;
+mod macros;
+mod message;
+mod plugin;
+mod quickfix;
+mod screen;
+mod script;
+mod scripting;
+mod select;
+mod session;
+mod snapshot;
+mod snippet;
+mod stats;
+mod swap;
+mod tags;
+mod theme;
 
 use crossterm::{
     cursor::{Hide, Show},
@@ -11,8 +35,16 @@ use crossterm::{
     execute,
     terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use std::collections::{HashMap, HashSet};
 use std::io::{stdout, Result};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Instant;
 
+use vicalc::{cell, chart, coltype, csv, formula, lint, parquet, pivot, records, render, sample, sheet, textable, validation};
+use vicalc::sheet::BlockMotion;
+
+use history::History;
+use message::{Messages, Severity};
 use sheet::Sheet;
 use ui::UI;
 
@@ -25,6 +57,8 @@ pub enum Mode {
     EditPreserve,   // F2 - edit preserving content
     Command,        // : commands
     Visual,         // Range selection
+    Chart,          // Full-screen chart of a range (:chart)
+    List,           // Full-screen list of text lines (:undolist)
 }
 
 /// Edit axis (row-oriented or column-oriented)
@@ -39,33 +73,148 @@ pub struct App {
     pub mode: Mode,
     pub axis: EditAxis,
     pub input_buffer: String,
+    // Character position of the edit cursor within input_buffer
+    pub input_cursor: usize,
     pub command_buffer: String,
-    pub status_message: String,
+    // Commands and search patterns entered, for Up/Down in Command mode
+    pub cmd_history: cmdhistory::CmdHistory,
+    // Matches cycled through by repeated Tabs on the command line
+    pub cmd_completion: Option<complete::CommandCompletion>,
+    pub messages: Messages,
     pub cursor_col: usize,
     pub cursor_row: usize,
     pub view_col: usize,
     pub view_row: usize,
     pub clipboard: Option<ClipboardContent>,
-    pub undo_stack: Vec<Sheet>,
-    pub redo_stack: Vec<Sheet>,
+    pub history: History,
+    // The column whose width was last changed with < or >, and the history
+    // generation after it, so a run of presses is undone in one step
+    width_change: Option<(usize, u64)>,
     pub running: bool,
     pub pending_operator: Option<char>,
     pub count_buffer: String,
     pub slash_pending: bool,
+    // Cell reference being typed after g (gB17<Enter>)
+    pub goto_buffer: Option<String>,
     pub current_file: Option<String>,
+    // Changed since the file was loaded or saved
+    pub modified: bool,
+    // The sheet as last loaded or saved, to tell which cells have changed
+    // since (:changed)
+    pub saved_sheet: sheet::Sheet,
+    // Mark the cells changed since then in the grid (:set changemarks)
+    pub change_marks: bool,
     // Visual mode selection
     pub visual_start_col: usize,
     pub visual_start_row: usize,
+    // Bounds of the selection a command was started from ('<,'>)
+    pub last_selection: Option<(usize, usize, usize, usize)>,
+    // Area picked up with m in Visual mode; the selection shows where it
+    // lands until Enter drops it there
+    pub grab: Option<(usize, usize, usize, usize)>,
+    // Selection the input being typed goes into, from r or c in Visual
+    // mode; its top-left cell is the one edited
+    pub fill_area: Option<(usize, usize, usize, usize)>,
+    // Chart shown in Chart mode
+    pub chart: Option<chart::Chart>,
+    // Lines shown in List mode
+    pub list: Option<ListView>,
+    // Second window (:split, :vsplit)
+    pub split: Option<Split>,
+    // Scrolling one window scrolls the other as far (:set scrollbind)
+    pub scroll_bind: bool,
+    // Cells found by the last scan (:check, :errors)
+    pub quickfix: Option<quickfix::Quickfix>,
+    // Cells traced by :precedents or :dependents, until Esc or a change
+    pub trace: Option<Trace>,
+    // Named copies of the sheet (:snapshot)
+    pub snapshots: Vec<snapshot::Snapshot>,
+    // Cells changed since the snapshot given to :diff, until Esc or :diff off
+    pub diff: Option<HashSet<(usize, usize)>>,
+    // Cells jumped away from, for Ctrl-O and Ctrl-I
+    pub jumps: jumplist::JumpList,
+    // Keys being written to a file (:set keylog)
+    pub keylog: Option<keylog::KeyLog>,
+    // Formula values logged as they change (:set auditlog)
+    pub audit: Option<audit::AuditLog>,
+    // Crash recovery copy of unsaved changes
+    pub swap: swap::Swap,
+    pub lock: lockfile::FileLock,
+    // Another vicalc has the file open: :w needs a !
+    pub read_only: bool,
+    // Template the sheet was made from (:newfrom), while it has no name
+    pub template: Option<String>,
+    // File where each file's cursor is kept between sessions; None in
+    // tests, replays and batch runs
+    pub session: Option<std::path::PathBuf>,
+    // Session time and cells entered, for :set stats
+    pub stats: stats::Stats,
+    // Screen-reader announcements (:set a11y)
+    pub a11y: a11y::Announcer,
+    // When formulas are worked out (:set calc), and the background worker
+    pub calc: calc::Calc,
+    // Settings from the config file and :set
+    pub colwidth: usize,
+    // How numbers are shown and typed (:set numfmt, :set decimal)
+    pub number_style: cell::NumberStyle,
+    // Rows scrolled per wheel notch, and whether the cursor moves with them
+    pub wheel_scroll: usize,
+    pub wheel_drag: bool,
+    pub keymap: config::Keymap,
+    pub aliases: HashMap<String, String>,
+    // Commands from the config file, by name: a program or a Rhai file
+    pub plugins: HashMap<String, String>,
+    // Recorded and saved keyboard macros (q, @, :macro)
+    pub macros: macros::Macros,
+    pub theme: theme::Theme,
     // Original cell content before editing (for cancel)
     pub edit_original: String,
     // Search
     pub last_search: String,
     pub search_forward: bool,
+    // Where the last search put the cursor relative to the match
+    pub search_offset: Option<commands::SearchOffset>,
+    // Area the last search was limited to (`:/total in B:B`)
+    pub search_scope: Option<(usize, usize, usize, usize)>,
+    // Whether searches match values or formulas (:set searchtarget)
+    pub search_target: sheet::SearchTarget,
+    // Whether search patterns are regular expressions (:set regex)
+    pub search_regex: bool,
+    // Pattern of the last search, its matches highlighted until :noh
+    pub hlsearch: Option<sheet::Pattern>,
+    // Row labels count rows from the cursor (:set relativenumber)
+    pub relative_number: bool,
+    // The command line being run, to label its undo entry
+    pub undo_label: Option<String>,
+    // Rows typed below a table take on its look, validation and formulas
+    // (:set autoextend)
+    pub auto_extend: bool,
     // Register pending ("* for system clipboard)
     pub register_pending: bool,
+    pub register: Option<char>,
     // Last paste info for repeat paste (pp)
     pub last_paste_cols: usize,
     pub last_paste_rows: usize,
+    // Reference being pointed at with the arrow keys while typing a formula
+    pub point_ref: Option<PointRef>,
+    // Values of the column offered with Ctrl-N/Ctrl-P while typing
+    pub completion: Option<Completion>,
+    pub snippets: snippet::Snippets,
+    // Places left to fill in the snippet being typed, next one last, in
+    // characters from the end of the edit buffer (typing at the cursor
+    // leaves them where they are)
+    pub snippet_stops: Vec<usize>,
+    // Function menu opened by = on an empty cell (:set funcmenu)
+    pub func_menu: Option<funcmenu::FuncMenu>,
+    pub func_menu_enabled: bool,
+    // Terminal size (width, height), refreshed on resize events only
+    pub term_size: (u16, u16),
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Clone)]
@@ -77,84 +226,676 @@ pub struct ClipboardContent {
     pub height: usize,
 }
 
+impl ClipboardContent {
+    /// Estimated heap usage in bytes
+    pub fn heap_size(&self) -> usize {
+        self.cells
+            .iter()
+            .map(|row| {
+                row.capacity() * std::mem::size_of::<(String, crate::cell::CellValue)>()
+                    + row.iter().map(|(input, value)| input.capacity() + value.heap_size()).sum::<usize>()
+            })
+            .sum()
+    }
+}
+
+/// Cursor and scroll position of a window onto the sheet
+#[derive(Clone, Copy, Default)]
+pub struct Window {
+    pub cursor_col: usize,
+    pub cursor_row: usize,
+    pub view_col: usize,
+    pub view_row: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SplitDir {
+    Horizontal, // :split - one window above the other
+    Vertical,   // :vsplit - side by side
+}
+
+/// Two windows onto the sheet. The active window's cursor and viewport are
+/// the App fields as usual; the other window is parked here.
+pub struct Split {
+    pub dir: SplitDir,
+    pub other: Window,
+    // Whether the active window is the top/left one
+    pub active_first: bool,
+}
+
+/// Screen area of a window: the column header row followed by the grid rows
+#[derive(Clone, Copy)]
+pub struct Pane {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Pane {
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+/// Read-only text shown full screen in List mode
+pub struct ListView {
+    pub title: String,
+    pub lines: Vec<String>,
+    // Index of the first line shown
+    pub scroll: usize,
+}
+
+/// Cells highlighted by :precedents or :dependents
+pub struct Trace {
+    pub dependents: bool,
+    pub cells: HashSet<(usize, usize)>,
+}
+
+/// Values from the same column offered for the text being typed
+pub struct Completion {
+    // Text as typed before completing
+    pub typed: String,
+    pub matches: Vec<String>,
+    // Match in the edit buffer, None when back at the typed text
+    pub index: Option<usize>,
+}
+
+/// Reference inserted into the edit buffer by pointing at cells
+#[derive(Clone, Copy)]
+pub struct PointRef {
+    pub anchor_col: usize,
+    pub anchor_row: usize,
+    pub col: usize,
+    pub row: usize,
+    // Character range of the inserted reference text within input_buffer
+    pub start: usize,
+    pub len: usize,
+}
+
 impl App {
     pub fn new() -> Self {
-        let mut app = App {
+        App {
             sheet: Sheet::new(),
             mode: Mode::Normal,
             axis: EditAxis::Row,
             input_buffer: String::new(),
+            input_cursor: 0,
             command_buffer: String::new(),
-            status_message: String::new(),
+            cmd_history: cmdhistory::CmdHistory::default(),
+            cmd_completion: None,
+            messages: Messages::default(),
             cursor_col: 0,
             cursor_row: 0,
             view_col: 0,
             view_row: 0,
             clipboard: None,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
+            history: History::default(),
+            width_change: None,
             running: true,
             pending_operator: None,
             count_buffer: String::new(),
             slash_pending: false,
+            goto_buffer: None,
             current_file: None,
+            modified: false,
+            saved_sheet: sheet::Sheet::new(),
+            change_marks: true,
             visual_start_col: 0,
             visual_start_row: 0,
+            last_selection: None,
+            grab: None,
+            fill_area: None,
+            chart: None,
+            list: None,
+            split: None,
+            scroll_bind: false,
+            quickfix: None,
+            trace: None,
+            snapshots: Vec::new(),
+            diff: None,
+            jumps: jumplist::JumpList::default(),
+            keylog: None,
+            audit: None,
+            swap: swap::Swap::default(),
+            lock: lockfile::FileLock::default(),
+            read_only: false,
+            template: None,
+            session: None,
+            stats: stats::Stats::new(Instant::now()),
+            a11y: a11y::Announcer::default(),
+            calc: calc::Calc::default(),
+            colwidth: sheet::DEFAULT_COL_WIDTH,
+            number_style: cell::NumberStyle::default(),
+            wheel_scroll: 3,
+            wheel_drag: true,
+            keymap: config::Keymap::default(),
+            aliases: HashMap::new(),
+            plugins: HashMap::new(),
+            macros: macros::Macros::default(),
+            theme: theme::Theme::initial(),
             edit_original: String::new(),
             last_search: String::new(),
             search_forward: true,
+            search_offset: None,
+            search_scope: None,
+            search_target: sheet::SearchTarget::Value,
+            search_regex: false,
+            hlsearch: None,
+            relative_number: false,
+            undo_label: None,
+            auto_extend: false,
             register_pending: false,
+            register: None,
             last_paste_cols: 0,
             last_paste_rows: 0,
+            point_ref: None,
+            completion: None,
+            snippets: snippet::Snippets::default(),
+            snippet_stops: Vec::new(),
+            func_menu: None,
+            func_menu_enabled: false,
+            term_size: terminal::size().unwrap_or((80, 24)),
+        }
+    }
+
+    /// Number of sheet rows shown in the active window
+    pub fn grid_height(&self) -> usize {
+        (self.panes().0.height as usize).saturating_sub(1)
+    }
+
+    /// Screen areas of the active window and, when split, the other one.
+    /// Windows share the space between the status bar and the formula bar.
+    pub fn panes(&self) -> (Pane, Option<Pane>) {
+        let (width, height) = self.term_size;
+        let whole = Pane { x: 0, y: 1, width, height: height.saturating_sub(3 + self.quickfix_height()) };
+        let Some(split) = &self.split else {
+            return (whole, None);
         };
-        app.update_status();
-        app
-    }
-
-    fn update_status(&mut self) {
-        let mode_str = match self.mode {
-            Mode::Normal => "NORMAL",
-            Mode::EditSingle => "EDIT",
-            Mode::EditContinuous => "EDIT+",
-            Mode::EditPreserve => "EDIT",
-            Mode::Command => "COMMAND",
-            Mode::Visual => "VISUAL",
+        let (first, second) = match split.dir {
+            SplitDir::Horizontal => {
+                let top = whole.height / 2;
+                (
+                    Pane { height: top, ..whole },
+                    Pane { y: whole.y + top, height: whole.height - top, ..whole },
+                )
+            }
+            SplitDir::Vertical => {
+                // One column in between for the separator
+                let left = width.saturating_sub(1) / 2;
+                (
+                    Pane { width: left, ..whole },
+                    Pane { x: left + 1, width: width.saturating_sub(left + 1), ..whole },
+                )
+            }
         };
-        let axis_str = match self.axis {
-            EditAxis::Row => "Row",
-            EditAxis::Column => "Col",
+        if split.active_first {
+            (first, Some(second))
+        } else {
+            (second, Some(first))
+        }
+    }
+
+    /// Cursor and viewport of the active window
+    pub fn window(&self) -> Window {
+        Window {
+            cursor_col: self.cursor_col,
+            cursor_row: self.cursor_row,
+            view_col: self.view_col,
+            view_row: self.view_row,
+        }
+    }
+
+    fn set_window(&mut self, window: Window) {
+        self.cursor_col = window.cursor_col;
+        self.cursor_row = window.cursor_row;
+        self.view_col = window.view_col;
+        self.view_row = window.view_row;
+    }
+
+    /// Open a second window on the same place in the sheet
+    pub fn split_window(&mut self, dir: SplitDir) {
+        if self.split.is_some() {
+            self.warn("Already split (:only closes the other window)");
+            return;
+        }
+        self.split = Some(Split { dir, other: self.window(), active_first: true });
+        self.adjust_view();
+        // The other window has shrunk too
+        self.switch_window();
+        self.adjust_view();
+        self.switch_window();
+    }
+
+    /// Make the other window the active one
+    pub fn switch_window(&mut self) {
+        let current = self.window();
+        if let Some(split) = &mut self.split {
+            let other = std::mem::replace(&mut split.other, current);
+            split.active_first = !split.active_first;
+            self.set_window(other);
+        }
+    }
+
+    /// Move to the window in a direction (Ctrl-w h/j/k/l): `first` asks for
+    /// the top or left one
+    pub fn focus_window(&mut self, dir: SplitDir, first: bool) {
+        if self.split.as_ref().is_some_and(|s| s.dir == dir && s.active_first != first) {
+            self.switch_window();
+        }
+    }
+
+    /// Where the active window's view starts, to scroll the other window
+    /// along with it afterwards (:set scrollbind)
+    pub fn scroll_anchor(&self) -> Option<(bool, usize)> {
+        self.split.as_ref().filter(|_| self.scroll_bind).map(|split| (split.active_first, self.view_row))
+    }
+
+    /// Scroll the other window, cursor and all, by as many rows as the active
+    /// one has scrolled since `anchor`. Switching windows is not scrolling.
+    pub fn scroll_bound(&mut self, anchor: Option<(bool, usize)>) {
+        let Some((active_first, view_row)) = anchor else {
+            return;
         };
-        let cell_name = crate::formula::cell_name(self.cursor_col, self.cursor_row);
-        let file_str = self.current_file.as_deref().unwrap_or("[New]");
-        self.status_message = format!("{} | {} | {} | {}", mode_str, cell_name, axis_str, file_str);
+        let scrolled = self.view_row as isize - view_row as isize;
+        if let Some(split) = self.split.as_mut().filter(|s| s.active_first == active_first && scrolled != 0) {
+            let other = &mut split.other;
+            other.view_row = (other.view_row as isize + scrolled).clamp(0, 9999) as usize;
+            other.cursor_row = (other.cursor_row as isize + scrolled).clamp(0, 9999) as usize;
+        }
+    }
+
+    /// Close the active window, keeping the other one
+    pub fn close_window(&mut self) {
+        if let Some(split) = self.split.take() {
+            self.set_window(split.other);
+            self.adjust_view();
+        } else {
+            self.warn("Cannot close the last window");
+        }
+    }
+
+    /// Close the other window
+    pub fn only_window(&mut self) {
+        if self.split.take().is_some() {
+            self.adjust_view();
+        }
+    }
+
+    /// Lines taken by the quickfix panel, between the windows and the formula bar
+    fn quickfix_height(&self) -> u16 {
+        let height = self.quickfix.as_ref().map_or(0, |qf| qf.height());
+        // Leave the windows a few rows on a small terminal
+        height.min(self.term_size.1.saturating_sub(6))
+    }
+
+    /// Screen area of the quickfix panel, if it is open
+    pub fn quickfix_pane(&self) -> Option<Pane> {
+        let height = self.quickfix_height();
+        (height > 0).then(|| Pane { x: 0, y: self.term_size.1.saturating_sub(2 + height), width: self.term_size.0, height })
+    }
+
+    /// Switch to a newly loaded or created sheet
+    pub fn replace_sheet(&mut self, sheet: Sheet) {
+        self.sheet = sheet;
+        self.sheet.set_default_col_width(self.colwidth);
+        self.sheet.set_number_style(self.number_style);
+        self.reset_windows();
+    }
+
+    /// Show and read numbers in `style` (:set numfmt, :set decimal)
+    pub fn set_number_style(&mut self, style: cell::NumberStyle) {
+        self.number_style = style;
+        self.sheet.set_number_style(style);
+    }
+
+    /// Move every window to A1 (a new sheet was loaded)
+    pub fn reset_windows(&mut self) {
+        self.set_window(Window::default());
+        if let Some(split) = &mut self.split {
+            split.other = Window::default();
+        }
+    }
+
+    /// Keep the cursor visible in every window (after a resize)
+    pub fn adjust_views(&mut self) {
+        self.adjust_view();
+        if self.split.is_some() {
+            self.switch_window();
+            self.adjust_view();
+            self.switch_window();
+        }
+    }
+
+    /// Activate the window under a screen position; true if it changed
+    pub fn focus_window_at(&mut self, x: u16, y: u16) -> bool {
+        match self.panes().1 {
+            Some(other) if other.contains(x, y) => {
+                self.switch_window();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Status line notifications
+
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.messages.push(Severity::Info, text.into());
+    }
+
+    pub fn warn(&mut self, text: impl Into<String>) {
+        self.messages.push(Severity::Warning, text.into());
+    }
+
+    pub fn error(&mut self, text: impl Into<String>) {
+        self.messages.push(Severity::Error, text.into());
     }
 
+    /// Save undo before the change made by the command being run, labeled
+    /// with the command
     pub fn save_undo(&mut self) {
-        self.undo_stack.push(self.sheet.clone());
-        self.redo_stack.clear();
-        if self.undo_stack.len() > 100 {
-            self.undo_stack.remove(0);
+        let label = self.undo_label();
+        self.save_undo_as(&label);
+    }
+
+    /// Save undo before a change, labeled for :undolist
+    pub fn save_undo_as(&mut self, label: &str) {
+        self.history.push(self.sheet.clone(), self.current_file.clone(), label);
+        if let Some(log) = self.audit.as_mut() {
+            log.change(label);
         }
+        self.modified = true;
+        // The traced cells may no longer be the ones that depend on each other
+        self.trace = None;
+    }
+
+    /// Note the sheet as just loaded or saved: nothing left to write
+    pub fn mark_saved(&mut self) {
+        self.modified = false;
+        self.swap.clean(self.history.generation());
+        self.saved_sheet = self.sheet.clone();
+    }
+
+    /// What the change being made is called in the undo history: the
+    /// command line being run, else "edit"
+    pub fn undo_label(&self) -> String {
+        self.undo_label.clone().unwrap_or_else(|| "edit".to_string())
+    }
+
+    /// Save undo before widening or narrowing a column. Widths, like the
+    /// filter and hidden rows, belong to the sheet and are undone with it;
+    /// pressing `>` several times on one column is a single step.
+    pub fn save_width_undo(&mut self, col: usize) {
+        if self.width_change != Some((col, self.history.generation())) {
+            self.save_undo();
+            self.width_change = Some((col, self.history.generation()));
+        }
+        self.modified = true;
     }
 
     pub fn undo(&mut self) {
-        if let Some(prev) = self.undo_stack.pop() {
-            self.redo_stack.push(self.sheet.clone());
-            self.sheet = prev;
-            self.status_message = "Undo".to_string();
-        } else {
-            self.status_message = "Nothing to undo".to_string();
+        match self.history.undo(self.sheet.clone(), self.current_file.clone()) {
+            Some(entry) => self.restore_entry(entry, "Undo"),
+            None => self.warn("Nothing to undo"),
+        }
+    }
+
+    /// Put back a sheet from the undo history, and the file it was open as
+    /// when undo or redo crosses `:e`
+    fn restore_entry(&mut self, entry: history::Entry, action: &str) {
+        if let Some(log) = self.audit.as_mut() {
+            log.other(format!("{} {}", action.to_lowercase(), entry.label));
+        }
+        self.sheet = entry.sheet;
+        self.sheet.set_number_style(self.number_style);
+        self.modified = true;
+        if entry.file == self.current_file {
+            self.info(format!("{}: {}", action, entry.label));
+            return;
         }
+        self.current_file = entry.file;
+        self.template = None;
+        match &self.current_file {
+            Some(file) => {
+                let file = file.clone();
+                self.read_only = self.lock.take(&file).is_some();
+                self.info(format!("{}: {} (back to {})", action, entry.label, file));
+            }
+            None => {
+                self.lock.release();
+                self.read_only = false;
+                self.info(format!("{}: {} (back to an unnamed sheet)", action, entry.label));
+            }
+        }
+    }
+
+    /// Work out every formula again (F9, :calc)
+    pub fn recalculate(&mut self) {
+        let started = Instant::now();
+        self.sheet.recalculate();
+        if let Some(log) = self.audit.as_mut() {
+            log.other("recalculate".to_string());
+        }
+        let formulas = self.sheet.cells().values().filter(|cell| cell.raw_input.starts_with('=')).count();
+        self.info(format!("Recalculated {} formulas in {} ms", formulas, started.elapsed().as_millis()));
     }
 
     pub fn redo(&mut self) {
-        if let Some(next) = self.redo_stack.pop() {
-            self.undo_stack.push(self.sheet.clone());
-            self.sheet = next;
-            self.status_message = "Redo".to_string();
-        } else {
-            self.status_message = "Nothing to redo".to_string();
+        match self.history.redo(self.sheet.clone(), self.current_file.clone()) {
+            Some(entry) => self.restore_entry(entry, "Redo"),
+            None => self.warn("Nothing to redo"),
+        }
+    }
+
+    // Edit buffer operations (the cursor counts characters, not bytes)
+
+    /// Replace the edit buffer and put the cursor at its end
+    pub fn set_input(&mut self, text: String) {
+        self.input_cursor = text.chars().count();
+        self.input_buffer = text;
+    }
+
+    pub fn clear_input(&mut self) {
+        self.input_buffer.clear();
+        self.input_cursor = 0;
+        self.point_ref = None;
+        self.completion = None;
+        self.snippet_stops.clear();
+        self.func_menu = None;
+        self.fill_area = None;
+    }
+
+    /// Tab while editing: go to the next place to fill in of the snippet
+    /// being typed, or expand the snippet whose trigger is before the
+    /// cursor. False if there is neither, so Tab moves to the next cell.
+    pub fn snippet_tab(&mut self) -> bool {
+        let len = self.input_buffer.chars().count();
+        if let Some(from_end) = self.snippet_stops.pop() {
+            self.input_cursor = len.saturating_sub(from_end);
+            return true;
+        }
+        let (before, after) = self.input_split();
+        let Some((trigger, text)) = self.snippets.find(before) else {
+            return false;
+        };
+        let start = before[..before.len() - trigger.len()].to_string();
+        let after = after.to_string();
+        let text = text.to_string();
+        self.insert_template(start, &text, after);
+        true
+    }
+
+    /// Put a snippet or function template between `start` and `after` in
+    /// the edit buffer, the cursor on its first place to fill in
+    pub fn insert_template(&mut self, start: String, template: &str, after: String) {
+        let (text, stops) = snippet::expand(template);
+        let offset = start.chars().count();
+        let expanded_len = text.chars().count();
+        self.input_buffer = format!("{}{}{}", start, text, after);
+        let len = self.input_buffer.chars().count();
+        let mut stops = stops.into_iter().map(|s| offset + s);
+        self.input_cursor = stops.next().unwrap_or(offset + expanded_len);
+        self.snippet_stops = stops.map(|s| len - s).rev().collect();
+    }
+
+    /// Replace the edit buffer with the next (or previous) value from the
+    /// same column that starts with what was typed. After the last match
+    /// the typed text comes back, as with vim's Ctrl-N.
+    pub fn complete(&mut self, forward: bool) {
+        if self.input_buffer.starts_with('=') {
+            return;
+        }
+        let mut completion = match self.completion.take() {
+            Some(completion) => completion,
+            None => {
+                let matches = self.sheet.column_completions(self.cursor_col, self.cursor_row, &self.input_buffer);
+                if matches.is_empty() {
+                    self.warn("No completions in this column");
+                    return;
+                }
+                Completion { typed: self.input_buffer.clone(), matches, index: None }
+            }
+        };
+        let count = completion.matches.len();
+        completion.index = match (completion.index, forward) {
+            (None, true) => Some(0),
+            (None, false) => Some(count - 1),
+            (Some(i), true) => (i + 1 < count).then_some(i + 1),
+            (Some(i), false) => i.checked_sub(1),
+        };
+        self.input_buffer = match completion.index {
+            Some(i) => completion.matches[i].clone(),
+            None => completion.typed.clone(),
+        };
+        self.input_end();
+        self.completion = Some(completion);
+    }
+
+    /// Byte offset of the edit cursor, clamped to the buffer
+    fn input_byte_pos(&self) -> usize {
+        self.input_buffer
+            .char_indices()
+            .nth(self.input_cursor)
+            .map(|(i, _)| i)
+            .unwrap_or(self.input_buffer.len())
+    }
+
+    pub fn input_insert(&mut self, c: char) {
+        let pos = self.input_byte_pos();
+        self.input_buffer.insert(pos, c);
+        self.input_cursor = self.input_buffer[..pos].chars().count() + 1;
+    }
+
+    /// Delete the character before the cursor
+    pub fn input_backspace(&mut self) {
+        let pos = self.input_byte_pos();
+        if let Some(c) = self.input_buffer[..pos].chars().next_back() {
+            self.input_buffer.remove(pos - c.len_utf8());
+            self.input_cursor = self.input_buffer[..pos - c.len_utf8()].chars().count();
+        }
+    }
+
+    /// Delete the character under the cursor
+    pub fn input_delete(&mut self) {
+        let pos = self.input_byte_pos();
+        if pos < self.input_buffer.len() {
+            self.input_buffer.remove(pos);
+        }
+    }
+
+    pub fn input_move(&mut self, delta: isize) {
+        let len = self.input_buffer.chars().count();
+        let cur = self.input_cursor.min(len) as isize;
+        self.input_cursor = (cur + delta).clamp(0, len as isize) as usize;
+    }
+
+    pub fn input_home(&mut self) {
+        self.input_cursor = 0;
+    }
+
+    pub fn input_end(&mut self) {
+        self.input_cursor = self.input_buffer.chars().count();
+    }
+
+    /// Split the edit buffer at the cursor (text before, text after)
+    pub fn input_split(&self) -> (&str, &str) {
+        self.input_buffer.split_at(self.input_byte_pos())
+    }
+
+    // Point mode (selecting formula references with the arrow keys or mouse)
+
+    /// Whether a reference can be inserted at the edit cursor: the buffer is a
+    /// formula and the text before the cursor ends with an operator, '(' or ','
+    pub fn can_point(&self) -> bool {
+        if self.point_ref.is_some() {
+            return true;
+        }
+        if !self.input_buffer.starts_with('=') {
+            return false;
+        }
+        let (before, _) = self.input_split();
+        matches!(
+            before.trim_end().chars().next_back(),
+            Some('=' | '+' | '-' | '*' | '/' | '^' | '&' | '<' | '>' | '(' | ',')
+        )
+    }
+
+    /// Point at (col, row). With `extend`, the reference becomes a range from
+    /// the current anchor; otherwise it is a single cell.
+    pub fn point_at(&mut self, col: usize, row: usize, extend: bool) {
+        let col = col.min(255);
+        let row = row.min(9999);
+        let mut point = match self.point_ref {
+            Some(p) => p,
+            None => PointRef {
+                anchor_col: col,
+                anchor_row: row,
+                col,
+                row,
+                start: self.input_cursor.min(self.input_buffer.chars().count()),
+                len: 0,
+            },
+        };
+        if !extend {
+            point.anchor_col = col;
+            point.anchor_row = row;
         }
+        point.col = col;
+        point.row = row;
+
+        let text = if (point.anchor_col, point.anchor_row) == (col, row) {
+            crate::formula::cell_name(col, row)
+        } else {
+            format!(
+                "{}:{}",
+                crate::formula::cell_name(point.anchor_col.min(col), point.anchor_row.min(row)),
+                crate::formula::cell_name(point.anchor_col.max(col), point.anchor_row.max(row))
+            )
+        };
+
+        // Replace the previously inserted reference text
+        let chars: Vec<char> = self.input_buffer.chars().collect();
+        let end = (point.start + point.len).min(chars.len());
+        let mut buffer: String = chars[..point.start].iter().collect();
+        buffer.push_str(&text);
+        buffer.extend(&chars[end..]);
+        self.input_buffer = buffer;
+
+        point.len = text.chars().count();
+        self.input_cursor = point.start + point.len;
+        self.point_ref = Some(point);
+    }
+
+    /// Move the pointed reference by (dx, dy), starting from the edited cell
+    pub fn point_move(&mut self, dx: isize, dy: isize, extend: bool) {
+        let (col, row) = match self.point_ref {
+            Some(p) => (p.col, p.row),
+            None => (self.cursor_col, self.cursor_row),
+        };
+        let col = (col as isize + dx).clamp(0, 255) as usize;
+        let row = (row as isize + dy).clamp(0, 9999) as usize;
+        self.point_at(col, row, extend);
     }
 
     pub fn get_count(&mut self) -> usize {
@@ -165,27 +906,144 @@ impl App {
 
     pub fn move_cursor(&mut self, dx: isize, dy: isize) {
         let count = self.get_count() as isize;
-        let new_col = (self.cursor_col as isize + dx * count).max(0).min(255) as usize;
-        let new_row = (self.cursor_row as isize + dy * count).max(0).min(9999) as usize;
-        self.cursor_col = new_col;
-        self.cursor_row = new_row;
-        self.adjust_view();
+        // A merged area is stepped over as one cell, from its far side
+        let (col, row) = match self.sheet.merge_at(self.cursor_col, self.cursor_row) {
+            Some((c1, r1, c2, r2)) => (if dx > 0 { c2 } else { c1 }, if dy > 0 { r2 } else { r1 }),
+            None => (self.cursor_col, self.cursor_row),
+        };
+        let new_col = (col as isize + dx * count).clamp(0, 255) as usize;
+        let new_row = (row as isize + dy * count).clamp(0, 9999) as usize;
+        let new_col = self.sheet.nearest_visible_col(new_col, dx);
+        let new_row = self.sheet.nearest_visible_row(new_row, dy);
+        self.move_cursor_to(new_col, new_row);
     }
 
+    /// Move the cursor to a cell, or to the top-left cell of the merged area
+    /// it is in
     pub fn move_cursor_to(&mut self, col: usize, row: usize) {
+        let (col, row) = match self.sheet.merge_at(col, row) {
+            Some((c1, r1, _, _)) => (c1, r1),
+            None => (col, row),
+        };
         self.cursor_col = col.min(255);
         self.cursor_row = row.min(9999);
         self.adjust_view();
     }
 
+    /// Move the cursor to a cell, remembering where it was in the jump list
+    pub fn jump_to(&mut self, col: usize, row: usize) {
+        if (col, row) != (self.cursor_col, self.cursor_row) {
+            self.jumps.push((self.cursor_col, self.cursor_row));
+        }
+        self.move_cursor_to(col, row);
+    }
+
+    /// Ctrl-O and Ctrl-I: go back or forward through the jump list
+    pub fn jump_back(&mut self, forward: bool) {
+        let count = self.get_count();
+        let mut target = None;
+        for _ in 0..count {
+            let next = if forward { self.jumps.forward() } else { self.jumps.back((self.cursor_col, self.cursor_row)) };
+            match next {
+                Some(pos) => target = Some(pos),
+                None => break,
+            }
+        }
+        match target {
+            Some((col, row)) => self.move_cursor_to(col, row),
+            None if forward => self.warn("At the newest jump"),
+            None => self.warn("At the oldest jump"),
+        }
+    }
+
+    /// gd: jump to the n-th cell or range (1-based) the formula under the
+    /// cursor refers to, the first cell of a range
+    pub fn go_to_reference(&mut self, n: usize) {
+        let (col, row) = (self.cursor_col, self.cursor_row);
+        let refs = self.sheet.references(col, row);
+        if refs.is_empty() {
+            self.warn(format!("No references in {}", formula::cell_name(col, row)));
+            return;
+        }
+        let Some((text, (ref_col, ref_row, _, _))) = refs.get(n.max(1) - 1).cloned() else {
+            self.warn(format!("{} has {} references", formula::cell_name(col, row), refs.len()));
+            return;
+        };
+        self.jump_to(ref_col, ref_row);
+        self.info(format!("{} ({} of {}), Ctrl-O to go back", text, n.max(1), refs.len()));
+    }
+
+    /// Ctrl-]: list every cell and range the formula under the cursor refers
+    /// to in the quickfix panel, and go to the first
+    pub fn list_references(&mut self) {
+        let (col, row) = (self.cursor_col, self.cursor_row);
+        let refs = self.sheet.references(col, row);
+        if refs.is_empty() {
+            self.warn(format!("No references in {}", formula::cell_name(col, row)));
+            return;
+        }
+        let entries = refs
+            .iter()
+            .map(|&(ref text, (c1, r1, c2, r2))| {
+                let text = if (c1, r1) == (c2, r2) {
+                    format!("value {}", self.sheet.evaluate(c1, r1))
+                } else {
+                    format!("{} ({} cells)", text, (c2 - c1 + 1) * (r2 - r1 + 1))
+                };
+                quickfix::Entry { col: c1, row: r1, text }
+            })
+            .collect();
+        quickfix::set(self, format!("References of {}", formula::cell_name(col, row)), entries);
+    }
+
+    /// Mouse wheel: scroll the view by wheelscroll rows (dy) or by a column
+    /// (dx). With wheeldrag the cursor moves along; without it, it stays
+    /// put unless it would leave the view.
+    pub fn wheel_scroll(&mut self, dx: isize, dy: isize) {
+        self.scroll_view(dx, dy * self.wheel_scroll as isize, self.wheel_drag);
+    }
+
+    /// Scroll the view by columns and rows, moving the cursor along by as
+    /// much (drag) or only as far as needed to keep it in view
+    pub fn scroll_view(&mut self, cols: isize, rows: isize, drag: bool) {
+        self.view_col = self.sheet.nearest_visible_col((self.view_col as isize + cols).clamp(0, 255) as usize, cols);
+        self.view_row = self.sheet.nearest_visible_row((self.view_row as isize + rows).clamp(0, 9999) as usize, rows);
+        if drag {
+            self.cursor_col = (self.cursor_col as isize + cols).clamp(0, 255) as usize;
+            self.cursor_row = (self.cursor_row as isize + rows).clamp(0, 9999) as usize;
+        } else {
+            let (last_col, last_row) = self.last_shown();
+            self.cursor_col = self.cursor_col.clamp(self.view_col, last_col.max(self.view_col));
+            self.cursor_row = self.cursor_row.clamp(self.view_row, last_row.max(self.view_row));
+        }
+        self.adjust_view();
+    }
+
+    /// The last column and row shown whole in the active window
+    fn last_shown(&self) -> (usize, usize) {
+        const ROW_LABEL_WIDTH: usize = 5;
+        let available_width = (self.panes().0.width as usize).saturating_sub(ROW_LABEL_WIDTH);
+        let mut x = 0;
+        let mut last_col = self.view_col;
+        for col in (self.view_col..=255).filter(|&c| !self.sheet.is_col_hidden(c)) {
+            x += self.sheet.get_col_width(col);
+            if x > available_width {
+                break;
+            }
+            last_col = col;
+        }
+        let rows = self.sheet.visible_rows(self.view_row, self.grid_height().max(1));
+        (last_col, rows.last().copied().unwrap_or(self.view_row))
+    }
+
     pub fn adjust_view(&mut self) {
         const ROW_LABEL_WIDTH: usize = 5;
         
-        let (term_width, term_height) = terminal::size().unwrap_or((80, 24));
-        let available_width = (term_width as usize).saturating_sub(ROW_LABEL_WIDTH);
-        let visible_rows = (term_height as usize).saturating_sub(5);
+        let available_width = (self.panes().0.width as usize).saturating_sub(ROW_LABEL_WIDTH);
+        let visible_rows = self.grid_height().max(1);
 
         // Adjust view_col to ensure cursor is visible
+        self.cursor_col = self.sheet.nearest_visible_col(self.cursor_col, 1);
         if self.cursor_col < self.view_col {
             self.view_col = self.cursor_col;
         } else {
@@ -195,6 +1053,10 @@ impl App {
             let mut cursor_visible = false;
             
             while x < available_width && col <= 255 {
+                if self.sheet.is_col_hidden(col) {
+                    col += 1;
+                    continue;
+                }
                 let col_width = self.sheet.get_col_width(col);
                 if col == self.cursor_col {
                     if x + col_width <= available_width {
@@ -212,24 +1074,35 @@ impl App {
             }
         }
 
-        // Adjust view_row
+        // Adjust view_row, counting only rows that are not hidden
+        self.cursor_row = self.sheet.nearest_visible_row(self.cursor_row, 1);
         if self.cursor_row < self.view_row {
             self.view_row = self.cursor_row;
-        } else if self.cursor_row >= self.view_row + visible_rows {
-            self.view_row = self.cursor_row.saturating_sub(visible_rows - 1);
+        } else if self.sheet.visible_rows(self.view_row, visible_rows).last().is_some_and(|&last| self.cursor_row > last) {
+            let shown = (0..=self.cursor_row)
+                .rev()
+                .filter(|&r| !self.sheet.is_row_hidden(r))
+                .take(visible_rows)
+                .last();
+            self.view_row = shown.unwrap_or(self.cursor_row);
         }
     }
 
+    /// Cell under a screen position in the active window
     pub fn screen_to_cell(&self, screen_col: u16, screen_row: u16) -> Option<(usize, usize)> {
         const ROW_LABEL_WIDTH: usize = 5;
-        const HEADER_ROWS: usize = 2;  // status bar + column headers
 
-        let screen_col = screen_col as usize;
-        let screen_row = screen_row as usize;
+        let pane = self.panes().0;
+        if !pane.contains(screen_col, screen_row) {
+            return None;
+        }
+        // Relative to the window: column header row, then the grid
+        const HEADER_ROWS: usize = 1;
+        let screen_col = (screen_col - pane.x) as usize;
+        let screen_row = (screen_row - pane.y) as usize;
 
-        // Get terminal size for bounds checking
-        let (term_width, term_height) = terminal::size().unwrap_or((80, 24));
-        let grid_height = (term_height as usize).saturating_sub(4);  // subtract header (2) + footer (2)
+        let term_width = pane.width;
+        let grid_height = self.grid_height();
 
         // Check if click is in the grid area
         if screen_col < ROW_LABEL_WIDTH || screen_row < HEADER_ROWS {
@@ -245,11 +1118,15 @@ impl App {
         let mut x = ROW_LABEL_WIDTH;
         let mut col = self.view_col;
         while x < term_width as usize && col <= 255 {
+            if self.sheet.is_col_hidden(col) {
+                col += 1;
+                continue;
+            }
             let col_width = self.sheet.get_col_width(col);
             if screen_col < x + col_width {
                 // Click is in this column
-                let row = self.view_row + (screen_row - HEADER_ROWS);
-                return Some((col, row));
+                let rows = self.sheet.visible_rows(self.view_row, screen_row - HEADER_ROWS + 1);
+                return rows.get(screen_row - HEADER_ROWS).map(|&row| (col, row));
             }
             x += col_width;
             col += 1;
@@ -296,8 +1173,21 @@ impl App {
     }
 
     // Structure operations
+
+    /// The cells from (col, row) to the end of its row or column along the
+    /// axis, which inserting a cell there shifts
+    fn rest_of_axis(&self, col: usize, row: usize) -> (usize, usize, usize, usize) {
+        match self.axis {
+            EditAxis::Row => (col, row, 255, row),
+            EditAxis::Column => (col, row, col, 9999),
+        }
+    }
+
     pub fn insert_at_cursor(&mut self) {
-        self.save_undo();
+        if self.refuse_locked(self.rest_of_axis(self.cursor_col, self.cursor_row)) {
+            return;
+        }
+        self.save_undo_as("insert cell");
         match self.axis {
             EditAxis::Row => {
                 self.sheet.shift_cells_right(self.cursor_col, self.cursor_row);
@@ -307,13 +1197,19 @@ impl App {
             }
         }
         self.mode = Mode::EditSingle;
-        self.input_buffer.clear();
+        self.clear_input();
         self.edit_original.clear();
-        self.update_status();
     }
 
     pub fn insert_at_start(&mut self) {
-        self.save_undo();
+        let start = match self.axis {
+            EditAxis::Row => (0, self.cursor_row),
+            EditAxis::Column => (self.cursor_col, 0),
+        };
+        if self.refuse_locked(self.rest_of_axis(start.0, start.1)) {
+            return;
+        }
+        self.save_undo_as("insert cell");
         match self.axis {
             EditAxis::Row => {
                 self.cursor_col = 0;
@@ -326,13 +1222,19 @@ impl App {
         }
         self.adjust_view();
         self.mode = Mode::EditSingle;
-        self.input_buffer.clear();
+        self.clear_input();
         self.edit_original.clear();
-        self.update_status();
     }
 
     pub fn append_after_cursor(&mut self) {
-        self.save_undo();
+        let next = match self.axis {
+            EditAxis::Row => (self.cursor_col + 1, self.cursor_row),
+            EditAxis::Column => (self.cursor_col, self.cursor_row + 1),
+        };
+        if self.refuse_locked(self.rest_of_axis(next.0, next.1)) {
+            return;
+        }
+        self.save_undo_as("insert cell");
         match self.axis {
             EditAxis::Row => {
                 self.cursor_col += 1;
@@ -345,9 +1247,8 @@ impl App {
         }
         self.adjust_view();
         self.mode = Mode::EditSingle;
-        self.input_buffer.clear();
+        self.clear_input();
         self.edit_original.clear();
-        self.update_status();
     }
 
     pub fn goto_axis_end_next(&mut self) {
@@ -364,64 +1265,231 @@ impl App {
         self.adjust_view();
     }
 
+    /// dd: delete rows or columns from the cursor on (count-aware), as one
+    /// undo step
     pub fn delete_structure(&mut self) {
-        self.save_undo();
+        let count = self.get_count().max(1);
+        let lines = match self.axis {
+            EditAxis::Row => (0, self.cursor_row, 255, (self.cursor_row + count - 1).min(9999)),
+            EditAxis::Column => (self.cursor_col, 0, (self.cursor_col + count - 1).min(255), 9999),
+        };
+        if self.refuse_locked(lines) {
+            return;
+        }
+        self.save_undo_as("delete");
+        match self.axis {
+            EditAxis::Row => {
+                let count = lines.3 - lines.1 + 1;
+                self.sheet.delete_rows(self.cursor_row, count);
+                self.info(if count == 1 { "Row deleted".to_string() } else { format!("{} rows deleted", count) });
+            }
+            EditAxis::Column => {
+                let count = lines.2 - lines.0 + 1;
+                self.sheet.delete_cols(self.cursor_col, count);
+                self.info(if count == 1 { "Column deleted".to_string() } else { format!("{} columns deleted", count) });
+            }
+        }
+    }
+
+    /// Hide rows or columns starting at the cursor (zh, count-aware)
+    pub fn hide_structure(&mut self) {
+        let count = self.get_count().max(1);
+        self.save_undo_as("hide");
+        match self.axis {
+            EditAxis::Row => {
+                let hidden = self.sheet.set_rows_hidden(self.cursor_row, self.cursor_row + count - 1, true);
+                self.info(format!("{} row(s) hidden", hidden));
+            }
+            EditAxis::Column => {
+                let hidden = self.sheet.set_cols_hidden(self.cursor_col, self.cursor_col + count - 1, true);
+                self.info(format!("{} column(s) hidden", hidden));
+            }
+        }
+        self.adjust_view();
+    }
+
+    /// Show all hidden rows or columns along the current axis (zs)
+    pub fn show_structure(&mut self) {
+        self.save_undo_as("show hidden");
         match self.axis {
             EditAxis::Row => {
-                self.sheet.adjust_formulas_for_row_delete(self.cursor_row);
-                self.sheet.delete_row(self.cursor_row);
-                self.status_message = "Row deleted".to_string();
+                let shown = self.sheet.set_rows_hidden(0, 9999, false);
+                self.info(format!("{} row(s) shown", shown));
             }
             EditAxis::Column => {
-                self.sheet.adjust_formulas_for_col_delete(self.cursor_col);
-                self.sheet.delete_col(self.cursor_col);
-                self.status_message = "Column deleted".to_string();
+                let shown = self.sheet.set_cols_hidden(0, 255, false);
+                self.info(format!("{} column(s) shown", shown));
             }
         }
+        self.adjust_view();
     }
 
+    /// o: insert rows below or columns to the right of the cursor
+    /// (count-aware, as many as fit on the sheet) and go to the first of them
     pub fn insert_structure_after(&mut self) {
-        self.save_undo();
+        let count = self.get_count().max(1);
+        self.save_undo_as("insert");
         match self.axis {
             EditAxis::Row => {
-                self.sheet.adjust_formulas_for_row_insert(self.cursor_row + 1);
-                self.sheet.insert_row(self.cursor_row + 1);
-                self.cursor_row += 1;
-                self.status_message = "Row inserted below".to_string();
+                let count = count.min(9999 - self.cursor_row).max(1);
+                self.sheet.insert_rows(self.cursor_row + 1, count);
+                self.cursor_row = (self.cursor_row + 1).min(9999);
+                self.info(if count == 1 { "Row inserted below".to_string() } else { format!("{} rows inserted below", count) });
             }
             EditAxis::Column => {
-                self.sheet.adjust_formulas_for_col_insert(self.cursor_col + 1);
-                self.sheet.insert_col(self.cursor_col + 1);
-                self.cursor_col += 1;
-                self.status_message = "Column inserted right".to_string();
+                let count = count.min(255 - self.cursor_col).max(1);
+                self.sheet.insert_cols(self.cursor_col + 1, count);
+                self.cursor_col = (self.cursor_col + 1).min(255);
+                self.info(if count == 1 { "Column inserted right".to_string() } else { format!("{} columns inserted right", count) });
             }
         }
         self.adjust_view();
     }
 
+    /// O: insert rows above or columns to the left of the cursor
+    /// (count-aware, as many as fit on the sheet)
     pub fn insert_structure_before(&mut self) {
-        self.save_undo();
+        let count = self.get_count().max(1);
+        self.save_undo_as("insert");
         match self.axis {
             EditAxis::Row => {
-                self.sheet.adjust_formulas_for_row_insert(self.cursor_row);
-                self.sheet.insert_row(self.cursor_row);
-                self.status_message = "Row inserted above".to_string();
+                let count = count.min(10000 - self.cursor_row);
+                self.sheet.insert_rows(self.cursor_row, count);
+                self.info(if count == 1 { "Row inserted above".to_string() } else { format!("{} rows inserted above", count) });
             }
             EditAxis::Column => {
-                self.sheet.adjust_formulas_for_col_insert(self.cursor_col);
-                self.sheet.insert_col(self.cursor_col);
-                self.status_message = "Column inserted left".to_string();
+                let count = count.min(256 - self.cursor_col);
+                self.sheet.insert_cols(self.cursor_col, count);
+                self.info(if count == 1 { "Column inserted left".to_string() } else { format!("{} columns inserted left", count) });
             }
         }
         self.adjust_view();
     }
 
+    /// Whether the area (min_col, min_row, max_col, max_row) has a locked
+    /// cell, in which case the change is refused with a warning
+    pub fn refuse_locked(&mut self, area: (usize, usize, usize, usize)) -> bool {
+        let Some((col, row)) = self.sheet.locked_in(area) else {
+            return false;
+        };
+        self.warn(format!("{} is locked (:unlock to change it)", crate::formula::cell_name(col, row)));
+        true
+    }
+
+    /// Check the edit buffer against the column type and validation rule of
+    /// the cell it goes into, or of every cell of the selection being
+    /// filled. Returns false if the input is refused and the edit must stay
+    /// open.
+    pub fn validate_input(&mut self) -> bool {
+        let area = self.fill_area.unwrap_or((self.cursor_col, self.cursor_row, self.cursor_col, self.cursor_row));
+        if self.refuse_locked(area) {
+            return false;
+        }
+        let mut warning = None;
+        for (col, row, input) in self.input_targets() {
+            let cell_name = crate::formula::cell_name(col, row);
+            if let Some(ty) = self.sheet.check_input_type(col, row, &input) {
+                self.warn(format!(
+                    "Invalid value for {}: column {} is {}, expected {}",
+                    cell_name,
+                    crate::formula::col_to_name(col),
+                    ty.name(),
+                    ty.expected()
+                ));
+                return false;
+            }
+            let Some(validation) = self.sheet.check_input(col, row, &input) else {
+                continue;
+            };
+            let expected = validation.rule.describe();
+            match validation.action {
+                crate::validation::Action::Reject => {
+                    self.warn(format!("Invalid value for {}: expected {}", cell_name, expected));
+                    return false;
+                }
+                crate::validation::Action::Warn => {
+                    warning.get_or_insert(format!("Warning: {} should be {}", cell_name, expected));
+                }
+            }
+        }
+        if let Some(warning) = warning {
+            self.warn(warning);
+        }
+        true
+    }
+
+    /// The cells the edit buffer goes into, with the input each one gets:
+    /// the cursor's cell, or the visible cells of the selection being
+    /// filled with a formula adjusted for each
+    fn input_targets(&self) -> Vec<(usize, usize, String)> {
+        let Some((c1, r1, c2, r2)) = self.fill_area else {
+            return vec![(self.cursor_col, self.cursor_row, self.input_buffer.clone())];
+        };
+        (r1..=r2)
+            .filter(|&r| !self.sheet.is_row_hidden(r))
+            .flat_map(|row| (c1..=c2).map(move |col| (col, row)))
+            .filter(|&(c, _)| !self.sheet.is_col_hidden(c))
+            .map(|(col, row)| {
+                let input = if self.input_buffer.starts_with('=') {
+                    formula::adjust_formula(&self.input_buffer, (col - c1) as isize, (row - r1) as isize)
+                } else {
+                    self.input_buffer.clone()
+                };
+                (col, row, input)
+            })
+            .collect()
+    }
+
+    /// Put the input into the cell under the cursor, as one undo step
+    fn store_input(&mut self) {
+        if let Some(area) = self.fill_area {
+            self.fill_input(area);
+            return;
+        }
+        let (col, row) = (self.cursor_col, self.cursor_row);
+        let new_cell = self.sheet.get_cell_ref(col, row).is_none();
+        self.save_undo_as("edit");
+        self.sheet.set_typed(col, row, &self.input_buffer);
+        // Typing below a table carries it on a row (:set autoextend)
+        if self.auto_extend && new_cell {
+            self.sheet.extend_table(col, row);
+        }
+        self.stats.record_entry();
+    }
+
+    /// Type into every cell of a selection at once (r or c in Visual mode):
+    /// a formula is adjusted for each cell as if filled from the top-left
+    /// one, as Ctrl+Enter does in Excel. Hidden cells are left alone.
+    pub fn edit_selection(&mut self) {
+        let area = self.get_selection_bounds();
+        self.mode = Mode::EditSingle;
+        self.move_cursor_to(area.0, area.1);
+        self.edit_original = self.sheet.get_cell(area.0, area.1).raw_input;
+        self.clear_input();
+        self.fill_area = Some(area);
+    }
+
+    fn fill_input(&mut self, area: (usize, usize, usize, usize)) {
+        if self.refuse_locked(area) {
+            return;
+        }
+        self.save_undo_as("edit");
+        let cells = self.input_targets();
+        for (col, row, input) in &cells {
+            self.sheet.set_typed(*col, *row, input);
+        }
+        self.stats.record_entry();
+        self.info(format!("Entered into {} cells of {}", cells.len(), crate::validation::area_name(area)));
+    }
+
     pub fn commit_input_and_move(&mut self) {
+        if !self.input_buffer.is_empty() && !self.validate_input() {
+            return;
+        }
         if !self.input_buffer.is_empty() {
-            self.save_undo();
-            self.sheet.set_cell(self.cursor_col, self.cursor_row, self.input_buffer.clone());
+            self.store_input();
         }
-        self.input_buffer.clear();
+        self.clear_input();
 
         match self.axis {
             EditAxis::Row => {
@@ -438,157 +1506,233 @@ impl App {
     
     /// Clear current cell (x command)
     pub fn clear_current_cell(&mut self) {
-        self.save_undo();
+        if self.refuse_locked((self.cursor_col, self.cursor_row, self.cursor_col, self.cursor_row)) {
+            return;
+        }
+        self.save_undo_as("clear");
         self.sheet.clear_cell(self.cursor_col, self.cursor_row);
-        self.status_message = "Cell cleared".to_string();
+        self.info("Cell cleared");
     }
 
-    /// Clear cells from current to end of axis (d$ command)
-    pub fn clear_to_axis_end(&mut self) {
-        self.save_undo();
-        match self.axis {
-            EditAxis::Row => {
-                // Clear from current column to end of row
-                let max_col = self.sheet.max_col_in_row(self.cursor_row).unwrap_or(self.cursor_col);
-                let mut count = 0;
-                for col in self.cursor_col..=max_col {
-                    self.sheet.clear_cell(col, self.cursor_row);
-                    count += 1;
-                }
-                self.status_message = format!("{} cells cleared", count);
+    /// Clear the cells of the current row from column `start` to `end`, or
+    /// of the current column from row `start` to `end`
+    fn clear_along_axis(&mut self, start: usize, end: usize) {
+        let area = match self.axis {
+            EditAxis::Row => (start, self.cursor_row, end, self.cursor_row),
+            EditAxis::Column => (self.cursor_col, start, self.cursor_col, end),
+        };
+        self.clear_area(area);
+    }
+
+    /// Clear the (min_col, min_row, max_col, max_row) area, unless it has
+    /// locked cells
+    fn clear_area(&mut self, (min_col, min_row, max_col, max_row): (usize, usize, usize, usize)) {
+        if self.refuse_locked((min_col, min_row, max_col, max_row)) {
+            return;
+        }
+        self.save_undo_as("clear");
+        let mut count = 0;
+        for col in min_col..=max_col {
+            for row in min_row..=max_row {
+                self.sheet.clear_cell(col, row);
+                count += 1;
             }
-            EditAxis::Column => {
-                // Clear from current row to end of column
-                let max_row = self.sheet.max_row_in_col(self.cursor_col).unwrap_or(self.cursor_row);
-                let mut count = 0;
-                for row in self.cursor_row..=max_row {
-                    self.sheet.clear_cell(self.cursor_col, row);
-                    count += 1;
-                }
-                self.status_message = format!("{} cells cleared", count);
+        }
+        self.info(format!("{} cells cleared", count));
+    }
+
+    /// d with a count and h, j, k or l: clear the cells from the cursor to
+    /// where the motion goes, both included (d3l clears four cells)
+    pub fn clear_motion(&mut self, dx: isize, dy: isize) {
+        let count = self.get_count() as isize;
+        let col = (self.cursor_col as isize + dx * count).clamp(0, 255) as usize;
+        let row = (self.cursor_row as isize + dy * count).clamp(0, 9999) as usize;
+        self.clear_to(col, row);
+    }
+
+    /// Clear the cells from the cursor to (col, row) and leave the cursor on
+    /// the first of them
+    fn clear_to(&mut self, col: usize, row: usize) {
+        let area = (self.cursor_col.min(col), self.cursor_row.min(row), self.cursor_col.max(col), self.cursor_row.max(row));
+        self.clear_area(area);
+        self.move_cursor_to(area.0, area.1);
+    }
+
+    /// w, b and e along the axis, and Ctrl+arrows: go to the start or end
+    /// of a run of filled cells (count-aware). After d the cells swept over
+    /// are cleared instead; as in vim, w and b stop short of the cell they
+    /// would go to.
+    pub fn block_move(&mut self, along_row: bool, motion: BlockMotion) {
+        let count = self.get_count();
+        let (mut col, mut row) = (self.cursor_col, self.cursor_row);
+        for _ in 0..count {
+            match self.sheet.block_motion(col, row, along_row, motion) {
+                Some(pos) => (col, row) = pos,
+                None => break,
             }
         }
+        if (col, row) == (self.cursor_col, self.cursor_row) {
+            self.pending_operator = None;
+            return;
+        }
+        if self.pending_operator != Some('d') {
+            self.move_cursor_to(col, row);
+            return;
+        }
+        self.pending_operator = None;
+        let before = |(col, row): (usize, usize)| if along_row { (col - 1, row) } else { (col, row - 1) };
+        let cursor = (self.cursor_col, self.cursor_row);
+        let (from, to) = match motion {
+            BlockMotion::NextStart => (cursor, before((col, row))),
+            BlockMotion::PrevStart => ((col, row), before(cursor)),
+            _ => (cursor, (col, row)),
+        };
+        self.move_cursor_to(from.0, from.1);
+        self.clear_to(to.0, to.1);
     }
 
-    /// Clear cells from axis start to current (d0 command)
-    pub fn clear_from_axis_start(&mut self) {
-        self.save_undo();
+    /// Position of the cursor along the axis
+    fn axis_position(&self) -> usize {
         match self.axis {
-            EditAxis::Row => {
-                // Clear from column A to current column
-                let mut count = 0;
-                for col in 0..=self.cursor_col {
-                    self.sheet.clear_cell(col, self.cursor_row);
-                    count += 1;
-                }
-                self.status_message = format!("{} cells cleared", count);
-            }
-            EditAxis::Column => {
-                // Clear from row 1 to current row
-                let mut count = 0;
-                for row in 0..=self.cursor_row {
-                    self.sheet.clear_cell(self.cursor_col, row);
-                    count += 1;
-                }
-                self.status_message = format!("{} cells cleared", count);
-            }
+            EditAxis::Row => self.cursor_col,
+            EditAxis::Column => self.cursor_row,
         }
     }
 
+    /// Clear cells from current to end of axis (d$ command)
+    pub fn clear_to_axis_end(&mut self) {
+        let pos = self.axis_position();
+        let end = match self.axis {
+            EditAxis::Row => self.sheet.max_col_in_row(self.cursor_row),
+            EditAxis::Column => self.sheet.max_row_in_col(self.cursor_col),
+        };
+        self.clear_along_axis(pos, end.unwrap_or(pos));
+    }
+
+    /// Clear cells from axis start to current (d0 command)
+    pub fn clear_from_axis_start(&mut self) {
+        self.clear_along_axis(0, self.axis_position());
+    }
+
     /// Clear cells from first non-empty to current (d^ command)
     pub fn clear_from_first_non_empty(&mut self) {
-        self.save_undo();
-        match self.axis {
-            EditAxis::Row => {
-                let start_col = self.sheet.first_non_empty_col_in_row(self.cursor_row).unwrap_or(0);
-                let mut count = 0;
-                for col in start_col..=self.cursor_col {
-                    self.sheet.clear_cell(col, self.cursor_row);
-                    count += 1;
-                }
-                self.status_message = format!("{} cells cleared", count);
-            }
-            EditAxis::Column => {
-                let start_row = self.sheet.first_non_empty_row_in_col(self.cursor_col).unwrap_or(0);
-                let mut count = 0;
-                for row in start_row..=self.cursor_row {
-                    self.sheet.clear_cell(self.cursor_col, row);
-                    count += 1;
-                }
-                self.status_message = format!("{} cells cleared", count);
-            }
-        }
+        let start = match self.axis {
+            EditAxis::Row => self.sheet.first_non_empty_col_in_row(self.cursor_row),
+            EditAxis::Column => self.sheet.first_non_empty_row_in_col(self.cursor_col),
+        };
+        self.clear_along_axis(start.unwrap_or(0), self.axis_position());
     }
 
     /// Clear cells from current to sheet end along axis (dG command)
     pub fn clear_to_sheet_end(&mut self) {
-        self.save_undo();
-        match self.axis {
-            EditAxis::Row => {
-                let max_col = self.sheet.max_col().unwrap_or(self.cursor_col);
-                let mut count = 0;
-                for col in self.cursor_col..=max_col {
-                    self.sheet.clear_cell(col, self.cursor_row);
-                    count += 1;
-                }
-                self.status_message = format!("{} cells cleared", count);
-            }
-            EditAxis::Column => {
-                let max_row = self.sheet.max_row().unwrap_or(self.cursor_row);
-                let mut count = 0;
-                for row in self.cursor_row..=max_row {
-                    self.sheet.clear_cell(self.cursor_col, row);
-                    count += 1;
-                }
-                self.status_message = format!("{} cells cleared", count);
-            }
+        let pos = self.axis_position();
+        let end = match self.axis {
+            EditAxis::Row => self.sheet.max_col(),
+            EditAxis::Column => self.sheet.max_row(),
+        };
+        self.clear_along_axis(pos, end.unwrap_or(pos));
+    }
+
+    /// Clear cells from sheet start to current along axis (dgg command)
+    pub fn clear_from_sheet_start(&mut self) {
+        self.clear_along_axis(0, self.axis_position());
+    }
+
+    /// Get selection bounds (min_col, min_row, max_col, max_row)
+    pub fn get_selection_bounds(&self) -> (usize, usize, usize, usize) {
+        let min_col = self.visual_start_col.min(self.cursor_col);
+        let max_col = self.visual_start_col.max(self.cursor_col);
+        let min_row = self.visual_start_row.min(self.cursor_row);
+        let max_row = self.visual_start_row.max(self.cursor_row);
+        (min_col, min_row, max_col, max_row)
+    }
+
+    /// Pick up the selection to move it with the movement keys (m in
+    /// Visual mode)
+    pub fn grab_selection(&mut self) {
+        let area = self.get_selection_bounds();
+        self.grab = Some(area);
+        self.info(format!("Moving {}: h/j/k/l to move, Enter to drop, Esc to cancel", crate::validation::area_name(area)));
+    }
+
+    /// Move the picked-up area by a cell, keeping it on the sheet
+    pub fn grab_move(&mut self, dx: isize, dy: isize) {
+        let (c1, r1, c2, r2) = self.get_selection_bounds();
+        let dx = dx.clamp(-(c1 as isize), 255 - c2 as isize);
+        let dy = dy.clamp(-(r1 as isize), 9999 - r2 as isize);
+        self.visual_start_col = (self.visual_start_col as isize + dx) as usize;
+        self.visual_start_row = (self.visual_start_row as isize + dy) as usize;
+        self.cursor_col = (self.cursor_col as isize + dx) as usize;
+        self.cursor_row = (self.cursor_row as isize + dy) as usize;
+        self.adjust_view();
+    }
+
+    /// Put the picked-up area back where it came from, still selected
+    pub fn grab_cancel(&mut self) {
+        let Some((c1, r1, _, _)) = self.grab.take() else {
+            return;
+        };
+        let (to_col, to_row, _, _) = self.get_selection_bounds();
+        self.grab_move(c1 as isize - to_col as isize, r1 as isize - to_row as isize);
+        self.info("Move cancelled");
+    }
+
+    /// Move the picked-up cells to where the selection is now, over what
+    /// is there, as one undo step. References to them follow.
+    pub fn grab_drop(&mut self) {
+        let Some(from) = self.grab.take() else {
+            return;
+        };
+        let to = self.get_selection_bounds();
+        let (dx, dy) = (to.0 as isize - from.0 as isize, to.1 as isize - from.1 as isize);
+        self.mode = Mode::Normal;
+        if (dx, dy) == (0, 0) || self.refuse_locked(from) || self.refuse_locked(to) {
+            return;
+        }
+        self.save_undo_as("move");
+        self.sheet.move_cells(from, dx, dy);
+        self.info(format!(
+            "Moved {} to {}",
+            crate::validation::area_name(from),
+            crate::validation::area_name(to)
+        ));
+    }
+
+    /// Move a row to another place, the rows in between closing up, with
+    /// the cursor going along (:moverow, ]e and [e)
+    pub fn move_row(&mut self, from: usize, to: usize) {
+        if from == to || self.refuse_locked((0, from.min(to), 255, from.max(to))) {
+            return;
+        }
+        self.save_undo_as("move row");
+        self.sheet.move_row(from, to);
+        self.move_cursor_to(self.cursor_col, to);
+        self.info(format!("Moved row {} to {}", from + 1, to + 1));
+    }
+
+    /// Move a column to another place (:movecol, ]e and [e in Column mode)
+    pub fn move_col(&mut self, from: usize, to: usize) {
+        if from == to || self.refuse_locked((from.min(to), 0, from.max(to), 9999)) {
+            return;
         }
+        self.save_undo_as("move column");
+        self.sheet.move_col(from, to);
+        self.move_cursor_to(to, self.cursor_row);
+        self.info(format!("Moved column {} to {}", formula::col_to_name(from), formula::col_to_name(to)));
     }
 
-    /// Clear cells from sheet start to current along axis (dgg command)
-    pub fn clear_from_sheet_start(&mut self) {
-        self.save_undo();
+    /// ]e and [e: move the cursor's row down or up, or in Column mode its
+    /// column right or left, by `delta` places
+    pub fn move_line(&mut self, delta: isize) {
         match self.axis {
-            EditAxis::Row => {
-                let mut count = 0;
-                for col in 0..=self.cursor_col {
-                    self.sheet.clear_cell(col, self.cursor_row);
-                    count += 1;
-                }
-                self.status_message = format!("{} cells cleared", count);
-            }
-            EditAxis::Column => {
-                let mut count = 0;
-                for row in 0..=self.cursor_row {
-                    self.sheet.clear_cell(self.cursor_col, row);
-                    count += 1;
-                }
-                self.status_message = format!("{} cells cleared", count);
-            }
+            EditAxis::Row => self.move_row(self.cursor_row, (self.cursor_row as isize + delta).clamp(0, 9999) as usize),
+            EditAxis::Column => self.move_col(self.cursor_col, (self.cursor_col as isize + delta).clamp(0, 255) as usize),
         }
     }
 
-    /// Get selection bounds (min_col, min_row, max_col, max_row)
-    pub fn get_selection_bounds(&self) -> (usize, usize, usize, usize) {
-        let min_col = self.visual_start_col.min(self.cursor_col);
-        let max_col = self.visual_start_col.max(self.cursor_col);
-        let min_row = self.visual_start_row.min(self.cursor_row);
-        let max_row = self.visual_start_row.max(self.cursor_row);
-        (min_col, min_row, max_col, max_row)
-    }
-
     /// Clear selected range (Visual mode)
     pub fn clear_selection(&mut self) {
-        let (min_col, min_row, max_col, max_row) = self.get_selection_bounds();
-        self.save_undo();
-        let mut count = 0;
-        for col in min_col..=max_col {
-            for row in min_row..=max_row {
-                self.sheet.clear_cell(col, row);
-                count += 1;
-            }
-        }
-        self.status_message = format!("{} cells cleared", count);
+        self.clear_area(self.get_selection_bounds());
         self.mode = Mode::Normal;
     }
 
@@ -626,7 +1770,7 @@ impl App {
         self.cursor_row = min_row;
         self.adjust_view();
 
-        self.status_message = format!("Copied {}x{} cells", width, height);
+        self.info(format!("Copied {}x{} cells", width, height));
         self.mode = Mode::Normal;
     }
 
@@ -656,12 +1800,12 @@ impl App {
         // Copy to system clipboard
         if let Ok(mut clipboard) = arboard::Clipboard::new() {
             if clipboard.set_text(&tsv).is_ok() {
-                self.status_message = format!("Copied {}x{} cells to clipboard", width, height);
+                self.info(format!("Copied {}x{} cells to clipboard", width, height));
             } else {
-                self.status_message = "Failed to copy to clipboard".to_string();
+                self.error("Failed to copy to clipboard");
             }
         } else {
-            self.status_message = "Clipboard not available".to_string();
+            self.warn("Clipboard not available");
         }
 
         // Move cursor to selection start (top-left)
@@ -675,12 +1819,20 @@ impl App {
     /// Paste from internal clipboard
     pub fn paste(&mut self, count: usize) {
         if self.clipboard.is_none() {
-            self.status_message = "Nothing to paste".to_string();
+            self.warn("Nothing to paste");
             return;
         }
 
         let clip = self.clipboard.clone().unwrap();
-        self.save_undo();
+        let (col, row) = (self.cursor_col, self.cursor_row);
+        let area = match self.axis {
+            EditAxis::Row => (col, row, col + clip.width * count - 1, row + clip.height - 1),
+            EditAxis::Column => (col, row, col + clip.width - 1, row + clip.height * count - 1),
+        };
+        if self.refuse_locked(area) {
+            return;
+        }
+        self.save_undo_as("paste");
 
         let mut paste_col = self.cursor_col;
         let mut paste_row = self.cursor_row;
@@ -716,67 +1868,85 @@ impl App {
         self.last_paste_rows = clip.height;
 
         let total = clip.width * clip.height * count;
-        self.status_message = format!("Pasted {} cells", total);
+        self.info(format!("Pasted {} cells", total));
     }
 
-    /// Paste from system clipboard
+    /// Paste from system clipboard: an HTML table if one was copied (from
+    /// a browser or another spreadsheet), otherwise the text as TSV or CSV
     pub fn paste_from_system(&mut self) {
-        let text = if let Ok(mut clipboard) = arboard::Clipboard::new() {
-            clipboard.get_text().unwrap_or_default()
-        } else {
-            self.status_message = "Clipboard not available".to_string();
+        let Ok(mut clipboard) = arboard::Clipboard::new() else {
+            self.warn("Clipboard not available");
             return;
         };
+        let table = clipboard.get().html().ok().and_then(|html| vicalc::html::parse_table(&html));
+        let rows = table.unwrap_or_else(|| {
+            let text = clipboard.get_text().unwrap_or_default();
+            csv::parse_records(&text, if text.contains('\t') { '\t' } else { ',' })
+        });
 
-        if text.is_empty() {
-            self.status_message = "Clipboard is empty".to_string();
+        if rows.is_empty() {
+            self.warn("Clipboard is empty");
             return;
         }
 
-        self.save_undo();
-
-        // Parse TSV/CSV
-        let lines: Vec<&str> = text.lines().collect();
-        let mut height = 0;
-        let mut width = 0;
-
-        for (r_offset, line) in lines.iter().enumerate() {
-            // Detect delimiter (tab first, then comma)
-            let cells: Vec<&str> = if line.contains('\t') {
-                line.split('\t').collect()
-            } else {
-                line.split(',').collect()
-            };
+        let width = rows.iter().map(Vec::len).max().unwrap_or(1);
+        let height = rows.len();
+        let (col, row) = (self.cursor_col, self.cursor_row);
+        if self.refuse_locked((col, row, col + width - 1, row + height - 1)) {
+            return;
+        }
+        self.save_undo_as("paste");
 
-            for (c_offset, cell_value) in cells.iter().enumerate() {
-                let dst_col = self.cursor_col + c_offset;
-                let dst_row = self.cursor_row + r_offset;
-                self.sheet.set_cell(dst_col, dst_row, cell_value.to_string());
-                width = width.max(c_offset + 1);
+        for (r_offset, cells) in rows.into_iter().enumerate() {
+            for (c_offset, value) in cells.into_iter().enumerate() {
+                self.sheet.set_typed(col + c_offset, row + r_offset, &value);
             }
-            height = r_offset + 1;
         }
 
         self.last_paste_cols = width;
         self.last_paste_rows = height;
 
-        self.status_message = format!("Pasted {}x{} cells from clipboard", width, height);
+        self.info(format!("Pasted {}x{} cells from clipboard", width, height));
     }
 }
 
 fn handle_key(app: &mut App, key: KeyEvent) {
+    let anchor = app.scroll_anchor();
+    map_key(app, key);
+    app.scroll_bound(anchor);
+}
+
+fn map_key(app: &mut App, key: KeyEvent) {
+    app.macros.record(key);
+    // Keys remapped in the config file, unless they complete a command
+    let mapped = app.mode == Mode::Normal
+        && app.pending_operator.is_none()
+        && !app.register_pending
+        && !app.slash_pending
+        && app.goto_buffer.is_none();
+    if let Some(keys) = app.keymap.get(&key).filter(|_| mapped).map(<[KeyEvent]>::to_vec) {
+        // Not remapped again, so a key can be mapped to itself plus more
+        for key in keys {
+            dispatch_key(app, key);
+        }
+        return;
+    }
+    dispatch_key(app, key);
+}
+
+fn dispatch_key(app: &mut App, key: KeyEvent) {
     // Handle register pending ("*)
     if app.register_pending {
         match key.code {
             KeyCode::Char('*') => {
                 // "* - system clipboard register selected
-                app.status_message = "\"* ...".to_string();
+                app.register = Some('*');
                 // Keep register_pending true, but now we expect y or p
                 return;
             }
             KeyCode::Char('y') => {
-                // Check if it's "*y (status shows "*)
-                if app.status_message.starts_with("\"*") {
+                // Check if it's "*y
+                if app.register == Some('*') {
                     app.yank_to_system();
                 } else {
                     // Just " followed by y - treat as normal yank
@@ -787,7 +1957,7 @@ fn handle_key(app: &mut App, key: KeyEvent) {
             }
             KeyCode::Char('p') => {
                 // Check if it's "*p
-                if app.status_message.starts_with("\"*") {
+                if app.register == Some('*') {
                     app.paste_from_system();
                 } else {
                     // Just " followed by p - treat as normal paste
@@ -797,35 +1967,31 @@ fn handle_key(app: &mut App, key: KeyEvent) {
                 app.register_pending = false;
                 return;
             }
-            KeyCode::Esc => {
-                app.register_pending = false;
-                app.update_status();
-                return;
-            }
             _ => {
                 app.register_pending = false;
-                app.update_status();
                 return;
             }
         }
     }
 
+    if app.goto_buffer.is_some() {
+        handle_goto_input(app, key);
+        return;
+    }
+
     // Handle slash commands (/c /r)
     if app.slash_pending {
         app.slash_pending = false;
         match key.code {
             KeyCode::Char('c') => {
                 app.axis = EditAxis::Column;
-                app.update_status();
                 return;
             }
             KeyCode::Char('r') => {
                 app.axis = EditAxis::Row;
-                app.update_status();
                 return;
             }
             _ => {
-                app.update_status();
                 return;
             }
         }
@@ -836,68 +2002,120 @@ fn handle_key(app: &mut App, key: KeyEvent) {
         Mode::EditSingle | Mode::EditContinuous | Mode::EditPreserve => handle_edit_mode(app, key),
         Mode::Command => handle_command_mode(app, key),
         Mode::Visual => handle_visual_mode(app, key),
+        Mode::Chart => handle_chart_mode(app, key),
+        Mode::List => handle_list_mode(app, key),
+    }
+}
+
+/// A cell reference typed after g, as in Excel's name box: Enter goes
+/// there, Esc gives up
+fn handle_goto_input(app: &mut App, key: KeyEvent) {
+    let Some(typed) = app.goto_buffer.as_mut() else {
+        return;
+    };
+    match key.code {
+        KeyCode::Char(c) if c.is_ascii_alphanumeric() || c == '$' => typed.push(c.to_ascii_uppercase()),
+        KeyCode::Backspace => {
+            typed.pop();
+            if typed.is_empty() {
+                app.goto_buffer = None;
+            }
+        }
+        KeyCode::Enter => {
+            let typed = app.goto_buffer.take().unwrap_or_default();
+            match formula::parse_cell_ref(&typed) {
+                _ if typed.is_empty() => {}
+                Some((col, row, _, _)) if col <= 255 && row <= 9999 => app.jump_to(col, row),
+                _ => app.error(format!("Invalid cell: {}", typed)),
+            }
+        }
+        KeyCode::Esc => app.goto_buffer = None,
+        _ => {}
     }
 }
 
 fn handle_mouse(app: &mut App, mouse: MouseEvent) {
     match mouse.kind {
         MouseEventKind::Down(MouseButton::Left) => {
+            if app.mode == Mode::Normal {
+                app.focus_window_at(mouse.column, mouse.row);
+            }
             let result = app.screen_to_cell(mouse.column, mouse.row);
             if let Some((col, row)) = result {
                 match app.mode {
                     Mode::Normal => {
                         app.move_cursor_to(col, row);
                     }
+                    Mode::EditSingle | Mode::EditContinuous | Mode::EditPreserve if app.can_point() => {
+                        // Insert the clicked cell into the formula (Shift extends to a range)
+                        let extend = mouse.modifiers.contains(KeyModifiers::SHIFT);
+                        app.point_at(col, row, extend);
+                    }
                     Mode::EditSingle | Mode::EditContinuous | Mode::EditPreserve => {
                         // Cancel edit on mouse click (restore original)
-                        app.input_buffer.clear();
+                        app.clear_input();
                         app.mode = Mode::Normal;
                         app.move_cursor_to(col, row);
-                        app.update_status();
                     }
                     Mode::Command => {
                         // Exit command mode on mouse click
                         app.mode = Mode::Normal;
                         app.command_buffer.clear();
                         app.move_cursor_to(col, row);
-                        app.update_status();
                     }
-                    Mode::Visual => {
-                        // Extend selection on mouse click
+                    // Extend selection on mouse click, unless it is being moved
+                    Mode::Visual if app.grab.is_none() => {
                         app.move_cursor_to(col, row);
-                        let (min_col, min_row, max_col, max_row) = app.get_selection_bounds();
-                        app.status_message = format!("VISUAL {}x{}", max_col - min_col + 1, max_row - min_row + 1);
                     }
+                    Mode::Visual => {}
+                    Mode::Chart | Mode::List => {}
                 }
             }
         }
-        MouseEventKind::ScrollUp => {
-            // Scroll up 3 rows
-            let scroll = 3;
-            app.view_row = app.view_row.saturating_sub(scroll);
-            app.cursor_row = app.cursor_row.saturating_sub(scroll);
-        }
-        MouseEventKind::ScrollDown => {
-            // Scroll down 3 rows
-            let scroll = 3;
-            app.view_row = (app.view_row + scroll).min(9999);
-            app.cursor_row = (app.cursor_row + scroll).min(9999);
-        }
+        // Sideways: the wheel's own left and right, or Shift with the wheel
+        MouseEventKind::ScrollLeft => app.wheel_scroll(-1, 0),
+        MouseEventKind::ScrollRight => app.wheel_scroll(1, 0),
+        MouseEventKind::ScrollUp if mouse.modifiers.contains(KeyModifiers::SHIFT) => app.wheel_scroll(-1, 0),
+        MouseEventKind::ScrollDown if mouse.modifiers.contains(KeyModifiers::SHIFT) => app.wheel_scroll(1, 0),
+        MouseEventKind::ScrollUp => app.wheel_scroll(0, -1),
+        MouseEventKind::ScrollDown => app.wheel_scroll(0, 1),
         _ => {}
     }
 }
 
 fn handle_normal_mode(app: &mut App, key: KeyEvent) {
+    // Ctrl-w prefix: window commands, with or without Ctrl on the second key
+    if app.pending_operator == Some('W') {
+        app.pending_operator = None;
+        match key.code {
+            KeyCode::Char('w') | KeyCode::Char('W') | KeyCode::Char('p') => app.switch_window(),
+            KeyCode::Char('h') | KeyCode::Left => app.focus_window(SplitDir::Vertical, true),
+            KeyCode::Char('l') | KeyCode::Right => app.focus_window(SplitDir::Vertical, false),
+            KeyCode::Char('k') | KeyCode::Up => app.focus_window(SplitDir::Horizontal, true),
+            KeyCode::Char('j') | KeyCode::Down => app.focus_window(SplitDir::Horizontal, false),
+            KeyCode::Char('s') | KeyCode::Char('S') => app.split_window(SplitDir::Horizontal),
+            KeyCode::Char('v') => app.split_window(SplitDir::Vertical),
+            KeyCode::Char('c') | KeyCode::Char('q') => app.close_window(),
+            KeyCode::Char('o') => app.only_window(),
+            _ => {}
+        }
+        return;
+    }
+
     // Handle Ctrl combinations first
     if key.modifiers.contains(KeyModifiers::CONTROL) {
-        // Get terminal height for page calculations
-        let (_, term_height) = terminal::size().unwrap_or((80, 24));
-        let page_size = (term_height as usize).saturating_sub(4);  // Grid height
+        let page_size = app.grid_height();
         let half_page = page_size / 2;
         
         match key.code {
-            KeyCode::Char('q') => app.running = false,
+            KeyCode::Char('q') => commands::execute_command(app, "q"),
             KeyCode::Char('r') => app.redo(),
+            KeyCode::Char('w') => app.pending_operator = Some('W'),
+            KeyCode::Char('o') => app.jump_back(false),
+            // Ctrl+arrows: to the edge of the data, as in Excel
+            KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down => edge_move(app, key.code),
+            // Ctrl-] arrives as Ctrl-5 on most terminals
+            KeyCode::Char(']') | KeyCode::Char('5') => app.list_references(),
             KeyCode::Char('s') => {
                 commands::execute_command(app, "w");
             }
@@ -929,11 +2147,63 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) {
                 app.view_row = app.view_row.saturating_sub(scroll);
                 app.cursor_row = app.cursor_row.saturating_sub(scroll);
             }
+            // Scroll the view a row down (up), the cursor staying where it is
+            KeyCode::Char('e') => {
+                let count = app.get_count() as isize;
+                app.scroll_view(0, count, false);
+            }
+            KeyCode::Char('y') => {
+                let count = app.get_count() as isize;
+                app.scroll_view(0, -count, false);
+            }
+            _ => {}
+        }
+        // Keep the cursor off hidden rows
+        app.adjust_view();
+        return;
+    }
+
+    // ]q and [q: next and previous quickfix entry; ]e and [e: move the row
+    // or column
+    if let Some(bracket @ (']' | '[')) = app.pending_operator {
+        app.pending_operator = None;
+        let count = app.get_count() as isize;
+        match key.code {
+            KeyCode::Char('q') => quickfix::step(app, if bracket == ']' { count } else { -count }),
+            KeyCode::Char('e') => app.move_line(if bracket == ']' { count } else { -count }),
             _ => {}
         }
         return;
     }
 
+    // q{register} records a macro, @{register} plays one
+    if let Some(op @ ('q' | '@')) = app.pending_operator {
+        app.pending_operator = None;
+        match key.code {
+            KeyCode::Char(c) if op == 'q' && macros::is_register(c) => {
+                app.count_buffer.clear();
+                app.macros.start(c);
+            }
+            KeyCode::Char(c) if op == '@' && (macros::is_register(c) || c == '@') => {
+                let count = app.get_count();
+                macros::play_register(app, c, count);
+            }
+            _ => app.count_buffer.clear(),
+        }
+        return;
+    }
+
+    // z prefix: zh hides, zs shows rows/columns
+    if app.pending_operator == Some('z') {
+        app.pending_operator = None;
+        match key.code {
+            KeyCode::Char('h') => app.hide_structure(),
+            KeyCode::Char('s') => app.show_structure(),
+            _ => app.count_buffer.clear(),
+        }
+        return;
+    }
+
     match key.code {
         // Count prefix
         KeyCode::Char(c @ '1'..='9') => {
@@ -943,27 +2213,49 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) {
             app.count_buffer.push('0');
         }
 
+        // g followed by a cell reference: gB17<Enter>
+        KeyCode::Char(c) if app.pending_operator == Some('g') && c.is_ascii_alphabetic() && !matches!(c, 'g' | 'd') => {
+            app.pending_operator = None;
+            app.count_buffer.clear();
+            app.goto_buffer = Some(c.to_ascii_uppercase().to_string());
+        }
+
+        // d and a motion: clear the cells it sweeps over (d3l, d2j)
+        KeyCode::Char('h' | 'j' | 'k' | 'l') | KeyCode::Left | KeyCode::Down | KeyCode::Up | KeyCode::Right
+            if app.pending_operator == Some('d') =>
+        {
+            app.pending_operator = None;
+            match key.code {
+                KeyCode::Char('h') | KeyCode::Left => app.clear_motion(-1, 0),
+                KeyCode::Char('j') | KeyCode::Down => app.clear_motion(0, 1),
+                KeyCode::Char('k') | KeyCode::Up => app.clear_motion(0, -1),
+                _ => app.clear_motion(1, 0),
+            }
+        }
+
         // Movement (always the same)
         KeyCode::Char('h') | KeyCode::Left => app.move_cursor(-1, 0),
         KeyCode::Char('j') | KeyCode::Down => app.move_cursor(0, 1),
         KeyCode::Char('k') | KeyCode::Up => app.move_cursor(0, -1),
         KeyCode::Char('l') | KeyCode::Right => app.move_cursor(1, 0),
 
+        // Runs of filled cells along the axis (after d, clear up to them)
+        KeyCode::Char('w') => app.block_move(app.axis == EditAxis::Row, BlockMotion::NextStart),
+        KeyCode::Char('b') => app.block_move(app.axis == EditAxis::Row, BlockMotion::PrevStart),
+        KeyCode::Char('e') => app.block_move(app.axis == EditAxis::Row, BlockMotion::NextEnd),
+
         // Global movement
         KeyCode::Char('g') => {
             if app.pending_operator == Some('d') {
                 // dg - waiting for second g
                 app.pending_operator = Some('D');  // Use 'D' to indicate "dg" state
-                app.status_message = "dg...".to_string();
             } else if app.pending_operator == Some('D') {
                 // dgg - clear from sheet start to current
                 app.clear_from_sheet_start();
                 app.pending_operator = None;
             } else if app.pending_operator == Some('g') {
                 // gg - goto A1
-                app.cursor_col = 0;
-                app.cursor_row = 0;
-                app.adjust_view();
+                app.jump_to(0, 0);
                 app.pending_operator = None;
             } else {
                 app.pending_operator = Some('g');
@@ -974,17 +2266,27 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) {
                 // dG - clear from current to sheet end
                 app.clear_to_sheet_end();
                 app.pending_operator = None;
+            } else if !app.count_buffer.is_empty() {
+                // 123G: row 123 of the same column, as in vim
+                let row = app.get_count().clamp(1, 10000) - 1;
+                app.jump_to(app.cursor_col, row);
             } else {
-                app.cursor_row = app.sheet.max_row().unwrap_or(0);
-                app.cursor_col = app.sheet.max_col().unwrap_or(0);
-                app.adjust_view();
+                app.jump_to(app.sheet.max_col().unwrap_or(0), app.sheet.max_row().unwrap_or(0));
             }
         }
 
+        // `B17 Enter: go to a cell, as gB17 does
+        KeyCode::Char('`') => {
+            app.count_buffer.clear();
+            app.goto_buffer = Some(String::new());
+        }
+
+        // Ctrl-I (Tab): forward through the jump list
+        KeyCode::Tab => app.jump_back(true),
+
         // Slash commands (/c /r)
         KeyCode::Char('/') => {
             app.slash_pending = true;
-            app.status_message = "/ ...".to_string();
         }
 
         // Cell content operations
@@ -992,37 +2294,40 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) {
             // Clear current cell
             app.clear_current_cell();
         }
+        // Locked cells (:lock) are not edited
+        KeyCode::Char('=') | KeyCode::Char('r') | KeyCode::Char('R') | KeyCode::F(2)
+            if app.refuse_locked((app.cursor_col, app.cursor_row, app.cursor_col, app.cursor_row)) => {}
         // = - Formula input (start with =)
         KeyCode::Char('=') => {
             let cell = app.sheet.get_cell(app.cursor_col, app.cursor_row);
             app.edit_original = cell.raw_input.clone();
             app.mode = Mode::EditSingle;
-            app.input_buffer = "=".to_string();
-            app.update_status();
+            app.set_input("=".to_string());
+            if app.func_menu_enabled && cell.raw_input.is_empty() {
+                app.func_menu = Some(funcmenu::FuncMenu::default());
+            }
         }
         // r - Single cell edit (return to Normal after Enter/arrows)
         KeyCode::Char('r') => {
             let cell = app.sheet.get_cell(app.cursor_col, app.cursor_row);
             app.edit_original = cell.raw_input.clone();
             app.mode = Mode::EditSingle;
-            app.input_buffer.clear();
-            app.update_status();
+            app.clear_input();
         }
         // R - Continuous edit (stay in edit mode after Enter/arrows)
         KeyCode::Char('R') => {
             let cell = app.sheet.get_cell(app.cursor_col, app.cursor_row);
             app.edit_original = cell.raw_input.clone();
             app.mode = Mode::EditContinuous;
-            app.input_buffer.clear();
-            app.update_status();
+            app.clear_input();
         }
+        KeyCode::F(9) => app.recalculate(),
         // F2 - Edit cell content (preserve existing content)
         KeyCode::F(2) => {
             let cell = app.sheet.get_cell(app.cursor_col, app.cursor_row);
             app.edit_original = cell.raw_input.clone();
             app.mode = Mode::EditPreserve;
-            app.input_buffer = cell.raw_input.clone();
-            app.update_status();
+            app.set_input(app.number_style.typed_input(&cell));
         }
 
         // Structure operations (axis-dependent)
@@ -1031,6 +2336,11 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) {
         KeyCode::Char('a') => app.append_after_cursor(),
         KeyCode::Char('A') => app.goto_axis_end_next(),
 
+        KeyCode::Char('d') if app.pending_operator == Some('g') => {
+            app.pending_operator = None;
+            let n = app.get_count();
+            app.go_to_reference(n);
+        }
         KeyCode::Char('d') => {
             if app.pending_operator == Some('d') {
                 // dd - delete structure (row or column)
@@ -1039,7 +2349,6 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) {
             } else {
                 // Start d operator, wait for motion or second d
                 app.pending_operator = Some('d');
-                app.status_message = "d...".to_string();
             }
         }
 
@@ -1075,21 +2384,31 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) {
             }
         }
 
+        KeyCode::Char('z') => app.pending_operator = Some('z'),
+        KeyCode::Char('q') => match app.macros.stop() {
+            Some((register, keys)) => app.info(format!("Recorded {} keys into @{}", keys, register)),
+            None => app.pending_operator = Some('q'),
+        },
+        KeyCode::Char('@') => app.pending_operator = Some('@'),
+        KeyCode::Char(c @ (']' | '[')) => app.pending_operator = Some(c),
+
         KeyCode::Char('o') => app.insert_structure_after(),
         KeyCode::Char('O') => app.insert_structure_before(),
 
         // Column width adjustment
         KeyCode::Char('<') => {
             let count = app.get_count() as isize;
+            app.save_width_undo(app.cursor_col);
             app.sheet.adjust_col_width(app.cursor_col, -count);
             let width = app.sheet.get_col_width(app.cursor_col);
-            app.status_message = format!("Column width: {}", width);
+            app.info(format!("Column width: {}", width));
         }
         KeyCode::Char('>') => {
             let count = app.get_count() as isize;
+            app.save_width_undo(app.cursor_col);
             app.sheet.adjust_col_width(app.cursor_col, count);
             let width = app.sheet.get_col_width(app.cursor_col);
-            app.status_message = format!("Column width: {}", width);
+            app.info(format!("Column width: {}", width));
         }
 
         // Undo
@@ -1109,7 +2428,7 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) {
         // Register prefix (for "*)
         KeyCode::Char('"') => {
             app.register_pending = true;
-            app.status_message = "\" ...".to_string();
+            app.register = None;
         }
 
         // Visual mode
@@ -1117,7 +2436,6 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) {
             app.mode = Mode::Visual;
             app.visual_start_col = app.cursor_col;
             app.visual_start_row = app.cursor_row;
-            app.status_message = "-- VISUAL --".to_string();
         }
 
         // Visual Line/Column mode (V)
@@ -1129,31 +2447,30 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) {
                     app.visual_start_col = 0;
                     app.visual_start_row = app.cursor_row;
                     app.cursor_col = app.sheet.max_col().unwrap_or(255);
-                    app.status_message = "-- VISUAL LINE --".to_string();
                 }
                 EditAxis::Column => {
                     // Select entire column
                     app.visual_start_col = app.cursor_col;
                     app.visual_start_row = 0;
                     app.cursor_row = app.sheet.max_row().unwrap_or(9999);
-                    app.status_message = "-- VISUAL COLUMN --".to_string();
                 }
             }
         }
 
         // Search next/prev
         KeyCode::Char('n') => {
-            commands::search_next(app);
+            let count = app.get_count();
+            commands::search_next(app, count);
         }
         KeyCode::Char('N') => {
-            commands::search_prev(app);
+            let count = app.get_count();
+            commands::search_prev(app, count);
         }
 
         // Command mode
         KeyCode::Char(':') => {
             app.mode = Mode::Command;
             app.command_buffer.clear();
-            app.status_message = ":".to_string();
         }
 
         KeyCode::Esc => {
@@ -1161,7 +2478,10 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) {
             app.count_buffer.clear();
             app.slash_pending = false;
             app.register_pending = false;
-            app.update_status();
+            // Acknowledge errors shown in the status line
+            app.messages.acknowledge();
+            app.trace = None;
+            app.diff = None;
         }
 
         _ => {}
@@ -1169,92 +2489,158 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) {
 }
 
 fn handle_command_mode(app: &mut App, key: KeyEvent) {
+    if !matches!(key.code, KeyCode::Up | KeyCode::Down) {
+        app.cmd_history.reset();
+    }
+    if !matches!(key.code, KeyCode::Tab | KeyCode::BackTab) {
+        app.cmd_completion = None;
+    }
     match key.code {
         KeyCode::Esc => {
             app.mode = Mode::Normal;
             app.command_buffer.clear();
-            app.update_status();
         }
         KeyCode::Enter => {
-            let cmd = app.command_buffer.clone();
+            // Taken before running, so a command can open the line again
+            let cmd = std::mem::take(&mut app.command_buffer);
+            let (kind, entry) = cmdhistory::classify(&cmd);
+            app.cmd_history.add(kind, entry);
             app.mode = Mode::Normal;
             commands::execute_command(app, &cmd);
-            app.command_buffer.clear();
+        }
+        KeyCode::Up | KeyCode::Down => {
+            // A search keeps its / or ? and recalls patterns only
+            let (kind, typed) = cmdhistory::classify(&app.command_buffer);
+            let lead = app.command_buffer[..app.command_buffer.len() - typed.len()].to_string();
+            match app.cmd_history.recall(kind, typed, key.code == KeyCode::Up) {
+                Some(entry) => app.command_buffer = lead + entry.as_str(),
+                None => app.warn("No more history"),
+            }
         }
         KeyCode::Backspace => {
             app.command_buffer.pop();
             if app.command_buffer.is_empty() {
                 app.mode = Mode::Normal;
-                app.update_status();
-            } else {
-                app.status_message = format!(":{}", app.command_buffer);
             }
         }
-        KeyCode::Tab => {
-            // Tab completion for file names
-            complete_filename(app);
-        }
+        KeyCode::Tab => complete::complete(app, true),
+        KeyCode::BackTab => complete::complete(app, false),
         KeyCode::Char(c) => {
             app.command_buffer.push(c);
-            app.status_message = format!(":{}", app.command_buffer);
         }
         _ => {}
     }
 }
 
+fn handle_chart_mode(app: &mut App, key: KeyEvent) {
+    if matches!(key.code, KeyCode::Esc | KeyCode::Char('q')) {
+        app.chart = None;
+        app.mode = Mode::Normal;
+    }
+}
+
+fn handle_list_mode(app: &mut App, key: KeyEvent) {
+    let page = app.grid_height().max(1);
+    let Some(list) = app.list.as_mut() else {
+        app.mode = Mode::Normal;
+        return;
+    };
+    let last = list.lines.len().saturating_sub(page);
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    match key.code {
+        KeyCode::Esc | KeyCode::Char('q') => {
+            app.list = None;
+            app.mode = Mode::Normal;
+            return;
+        }
+        KeyCode::Char('f') | KeyCode::Char('d') if ctrl => list.scroll += page,
+        KeyCode::Char('b') | KeyCode::Char('u') if ctrl => list.scroll = list.scroll.saturating_sub(page),
+        KeyCode::Char('j') | KeyCode::Down => list.scroll += 1,
+        KeyCode::Char('k') | KeyCode::Up => list.scroll = list.scroll.saturating_sub(1),
+        KeyCode::PageDown | KeyCode::Char(' ') => list.scroll += page,
+        KeyCode::PageUp => list.scroll = list.scroll.saturating_sub(page),
+        KeyCode::Char('g') => list.scroll = 0,
+        KeyCode::Char('G') => list.scroll = last,
+        _ => {}
+    }
+    list.scroll = list.scroll.min(last);
+}
+
+/// Ctrl+arrow: to the far end of the run of filled cells, the next filled
+/// cell or the edge of the sheet
+fn edge_move(app: &mut App, code: KeyCode) {
+    match code {
+        KeyCode::Left => app.block_move(true, BlockMotion::EdgePrev),
+        KeyCode::Right => app.block_move(true, BlockMotion::EdgeNext),
+        KeyCode::Up => app.block_move(false, BlockMotion::EdgePrev),
+        _ => app.block_move(false, BlockMotion::EdgeNext),
+    }
+}
+
 fn handle_visual_mode(app: &mut App, key: KeyEvent) {
+    // Moving a picked-up area: nothing moves until it is dropped
+    if app.grab.is_some() {
+        match key.code {
+            KeyCode::Char('h') | KeyCode::Left => app.grab_move(-1, 0),
+            KeyCode::Char('j') | KeyCode::Down => app.grab_move(0, 1),
+            KeyCode::Char('k') | KeyCode::Up => app.grab_move(0, -1),
+            KeyCode::Char('l') | KeyCode::Right => app.grab_move(1, 0),
+            KeyCode::Enter => app.grab_drop(),
+            KeyCode::Esc => app.grab_cancel(),
+            _ => {}
+        }
+        return;
+    }
+
     match key.code {
         KeyCode::Esc => {
             app.mode = Mode::Normal;
-            app.update_status();
         }
 
         // Movement - extend selection
+        KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            edge_move(app, key.code);
+        }
+        KeyCode::Char('w') => app.block_move(app.axis == EditAxis::Row, BlockMotion::NextStart),
+        KeyCode::Char('b') => app.block_move(app.axis == EditAxis::Row, BlockMotion::PrevStart),
+        KeyCode::Char('e') => app.block_move(app.axis == EditAxis::Row, BlockMotion::NextEnd),
         KeyCode::Char('h') | KeyCode::Left => {
             app.cursor_col = app.cursor_col.saturating_sub(1);
             app.adjust_view();
-            update_visual_status(app);
         }
         KeyCode::Char('j') | KeyCode::Down => {
             app.cursor_row = (app.cursor_row + 1).min(9999);
             app.adjust_view();
-            update_visual_status(app);
         }
         KeyCode::Char('k') | KeyCode::Up => {
             app.cursor_row = app.cursor_row.saturating_sub(1);
             app.adjust_view();
-            update_visual_status(app);
         }
         KeyCode::Char('l') | KeyCode::Right => {
             app.cursor_col = (app.cursor_col + 1).min(255);
             app.adjust_view();
-            update_visual_status(app);
         }
 
         // Jump to start/end
         KeyCode::Char('0') => {
             app.cursor_col = 0;
             app.adjust_view();
-            update_visual_status(app);
         }
         KeyCode::Char('$') => {
             if let Some(max_col) = app.sheet.max_col_in_row(app.cursor_row) {
                 app.cursor_col = max_col;
             }
             app.adjust_view();
-            update_visual_status(app);
         }
         KeyCode::Char('g') => {
             app.cursor_col = 0;
             app.cursor_row = 0;
             app.adjust_view();
-            update_visual_status(app);
         }
         KeyCode::Char('G') => {
             app.cursor_row = app.sheet.max_row().unwrap_or(0);
             app.cursor_col = app.sheet.max_col().unwrap_or(0);
             app.adjust_view();
-            update_visual_status(app);
         }
 
         // Yank (copy) selection
@@ -1262,165 +2648,100 @@ fn handle_visual_mode(app: &mut App, key: KeyEvent) {
             app.yank();
         }
 
+        // Pick up the selection to move it
+        KeyCode::Char('m') => app.grab_selection(),
+
+        // Type once into every selected cell
+        KeyCode::Char('r') | KeyCode::Char('c') => app.edit_selection(),
+
         // Actions on selection
         KeyCode::Char('d') | KeyCode::Char('x') => {
             app.clear_selection();
         }
 
+        // Command on the selection
+        KeyCode::Char(':') => {
+            app.last_selection = Some(app.get_selection_bounds());
+            app.mode = Mode::Command;
+            app.command_buffer = "'<,'>".to_string();
+        }
+
         // Register prefix for system clipboard
         KeyCode::Char('"') => {
             app.register_pending = true;
-            app.status_message = "\" ...".to_string();
+            app.register = None;
         }
 
         _ => {}
     }
 }
 
-fn update_visual_status(app: &mut App) {
-    let (min_col, min_row, max_col, max_row) = app.get_selection_bounds();
-    let cols = max_col - min_col + 1;
-    let rows = max_row - min_row + 1;
-    app.status_message = format!("-- VISUAL -- {}x{}", cols, rows);
-}
-
 /// Complete filename in command buffer
-fn complete_filename(app: &mut App) {
-    let cmd = &app.command_buffer;
-    
-    // Check if command is :e or :w with partial filename
-    let (prefix, partial) = if cmd.starts_with("e ") {
-        ("e ", &cmd[2..])
-    } else if cmd.starts_with("w ") {
-        ("w ", &cmd[2..])
-    } else if cmd == "e" {
-        ("e ", "")
-    } else if cmd == "w" {
-        ("w ", "")
-    } else {
+fn handle_edit_mode(app: &mut App, key: KeyEvent) {
+    let current_mode = app.mode;
+
+    if app.func_menu.is_some() && funcmenu::handle_key(app, key) {
         return;
-    };
-    
-    // Get directory and file prefix
-    let (dir, file_prefix) = if partial.contains('/') || partial.contains('\\') {
-        let path = std::path::Path::new(partial);
-        if let Some(parent) = path.parent() {
-            (parent.to_string_lossy().to_string(), 
-             path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default())
-        } else {
-            (".".to_string(), partial.to_string())
-        }
-    } else {
-        (".".to_string(), partial.to_string())
-    };
-    
-    // List matching files
-    let matches: Vec<String> = match std::fs::read_dir(&dir) {
-        Ok(entries) => {
-            entries
-                .filter_map(|e| e.ok())
-                .filter_map(|e| {
-                    let name = e.file_name().to_string_lossy().to_string();
-                    let lower_name = name.to_lowercase();
-                    let lower_prefix = file_prefix.to_lowercase();
-                    
-                    // Match files starting with prefix
-                    if lower_name.starts_with(&lower_prefix) {
-                        // Filter for spreadsheet-related extensions or directories
-                        let is_dir = e.file_type().map(|t| t.is_dir()).unwrap_or(false);
-                        let ext = std::path::Path::new(&name)
-                            .extension()
-                            .map(|e| e.to_string_lossy().to_lowercase())
-                            .unwrap_or_default();
-                        
-                        if is_dir || ext == "json" || ext == "csv" || file_prefix.is_empty() {
-                            if dir == "." {
-                                Some(name)
-                            } else {
-                                Some(format!("{}/{}", dir, name))
-                            }
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                })
-                .collect()
-        }
-        Err(_) => Vec::new(),
-    };
-    
-    if matches.len() == 1 {
-        // Single match - complete it
-        app.command_buffer = format!("{}{}", prefix, matches[0]);
-        app.status_message = format!(":{}", app.command_buffer);
-    } else if matches.len() > 1 {
-        // Multiple matches - find common prefix and show options
-        let common = find_common_prefix(&matches);
-        if common.len() > partial.len() {
-            app.command_buffer = format!("{}{}", prefix, common);
-        }
-        // Limit displayed matches to avoid overflow
-        let display_matches: Vec<&str> = matches.iter()
-            .take(5)
-            .map(|s| s.as_str())
-            .collect();
-        let suffix = if matches.len() > 5 {
-            format!(" +{}", matches.len() - 5)
-        } else {
-            String::new()
-        };
-        app.status_message = format!(":{} [{}{}]", app.command_buffer, display_matches.join(" "), suffix);
-    } else if !partial.is_empty() {
-        // No matches - keep as is (will create new file)
-        app.status_message = format!(":{} (new file)", app.command_buffer);
     }
-}
 
-/// Find common prefix among strings
-fn find_common_prefix(strings: &[String]) -> String {
-    if strings.is_empty() {
-        return String::new();
+    // Any key but Ctrl-N/Ctrl-P settles on the completion shown
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    if !(ctrl && matches!(key.code, KeyCode::Char('n') | KeyCode::Char('p'))) {
+        app.completion = None;
     }
-    
-    let first = &strings[0];
-    let mut prefix_len = first.len();
-    
-    for s in strings.iter().skip(1) {
-        prefix_len = first.chars()
-            .zip(s.chars())
-            .take_while(|(a, b)| a.to_lowercase().eq(b.to_lowercase()))
-            .count()
-            .min(prefix_len);
+
+    // Cursor movement within the input buffer (emacs-style, like the shell)
+    if ctrl {
+        match key.code {
+            KeyCode::Char('n') => app.complete(true),
+            KeyCode::Char('p') => app.complete(false),
+            KeyCode::Char('b') => app.input_move(-1),
+            KeyCode::Char('f') => app.input_move(1),
+            KeyCode::Char('a') => app.input_home(),
+            KeyCode::Char('e') => app.input_end(),
+            KeyCode::Char('h') => app.input_backspace(),
+            KeyCode::Char('d') => app.input_delete(),
+            _ => {}
+        }
+        return;
     }
-    
-    first[..prefix_len].to_string()
-}
 
-fn handle_edit_mode(app: &mut App, key: KeyEvent) {
-    let current_mode = app.mode;
-    
+    // Point mode: arrow keys pick the reference to insert into a formula,
+    // Shift+arrow extends it to a range. Any other key keeps the reference.
+    if let KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right = key.code {
+        if app.can_point() {
+            let extend = key.modifiers.contains(KeyModifiers::SHIFT);
+            match key.code {
+                KeyCode::Up => app.point_move(0, -1, extend),
+                KeyCode::Down => app.point_move(0, 1, extend),
+                KeyCode::Left => app.point_move(-1, 0, extend),
+                _ => app.point_move(1, 0, extend),
+            }
+            return;
+        }
+    }
+    app.point_ref = None;
+
     match key.code {
         KeyCode::Esc => {
             // Cancel - restore original content
-            app.input_buffer.clear();
+            app.clear_input();
             app.mode = Mode::Normal;
-            app.update_status();
         }
         KeyCode::Enter => {
             // Commit if there's input, then move/exit based on mode
+            if !app.input_buffer.is_empty() && !app.validate_input() {
+                return;
+            }
             if !app.input_buffer.is_empty() {
-                app.save_undo();
-                app.sheet.set_cell(app.cursor_col, app.cursor_row, app.input_buffer.clone());
+                app.store_input();
             }
-            app.input_buffer.clear();
+            app.clear_input();
             
             match current_mode {
                 Mode::EditSingle | Mode::EditPreserve => {
                     // Return to Normal mode
                     app.mode = Mode::Normal;
-                    app.update_status();
                 }
                 Mode::EditContinuous => {
                     // Move to next cell and continue editing
@@ -1437,16 +2758,21 @@ fn handle_edit_mode(app: &mut App, key: KeyEvent) {
             }
         }
         KeyCode::Backspace => {
-            app.input_buffer.pop();
+            app.input_backspace();
         }
         KeyCode::Delete => {
-            if !app.input_buffer.is_empty() {
-                app.input_buffer.remove(0);
-            }
+            app.input_delete();
+        }
+        KeyCode::Home => {
+            app.input_home();
+        }
+        KeyCode::End => {
+            app.input_end();
         }
         KeyCode::Char(c) => {
-            app.input_buffer.push(c);
+            app.input_insert(c);
         }
+        KeyCode::Tab if app.snippet_tab() => {}
         KeyCode::Up | KeyCode::Down | KeyCode::Left | KeyCode::Right | KeyCode::Tab | KeyCode::BackTab => {
             // Calculate new position
             let (new_col, new_row) = match key.code {
@@ -1459,11 +2785,13 @@ fn handle_edit_mode(app: &mut App, key: KeyEvent) {
             };
             
             // Commit if there's input
+            if !app.input_buffer.is_empty() && !app.validate_input() {
+                return;
+            }
             if !app.input_buffer.is_empty() {
-                app.save_undo();
-                app.sheet.set_cell(app.cursor_col, app.cursor_row, app.input_buffer.clone());
+                app.store_input();
             }
-            app.input_buffer.clear();
+            app.clear_input();
             
             match current_mode {
                 Mode::EditSingle | Mode::EditPreserve => {
@@ -1472,7 +2800,6 @@ fn handle_edit_mode(app: &mut App, key: KeyEvent) {
                     app.cursor_col = new_col;
                     app.cursor_row = new_row;
                     app.adjust_view();
-                    app.update_status();
                 }
                 Mode::EditContinuous => {
                     // Move and continue editing
@@ -1490,46 +2817,349 @@ fn handle_edit_mode(app: &mut App, key: KeyEvent) {
     }
 }
 
+/// Stack size of the thread running the editor. Formula evaluation recurses once
+/// per nested expression or referenced cell, up to `engine::MAX_EVAL_DEPTH` levels.
+pub const APP_STACK_SIZE: usize = 64 * 1024 * 1024;
+
 fn main() -> Result<()> {
     let args: Vec<String> = std::env::args().collect();
-    
+    std::thread::Builder::new()
+        .stack_size(APP_STACK_SIZE)
+        .spawn(move || run(args))?
+        .join()
+        .unwrap_or_else(|_| Err(std::io::Error::other("vicalc thread panicked")))
+}
+
+/// Put the terminal back into its normal state (cooked mode, main screen)
+fn restore_terminal() {
+    let _ = execute!(stdout(), Show, DisableMouseCapture, LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+}
+
+/// Suspend to the shell (Ctrl-Z), returning once the job is resumed with `fg`.
+/// Raw mode disables the terminal's own SIGTSTP, so the signal is raised here
+/// after the terminal has been put back into a state the shell can use.
+#[cfg(unix)]
+fn suspend(app: &mut App) -> Result<()> {
+    restore_terminal();
+    // SAFETY: raise() only delivers a signal to the current process
+    unsafe {
+        libc::raise(libc::SIGTSTP);
+    }
+    terminal::enable_raw_mode()?;
+    execute!(stdout(), EnterAlternateScreen, Hide, EnableMouseCapture)?;
+    // The window may have been resized while we were stopped
+    app.term_size = terminal::size().unwrap_or(app.term_size);
+    app.adjust_views();
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn suspend(app: &mut App) -> Result<()> {
+    app.warn("Suspend is not supported on this platform");
+    Ok(())
+}
+
+fn run(args: Vec<String>) -> Result<()> {
+
+    // Headless developer subcommands
+    if args.get(1).map(String::as_str) == Some("test-fixtures") {
+        std::process::exit(fixtures::run_cli(&args[2..]));
+    }
+    if args.get(1).map(String::as_str) == Some("bench") {
+        std::process::exit(bench::run_cli(&args[2..]));
+    }
+    if args.get(1).map(String::as_str) == Some("--replay") {
+        std::process::exit(keylog::run_cli(&args[2..]));
+    }
+
+    let args = match script::parse_args(&args[1..]) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("vicalc: {}", e);
+            std::process::exit(2);
+        }
+    };
+    // -c and --script run without the interface
+    if args.batch {
+        std::process::exit(script::run(&args));
+    }
+
+    // Restore the terminal before the panic message is printed, otherwise it
+    // lands on the alternate screen and the shell is left in raw mode. A
+    // panic on another thread, such as the recalculation worker, leaves the
+    // interface running.
+    let ui_thread = std::thread::current().id();
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if std::thread::current().id() == ui_thread {
+            restore_terminal();
+        }
+        default_hook(info);
+    }));
+
+    // Ask about a swap file left by an earlier session while the terminal
+    // is still in its normal state
+    let mut recovery = None;
+    let mut take_over = false;
+    if let Some(filename) = &args.file {
+        // Another session's swap file is no crash to recover from, unless
+        // the user knows that session is gone
+        let swap_path = swap::path_for(filename);
+        let mut ask_swap = true;
+        if let Some(holder) = lockfile::holder(filename) {
+            match lockfile::ask(filename, &holder, Some(swap_path.as_path()).filter(|p| p.exists())) {
+                lockfile::Choice::ReadOnly => ask_swap = false,
+                lockfile::Choice::Recover => {
+                    ask_swap = false;
+                    take_over = true;
+                    recovery = Some(swap_path.clone());
+                }
+                lockfile::Choice::Override => take_over = true,
+                lockfile::Choice::Quit => return Ok(()),
+            }
+        }
+        if ask_swap && swap_path.exists() {
+            match swap::ask(filename, &swap_path) {
+                swap::Recovery::Recover => recovery = Some(swap_path),
+                swap::Recovery::Delete => std::fs::remove_file(&swap_path)?,
+                swap::Recovery::Edit => {}
+                swap::Recovery::Quit => return Ok(()),
+            }
+        }
+    }
+
     let mut stdout = stdout();
     terminal::enable_raw_mode()?;
     execute!(stdout, EnterAlternateScreen, Hide, EnableMouseCapture)?;
 
     let mut app = App::new();
+    // Opening the file and recovering the swap file can panic as well as
+    // the event loop; either way the sheet goes to the emergency save
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        config::load(&mut app);
+        app.session = session::path();
+
+        // Open file from command line argument
+        if let Some(filename) = &args.file {
+            if take_over {
+                app.lock.force(filename);
+            }
+            let cmd = format!("e {}", filename);
+            commands::execute_command(&mut app, &cmd);
+        }
+        if let Some(template) = &args.template {
+            commands::execute_command(&mut app, &format!("newfrom {}", template));
+        }
+        if let Some(swap_path) = recovery {
+            swap::recover(&mut app, &swap_path);
+        }
+
+        event_loop(&mut app)
+    }));
+    match result {
+        Ok(result) => {
+            restore_terminal();
+            if let Err(e) = app.cmd_history.write() {
+                eprintln!("vicalc: could not save the command history: {}", e);
+            }
+            if let Err(e) = session::remember(&app) {
+                eprintln!("vicalc: could not save the session: {}", e);
+            }
+            result
+        }
+        Err(_) => {
+            // The hook has already restored the terminal and reported the panic
+            if let Some(path) = commands::emergency_save(&app) {
+                eprintln!("vicalc crashed; your work was saved to {}", path);
+            }
+            // Same exit status as an uncaught panic
+            std::process::exit(101);
+        }
+    }
+}
+
+/// Read terminal events on their own thread, so the main loop can block on
+/// the channel instead of waking up every 100ms to poll
+fn spawn_input_thread() -> Receiver<Result<Event>> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || loop {
+        let event = event::read();
+        let failed = event.is_err();
+        if tx.send(event).is_err() || failed {
+            break;
+        }
+    });
+    rx
+}
 
-    // Open file from command line argument
-    if args.len() > 1 {
-        let filename = &args[1];
-        let cmd = format!("e {}", filename);
-        commands::execute_command(&mut app, &cmd);
+/// Apply one terminal event. Returns true if the screen needs to be redrawn.
+fn handle_event(app: &mut App, ui: &mut UI, event: Event) -> Result<bool> {
+    match event {
+        Event::Key(key)
+            if key.kind == event::KeyEventKind::Press
+                && key.modifiers.contains(KeyModifiers::CONTROL)
+                && key.code == KeyCode::Char('z') =>
+        {
+            suspend(app)?;
+            ui.invalidate();
+        }
+        Event::Key(key) if key.kind == event::KeyEventKind::Press => {
+            if let Some(log) = app.keylog.as_mut() {
+                log.record(&key, app.current_file.as_deref(), app.term_size);
+            }
+            handle_key(app, key)
+        }
+        Event::Mouse(mouse) => {
+            let anchor = app.scroll_anchor();
+            handle_mouse(app, mouse);
+            app.scroll_bound(anchor);
+        }
+        Event::Resize(width, height) => {
+            app.term_size = (width, height);
+            app.adjust_views();
+            ui.invalidate();
+        }
+        _ => return Ok(false),
     }
+    Ok(true)
+}
 
-    UI::draw(&app)?;
+fn event_loop(app: &mut App) -> Result<()> {
+    let events = spawn_input_thread();
+    let mut ui = UI::new(app);
+    calc::update(app);
+    a11y::update(app);
+    audit::update(app);
+    ui.draw(app)?;
 
     while app.running {
-        if event::poll(std::time::Duration::from_millis(100))? {
-            match event::read()? {
-                Event::Key(key) => {
-                    if key.kind == event::KeyEventKind::Press {
-                        handle_key(&mut app, key);
-                        UI::draw(&app)?;
-                    }
-                }
-                Event::Mouse(mouse) => {
-                    handle_mouse(&mut app, mouse);
-                    UI::draw(&app)?;
-                }
-                Event::Resize(_, _) => {
-                    UI::draw(&app)?;
+        // Sleep until input arrives, the status message is due to expire or
+        // the swap file is due to be written
+        let now = Instant::now();
+        let deadlines = [app.messages.deadline(), app.swap.deadline(), app.stats.deadline(now), app.calc.deadline(now)];
+        let deadline = deadlines.into_iter().flatten().min();
+        let event = match deadline {
+            Some(deadline) => match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(event) => Some(event),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match events.recv() {
+                Ok(event) => Some(event),
+                Err(_) => break,
+            },
+        };
+
+        let mut redraw = app.messages.expire(Instant::now());
+        redraw |= app.stats.tick(Instant::now());
+        if let Some(event) = event {
+            redraw |= handle_event(app, &mut ui, event?)?;
+            // Work through everything already queued (key repeat, pasted
+            // text) and redraw once, so the sheet is only re-evaluated when
+            // input goes idle
+            while app.running {
+                match events.try_recv() {
+                    Ok(event) => redraw |= handle_event(app, &mut ui, event?)?,
+                    Err(_) => break,
                 }
-                _ => {}
             }
         }
+        // A sheet opened or restored since follows the calc option too
+        redraw |= calc::update(app);
+        redraw |= a11y::update(app);
+        audit::update(app);
+        swap::update(app, Instant::now());
+        if redraw && app.running {
+            ui.draw(app)?;
+        }
     }
 
-    execute!(stdout, Show, DisableMouseCapture, LeaveAlternateScreen)?;
-    terminal::disable_raw_mode()?;
+    // A normal exit needs no recovery
+    app.swap.remove();
+    app.lock.release();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+
+    fn type_keys(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            handle_key(app, KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+    }
+
+    #[test]
+    fn test_counted_structure_changes() {
+        let mut app = App::new();
+        for row in 0..6 {
+            app.sheet.set_cell(0, row, (row + 1).to_string());
+        }
+        app.sheet.set_cell(0, 7, "=SUM(A1:A6)".to_string());
+
+        // 3dd deletes rows 2 to 4 as one change
+        app.cursor_row = 1;
+        type_keys(&mut app, "3dd");
+        assert_eq!(app.sheet.evaluate(0, 1), "5");
+        assert_eq!(app.sheet.get_cell(0, 4).raw_input, "=SUM(A1:A3)");
+        assert_eq!(app.history.undo_entries().len(), 1);
+
+        // 5o inserts five rows below the cursor
+        app.cursor_row = 0;
+        type_keys(&mut app, "5o");
+        assert_eq!((app.mode, app.cursor_row), (Mode::Normal, 1));
+        assert_eq!(app.sheet.evaluate(0, 6), "5");
+        assert_eq!(app.sheet.get_cell(0, 9).raw_input, "=SUM(A1:A8)");
+        assert_eq!(app.sheet.evaluate(0, 9), "12");
+        assert_eq!(app.history.undo_entries().len(), 2);
+
+        // Counts stop at the edge of the sheet
+        app.cursor_row = 9990;
+        type_keys(&mut app, "99999o");
+        assert_eq!(app.sheet.get_cell(0, 9).raw_input, "=SUM(A1:A8)");
+        assert_eq!(app.history.undo_entries().len(), 3);
+        app.cursor_row = 9998;
+        type_keys(&mut app, "99999dd");
+        assert_eq!(app.sheet.evaluate(0, 6), "5");
+        assert_eq!(app.history.undo_entries().len(), 4);
+    }
+
+    #[test]
+    fn test_fill_checks_every_cell() {
+        let mut app = App::new();
+        app.sheet.set_cell(0, 0, "Qty".to_string());
+        app.sheet.set_cell(1, 0, "Score".to_string());
+        commands::execute_command(&mut app, "coltype A number");
+        commands::execute_command(&mut app, "validate B3:B4 number 0..100");
+        let undo = app.history.undo_entries().len();
+        let fill = |app: &mut App, col: usize, input: &str| {
+            app.mode = Mode::Normal;
+            app.cursor_col = col;
+            app.cursor_row = 1;
+            type_keys(app, "vjjc");
+            type_keys(app, input);
+            handle_key(app, KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        };
+
+        // Text is refused anywhere in a number column
+        fill(&mut app, 0, "abc");
+        assert_eq!(app.mode, Mode::EditSingle);
+        assert!(app.messages.current().unwrap().text.contains("A2: column A is number"));
+        app.clear_input();
+
+        // The rule on B3:B4 is checked though the fill starts at B2
+        fill(&mut app, 1, "150");
+        assert_eq!(app.mode, Mode::EditSingle);
+        assert!(app.messages.current().unwrap().text.contains("Invalid value for B3"));
+        assert!((1..4).all(|row| app.sheet.get_cell_ref(1, row).is_none()));
+        assert_eq!(app.history.undo_entries().len(), undo);
+        app.clear_input();
+
+        fill(&mut app, 1, "50");
+        assert_eq!(app.mode, Mode::Normal);
+        assert!((1..4).all(|row| app.sheet.evaluate(1, row) == "50"));
+    }
+}
@@ -0,0 +1,144 @@
+//! Notifications shown in the status line.
+//!
+//! Info and warning messages replace the normal status line for a few seconds
+//! and then expire. Errors stay until they are acknowledged (Esc in Normal
+//! mode), and a badge counts the ones not yet acknowledged. The last
+//! `HISTORY_LEN` messages of every kind are kept for `:messages`.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Messages kept for `:messages`
+pub const HISTORY_LEN: usize = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+
+    /// How long a message stays visible, None if it stays until acknowledged
+    pub fn timeout(self) -> Option<Duration> {
+        match self {
+            Severity::Info => Some(Duration::from_secs(3)),
+            Severity::Warning => Some(Duration::from_secs(5)),
+            Severity::Error => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub text: String,
+    pub severity: Severity,
+    pub time: Instant,
+    // Wall clock time, for the history
+    pub logged: SystemTime,
+}
+
+impl Message {
+    fn expired(&self, now: Instant) -> bool {
+        match self.severity.timeout() {
+            Some(timeout) => now.duration_since(self.time) >= timeout,
+            None => false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Messages {
+    current: Option<Message>,
+    // Errors not yet acknowledged, oldest first
+    errors: Vec<Message>,
+    // Recent messages, oldest first
+    history: VecDeque<Message>,
+}
+
+impl Messages {
+    pub fn push(&mut self, severity: Severity, text: String) {
+        let message = Message { text, severity, time: Instant::now(), logged: SystemTime::now() };
+        if severity == Severity::Error {
+            self.errors.push(message.clone());
+        }
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(message.clone());
+        self.current = Some(message);
+    }
+
+    /// Recent messages, oldest first
+    pub fn history(&self) -> impl Iterator<Item = &Message> {
+        self.history.iter()
+    }
+
+    /// Forget the message history (:messages clear)
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// The message to show in place of the normal status line
+    pub fn current(&self) -> Option<&Message> {
+        self.current.as_ref()
+    }
+
+    /// Errors waiting to be acknowledged
+    pub fn errors(&self) -> &[Message] {
+        &self.errors
+    }
+
+    /// Dismiss the current message and all pending errors
+    pub fn acknowledge(&mut self) {
+        self.current = None;
+        self.errors.clear();
+    }
+
+    /// When the current message expires, None if nothing is waiting to expire
+    pub fn deadline(&self) -> Option<Instant> {
+        let message = self.current.as_ref()?;
+        message.severity.timeout().map(|timeout| message.time + timeout)
+    }
+
+    /// Drop the current message if its time is up. Returns true if the
+    /// status line needs to be redrawn.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        if self.current.as_ref().is_some_and(|m| m.expired(now)) {
+            self.current = None;
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_keeps_the_latest() {
+        let mut messages = Messages::default();
+        for i in 0..HISTORY_LEN + 5 {
+            messages.push(if i % 2 == 0 { Severity::Info } else { Severity::Error }, i.to_string());
+        }
+        let texts: Vec<&str> = messages.history().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts.len(), HISTORY_LEN);
+        assert_eq!(texts[0], "5");
+        assert_eq!(*texts.last().unwrap(), (HISTORY_LEN + 4).to_string());
+
+        // Acknowledging errors does not drop them from the history
+        messages.acknowledge();
+        assert_eq!(messages.history().count(), HISTORY_LEN);
+        messages.clear_history();
+        assert_eq!(messages.history().count(), 0);
+    }
+}
@@ -0,0 +1,151 @@
+//! Parquet files, opened read-only with `:e data.parquet` to look through
+//! data exported from analytics tools.
+//!
+//! The column names from the schema go in a header row and each record in
+//! a row below it. Columns whose schema says they hold numbers, dates,
+//! booleans or text are declared that type (see `:coltype`). Only the first
+//! 9999 records and 256 columns fit in a sheet; the rest are left out.
+
+use std::fs::File;
+use std::path::Path;
+
+use ::parquet::basic::{ConvertedType, LogicalType, Type as PhysicalType};
+use ::parquet::file::reader::{FileReader, SerializedFileReader};
+use ::parquet::record::Field;
+use ::parquet::schema::types::Type;
+
+use crate::coltype::ColType;
+use crate::sheet::Sheet;
+
+/// Read a Parquet file into a sheet. Also returns how many records the
+/// file holds, which may be more than were read.
+pub fn read(path: &Path) -> Result<(Sheet, usize), String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let reader = SerializedFileReader::new(file).map_err(|e| e.to_string())?;
+    let total = reader.metadata().file_metadata().num_rows().max(0) as usize;
+
+    let mut sheet = Sheet::new();
+    let fields = reader.metadata().file_metadata().schema_descr().root_schema().get_fields().to_vec();
+    for (col, field) in fields.iter().take(256).enumerate() {
+        sheet.set_cell(col, 0, field.name().to_string());
+    }
+
+    let rows = reader.get_row_iter(None).map_err(|e| e.to_string())?;
+    for (i, row) in rows.take(9999).enumerate() {
+        let row = row.map_err(|e| e.to_string())?;
+        for (col, (_, value)) in row.get_column_iter().take(256).enumerate() {
+            if let Some(input) = input(value) {
+                sheet.set_cell(col, i + 1, input);
+            }
+        }
+    }
+
+    for (col, field) in fields.iter().take(256).enumerate() {
+        if let Some(ty) = col_type(field) {
+            sheet.set_col_type(col, Some(ty));
+        }
+    }
+    Ok((sheet, total))
+}
+
+/// A value as it is typed into a cell; None for a null
+fn input(value: &Field) -> Option<String> {
+    let text = match value {
+        Field::Null => return None,
+        Field::Bool(b) => if *b { "TRUE" } else { "FALSE" }.to_string(),
+        Field::Byte(n) => n.to_string(),
+        Field::Short(n) => n.to_string(),
+        Field::Int(n) => n.to_string(),
+        Field::Long(n) => n.to_string(),
+        Field::UByte(n) => n.to_string(),
+        Field::UShort(n) => n.to_string(),
+        Field::UInt(n) => n.to_string(),
+        Field::ULong(n) => n.to_string(),
+        Field::Float(n) => n.to_string(),
+        Field::Double(n) => n.to_string(),
+        Field::Str(s) => s.clone(),
+        // Dates as YYYY-MM-DD, decimals with their scale; nested groups,
+        // lists and maps as their text
+        other => other.to_string(),
+    };
+    Some(text)
+}
+
+/// The column type a schema field's values fit, if it is a plain column
+fn col_type(field: &Type) -> Option<ColType> {
+    if !field.is_primitive() {
+        return None;
+    }
+    let info = field.get_basic_info();
+    match (info.logical_type(), info.converted_type()) {
+        (Some(LogicalType::Date), _) | (_, ConvertedType::DATE) => return Some(ColType::Date),
+        (Some(LogicalType::String), _) | (_, ConvertedType::UTF8) => return Some(ColType::Text),
+        (Some(LogicalType::Timestamp { .. } | LogicalType::Time { .. }), _) => return None,
+        (_, ConvertedType::TIMESTAMP_MILLIS | ConvertedType::TIMESTAMP_MICROS) => return None,
+        _ => {}
+    }
+    match field.get_physical_type() {
+        PhysicalType::BOOLEAN => Some(ColType::Bool),
+        PhysicalType::INT32 | PhysicalType::INT64 | PhysicalType::FLOAT | PhysicalType::DOUBLE => Some(ColType::Number),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ::parquet::data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int32Type};
+    use ::parquet::file::properties::WriterProperties;
+    use ::parquet::file::writer::SerializedFileWriter;
+    use ::parquet::schema::parser::parse_message_type;
+
+    use super::*;
+    use crate::cell::CellValue;
+
+    #[test]
+    fn test_read() {
+        let schema = "message sales {
+            required binary item (UTF8);
+            optional double price;
+            required boolean ok;
+            optional int32 sold (DATE);
+        }";
+        let path = std::env::temp_dir().join(format!("vicalc-test-{}.parquet", std::process::id()));
+        let file = File::create(&path).unwrap();
+        let schema = Arc::new(parse_message_type(schema).unwrap());
+        let mut writer = SerializedFileWriter::new(file, schema, Arc::new(WriterProperties::default())).unwrap();
+        let mut group = writer.next_row_group().unwrap();
+
+        let mut column = group.next_column().unwrap().unwrap();
+        let items = [ByteArray::from("Apple"), ByteArray::from("Pear")];
+        column.typed::<ByteArrayType>().write_batch(&items, None, None).unwrap();
+        column.close().unwrap();
+        let mut column = group.next_column().unwrap().unwrap();
+        column.typed::<DoubleType>().write_batch(&[1.5], Some(&[1, 0]), None).unwrap();
+        column.close().unwrap();
+        let mut column = group.next_column().unwrap().unwrap();
+        column.typed::<BoolType>().write_batch(&[true, false], None, None).unwrap();
+        column.close().unwrap();
+        let mut column = group.next_column().unwrap().unwrap();
+        column.typed::<Int32Type>().write_batch(&[19723], Some(&[0, 1]), None).unwrap();
+        column.close().unwrap();
+        group.close().unwrap();
+        writer.close().unwrap();
+
+        let (sheet, total) = read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(total, 2);
+        assert_eq!(sheet.evaluate(0, 0), "item");
+        assert_eq!(sheet.evaluate(3, 0), "sold");
+        assert_eq!(sheet.evaluate(0, 2), "Pear");
+        assert_eq!(sheet.value(1, 1), CellValue::Number(1.5));
+        assert!(sheet.get_cell_ref(1, 2).is_none());
+        assert_eq!(sheet.value(2, 1), CellValue::Boolean(true));
+        assert_eq!(sheet.evaluate(3, 2), "2024-01-01");
+        let types: Vec<(usize, ColType)> = sheet.col_types().collect();
+        assert!(types.contains(&(0, ColType::Text)) && types.contains(&(3, ColType::Date)));
+
+        assert!(read(Path::new("no-such-file.parquet")).is_err());
+    }
+}
@@ -0,0 +1,377 @@
+//! Group-by summaries for `:pivot`.
+//!
+//! The rows of a data range are grouped by the value of a key column (and
+//! optionally a second key that spreads the groups across columns) and
+//! another column is aggregated per group. The first row of the range is the
+//! header. The result is a grid of cell values ready to be written back into
+//! the sheet, with a Total row (and column) at the end. Keys keep the value
+//! they have in the data, so the text "00123" stays text, and the group of
+//! rows with no key is headed by an empty cell.
+
+use std::cmp::Ordering;
+
+use crate::cell::CellValue;
+use crate::coltype::ColType;
+use crate::formula;
+use crate::sheet::Sheet;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aggregate {
+    Sum,
+    Count,
+    Avg,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "sum" => Some(Aggregate::Sum),
+            "count" => Some(Aggregate::Count),
+            "avg" | "average" => Some(Aggregate::Avg),
+            "min" => Some(Aggregate::Min),
+            "max" => Some(Aggregate::Max),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aggregate::Sum => "SUM",
+            Aggregate::Count => "COUNT",
+            Aggregate::Avg => "AVG",
+            Aggregate::Min => "MIN",
+            Aggregate::Max => "MAX",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pivot {
+    /// Column whose values become the rows of the result
+    pub row_key: usize,
+    /// Column whose values become the columns of the result
+    pub col_key: Option<usize>,
+    pub aggregate: Aggregate,
+    pub value_col: usize,
+    /// Top-left cell of the result, None to place it next to the data
+    pub target: Option<(usize, usize)>,
+}
+
+/// Running totals for one group
+#[derive(Clone, Copy, Default)]
+struct Acc {
+    count: usize,
+    numbers: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Acc {
+    fn add(&mut self, value: &CellValue) {
+        match value {
+            CellValue::Empty => return,
+            CellValue::Number(n) => {
+                if self.numbers == 0 {
+                    (self.min, self.max) = (*n, *n);
+                } else {
+                    self.min = self.min.min(*n);
+                    self.max = self.max.max(*n);
+                }
+                self.numbers += 1;
+                self.sum += n;
+            }
+            _ => {}
+        }
+        self.count += 1;
+    }
+
+    fn merge(&mut self, other: &Acc) {
+        if other.numbers > 0 {
+            if self.numbers == 0 {
+                (self.min, self.max) = (other.min, other.max);
+            } else {
+                self.min = self.min.min(other.min);
+                self.max = self.max.max(other.max);
+            }
+        }
+        self.count += other.count;
+        self.numbers += other.numbers;
+        self.sum += other.sum;
+    }
+
+    fn result(&self, aggregate: Aggregate) -> CellValue {
+        let n = match aggregate {
+            Aggregate::Count => self.count as f64,
+            _ if self.numbers == 0 => return CellValue::Empty,
+            Aggregate::Sum => self.sum,
+            Aggregate::Avg => self.sum / self.numbers as f64,
+            Aggregate::Min => self.min,
+            Aggregate::Max => self.max,
+        };
+        CellValue::Number(n)
+    }
+}
+
+/// Parse the arguments of `:pivot`, e.g. `A sum(C)`, `A B avg(D)` or
+/// `A B count(C) to H1`
+pub fn parse(args: &str) -> Result<Pivot, String> {
+    const USAGE: &str = "Usage: :pivot <key> [<column key>] sum|count|avg|min|max(<column>) [to <cell>]";
+
+    let mut words: Vec<&str> = args.split_whitespace().collect();
+    let mut target = None;
+    if words.len() >= 2 && words[words.len() - 2].eq_ignore_ascii_case("to") {
+        let cell = words[words.len() - 1];
+        let (col, row, _, _) = formula::parse_cell_ref(cell).ok_or_else(|| format!("Invalid cell: {}", cell))?;
+        target = Some((col, row));
+        words.truncate(words.len() - 2);
+    }
+
+    let (spec, keys) = words.split_last().ok_or(USAGE)?;
+    let (name, value) = spec
+        .strip_suffix(')')
+        .and_then(|s| s.split_once('('))
+        .ok_or(USAGE)?;
+    let aggregate = Aggregate::parse(name).ok_or_else(|| format!("Unknown aggregate: {} (use sum, count, avg, min or max)", name))?;
+    let value_col = parse_col(value)?;
+
+    let (row_key, col_key) = match keys {
+        [row] => (parse_col(row)?, None),
+        [row, col] => (parse_col(row)?, Some(parse_col(col)?)),
+        _ => return Err(USAGE.to_string()),
+    };
+
+    Ok(Pivot { row_key, col_key, aggregate, value_col, target })
+}
+
+fn parse_col(name: &str) -> Result<usize, String> {
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(format!("Invalid column: {}", name));
+    }
+    formula::col_from_letters(&name.to_ascii_uppercase())
+        .filter(|&col| col <= 255)
+        .ok_or_else(|| format!("Invalid column: {}", name))
+}
+
+/// Group key of a cell: its displayed value, None if blank
+fn key(sheet: &Sheet, col: usize, row: usize) -> Option<String> {
+    let text = sheet.evaluate(col, row);
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Numbers in numeric order, then text case-insensitively
+fn compare_keys(a: &str, b: &str) -> Ordering {
+    let rank = |s: &str| s.parse::<f64>().is_err();
+    rank(a).cmp(&rank(b)).then_with(|| match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(x), Ok(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+        _ => a.to_lowercase().cmp(&b.to_lowercase()),
+    })
+}
+
+fn same_key(a: &Option<String>, b: &Option<String>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+        _ => a.is_none() && b.is_none(),
+    }
+}
+
+/// Sorted distinct keys, each with the first of `keys` it stands for; keys
+/// differing only in case are one group and blanks come last. A key column
+/// with a declared type sorts by that type.
+fn distinct_keys(keys: &[Option<String>], col_type: Option<ColType>) -> Vec<(Option<String>, usize)> {
+    let mut distinct: Vec<(Option<String>, usize)> = Vec::new();
+    for (i, k) in keys.iter().enumerate() {
+        if !distinct.iter().any(|(d, _)| same_key(d, k)) {
+            distinct.push((k.clone(), i));
+        }
+    }
+    distinct.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) => col_type.map_or_else(|| compare_keys(a, b), |ty| ty.compare(a, b)),
+        _ => a.is_none().cmp(&b.is_none()),
+    });
+    distinct
+}
+
+fn position(keys: &[(Option<String>, usize)], key: &Option<String>) -> usize {
+    keys.iter().position(|(k, _)| same_key(k, key)).unwrap_or(0)
+}
+
+impl Pivot {
+    /// Summarize rows `start_row..=end_row`, the first of which is the header.
+    /// Returns the result as rows of cell values.
+    pub fn compute(&self, sheet: &Sheet, start_row: usize, end_row: usize) -> Vec<Vec<CellValue>> {
+        let data: Vec<usize> = ((start_row + 1)..=end_row).collect();
+        let row_keys: Vec<Option<String>> = data.iter().map(|&row| key(sheet, self.row_key, row)).collect();
+        let col_keys: Vec<Option<String>> = match self.col_key {
+            Some(col) => data.iter().map(|&row| key(sheet, col, row)).collect(),
+            None => Vec::new(),
+        };
+        let rows = distinct_keys(&row_keys, sheet.col_type(self.row_key));
+        let cols = distinct_keys(&col_keys, self.col_key.and_then(|col| sheet.col_type(col)));
+        // A group's key as the value of its first row in the data
+        let key_value = |col: usize, (key, first): &(Option<String>, usize)| match key {
+            Some(_) => sheet.value(col, data[*first]),
+            None => CellValue::Empty,
+        };
+
+        // One accumulator per (row group, column group), plus the totals
+        let width = cols.len().max(1);
+        let mut cells = vec![vec![Acc::default(); width]; rows.len()];
+        for (i, &row) in data.iter().enumerate() {
+            let r = position(&rows, &row_keys[i]);
+            let c = if self.col_key.is_some() { position(&cols, &col_keys[i]) } else { 0 };
+            cells[r][c].add(&sheet.value(self.value_col, row));
+        }
+
+        let header = |col: usize| key(sheet, col, start_row).unwrap_or_default();
+        let label = CellValue::Text(format!("{} of {}", self.aggregate.name(), header(self.value_col)));
+        let total_label = || CellValue::Text("Total".to_string());
+        let mut grid = Vec::with_capacity(rows.len() + 2);
+        let mut column_totals = vec![Acc::default(); width];
+        let mut grand_total = Acc::default();
+
+        if let Some(col_key) = self.col_key {
+            let mut line = vec![label];
+            line.extend(cols.iter().map(|key| key_value(col_key, key)));
+            line.push(total_label());
+            grid.push(line);
+        } else {
+            grid.push(vec![sheet.value(self.row_key, start_row), label]);
+        }
+
+        for (key, accs) in rows.iter().zip(&cells) {
+            let mut line = vec![key_value(self.row_key, key)];
+            let mut row_total = Acc::default();
+            for (c, acc) in accs.iter().enumerate() {
+                line.push(acc.result(self.aggregate));
+                row_total.merge(acc);
+                column_totals[c].merge(acc);
+            }
+            if self.col_key.is_some() {
+                line.push(row_total.result(self.aggregate));
+            }
+            grand_total.merge(&row_total);
+            grid.push(line);
+        }
+
+        let mut total = vec![total_label()];
+        if self.col_key.is_some() {
+            total.extend(column_totals.iter().map(|acc| acc.result(self.aggregate)));
+        }
+        total.push(grand_total.result(self.aggregate));
+        grid.push(total);
+        grid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The grid as text, as it would show
+    fn shown(grid: Vec<Vec<CellValue>>) -> Vec<Vec<String>> {
+        let text = |value: CellValue| match value {
+            CellValue::Empty => String::new(),
+            CellValue::Number(n) => n.to_string(),
+            CellValue::Text(s) => s,
+            other => format!("{:?}", other),
+        };
+        grid.into_iter().map(|line| line.into_iter().map(text).collect()).collect()
+    }
+
+    fn sheet(rows: &[&[&str]]) -> Sheet {
+        let mut sheet = Sheet::new();
+        for (r, row) in rows.iter().enumerate() {
+            for (c, value) in row.iter().enumerate() {
+                sheet.set_cell(c, r, value.to_string());
+            }
+        }
+        sheet
+    }
+
+    #[test]
+    fn test_parse() {
+        let p = parse("A sum(C)").unwrap();
+        assert_eq!((p.row_key, p.col_key, p.aggregate, p.value_col, p.target), (0, None, Aggregate::Sum, 2, None));
+        let p = parse("a b AVG(d) to H2").unwrap();
+        assert_eq!((p.row_key, p.col_key, p.aggregate, p.value_col, p.target), (0, Some(1), Aggregate::Avg, 3, Some((7, 1))));
+        assert!(parse("A").is_err());
+        assert!(parse("A median(C)").is_err());
+        assert!(parse("A B C sum(D)").is_err());
+        assert!(parse("A sum(C) to nowhere").is_err());
+    }
+
+    #[test]
+    fn test_group_by_one_key() {
+        let s = sheet(&[
+            &["Region", "Amount"],
+            &["West", "10"],
+            &["East", "5"],
+            &["west", "7"],
+            &["", "1"],
+        ]);
+        let p = parse("A sum(B)").unwrap();
+        assert_eq!(
+            shown(p.compute(&s, 0, 4)),
+            vec![
+                vec!["Region", "SUM of Amount"],
+                vec!["East", "5"],
+                vec!["West", "17"],
+                vec!["", "1"],
+                vec!["Total", "23"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keys_keep_their_values() {
+        let mut s = Sheet::new();
+        s.set_col_type(0, Some(ColType::Text));
+        for (r, (key, amount)) in [("Code", "Amount"), ("00123", "1"), ("TRUE", "2"), ("(blank)", "4"), ("", "8"), ("7", "16")]
+            .into_iter()
+            .enumerate()
+        {
+            s.set_cell(0, r, key.to_string());
+            s.set_cell(1, r, amount.to_string());
+        }
+        let grid = parse("A sum(B)").unwrap().compute(&s, 0, 5);
+        let keys: Vec<CellValue> = grid.iter().map(|line| line[0].clone()).collect();
+        let text = |s: &str| CellValue::Text(s.to_string());
+        assert_eq!(keys, vec![text("Code"), text("(blank)"), text("00123"), text("7"), text("TRUE"), CellValue::Empty, text("Total")]);
+        assert_eq!(grid[1][1], CellValue::Number(4.0));
+        assert_eq!(grid[6][1], CellValue::Number(31.0));
+
+        // A number key stays a number
+        let s = sheet(&[&["N", "V"], &["7", "1"]]);
+        assert_eq!(parse("A count(B)").unwrap().compute(&s, 0, 1)[1], vec![CellValue::Number(7.0), CellValue::Number(1.0)]);
+    }
+
+    #[test]
+    fn test_group_by_two_keys() {
+        let s = sheet(&[
+            &["Region", "Product", "Units"],
+            &["West", "Pen", "2"],
+            &["East", "Pen", "4"],
+            &["West", "Ink", "6"],
+            &["West", "Pen", "x"],
+        ]);
+        let p = parse("A B count(C)").unwrap();
+        assert_eq!(
+            shown(p.compute(&s, 0, 4)),
+            vec![
+                vec!["COUNT of Units", "Ink", "Pen", "Total"],
+                vec!["East", "0", "1", "1"],
+                vec!["West", "1", "2", "3"],
+                vec!["Total", "1", "3", "4"],
+            ]
+        );
+        let p = parse("A B avg(C)").unwrap();
+        let grid = shown(p.compute(&s, 0, 4));
+        assert_eq!(grid[1], vec!["East", "", "4", "4"]);
+        assert_eq!(grid[3], vec!["Total", "6", "3", "4"]);
+    }
+}
@@ -0,0 +1,169 @@
+//! Commands added from the config file.
+//!
+//! The `[commands]` table names new `:` commands, each run by an external
+//! program or a Rhai script:
+//!
+//! ```toml
+//! [commands]
+//! dedupe = "python3 ~/.vicalc/dedupe.py"
+//! tally = "~/.vicalc/tally.rhai"
+//! ```
+//!
+//! A command is only looked up when no built-in command has its name.
+//! `:dedupe A` runs the program through the shell with `A` appended, and
+//! writes the sheet to its standard input as JSON:
+//!
+//! ```json
+//! {"command": "dedupe", "args": "A", "file": "data.csv", "cursor": "B3",
+//!  "selection": "A1:C9", "cells": {"A1": "Name", "B2": "=SUM(B3:B9)"}}
+//! ```
+//!
+//! `selection` is null unless the command was given from Visual mode, and
+//! `cells` holds what was typed into every cell that is not empty. The
+//! program answers with the changes on its standard output, all optional:
+//!
+//! ```json
+//! {"cells": {"D1": "Total", "C4": ""}, "cursor": "D1", "message": "2 duplicates"}
+//! ```
+//!
+//! Each cell is set as if typed, an empty string clearing it, in one undo
+//! step. No output at all changes nothing. A file ending in `.rhai` is run
+//! as a script instead (see `scripting`), with `ARGS` and `SELECTION` set.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::commands;
+use crate::formula;
+use crate::validation::area_name;
+use crate::App;
+
+/// What a program is given
+#[derive(Serialize)]
+struct Request<'a> {
+    command: &'a str,
+    args: &'a str,
+    file: Option<&'a str>,
+    cursor: String,
+    selection: Option<String>,
+    cells: BTreeMap<String, &'a str>,
+}
+
+/// What a program gives back
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Response {
+    cells: BTreeMap<String, String>,
+    cursor: Option<String>,
+    message: Option<String>,
+}
+
+/// Run the command `name` from the config file, if there is one. Returns
+/// false when there is none.
+pub fn run(app: &mut App, name: &str, args: &str, range: Option<(usize, usize, usize, usize)>) -> bool {
+    let Some(program) = app.plugins.get(name).cloned() else {
+        return false;
+    };
+    if program.ends_with(".rhai") {
+        let selection = range.map(area_name);
+        crate::scripting::run_command(app, &program, args, selection.as_deref());
+        return true;
+    }
+    if let Err(e) = run_program(app, name, &program, args, range) {
+        app.error(format!("{}: {}", name, e));
+    }
+    true
+}
+
+fn run_program(
+    app: &mut App,
+    name: &str,
+    program: &str,
+    args: &str,
+    range: Option<(usize, usize, usize, usize)>,
+) -> Result<(), String> {
+    let request = Request {
+        command: name,
+        args,
+        file: app.current_file.as_deref(),
+        cursor: formula::cell_name(app.cursor_col, app.cursor_row),
+        selection: range.map(area_name),
+        cells: app
+            .sheet
+            .cells()
+            .iter()
+            .filter(|(_, cell)| !cell.raw_input.is_empty())
+            .map(|(&(col, row), cell)| (formula::cell_name(col, row), cell.raw_input.as_str()))
+            .collect(),
+    };
+    let input = serde_json::to_string(&request).map_err(|e| e.to_string())?;
+    let command_line = if args.is_empty() { program.to_string() } else { format!("{} {}", program, args) };
+    let output = commands::run_filter(&command_line, input)?;
+    if output.trim().is_empty() {
+        app.info(format!("{}: done", name));
+        return Ok(());
+    }
+    let response: Response = serde_json::from_str(&output).map_err(|e| format!("bad output: {}", e))?;
+    apply(app, name, response)
+}
+
+/// Make the changes a program asked for, all or none of them
+fn apply(app: &mut App, name: &str, response: Response) -> Result<(), String> {
+    let mut changes = Vec::new();
+    for (cell, input) in response.cells {
+        match formula::parse_cell_ref(&cell) {
+            Some((col, row, _, _)) if col < 256 && row < 10000 => {
+                if app.sheet.is_locked(col, row) {
+                    return Err(format!("{} is locked", formula::cell_name(col, row)));
+                }
+                changes.push((col, row, input));
+            }
+            _ => return Err(format!("not a cell: {}", cell)),
+        }
+    }
+    let cursor = match response.cursor.as_deref().map(formula::parse_cell_ref) {
+        None => None,
+        Some(Some((col, row, _, _))) if col < 256 && row < 10000 => Some((col, row)),
+        Some(_) => return Err(format!("not a cell: {}", response.cursor.unwrap_or_default())),
+    };
+
+    if !changes.is_empty() {
+        app.save_undo();
+    }
+    let count = changes.len();
+    for (col, row, input) in changes {
+        app.sheet.set_cell(col, row, input);
+    }
+    if let Some((col, row)) = cursor {
+        app.move_cursor_to(col, row);
+    }
+    match response.message {
+        Some(message) => app.info(message),
+        None if count > 0 => app.info(format!("{}: {} cell(s) set", name, count)),
+        None => app.info(format!("{}: done", name)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let mut app = App::new();
+        app.sheet.set_cell(0, 0, "keep".to_string());
+        let response: Response = serde_json::from_str(r#"{"cells": {"A1": "", "B2": "=1+1"}, "cursor": "B2"}"#).unwrap();
+        apply(&mut app, "test", response).unwrap();
+        assert_eq!(app.sheet.evaluate(0, 0), "");
+        assert_eq!(app.sheet.evaluate(1, 1), "2");
+        assert_eq!((app.cursor_col, app.cursor_row), (1, 1));
+
+        // One bad cell and nothing changes
+        let response: Response = serde_json::from_str(r#"{"cells": {"A1": "x", "ZZ1": "y"}}"#).unwrap();
+        assert!(apply(&mut app, "test", response).is_err());
+        assert_eq!(app.sheet.evaluate(0, 0), "");
+        assert!(serde_json::from_str::<Response>(r#"{"cell": {}}"#).is_err());
+    }
+}
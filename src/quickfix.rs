@@ -0,0 +1,95 @@
+//! Quickfix list: the cells a scan turned up (`:check`, `:errors`), shown in
+//! a panel under the grid and visited in turn with `]q` and `[q`, as vim
+//! does with compiler errors.
+
+use crate::App;
+
+/// Most entries shown in the panel at once
+const PANEL_LINES: usize = 6;
+
+pub struct Entry {
+    pub col: usize,
+    pub row: usize,
+    pub text: String,
+}
+
+pub struct Quickfix {
+    pub title: String,
+    pub entries: Vec<Entry>,
+    // Entry last gone to
+    pub current: usize,
+    // Whether the panel is shown; the list stays usable while it is closed
+    pub open: bool,
+    // First entry shown in the panel
+    pub scroll: usize,
+}
+
+impl Quickfix {
+    /// Screen lines taken by the panel, title included
+    pub fn height(&self) -> u16 {
+        if self.open {
+            (self.entries.len().min(PANEL_LINES) + 1) as u16
+        } else {
+            0
+        }
+    }
+}
+
+/// Replace the list with the results of a scan, open the panel and go to
+/// the first entry
+pub fn set(app: &mut App, title: String, entries: Vec<Entry>) {
+    if entries.is_empty() {
+        app.quickfix = None;
+        return;
+    }
+    app.quickfix = Some(Quickfix { title, entries, current: 0, open: true, scroll: 0 });
+    app.adjust_views();
+    go(app, 0);
+}
+
+/// Go to entry `index` (0-based)
+pub fn go(app: &mut App, index: usize) {
+    let Some(qf) = app.quickfix.as_mut() else {
+        app.error("No quickfix list");
+        return;
+    };
+    let index = index.min(qf.entries.len() - 1);
+    qf.current = index;
+    if index < qf.scroll {
+        qf.scroll = index;
+    } else if index >= qf.scroll + PANEL_LINES {
+        qf.scroll = index + 1 - PANEL_LINES;
+    }
+    let entry = &qf.entries[index];
+    let (col, row) = (entry.col, entry.row);
+    let message = format!("({} of {}) {}: {}", index + 1, qf.entries.len(), crate::formula::cell_name(col, row), entry.text);
+    app.jump_to(col, row);
+    app.info(message);
+}
+
+/// Go `count` entries forward (or back when negative), stopping at the ends
+pub fn step(app: &mut App, count: isize) {
+    let Some(qf) = &app.quickfix else {
+        app.error("No quickfix list");
+        return;
+    };
+    let last = qf.entries.len() as isize - 1;
+    let target = (qf.current as isize + count).clamp(0, last) as usize;
+    if target == qf.current && count != 0 {
+        app.warn(if count < 0 { "No previous entry" } else { "No more entries" });
+        return;
+    }
+    go(app, target);
+}
+
+/// Show or hide the panel (:copen, :cclose)
+pub fn show(app: &mut App, open: bool) {
+    match app.quickfix.as_mut() {
+        Some(qf) => {
+            qf.open = open;
+            app.adjust_views();
+        }
+        None if open => app.error("No quickfix list"),
+        None => {}
+    }
+}
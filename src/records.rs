@@ -0,0 +1,124 @@
+//! JSON records: an array of objects, one per row, as web APIs and most
+//! data tools exchange tables (`:import data.json records`,
+//! `:export out.json records`).
+//!
+//! Reading puts the field names in a header row, in the order they first
+//! appear, and each record in the row below. Writing takes the names from
+//! the top row of the area and gives numbers and booleans their JSON
+//! types; empty cells become null.
+
+use serde_json::{Map, Number, Value};
+
+use crate::cell::CellValue;
+use crate::sheet::Sheet;
+
+/// Build a sheet from a JSON array of objects
+pub fn read(text: &str) -> Result<Sheet, String> {
+    let value: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let Value::Array(items) = value else {
+        return Err("expected an array of records".to_string());
+    };
+    if items.len() > 9999 {
+        return Err(format!("{} records (at most 9999 fit)", items.len()));
+    }
+
+    let mut sheet = Sheet::new();
+    let mut fields: Vec<String> = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let Value::Object(record) = item else {
+            return Err(format!("record {} is not an object", i + 1));
+        };
+        for (name, value) in record {
+            let col = match fields.iter().position(|f| f == name) {
+                Some(col) => col,
+                None if fields.len() < 256 => {
+                    sheet.set_cell(fields.len(), 0, name.clone());
+                    fields.push(name.clone());
+                    fields.len() - 1
+                }
+                None => return Err("more than 256 fields".to_string()),
+            };
+            let input = match value {
+                Value::Null => continue,
+                Value::Bool(b) => if *b { "TRUE" } else { "FALSE" }.to_string(),
+                Value::Number(n) => n.to_string(),
+                Value::String(s) => s.clone(),
+                // Nested arrays and objects are kept as their JSON text
+                other => other.to_string(),
+            };
+            sheet.set_cell(col, i + 1, input);
+        }
+    }
+    Ok(sheet)
+}
+
+/// The (min_col, min_row, max_col, max_row) area as a JSON array of
+/// objects, the top row naming the fields. Rows with nothing in them are
+/// left out.
+pub fn write(sheet: &Sheet, (c1, r1, c2, r2): (usize, usize, usize, usize)) -> String {
+    let names: Vec<String> = (c1..=c2)
+        .map(|col| {
+            let name = sheet.evaluate(col, r1);
+            if name.is_empty() { crate::formula::col_to_name(col) } else { name }
+        })
+        .collect();
+
+    let mut records = Vec::new();
+    for row in r1 + 1..=r2 {
+        if (c1..=c2).all(|col| sheet.get_cell_ref(col, row).is_none()) {
+            continue;
+        }
+        let mut record = Map::new();
+        for (col, name) in (c1..=c2).zip(&names) {
+            let value = match sheet.value(col, row) {
+                CellValue::Empty => Value::Null,
+                // Whole numbers without a trailing .0
+                CellValue::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => Value::Number(Number::from(n as i64)),
+                CellValue::Number(n) => Number::from_f64(n).map_or(Value::Null, Value::Number),
+                CellValue::Boolean(b) => Value::Bool(b),
+                CellValue::Text(s) => Value::String(s),
+                _ => Value::String(sheet.evaluate(col, row)),
+            };
+            record.insert(name.clone(), value);
+        }
+        records.push(Value::Object(record));
+    }
+    let mut json = serde_json::to_string_pretty(&Value::Array(records)).unwrap_or_default();
+    json.push('\n');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let sheet = read(r#"[{"name": "Apple", "qty": 3, "ok": true}, {"qty": 1.5, "tags": ["a"], "name": null}]"#).unwrap();
+        assert_eq!(sheet.evaluate(0, 0), "name");
+        assert_eq!(sheet.evaluate(1, 0), "qty");
+        assert_eq!(sheet.evaluate(2, 0), "ok");
+        assert_eq!(sheet.evaluate(3, 0), "tags");
+        assert_eq!(sheet.value(1, 2), CellValue::Number(1.5));
+        assert_eq!(sheet.value(2, 1), CellValue::Boolean(true));
+        assert_eq!(sheet.evaluate(3, 2), "[\"a\"]");
+        assert!(sheet.get_cell_ref(0, 2).is_none());
+
+        assert!(read("{}").is_err());
+        assert!(read("[1, 2]").is_err());
+    }
+
+    #[test]
+    fn test_write() {
+        let mut sheet = Sheet::new();
+        for (col, row, input) in [(0, 0, "Item"), (1, 0, "Qty"), (0, 1, "Pear"), (1, 1, "=2*3"), (1, 3, "TRUE")] {
+            sheet.set_cell(col, row, input.to_string());
+        }
+        let json: Value = serde_json::from_str(&write(&sheet, (0, 0, 2, 3))).unwrap();
+        let expected: Value = serde_json::from_str(
+            r#"[{"Item": "Pear", "Qty": 6, "C": null}, {"Item": null, "Qty": true, "C": null}]"#,
+        )
+        .unwrap();
+        assert_eq!(json, expected);
+    }
+}
@@ -0,0 +1,147 @@
+//! A sheet range as rows of styled text, for showing sheets in other
+//! programs (a file manager's preview pane, say) without a terminal.
+//!
+//! Each cell becomes one span as wide as its column, laid out the way the
+//! grid draws it: numbers right-aligned and text left-aligned unless set
+//! with `:align`, merged areas centered across their columns, text too wide
+//! for the column cut off with `…` and numbers shown as `###`. The span
+//! carries the cell's `:style` and whether it is locked or breaks its
+//! validation rule, so the caller can color it with its own theme. Hidden
+//! rows and columns are left out.
+
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::cell::{Align, CellStyle, CellValue};
+use crate::sheet::Sheet;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Span {
+    /// Padded to the column's width, with one space of padding at the right
+    pub text: String,
+    pub style: CellStyle,
+    pub number: bool,
+    pub locked: bool,
+    pub invalid: bool,
+}
+
+/// Lay out the (min_col, min_row, max_col, max_row) area of the sheet as one
+/// row of spans per shown row
+pub fn render(sheet: &Sheet, (c1, r1, c2, r2): (usize, usize, usize, usize)) -> Vec<Vec<Span>> {
+    let cols: Vec<usize> = (c1..=c2).filter(|&c| !sheet.is_col_hidden(c)).collect();
+    (r1..=r2)
+        .filter(|&r| !sheet.is_row_hidden(r))
+        .map(|row| {
+            let mut spans = Vec::new();
+            for &col in &cols {
+                let merge = sheet.merge_at(col, row);
+                let mut width = sheet.get_col_width(col);
+                if let Some((first, _, last, _)) = merge {
+                    // Drawn once, from the first of its columns in the area
+                    if cols.iter().any(|&c| c >= first && c < col) {
+                        continue;
+                    }
+                    width = cols.iter().filter(|&&c| c >= col && c <= last).map(|&c| sheet.get_col_width(c)).sum();
+                }
+                // A merged area shows its top-left cell on its first row
+                let (value_col, value_row) = merge.map_or((col, row), |(first, top, _, _)| (first, top));
+                let value = match merge {
+                    Some((_, top, _, _)) if row != top => String::new(),
+                    _ => sheet.evaluate(value_col, value_row),
+                };
+                let style = sheet.get_cell_ref(value_col, value_row).map(|cell| cell.style).unwrap_or_default();
+                let number = is_number(sheet, value_col, value_row);
+                let align = cell_align(sheet, value_col, value_row, merge.is_some());
+                let content_width = width.saturating_sub(1);
+                spans.push(Span {
+                    text: format!("{} ", fit(&value, content_width, align, number)),
+                    style,
+                    number,
+                    locked: sheet.is_locked(col, row),
+                    invalid: sheet.is_invalid(col, row),
+                });
+            }
+            spans
+        })
+        .collect()
+}
+
+/// Whether a cell shows a number: typed as one, a formula, or spilled from
+/// an array formula
+pub fn is_number(sheet: &Sheet, col: usize, row: usize) -> bool {
+    match sheet.get_cell_ref(col, row).map(|cell| &cell.value) {
+        Some(CellValue::Number(_)) | Some(CellValue::Formula(_)) => true,
+        Some(CellValue::Empty) | None => matches!(sheet.value(col, row), CellValue::Number(_)),
+        _ => false,
+    }
+}
+
+/// How a cell's value lines up: as set with `:align`, else centered across
+/// a merged area, numbers right and text left
+pub fn cell_align(sheet: &Sheet, col: usize, row: usize, merged: bool) -> Align {
+    let style = sheet.get_cell_ref(col, row).map(|cell| cell.style).unwrap_or_default();
+    style.align.unwrap_or(if merged {
+        Align::Center
+    } else if is_number(sheet, col, row) {
+        Align::Right
+    } else {
+        Align::Left
+    })
+}
+
+/// A value padded to `width` display columns. Text too wide is cut off with
+/// `…`; a number too wide becomes `###`, as part of one would mislead.
+pub fn fit(value: &str, width: usize, align: Align, number: bool) -> String {
+    let content = if value.width() <= width {
+        value.to_string()
+    } else if number {
+        "#".repeat(width)
+    } else {
+        format!("{}…", truncate(value, width.saturating_sub(1)))
+    };
+    let space = width.saturating_sub(content.width());
+    match align {
+        Align::Left => format!("{}{}", content, " ".repeat(space)),
+        Align::Right => format!("{}{}", " ".repeat(space), content),
+        Align::Center => format!("{}{}{}", " ".repeat(space / 2), content, " ".repeat(space - space / 2)),
+    }
+}
+
+/// The longest start of `s` that fits in `width` display columns
+fn truncate(s: &str, width: usize) -> String {
+    let mut used = 0;
+    s.chars()
+        .take_while(|c| {
+            used += c.width().unwrap_or(1);
+            used <= width
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut sheet = Sheet::new();
+        sheet.set_col_width(0, 6);
+        sheet.set_col_width(1, 4);
+        sheet.set_cell(0, 0, "Item".to_string());
+        sheet.set_cell(1, 0, "Qty".to_string());
+        sheet.set_cell(0, 1, "Apples".to_string());
+        sheet.set_cell(1, 1, "=1200*2".to_string());
+        sheet.set_cell(0, 2, "Pear".to_string());
+        sheet.set_cell(1, 2, "7".to_string());
+        sheet.style_cells((0, 0, 1, 0), |style| style.bold = true);
+        sheet.set_rows_hidden(2, 2, true);
+
+        let rows = render(&sheet, (0, 0, 1, 2));
+        let text: Vec<String> = rows.iter().map(|spans| spans.iter().map(|s| s.text.as_str()).collect()).collect();
+        assert_eq!(text, vec!["Item  Qty ", "Appl… ### "]);
+        assert!(rows[0][0].style.bold && !rows[1][0].style.bold);
+        assert!(rows[1][1].number && !rows[1][0].number);
+
+        assert_eq!(fit("7", 5, Align::Center, true), "  7  ");
+        assert_eq!(fit("日本語", 4, Align::Left, false), "日… ");
+    }
+}
@@ -0,0 +1,75 @@
+//! Random row orders for `:sample` and `:shuffle`, used to cut a test
+//! dataset out of a large import.
+//!
+//! The generator is a small xorshift; it only has to look random, and a
+//! seed given to the command makes the result repeatable.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // The state must never be zero
+        Rng((seed ^ 0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    /// Seeded from the clock and the process, for a different order each run
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        Rng::new(nanos ^ ((std::process::id() as u64) << 32))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in 0..n
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Put the items in a random order
+pub fn shuffle<T>(items: &mut [T], rng: &mut Rng) {
+    for i in (1..items.len()).rev() {
+        items.swap(i, rng.below(i + 1));
+    }
+}
+
+/// `count` of the items picked at random, kept in their original order
+pub fn sample<T: Ord>(mut items: Vec<T>, count: usize, rng: &mut Rng) -> Vec<T> {
+    let count = count.min(items.len());
+    // The first `count` places of a partial shuffle
+    for i in 0..count {
+        let j = i + rng.below(items.len() - i);
+        items.swap(i, j);
+    }
+    items.truncate(count);
+    items.sort();
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shuffle_and_sample() {
+        let mut rows: Vec<usize> = (0..50).collect();
+        shuffle(&mut rows, &mut Rng::new(7));
+        assert_ne!(rows, (0..50).collect::<Vec<_>>());
+        rows.sort();
+        assert_eq!(rows, (0..50).collect::<Vec<_>>());
+
+        let picked = sample((0..50).collect(), 10, &mut Rng::new(7));
+        assert_eq!(picked.len(), 10);
+        assert!(picked.windows(2).all(|w| w[0] < w[1]));
+        // The same seed picks the same rows
+        assert_eq!(picked, sample((0..50).collect(), 10, &mut Rng::new(7)));
+        assert_eq!(sample(vec![1, 2], 5, &mut Rng::new(1)), vec![1, 2]);
+    }
+}
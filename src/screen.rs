@@ -0,0 +1,282 @@
+//! Off-screen frame buffer.
+//!
+//! The UI draws every frame into a `Screen` with the same move/color/print
+//! calls it would send to the terminal. `flush` then compares the frame with
+//! the previous one and writes only the runs of cells that changed, so a
+//! cursor move costs a few dozen bytes instead of a full repaint.
+
+use crossterm::{
+    cursor::MoveTo,
+    queue,
+    style::{Attribute, Color, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor},
+};
+use std::io::{Result, Write};
+use unicode_width::UnicodeWidthChar;
+
+// Marks the right half of a double-width character
+const CONTINUATION: char = '\0';
+
+// Unchanged cells shorter than this between two changes are rewritten rather
+// than skipped with a cursor move, which costs about as many bytes
+const MAX_GAP: usize = 8;
+
+/// Text attributes: bold and underline
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Attrs {
+    pub bold: bool,
+    pub underline: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Cell {
+    ch: char,
+    fg: Color,
+    bg: Color,
+    attrs: Attrs,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell { ch: ' ', fg: Color::Reset, bg: Color::Reset, attrs: Attrs::default() }
+    }
+}
+
+pub struct Screen {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    // What the terminal currently shows; empty when it is unknown
+    previous: Vec<Cell>,
+    x: usize,
+    y: usize,
+    fg: Color,
+    bg: Color,
+    attrs: Attrs,
+}
+
+impl Screen {
+    pub fn new(width: u16, height: u16) -> Self {
+        let (width, height) = (width as usize, height as usize);
+        Screen {
+            width,
+            height,
+            cells: vec![Cell::default(); width * height],
+            previous: Vec::new(),
+            x: 0,
+            y: 0,
+            fg: Color::Reset,
+            bg: Color::Reset,
+            attrs: Attrs::default(),
+        }
+    }
+
+    /// Match the terminal size. Changing it forces a full repaint.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        if (width as usize, height as usize) != (self.width, self.height) {
+            *self = Screen::new(width, height);
+        }
+    }
+
+    /// Forget what the terminal shows so the next flush repaints everything
+    pub fn invalidate(&mut self) {
+        self.previous.clear();
+    }
+
+    pub fn move_to(&mut self, x: u16, y: u16) {
+        self.x = x as usize;
+        self.y = y as usize;
+    }
+
+    pub fn set_colors(&mut self, bg: Color, fg: Color) {
+        self.bg = bg;
+        self.fg = fg;
+    }
+
+    pub fn set_bg(&mut self, bg: Color) {
+        self.bg = bg;
+    }
+
+    pub fn set_fg(&mut self, fg: Color) {
+        self.fg = fg;
+    }
+
+    pub fn set_attrs(&mut self, attrs: Attrs) {
+        self.attrs = attrs;
+    }
+
+    /// Back to the terminal's colors, without attributes
+    pub fn reset_colors(&mut self) {
+        self.fg = Color::Reset;
+        self.bg = Color::Reset;
+        self.attrs = Attrs::default();
+    }
+
+    /// Write text at the current position with the current colors, clipped
+    /// at the right edge
+    pub fn print(&mut self, text: &str) {
+        for c in text.chars() {
+            let w = UnicodeWidthChar::width(c).unwrap_or(1);
+            if w == 0 {
+                continue;
+            }
+            if self.y >= self.height || self.x + w > self.width {
+                // A wide character that does not fit leaves a blank
+                if self.y < self.height && self.x < self.width {
+                    self.put(' ');
+                }
+                self.x = self.width;
+                break;
+            }
+            self.put(c);
+            if w == 2 {
+                self.put(CONTINUATION);
+            }
+        }
+    }
+
+    fn put(&mut self, ch: char) {
+        let i = self.y * self.width + self.x;
+        // Overwriting half of a wide character blanks the other half
+        if self.cells[i].ch == CONTINUATION && ch != CONTINUATION && self.x > 0 {
+            self.cells[i - 1].ch = ' ';
+        }
+        if self.x + 1 < self.width && self.cells[i + 1].ch == CONTINUATION && ch != CONTINUATION {
+            self.cells[i + 1].ch = ' ';
+        }
+        self.cells[i] = Cell { ch, fg: self.fg, bg: self.bg, attrs: self.attrs };
+        self.x += 1;
+    }
+
+    /// Send the changes since the last flush to `out`
+    pub fn flush<W: Write>(&mut self, out: &mut W) -> Result<()> {
+        let full = self.previous.len() != self.cells.len();
+        let mut pen: Option<(Color, Color)> = None;
+        // The terminal starts each flush without attributes
+        let mut pen_attrs = Attrs::default();
+
+        for y in 0..self.height {
+            let row = y * self.width;
+            let changed = |x: usize| full || self.cells[row + x] != self.previous[row + x];
+
+            let mut x = 0;
+            while x < self.width {
+                if !changed(x) {
+                    x += 1;
+                    continue;
+                }
+                // Start the run on the left half of a wide character
+                let start = if self.cells[row + x].ch == CONTINUATION && x > 0 { x - 1 } else { x };
+                // Extend the run across short unchanged gaps
+                let mut end = x + 1;
+                let mut gap = 0;
+                let mut probe = end;
+                while probe < self.width && gap <= MAX_GAP {
+                    if changed(probe) {
+                        end = probe + 1;
+                        gap = 0;
+                    } else {
+                        gap += 1;
+                    }
+                    probe += 1;
+                }
+
+                queue!(out, MoveTo(start as u16, y as u16))?;
+                for cell in &self.cells[row + start..row + end] {
+                    if cell.ch == CONTINUATION {
+                        continue;
+                    }
+                    if pen_attrs != cell.attrs {
+                        // Resetting the attributes resets the colors too
+                        queue!(out, SetAttribute(Attribute::Reset))?;
+                        if cell.attrs.bold {
+                            queue!(out, SetAttribute(Attribute::Bold))?;
+                        }
+                        if cell.attrs.underline {
+                            queue!(out, SetAttribute(Attribute::Underlined))?;
+                        }
+                        pen_attrs = cell.attrs;
+                        pen = None;
+                    }
+                    if pen != Some((cell.fg, cell.bg)) {
+                        queue!(out, SetForegroundColor(cell.fg), SetBackgroundColor(cell.bg))?;
+                        pen = Some((cell.fg, cell.bg));
+                    }
+                    queue!(out, Print(cell.ch))?;
+                }
+                x = end;
+            }
+        }
+
+        if pen_attrs != Attrs::default() {
+            queue!(out, SetAttribute(Attribute::Reset))?;
+        }
+        if pen.is_some() {
+            queue!(out, ResetColor)?;
+        }
+        out.flush()?;
+        self.previous.clone_from(&self.cells);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flush(screen: &mut Screen) -> String {
+        let mut out = Vec::new();
+        screen.flush(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_unchanged_frame_writes_nothing() {
+        let mut screen = Screen::new(20, 3);
+        screen.print("hello");
+        assert!(flush(&mut screen).contains("hello"));
+        screen.move_to(0, 0);
+        screen.print("hello");
+        assert_eq!(flush(&mut screen), "");
+    }
+
+    #[test]
+    fn test_only_changed_run_is_written() {
+        let mut screen = Screen::new(40, 2);
+        screen.print(&"x".repeat(40));
+        flush(&mut screen);
+        screen.move_to(30, 0);
+        screen.print("y");
+        let out = flush(&mut screen);
+        assert!(out.contains('y'));
+        assert!(!out.contains('x'));
+    }
+
+    #[test]
+    fn test_wide_characters() {
+        let mut screen = Screen::new(5, 1);
+        screen.print("日本語");
+        // The third character does not fit in the last column
+        let out = flush(&mut screen);
+        assert!(out.contains("日本"));
+        assert!(!out.contains('語'));
+        // Overwriting the right half of a wide character blanks its left half
+        screen.move_to(1, 0);
+        screen.print("a");
+        let out = flush(&mut screen);
+        assert!(out.contains(" a"));
+    }
+
+    #[test]
+    fn test_attributes() {
+        let mut screen = Screen::new(10, 1);
+        screen.print("plain");
+        assert!(!flush(&mut screen).contains("\x1b[1m"));
+        // Making the same text bold rewrites it
+        screen.move_to(0, 0);
+        screen.set_attrs(Attrs { bold: true, underline: false });
+        screen.print("plain");
+        let out = flush(&mut screen);
+        assert!(out.contains("\x1b[1m"));
+        assert!(out.contains("plain"));
+    }
+}
@@ -0,0 +1,249 @@
+//! Running ex commands without the interface, for reports made from cron:
+//!
+//! ```text
+//! vicalc sales.csv -c ":filter C >100" -c ":export big.csv" -c ":q"
+//! vicalc sales.csv --script report.vic
+//! vicalc --template invoice.json -c ":set name=March" -c ":w march.json"
+//! vicalc --preview --rows 30 sales.csv
+//! ```
+//!
+//! The commands are the ones typed after `:`, and the config file applies
+//! as it does interactively, aliases included. A script holds one command
+//! per line; blank lines and lines starting with `"` or `#` are skipped.
+//! Errors and warnings go to stderr. The first error stops the run with
+//! exit status 1; `:q` ends it early, unsaved changes or not.
+//!
+//! `--preview` prints the top-left of the sheet as an aligned table and
+//! exits, for the preview panes of file managers such as ranger, lf and
+//! fzf: `--rows` rows (50 unless given) and `--cols` columns, or as many as
+//! fit in `$COLUMNS` (80 if it is not set). Commands given with `-c` run
+//! first, so the preview can be of a filtered or sorted sheet.
+
+use vicalc::render;
+
+use crate::message::Severity;
+use crate::{commands, config, App};
+
+/// Rows printed by `--preview` unless `--rows` says otherwise
+const PREVIEW_ROWS: usize = 50;
+
+/// Command line arguments of an interactive or batch run
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    pub file: Option<String>,
+    // A file to start a new, unnamed sheet from (--template)
+    pub template: Option<String>,
+    // Commands from -c and --script, in the order given
+    pub commands: Vec<String>,
+    // Whether to run the commands without the interface
+    pub batch: bool,
+    // What to print for --preview
+    pub preview: Option<Preview>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Preview {
+    pub rows: usize,
+    // As many as fit the terminal when not given
+    pub cols: Option<usize>,
+}
+
+/// Split the arguments after the program name into the file to open and
+/// the commands to run
+pub fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let (mut preview, mut rows, mut cols) = (false, None, None);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" => {
+                let command = args.next().ok_or("-c needs a command")?;
+                parsed.commands.push(command.clone());
+                parsed.batch = true;
+            }
+            "--script" => {
+                let path = args.next().ok_or("--script needs a file")?;
+                let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+                parsed.commands.extend(parse_script(&text));
+                parsed.batch = true;
+            }
+            "--template" => {
+                let path = args.next().ok_or("--template needs a file")?;
+                parsed.template = Some(path.clone());
+            }
+            "--preview" => preview = true,
+            "--rows" => rows = Some(count(args.next(), "--rows")?),
+            "--cols" => cols = Some(count(args.next(), "--cols")?),
+            _ if parsed.file.is_none() => parsed.file = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    if parsed.file.is_some() && parsed.template.is_some() {
+        return Err("give either a file or --template, not both".to_string());
+    }
+    if preview {
+        parsed.preview = Some(Preview { rows: rows.unwrap_or(PREVIEW_ROWS), cols });
+        parsed.batch = true;
+    } else if rows.is_some() || cols.is_some() {
+        return Err("--rows and --cols go with --preview".to_string());
+    }
+    Ok(parsed)
+}
+
+/// The number after `--rows` or `--cols`
+fn count(arg: Option<&String>, option: &str) -> Result<usize, String> {
+    match arg.map(|n| n.parse::<usize>()) {
+        Some(Ok(n)) if n > 0 => Ok(n),
+        Some(_) => Err(format!("{} needs a number above 0", option)),
+        None => Err(format!("{} needs a number", option)),
+    }
+}
+
+/// The commands of a script, one per line
+pub fn parse_script(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with(['"', '#']))
+        .map(String::from)
+        .collect()
+}
+
+/// Run the commands on the file and return the exit status
+pub fn run(args: &Args) -> i32 {
+    let mut app = App::new();
+    app.swap.set_update_time(0);
+    config::load(&mut app);
+    if report(&mut app, "config") {
+        return 1;
+    }
+    if let Some(file) = &args.file {
+        commands::execute_command(&mut app, &format!("e {}", file));
+        if report(&mut app, file) {
+            return 1;
+        }
+    }
+    if let Some(template) = &args.template {
+        commands::execute_command(&mut app, &format!("newfrom {}", template));
+        if report(&mut app, template) {
+            return 1;
+        }
+    }
+
+    for command in &args.commands {
+        let command = command.trim().trim_start_matches(':');
+        if matches!(command, "q" | "quit" | "q!" | "quit!" | "qa" | "qa!") {
+            break;
+        }
+        commands::execute_command(&mut app, command);
+        if report(&mut app, command) || !app.running {
+            return if app.running { 1 } else { 0 };
+        }
+    }
+    if let Some(preview) = &args.preview {
+        crate::calc::settle(&mut app);
+        print!("{}", preview_text(&app, preview));
+    }
+    0
+}
+
+/// The top-left of the sheet as lines of text, each cell as wide as its
+/// column
+fn preview_text(app: &App, preview: &Preview) -> String {
+    let (Some(max_col), Some(max_row)) = (app.sheet.max_col(), app.sheet.max_row()) else {
+        return String::new();
+    };
+    let max_row = max_row.min(preview.rows - 1);
+    let max_col = match preview.cols {
+        Some(cols) => max_col.min(cols - 1),
+        None => {
+            let width = std::env::var("COLUMNS").ok().and_then(|w| w.parse().ok()).unwrap_or(80);
+            let mut used = 0;
+            let fits = (0..=max_col)
+                .filter(|&col| !app.sheet.is_col_hidden(col))
+                .take_while(|&col| {
+                    used += app.sheet.get_col_width(col);
+                    used <= width
+                })
+                .last();
+            // At least one column, cut off by the pane
+            fits.unwrap_or_else(|| app.sheet.nearest_visible_col(0, 1))
+        }
+    };
+    render::render(&app.sheet, (0, 0, max_col, max_row))
+        .iter()
+        .map(|spans| {
+            let line: String = spans.iter().map(|span| span.text.as_str()).collect();
+            format!("{}\n", line.trim_end())
+        })
+        .collect()
+}
+
+/// Print the warnings and errors from the last step to stderr and forget
+/// them; true if there was an error
+fn report(app: &mut App, step: &str) -> bool {
+    let mut failed = false;
+    for message in app.messages.history() {
+        match message.severity {
+            Severity::Info => {}
+            Severity::Warning => eprintln!("vicalc: {}: warning: {}", step, message.text),
+            Severity::Error => {
+                eprintln!("vicalc: {}: {}", step, message.text);
+                failed = true;
+            }
+        }
+    }
+    app.messages.clear_history();
+    failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse_args(&strings(&["sales.csv", "-c", ":export out.csv", "-c", ":q"])).unwrap();
+        assert_eq!(args.file.as_deref(), Some("sales.csv"));
+        assert_eq!(args.commands, vec![":export out.csv", ":q"]);
+        assert!(args.batch);
+
+        let args = parse_args(&strings(&["sales.csv"])).unwrap();
+        assert!(!args.batch);
+        assert!(parse_args(&strings(&["a.csv", "b.csv"])).is_err());
+        assert!(parse_args(&strings(&["-c"])).is_err());
+
+        let args = parse_args(&strings(&["--template", "invoice.json"])).unwrap();
+        assert_eq!((args.file, args.template.as_deref()), (None, Some("invoice.json")));
+        assert!(parse_args(&strings(&["a.csv", "--template", "invoice.json"])).is_err());
+
+        let args = parse_args(&strings(&["--preview", "a.csv", "--rows", "5"])).unwrap();
+        assert_eq!(args.preview, Some(Preview { rows: 5, cols: None }));
+        assert!(args.batch);
+        assert!(parse_args(&strings(&["a.csv", "--cols", "3"])).is_err());
+        assert!(parse_args(&strings(&["--preview", "--rows", "0"])).is_err());
+    }
+
+    #[test]
+    fn test_preview_text() {
+        let mut app = App::new();
+        app.sheet.set_default_col_width(6);
+        for (row, (item, qty)) in [("Item", "Qty"), ("Apple", "3"), ("Pear", "=B2*4")].iter().enumerate() {
+            app.sheet.set_cell(0, row, item.to_string());
+            app.sheet.set_cell(1, row, qty.to_string());
+            app.sheet.set_cell(2, row, "x".to_string());
+        }
+        let preview = Preview { rows: 2, cols: Some(2) };
+        assert_eq!(preview_text(&app, &preview), "Item  Qty\nApple     3\n");
+        assert_eq!(preview_text(&App::new(), &preview), "");
+    }
+
+    #[test]
+    fn test_parse_script() {
+        let script = "\" weekly report\n:filter C >100\n\n# then save it\nexport big.csv\n";
+        assert_eq!(parse_script(script), vec![":filter C >100", "export big.csv"]);
+    }
+}
@@ -0,0 +1,317 @@
+//! Rhai scripts, for automation and for functions used in formulas.
+//!
+//! `:rhai <code>` runs a line of script and `:rhaifile <file>` a file of
+//! it, with a few functions to read and change the sheet:
+//!
+//! ```text
+//! :rhai for r in 1..=10 { set(cell(1, r), r * r) }
+//! :rhai set("C1", get("A1") + get("B1"))
+//! ```
+//!
+//! `get("B2")` gives a cell's value (a number, text, a boolean or `()` when
+//! empty), `set("B2", value)` puts a value or formula in it, `clear("B2")`
+//! empties it, `cell(2, 3)` names the cell in column 2, row 3 ("B3"), and
+//! `last_row()` and `last_col()` give the extent of the data. A script works
+//! on a copy of the sheet which replaces it only once the whole script has
+//! run, so an error leaves the sheet as it was and a run is one undo step.
+//!
+//! Every function a script defines (`fn myrate(x) { x * 1.08 }`) can be
+//! called in formulas under its name in capitals, `=MYRATE(A1)`; a range
+//! argument arrives as an array. Files listed under `scripts` in the config
+//! file are run at startup, which is the place for such functions. A script
+//! file can also be a command of its own under `[commands]` (see `plugin`),
+//! reading its arguments from `ARGS` and the Visual selection ("A1:C9", or
+//! `()` without one) from `SELECTION`. Scripts cannot touch files or run
+//! programs, and one that runs too long or grows too big is stopped.
+
+use std::sync::{Arc, Mutex, OnceLock};
+
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, FnAccess, Scope, AST};
+
+use crate::cell::CellValue;
+use crate::formula;
+use crate::sheet::Sheet;
+use crate::App;
+
+/// Operations a script run may take before it is stopped
+const MAX_SCRIPT_OPERATIONS: u64 = 50_000_000;
+
+/// Operations one call of a function from a formula may take
+const MAX_FUNCTION_OPERATIONS: u64 = 1_000_000;
+
+/// Bytes in a string, and items in an array or map, a script may build
+const MAX_STRING_SIZE: usize = 10_000_000;
+const MAX_ARRAY_SIZE: usize = 1_000_000;
+const MAX_MAP_SIZE: usize = 100_000;
+
+/// Depth of function calls, so runaway recursion stops before the stack does
+const MAX_CALL_LEVELS: usize = 64;
+
+/// An engine stopped after `operations`, and before a script runs out of
+/// memory or stack
+fn limited_engine(operations: u64) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(operations);
+    engine.set_max_string_size(MAX_STRING_SIZE);
+    engine.set_max_array_size(MAX_ARRAY_SIZE);
+    engine.set_max_map_size(MAX_MAP_SIZE);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+    engine
+}
+
+/// State shared with the functions a script calls
+#[derive(Default)]
+struct Run {
+    sheet: Sheet,
+    changed: usize,
+    printed: Vec<String>,
+}
+
+/// Run script code on the sheet: `name` says where it came from in errors
+pub fn run(app: &mut App, code: &str, name: &str) {
+    let result = execute(app, code, Scope::new());
+    report(app, result, name);
+}
+
+/// Run a script file as a command from the config file
+pub fn run_command(app: &mut App, path: &str, args: &str, selection: Option<&str>) {
+    let code = match std::fs::read_to_string(path) {
+        Ok(code) => code,
+        Err(e) => return app.error(format!("{}: {}", path, e)),
+    };
+    let mut scope = Scope::new();
+    scope.push_constant("ARGS", args.to_string());
+    scope.push_constant("SELECTION", selection.map_or(Dynamic::UNIT, |s| Dynamic::from(s.to_string())));
+    let result = execute(app, &code, scope);
+    report(app, result, path);
+}
+
+fn report(app: &mut App, result: Result<(usize, Vec<String>, Option<String>), String>, name: &str) {
+    match result {
+        Err(e) => app.error(format!("{}: {}", name, e)),
+        Ok((changed, functions, printed)) => {
+            let mut parts = Vec::new();
+            if changed > 0 {
+                parts.push(format!("{} cell(s) set", changed));
+            }
+            if !functions.is_empty() {
+                parts.push(format!("functions {}", functions.join(", ")));
+            }
+            match (printed, parts.is_empty()) {
+                (Some(text), _) => app.info(text),
+                (None, true) => app.info(format!("{}: done", name)),
+                (None, false) => app.info(parts.join("; ")),
+            }
+        }
+    }
+}
+
+/// Run a script file
+pub fn run_file(app: &mut App, path: &str) {
+    match std::fs::read_to_string(path) {
+        Ok(code) => run(app, &code, path),
+        Err(e) => app.error(format!("{}: {}", path, e)),
+    }
+}
+
+/// Run a script file from the config file, without a message unless it fails
+pub fn load(app: &mut App, path: &str) -> Result<(), String> {
+    let code = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    execute(app, &code, Scope::new()).map(|_| ()).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Run script code, putting its copy of the sheet in place if it changed
+/// any cells. Returns how many it set, the functions it defined and the
+/// last line it printed.
+fn execute(app: &mut App, code: &str, mut scope: Scope) -> Result<(usize, Vec<String>, Option<String>), String> {
+    let run = Arc::new(Mutex::new(Run { sheet: app.sheet.clone(), ..Run::default() }));
+    let engine = script_engine(&run);
+    let result = engine.compile(code).map_err(|e| e.to_string()).and_then(|ast| {
+        let ast = Arc::new(ast);
+        engine.run_ast_with_scope(&mut scope, &ast).map_err(|e| e.to_string())?;
+        Ok(register_functions(&ast))
+    });
+    // The sheet functions hold on to the run until the engine goes
+    drop(engine);
+
+    let functions = result?;
+    let mut run = Arc::try_unwrap(run)
+        .map_err(|_| "script still running".to_string())?
+        .into_inner()
+        .map_err(|e| e.to_string())?;
+    if run.changed > 0 {
+        app.save_undo();
+        std::mem::swap(&mut app.sheet, &mut run.sheet);
+    }
+    Ok((run.changed, functions, run.printed.pop()))
+}
+
+/// An engine with the sheet functions, working on the run's copy
+fn script_engine(run: &Arc<Mutex<Run>>) -> Engine {
+    let mut engine = limited_engine(MAX_SCRIPT_OPERATIONS);
+
+    let state = run.clone();
+    engine.on_print(move |text| {
+        if let Ok(mut run) = state.lock() {
+            run.printed.push(text.to_string());
+        }
+    });
+    let state = run.clone();
+    engine.register_fn("get", move |name: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+        let (col, row) = parse_cell(name)?;
+        let run = state.lock().map_err(|e| e.to_string())?;
+        Ok(to_dynamic(run.sheet.value(col, row), || run.sheet.evaluate(col, row)))
+    });
+    let state = run.clone();
+    engine.register_fn("set", move |name: &str, value: Dynamic| -> Result<(), Box<EvalAltResult>> {
+        set_cell(&state, name, to_input(value))
+    });
+    let state = run.clone();
+    engine.register_fn("clear", move |name: &str| -> Result<(), Box<EvalAltResult>> {
+        set_cell(&state, name, String::new())
+    });
+    engine.register_fn("cell", |col: i64, row: i64| -> Result<String, Box<EvalAltResult>> {
+        if !(1..=256).contains(&col) || !(1..=10000).contains(&row) {
+            return Err(format!("no cell at column {}, row {}", col, row).into());
+        }
+        Ok(formula::cell_name(col as usize - 1, row as usize - 1))
+    });
+    let state = run.clone();
+    engine.register_fn("last_row", move || -> i64 {
+        state.lock().ok().and_then(|run| run.sheet.max_row()).map_or(0, |r| r as i64 + 1)
+    });
+    let state = run.clone();
+    engine.register_fn("last_col", move || -> i64 {
+        state.lock().ok().and_then(|run| run.sheet.max_col()).map_or(0, |c| c as i64 + 1)
+    });
+    engine
+}
+
+fn parse_cell(name: &str) -> Result<(usize, usize), Box<EvalAltResult>> {
+    match formula::parse_cell_ref(name) {
+        Some((col, row, _, _)) if col < 256 && row < 10000 => Ok((col, row)),
+        _ => Err(format!("not a cell: {}", name).into()),
+    }
+}
+
+fn set_cell(state: &Mutex<Run>, name: &str, input: String) -> Result<(), Box<EvalAltResult>> {
+    let (col, row) = parse_cell(name)?;
+    let mut run = state.lock().map_err(|e| e.to_string())?;
+    if run.sheet.is_locked(col, row) {
+        return Err(format!("{} is locked", formula::cell_name(col, row)).into());
+    }
+    run.sheet.set_cell(col, row, input);
+    run.changed += 1;
+    Ok(())
+}
+
+/// Make the public functions a script defined usable in formulas; returns
+/// their names as formulas call them
+fn register_functions(ast: &Arc<AST>) -> Vec<String> {
+    let mut names = Vec::new();
+    for function in ast.iter_functions().filter(|f| f.access != FnAccess::Private) {
+        let (name, ast) = (function.name.to_string(), ast.clone());
+        vicalc::engine::register_function(
+            function.name,
+            Arc::new(move |args: &[Vec<CellValue>]| {
+                let args: Vec<Dynamic> = args
+                    .iter()
+                    .map(|values| match values.as_slice() {
+                        [value] => to_dynamic(value.clone(), String::new),
+                        _ => Dynamic::from(values.iter().map(|v| to_dynamic(v.clone(), String::new)).collect::<Array>()),
+                    })
+                    .collect();
+                let options = CallFnOptions::new().eval_ast(false);
+                formula_engine()
+                    .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &ast, &name, args)
+                    .map(to_value)
+                    .map_err(|_| "#VALUE!".to_string())
+            }),
+        );
+        names.push(function.name.to_uppercase());
+    }
+    names
+}
+
+/// The engine functions from formulas run in: none of the sheet functions,
+/// so a formula cannot change cells
+fn formula_engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| limited_engine(MAX_FUNCTION_OPERATIONS))
+}
+
+/// A cell's value for a script; `shown` gives the text of an error
+fn to_dynamic(value: CellValue, shown: impl Fn() -> String) -> Dynamic {
+    match value {
+        CellValue::Empty => Dynamic::UNIT,
+        CellValue::Number(n) => Dynamic::from_float(n),
+        CellValue::Boolean(b) => Dynamic::from_bool(b),
+        CellValue::Text(s) => Dynamic::from(s),
+        _ => Dynamic::from(shown()),
+    }
+}
+
+/// A script's value as a formula result
+fn to_value(value: Dynamic) -> CellValue {
+    if value.is_unit() {
+        CellValue::Empty
+    } else if let Some(n) = value.clone().try_cast::<f64>() {
+        CellValue::Number(n)
+    } else if let Some(n) = value.clone().try_cast::<i64>() {
+        CellValue::Number(n as f64)
+    } else if let Some(b) = value.clone().try_cast::<bool>() {
+        CellValue::Boolean(b)
+    } else {
+        CellValue::Text(value.to_string())
+    }
+}
+
+/// A script's value as input typed into a cell; `()` empties it
+fn to_input(value: Dynamic) -> String {
+    match to_value(value) {
+        CellValue::Empty => String::new(),
+        CellValue::Number(n) => n.to_string(),
+        CellValue::Boolean(b) => if b { "TRUE" } else { "FALSE" }.to_string(),
+        CellValue::Text(s) => s,
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let mut app = App::new();
+        run(&mut app, "for r in 1..=3 { set(cell(1, r), r * 2) } fn script_double(x) { x * 2 }", "test");
+        assert_eq!(app.sheet.evaluate(0, 2), "6");
+        app.sheet.set_cell(1, 0, "=SCRIPT_DOUBLE(A3)+1".to_string());
+        assert_eq!(app.sheet.evaluate(1, 0), "13");
+
+        // A failing script changes nothing
+        run(&mut app, "set(\"A1\", 9); set(\"nowhere\", 1)", "test");
+        assert_eq!(app.sheet.evaluate(0, 0), "2");
+        assert_eq!(app.history.undo_entries().len(), 1);
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut app = App::new();
+        let runaway = [
+            r#"let s = "x"; loop { s += s }"#,
+            "let a = [1]; loop { a += a }",
+            "fn down(n) { down(n + 1) } down(0)",
+        ];
+        for code in runaway {
+            run(&mut app, code, "test");
+            let message = app.messages.current().unwrap();
+            assert_eq!(message.severity, crate::message::Severity::Error, "{}: {}", code, message.text);
+        }
+
+        // The same holds for a function called from a formula
+        run(&mut app, r#"fn script_grow(x) { let s = "x"; loop { s += s } }"#, "test");
+        app.sheet.set_cell(0, 0, "=SCRIPT_GROW(1)".to_string());
+        assert!(app.sheet.evaluate(0, 0).starts_with('#'));
+    }
+}
@@ -0,0 +1,156 @@
+//! `:select`: find the cells of a kind across the sheet.
+//!
+//! `:select type=formula` or `:select format=currency bold` lists every
+//! cell matching all the conditions given in the quickfix panel, before
+//! reformatting them in bulk or to audit a sheet. From Visual mode only the
+//! selection is searched. The conditions are:
+//!
+//! - `type=formula|number|text|boolean|error`: what was typed; `error` also
+//!   takes in formulas whose result is an error
+//! - `format=general|number|grouped|currency|percent|scientific|date|text`
+//! - `align=left|right|center`, and the bare words `bold`, `underline`,
+//!   `color` and `locked`
+
+use crate::cell::{Align, Cell, CellValue, DisplayFormat};
+use crate::quickfix::Entry;
+use crate::sheet::Sheet;
+use crate::validation::area_name;
+use crate::App;
+
+const USAGE: &str =
+    "Usage: :select type=formula|number|text|boolean|error format=<format> align=<side> bold underline color locked";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Type {
+    Formula,
+    Number,
+    Text,
+    Boolean,
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Condition {
+    Type(Type),
+    // Display formats of the same kind, whatever their decimals
+    Format(DisplayFormat),
+    Align(Align),
+    Bold,
+    Underline,
+    Color,
+    Locked,
+}
+
+fn parse(word: &str) -> Result<Condition, String> {
+    let word = word.to_lowercase();
+    let condition = match word.split_once('=') {
+        Some(("type", value)) => Condition::Type(match value {
+            "formula" | "formulas" => Type::Formula,
+            "number" | "numbers" => Type::Number,
+            "text" => Type::Text,
+            "boolean" | "bool" => Type::Boolean,
+            "error" | "errors" => Type::Error,
+            _ => return Err(format!("Unknown type: {} (formula, number, text, boolean or error)", value)),
+        }),
+        Some(("format", value)) => {
+            Condition::Format(DisplayFormat::parse(value, 0).ok_or(format!("Unknown format: {}", value))?)
+        }
+        Some(("align", value)) => Condition::Align(Align::parse(value).ok_or(format!("Unknown alignment: {}", value))?),
+        None if word == "bold" => Condition::Bold,
+        None if word == "underline" => Condition::Underline,
+        None if word == "color" => Condition::Color,
+        None if word == "locked" => Condition::Locked,
+        _ => return Err(USAGE.to_string()),
+    };
+    Ok(condition)
+}
+
+impl Condition {
+    fn matches(&self, sheet: &Sheet, col: usize, row: usize, cell: &Cell) -> bool {
+        match self {
+            Condition::Type(Type::Formula) => matches!(cell.value, CellValue::Formula(_)),
+            Condition::Type(Type::Number) => matches!(cell.value, CellValue::Number(_)),
+            Condition::Type(Type::Text) => matches!(cell.value, CellValue::Text(_)),
+            Condition::Type(Type::Boolean) => matches!(cell.value, CellValue::Boolean(_)),
+            Condition::Type(Type::Error) => matches!(sheet.value(col, row), CellValue::Error(_)),
+            Condition::Format(format) => std::mem::discriminant(&cell.format) == std::mem::discriminant(format),
+            Condition::Align(align) => cell.style.align == Some(*align),
+            Condition::Bold => cell.style.bold,
+            Condition::Underline => cell.style.underline,
+            Condition::Color => cell.style.color.is_some(),
+            Condition::Locked => sheet.is_locked(col, row),
+        }
+    }
+}
+
+/// The cells in the area meeting every condition, in reading order
+fn find(sheet: &Sheet, conditions: &[Condition], (c1, r1, c2, r2): (usize, usize, usize, usize)) -> Vec<(usize, usize)> {
+    let mut found: Vec<(usize, usize)> = sheet
+        .cells()
+        .iter()
+        .filter(|&(&(col, row), cell)| {
+            (c1..=c2).contains(&col)
+                && (r1..=r2).contains(&row)
+                && !cell.raw_input.is_empty()
+                && conditions.iter().all(|c| c.matches(sheet, col, row, cell))
+        })
+        .map(|(&position, _)| position)
+        .collect();
+    found.sort_by_key(|&(col, row)| (row, col));
+    found
+}
+
+pub fn command(app: &mut App, args: &str, range: Option<(usize, usize, usize, usize)>) {
+    let conditions: Result<Vec<Condition>, String> = args.split_whitespace().map(parse).collect();
+    let conditions = match conditions {
+        Ok(conditions) if !conditions.is_empty() => conditions,
+        Ok(_) => {
+            app.warn(USAGE);
+            return;
+        }
+        Err(e) => {
+            app.error(e);
+            return;
+        }
+    };
+    let area = range.unwrap_or((0, 0, 255, 9999));
+    let found = find(&app.sheet, &conditions, area);
+    let within = range.map_or(String::new(), |area| format!(" in {}", area_name(area)));
+    if found.is_empty() {
+        app.quickfix = None;
+        app.warn(format!("No cells with {}{}", args.trim(), within));
+        return;
+    }
+    let entries = found
+        .iter()
+        .map(|&(col, row)| Entry { col, row, text: app.sheet.get_cell(col, row).raw_input })
+        .collect();
+    let plural = if found.len() == 1 { "" } else { "s" };
+    let title = format!("Select {}{}: {} cell{}", args.trim(), within, found.len(), plural);
+    crate::quickfix::set(app, title, entries);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let mut sheet = Sheet::new();
+        sheet.set_cell(0, 0, "Item".to_string());
+        sheet.set_cell(1, 0, "12".to_string());
+        sheet.set_cell(1, 1, "=B1*2".to_string());
+        sheet.set_cell(2, 1, "=1/0".to_string());
+        sheet.style_cells((0, 0, 0, 0), |style| style.bold = true);
+        let conditions = |words: &str| words.split_whitespace().map(parse).collect::<Result<Vec<_>, _>>().unwrap();
+        let all = (0, 0, 255, 9999);
+
+        assert_eq!(find(&sheet, &conditions("type=formula"), all), vec![(1, 1), (2, 1)]);
+        assert_eq!(find(&sheet, &conditions("type=formula"), (0, 0, 1, 9)), vec![(1, 1)]);
+        assert_eq!(find(&sheet, &conditions("type=error"), all), vec![(2, 1)]);
+        assert_eq!(find(&sheet, &conditions("TYPE=Text bold"), all), vec![(0, 0)]);
+        assert_eq!(find(&sheet, &conditions("format=general type=number"), all), vec![(1, 0)]);
+        assert!(find(&sheet, &conditions("format=currency"), all).is_empty());
+        assert!(parse("type=date").is_err() && parse("italic").is_err());
+    }
+}
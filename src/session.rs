@@ -0,0 +1,144 @@
+//! Where each file was left, so it opens there again, as vim's viminfo
+//! does.
+//!
+//! On quitting, and on `:e` to another file, the cursor, the top-left cell
+//! shown and the editing axis are written for the file to
+//! `~/.vicalc_session` (or `$VICALC_SESSION`), with the column widths when
+//! the file cannot keep its own (a CSV file without its `.vicalc` settings,
+//! a Parquet file). Opening the file puts them back, over what a saved file
+//! says, as they are more recent. The last `MAX_FILES` files are kept.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::formula::{cell_name, col_to_name, parse_cell_ref};
+use crate::App;
+
+/// Most files remembered, the least recently left dropped first
+pub const MAX_FILES: usize = 100;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    file: String,
+    cursor: String,
+    // Top-left cell shown
+    view: String,
+    // "col" when editing down columns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    axis: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    col_widths: BTreeMap<String, usize>,
+}
+
+/// Where the state is kept: `$VICALC_SESSION`, else `~/.vicalc_session`
+pub fn path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("VICALC_SESSION") {
+        return Some(PathBuf::from(path));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".vicalc_session"))
+}
+
+/// The name a file is remembered by, the same however it was opened
+fn key(filename: &str) -> String {
+    std::fs::canonicalize(filename).map_or(filename.to_string(), |path| path.display().to_string())
+}
+
+impl Entry {
+    fn of(app: &App, file: String) -> Entry {
+        Entry {
+            file,
+            cursor: cell_name(app.cursor_col, app.cursor_row),
+            view: cell_name(app.view_col, app.view_row),
+            axis: (app.axis == crate::EditAxis::Column).then(|| "col".to_string()),
+            col_widths: app.sheet.col_widths().map(|(col, width)| (col_to_name(col), width)).collect(),
+        }
+    }
+
+    fn apply(&self, app: &mut App) {
+        // Widths kept in the file itself are left as they are
+        if app.sheet.col_widths().next().is_none() {
+            for (name, &width) in &self.col_widths {
+                if let Some((col, _, _, _)) = parse_cell_ref(&format!("{}1", name)) {
+                    app.sheet.set_col_width(col, width);
+                }
+            }
+        }
+        app.axis = if self.axis.as_deref() == Some("col") { crate::EditAxis::Column } else { crate::EditAxis::Row };
+        if let Some((col, row, _, _)) = parse_cell_ref(&self.view) {
+            app.view_col = col;
+            app.view_row = row;
+        }
+        if let Some((col, row, _, _)) = parse_cell_ref(&self.cursor) {
+            let col = app.sheet.nearest_visible_col(col, 1);
+            let row = app.sheet.nearest_visible_row(row, 1);
+            app.move_cursor_to(col, row);
+        }
+    }
+}
+
+/// The remembered files, most recently left first; a damaged state file
+/// counts as empty
+fn read(path: &Path) -> Vec<Entry> {
+    std::fs::read_to_string(path).ok().and_then(|text| serde_json::from_str(&text).ok()).unwrap_or_default()
+}
+
+/// Put `entry` first, in place of an earlier one for the same file
+fn add(entries: &mut Vec<Entry>, entry: Entry) {
+    entries.retain(|e| e.file != entry.file);
+    entries.insert(0, entry);
+    entries.truncate(MAX_FILES);
+}
+
+/// Note where the current file was left. The state file is read again
+/// first, so other vicalc sessions' files are kept.
+pub fn remember(app: &App) -> Result<(), String> {
+    let (Some(path), Some(file)) = (&app.session, &app.current_file) else {
+        return Ok(());
+    };
+    let mut entries = read(path);
+    add(&mut entries, Entry::of(app, key(file)));
+    let text = serde_json::to_string_pretty(&entries).map_err(|e| e.to_string())?;
+    std::fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Go back to where the file just opened was left
+pub fn restore(app: &mut App) {
+    let (Some(path), Some(file)) = (&app.session, &app.current_file) else {
+        return;
+    };
+    let file = key(file);
+    if let Some(entry) = read(path).into_iter().find(|e| e.file == file) {
+        entry.apply(app);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_round_trip() {
+        let mut app = App::new();
+        app.sheet.set_cell(0, 0, "x".to_string());
+        app.sheet.set_col_width(1, 20);
+        app.axis = crate::EditAxis::Column;
+        app.view_row = 30;
+        app.cursor_row = 40;
+        app.cursor_col = 2;
+        let entry = Entry::of(&app, "/tmp/a.csv".to_string());
+        assert_eq!(entry.cursor, "C41");
+
+        let mut other = App::new();
+        entry.apply(&mut other);
+        assert_eq!((other.cursor_col, other.cursor_row), (2, 40));
+        assert_eq!(other.sheet.get_col_width(1), 20);
+        assert_eq!(other.axis, crate::EditAxis::Column);
+
+        let mut entries = vec![Entry::of(&other, "/tmp/b.csv".to_string())];
+        add(&mut entries, entry.clone());
+        add(&mut entries, entry);
+        assert_eq!(entries.iter().map(|e| e.file.as_str()).collect::<Vec<_>>(), vec!["/tmp/a.csv", "/tmp/b.csv"]);
+    }
+}
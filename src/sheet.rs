@@ -1,18 +1,170 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
 use serde::{Deserialize, Serialize};
 
-use crate::cell::{self, Cell, CellValue};
-use crate::engine::Engine;
+use crate::cell::{self, Cell, CellStyle, CellValue, DisplayFormat};
+use crate::coltype::ColType;
+use crate::engine::{self, Engine};
+use crate::formula::{self, StructureChange};
+use crate::validation::Validation;
 
 pub const DEFAULT_COL_WIDTH: usize = 10;
 pub const MIN_COL_WIDTH: usize = 3;
 pub const MAX_COL_WIDTH: usize = 50;
 
+/// Auto-filter: data rows whose value in `col` does not match `criteria` are hidden
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RowFilter {
+    pub col: usize,
+    pub criteria: String,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Sheet {
     pub name: String,
     cells: HashMap<(usize, usize), Cell>,
     col_widths: HashMap<usize, usize>,
+    // Width of columns not in col_widths (:set colwidth)
+    #[serde(default = "default_col_width")]
+    default_width: usize,
+    #[serde(default)]
+    validations: Vec<Validation>,
+    #[serde(default)]
+    filter: Option<RowFilter>,
+    // Rows hidden by the filter
+    #[serde(default)]
+    filtered_rows: BTreeSet<usize>,
+    // Rows and columns hidden with :hide
+    #[serde(default)]
+    hidden_rows: BTreeSet<usize>,
+    #[serde(default)]
+    hidden_cols: BTreeSet<usize>,
+    // Declared column types (:coltype)
+    #[serde(default)]
+    col_types: BTreeMap<usize, ColType>,
+    // Formulas filled into every data row of a column (:colformula), with
+    // @ for the row
+    #[serde(default)]
+    col_formulas: BTreeMap<usize, String>,
+    // Areas protected with :lock, as (min_col, min_row, max_col, max_row)
+    #[serde(default)]
+    locks: Vec<(usize, usize, usize, usize)>,
+    // Areas shown as one cell with :merge, as (min_col, min_row, max_col,
+    // max_row); the top-left cell holds the content
+    #[serde(default)]
+    merges: Vec<(usize, usize, usize, usize)>,
+    // Labels on cells (:tag), by position, kept whatever the cell holds
+    #[serde(default)]
+    tags: BTreeMap<(usize, usize), Vec<String>>,
+    // Values of array formulas and the cells they spill into, worked out on
+    // first need and forgotten whenever a cell changes
+    #[serde(skip)]
+    spills: OnceLock<HashMap<(usize, usize), CellValue>>,
+    // Formula and spill values as of the last recalculation, when they are
+    // kept rather than worked out whenever shown (:set calc); else None
+    #[serde(skip)]
+    calculated: Option<Calculated>,
+    // Cells changed since the last recalculation
+    #[serde(skip)]
+    stale: bool,
+    // Stamp of the cells' contents, new with every change
+    #[serde(skip, default = "next_version")]
+    version: u64,
+    // How numbers are shown (:set numfmt, :set decimal)
+    #[serde(skip)]
+    number_style: cell::NumberStyle,
+}
+
+/// Results of every formula, and the values array formulas spill, by cell
+pub type Calculated = HashMap<(usize, usize), Result<CellValue, String>>;
+
+/// Work out every formula in `cells`, as `recalculate` does; the cells can
+/// be a copy, so this can run on another thread
+pub fn calculate(cells: &HashMap<(usize, usize), Cell>) -> Calculated {
+    let spills = Engine::new(cells).spills();
+    let mut engine = Engine::new(cells).with_spills(&spills);
+    let mut calculated: Calculated = spills.iter().map(|(&pos, value)| (pos, Ok(value.clone()))).collect();
+    for (&pos, cell) in cells {
+        match &cell.value {
+            CellValue::Formula(f) if !engine::is_array_formula(f) => {
+                calculated.insert(pos, engine.evaluate_formula(f));
+            }
+            _ => {}
+        }
+    }
+    calculated
+}
+
+fn next_version() -> u64 {
+    static VERSION: AtomicU64 = AtomicU64::new(1);
+    VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// What a search looks at in each cell (`:set searchtarget`)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SearchTarget {
+    // The displayed value
+    #[default]
+    Value,
+    // What was typed, formulas as written
+    Formula,
+}
+
+/// What a search looks for, ignoring case: text anywhere in a cell, or a
+/// regular expression
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    // Kept uppercased
+    Text(String),
+    Regex(regex::Regex),
+}
+
+impl Pattern {
+    pub fn text(term: &str) -> Self {
+        Pattern::Text(term.to_uppercase())
+    }
+
+    pub fn regex(expr: &str) -> Result<Self, String> {
+        regex::RegexBuilder::new(expr)
+            .case_insensitive(true)
+            .build()
+            .map(Pattern::Regex)
+            // The last line of a syntax error says what is wrong
+            .map_err(|e| e.to_string().lines().last().unwrap_or_default().trim_start_matches("error: ").to_string())
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        match self {
+            Pattern::Text(term) => text.to_uppercase().contains(term.as_str()),
+            Pattern::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+/// Motions between runs of filled cells along a row or column
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockMotion {
+    // w: the start of the next run
+    NextStart,
+    // e: the end of this run, or of the next one from its end
+    NextEnd,
+    // b: the start of this run, or of the previous one from its start
+    PrevStart,
+    // Ctrl+arrow, as in Excel: the far end of this run, else the next
+    // filled cell, else the edge of the sheet
+    EdgeNext,
+    EdgePrev,
+}
+
+fn default_col_width() -> usize {
+    DEFAULT_COL_WIDTH
+}
+
+impl Default for Sheet {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Sheet {
@@ -21,16 +173,56 @@ impl Sheet {
             name: "Sheet1".to_string(),
             cells: HashMap::new(),
             col_widths: HashMap::new(),
+            default_width: DEFAULT_COL_WIDTH,
+            validations: Vec::new(),
+            filter: None,
+            filtered_rows: BTreeSet::new(),
+            hidden_rows: BTreeSet::new(),
+            hidden_cols: BTreeSet::new(),
+            col_types: BTreeMap::new(),
+            col_formulas: BTreeMap::new(),
+            locks: Vec::new(),
+            merges: Vec::new(),
+            tags: BTreeMap::new(),
+            spills: OnceLock::new(),
+            calculated: None,
+            stale: false,
+            version: next_version(),
+            number_style: cell::NumberStyle::default(),
         }
     }
 
+    pub fn number_style(&self) -> cell::NumberStyle {
+        self.number_style
+    }
+
+    pub fn set_number_style(&mut self, style: cell::NumberStyle) {
+        self.number_style = style;
+    }
+
     pub fn get_col_width(&self, col: usize) -> usize {
-        *self.col_widths.get(&col).unwrap_or(&DEFAULT_COL_WIDTH)
+        *self.col_widths.get(&col).unwrap_or(&self.default_width)
+    }
+
+    /// Columns whose width has been set, with their widths
+    pub fn col_widths(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.col_widths.iter().map(|(&col, &width)| (col, width))
+    }
+
+    pub fn default_col_width(&self) -> usize {
+        self.default_width
+    }
+
+    /// Change the width of the columns whose width has not been set
+    pub fn set_default_col_width(&mut self, width: usize) {
+        let width = width.clamp(MIN_COL_WIDTH, MAX_COL_WIDTH);
+        self.default_width = width;
+        self.col_widths.retain(|_, w| *w != width);
     }
 
     pub fn set_col_width(&mut self, col: usize, width: usize) {
-        let width = width.max(MIN_COL_WIDTH).min(MAX_COL_WIDTH);
-        if width == DEFAULT_COL_WIDTH {
+        let width = width.clamp(MIN_COL_WIDTH, MAX_COL_WIDTH);
+        if width == self.default_width {
             self.col_widths.remove(&col);
         } else {
             self.col_widths.insert(col, width);
@@ -52,15 +244,86 @@ impl Sheet {
     }
 
     pub fn set_cell(&mut self, col: usize, row: usize, input: String) {
+        self.cells_changed();
         if input.trim().is_empty() {
             self.cells.remove(&(col, row));
         } else {
-            let value = cell::parse_input(&input);
-            self.cells.insert((col, row), Cell::new(input, value));
+            let (value, typed_format) = self.parse_typed(col, &input);
+            // Editing a cell keeps its format, unless typed in another
+            // (`$5`, `45%`), and its style
+            let (format, style) = self
+                .cells
+                .get(&(col, row))
+                .map(|cell| (cell.format.clone(), cell.style))
+                .unwrap_or_default();
+            let format = typed_format.unwrap_or(format);
+            self.cells.insert((col, row), Cell { format, style, ..Cell::new(input, value) });
+            self.fill_col_formulas_at(col, row);
+        }
+    }
+
+    /// Put input typed in the sheet's number style into a cell. Numbers are
+    /// kept with a decimal point; input the style does not read as a number
+    /// stays text, even if it would be one with a decimal point.
+    pub fn set_typed(&mut self, col: usize, row: usize, input: &str) {
+        if self.number_style.is_foreign_number(input) {
+            self.set_value(col, row, CellValue::Text(input.trim().to_string()));
+        } else {
+            self.set_cell(col, row, self.number_style.canonical_input(input));
+        }
+    }
+
+    /// Put a value in a cell as it is rather than read from typed input, so
+    /// text such as "00123" or "TRUE" stays text. The cell keeps its format
+    /// and style.
+    pub fn set_value(&mut self, col: usize, row: usize, value: CellValue) {
+        let input = match &value {
+            CellValue::Empty => String::new(),
+            CellValue::Number(n) => n.to_string(),
+            CellValue::Text(s) => s.clone(),
+            CellValue::Boolean(b) => if *b { "TRUE" } else { "FALSE" }.to_string(),
+            CellValue::Error(e) => e.to_string().to_string(),
+            CellValue::Formula(f) => f.clone(),
+        };
+        if input.trim().is_empty() || matches!(value, CellValue::Formula(_)) {
+            return self.set_cell(col, row, input);
+        }
+        self.cells_changed();
+        let (format, style) = self
+            .cells
+            .get(&(col, row))
+            .map(|cell| (cell.format.clone(), cell.style))
+            .unwrap_or_default();
+        self.cells.insert((col, row), Cell { format, style, ..Cell::new(input, value) });
+    }
+
+    /// Value of input typed into a column: as usual, except that text
+    /// columns keep numbers and booleans as typed
+    fn parse_input(&self, col: usize, input: &str) -> CellValue {
+        self.parse_typed(col, input).0
+    }
+
+    /// Value of input typed into a column in the sheet's number style, as
+    /// `set_typed` would store it
+    fn typed_value(&self, col: usize, input: &str) -> CellValue {
+        if self.number_style.is_foreign_number(input) {
+            return CellValue::Text(input.trim().to_string());
+        }
+        self.parse_input(col, &self.number_style.canonical_input(input))
+    }
+
+    /// Value of input typed into a column, with the format it was written
+    /// in (`$1,234.50`, `45%`)
+    fn parse_typed(&self, col: usize, input: &str) -> (CellValue, Option<DisplayFormat>) {
+        let (value, format) = cell::parse_typed(input, cell::NumberStyle::default());
+        match (self.col_type(col), &value) {
+            (Some(ColType::Text), CellValue::Number(_) | CellValue::Boolean(_)) => (CellValue::Text(input.trim().to_string()), None),
+            _ => (value, format),
         }
     }
 
     pub fn clear_cell(&mut self, col: usize, row: usize) {
+        self.cells_changed();
         self.cells.remove(&(col, row));
     }
 
@@ -68,29 +331,751 @@ impl Sheet {
         &self.cells
     }
 
+    /// Estimated heap usage in bytes. Hash tables are counted by capacity plus
+    /// a control byte per slot; tree sets by their elements only.
+    pub fn heap_size(&self) -> usize {
+        use std::mem::size_of;
+        let cells = self.cells.capacity() * (size_of::<((usize, usize), Cell)>() + 1)
+            + self.cells.values().map(Cell::heap_size).sum::<usize>();
+        let widths = self.col_widths.capacity() * (size_of::<(usize, usize)>() + 1);
+        let validations = self.validations.capacity() * size_of::<Validation>();
+        let hidden = (self.filtered_rows.len() + self.hidden_rows.len() + self.hidden_cols.len()) * size_of::<usize>();
+        self.name.capacity() + cells + widths + validations + hidden
+    }
+
+    /// Values of array formulas and the cells they spill into
+    fn spills(&self) -> &HashMap<(usize, usize), CellValue> {
+        self.spills.get_or_init(|| Engine::new(&self.cells).spills())
+    }
+
+    /// Value spilled into (col, row) by an array formula, if any
+    fn spill_value(&self, col: usize, row: usize) -> CellValue {
+        let value = match &self.calculated {
+            Some(calculated) => calculated.get(&(col, row)).cloned().and_then(Result::ok),
+            None => self.spills().get(&(col, row)).cloned(),
+        };
+        value.unwrap_or(CellValue::Empty)
+    }
+
+    /// Result of the formula `f` in (col, row): as last recalculated when
+    /// calculation is deferred, else worked out now. A formula entered
+    /// since the last recalculation is worked out on its own.
+    fn formula_result(&self, col: usize, row: usize, f: &str) -> Result<CellValue, String> {
+        if let Some(result) = self.calculated.as_ref().and_then(|calculated| calculated.get(&(col, row))) {
+            return result.clone();
+        }
+        if engine::is_array_formula(f) {
+            return Ok(self.spill_value(col, row));
+        }
+        Engine::new(&self.cells).with_spills(self.spills()).evaluate_formula(f)
+    }
+
     pub fn evaluate(&self, col: usize, row: usize) -> String {
         let cell = self.get_cell(col, row);
-        match &cell.value {
+        let value = match &cell.value {
+            CellValue::Empty => self.spill_value(col, row),
+            CellValue::Formula(f) => match self.formula_result(col, row, f) {
+                Ok(value) => value,
+                Err(e) => return e,
+            },
+            value => value.clone(),
+        };
+        match value {
             CellValue::Empty => String::new(),
-            CellValue::Number(n) => cell.format_number(*n),
-            CellValue::Text(s) => s.clone(),
-            CellValue::Boolean(b) => if *b { "TRUE" } else { "FALSE" }.to_string(),
+            CellValue::Number(n) => cell.format_number_in(n, self.number_style),
+            CellValue::Text(s) => s,
+            CellValue::Boolean(b) => if b { "TRUE" } else { "FALSE" }.to_string(),
             CellValue::Error(e) => e.to_string().to_string(),
-            CellValue::Formula(f) => {
-                let mut engine = Engine::new(&self.cells);
-                match engine.evaluate_formula(f) {
-                    Ok(result) => match result {
-                        CellValue::Number(n) => cell.format_number(n),
-                        CellValue::Text(s) => s,
-                        CellValue::Boolean(b) => if b { "TRUE" } else { "FALSE" }.to_string(),
-                        CellValue::Error(e) => e.to_string().to_string(),
-                        CellValue::Empty => String::new(),
-                        CellValue::Formula(_) => "ERR".to_string(),
-                    },
-                    Err(e) => e,
+            CellValue::Formula(_) => "ERR".to_string(),
+        }
+    }
+
+    /// Evaluated value of a cell (formulas are computed)
+    pub fn value(&self, col: usize, row: usize) -> CellValue {
+        match self.cells.get(&(col, row)) {
+            Some(cell) => match &cell.value {
+                CellValue::Formula(f) => {
+                    self.formula_result(col, row, f).unwrap_or(CellValue::Error(cell::CellError::Value))
+                }
+                value => value.clone(),
+            },
+            None => self.spill_value(col, row),
+        }
+    }
+
+    // Deferred calculation
+
+    /// Keep the values of formulas from the last recalculation and show
+    /// those, rather than working formulas out whenever they are shown.
+    /// Used for manual calculation and for recalculating big sheets in the
+    /// background.
+    pub fn set_deferred_calc(&mut self, deferred: bool) {
+        if deferred == self.calculated.is_some() {
+            return;
+        }
+        if deferred {
+            self.calculated = Some(HashMap::new());
+            self.recalculate();
+        } else {
+            self.calculated = None;
+            self.stale = false;
+            self.spills.take();
+        }
+    }
+
+    pub fn deferred_calc(&self) -> bool {
+        self.calculated.is_some()
+    }
+
+    /// Whether cells changed since the last recalculation, so formulas may
+    /// show out-of-date values
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Stamp of the cells' contents: a sheet with the same version has the
+    /// same cells
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Work out every formula again, when calculation is deferred
+    pub fn recalculate(&mut self) {
+        self.spills.take();
+        self.stale = false;
+        if self.calculated.is_some() {
+            self.calculated = Some(calculate(&self.cells));
+        }
+    }
+
+    /// Take values worked out elsewhere by `calculate` from the cells of
+    /// `version`. Returns false, keeping the values shown, if the cells
+    /// have changed since.
+    pub fn finish_recalculation(&mut self, version: u64, calculated: Calculated) -> bool {
+        if version != self.version || self.calculated.is_none() {
+            return false;
+        }
+        self.calculated = Some(calculated);
+        self.stale = false;
+        true
+    }
+
+    /// Cells changed: array formulas spill again, or with deferred
+    /// calculation the values shown are out of date
+    fn cells_changed(&mut self) {
+        self.version = next_version();
+        if self.calculated.is_some() {
+            self.stale = true;
+        } else {
+            self.spills.take();
+        }
+    }
+
+    /// Cells moved, each from a position to the one `to` gives, if any: their
+    /// tags and the values from the last recalculation move with them
+    fn cells_moved(&mut self, to: impl Fn(usize, usize) -> Option<(usize, usize)>) {
+        self.version = next_version();
+        self.spills.take();
+        let tags = std::mem::take(&mut self.tags).into_iter();
+        self.tags = tags.filter_map(|((col, row), tags)| Some((to(col, row)?, tags))).collect();
+        if let Some(calculated) = self.calculated.take() {
+            let moved = calculated.into_iter().filter_map(|((col, row), value)| Some((to(col, row)?, value)));
+            self.calculated = Some(moved.collect());
+            self.stale = true;
+        }
+    }
+
+    // Tags (:tag)
+
+    pub fn tags(&self, col: usize, row: usize) -> &[String] {
+        self.tags.get(&(col, row)).map_or(&[], Vec::as_slice)
+    }
+
+    /// Tag a cell, empty or not. Returns false if it already had the tag.
+    pub fn add_tag(&mut self, col: usize, row: usize, tag: &str) -> bool {
+        let tags = self.tags.entry((col, row)).or_default();
+        if tags.iter().any(|t| t == tag) {
+            return false;
+        }
+        tags.push(tag.to_string());
+        true
+    }
+
+    /// Take a tag, or with None every tag, off a cell. Returns how many
+    /// were taken off.
+    pub fn remove_tags(&mut self, col: usize, row: usize, tag: Option<&str>) -> usize {
+        let Some(tags) = self.tags.get_mut(&(col, row)) else {
+            return 0;
+        };
+        let before = tags.len();
+        tags.retain(|t| tag.is_some_and(|tag| t != tag));
+        let removed = before - tags.len();
+        if tags.is_empty() {
+            self.tags.remove(&(col, row));
+        }
+        removed
+    }
+
+    /// Every tagged cell with its tags, in reading order
+    pub fn tagged_cells(&self) -> Vec<((usize, usize), &[String])> {
+        let mut tagged: Vec<_> = self.tags.iter().map(|(&pos, tags)| (pos, tags.as_slice())).collect();
+        tagged.sort_by_key(|&((col, row), _)| (row, col));
+        tagged
+    }
+
+    // Cell protection
+
+    pub fn locks(&self) -> &[(usize, usize, usize, usize)] {
+        &self.locks
+    }
+
+    pub fn lock(&mut self, area: (usize, usize, usize, usize)) {
+        // Keep the areas apart so unlocking can count cells
+        self.unlock(area);
+        self.locks.push(area);
+    }
+
+    /// Unlock the cells of an area, keeping the rest of any lock it cuts
+    /// through. Returns how many cells were unlocked.
+    pub fn unlock(&mut self, (c1, r1, c2, r2): (usize, usize, usize, usize)) -> usize {
+        let mut unlocked = 0;
+        let mut locks = Vec::new();
+        for lock in std::mem::take(&mut self.locks) {
+            let (lc1, lr1, lc2, lr2) = lock;
+            let (ic1, ir1, ic2, ir2) = (lc1.max(c1), lr1.max(r1), lc2.min(c2), lr2.min(r2));
+            if ic1 > ic2 || ir1 > ir2 {
+                locks.push(lock);
+                continue;
+            }
+            unlocked += (ic2 - ic1 + 1) * (ir2 - ir1 + 1);
+            // What is left: full-width bands above and below, and the parts
+            // beside the unlocked area
+            if ir1 > lr1 {
+                locks.push((lc1, lr1, lc2, ir1 - 1));
+            }
+            if ir2 < lr2 {
+                locks.push((lc1, ir2 + 1, lc2, lr2));
+            }
+            if ic1 > lc1 {
+                locks.push((lc1, ir1, ic1 - 1, ir2));
+            }
+            if ic2 < lc2 {
+                locks.push((ic2 + 1, ir1, lc2, ir2));
+            }
+        }
+        self.locks = locks;
+        unlocked
+    }
+
+    pub fn is_locked(&self, col: usize, row: usize) -> bool {
+        self.locks.iter().any(|&(c1, r1, c2, r2)| (c1..=c2).contains(&col) && (r1..=r2).contains(&row))
+    }
+
+    /// The first locked cell of an area in reading order, if any
+    pub fn locked_in(&self, (c1, r1, c2, r2): (usize, usize, usize, usize)) -> Option<(usize, usize)> {
+        self.locks
+            .iter()
+            .filter(|&&(lc1, lr1, lc2, lr2)| lc1.max(c1) <= lc2.min(c2) && lr1.max(r1) <= lr2.min(r2))
+            .map(|&(lc1, lr1, _, _)| (lc1.max(c1), lr1.max(r1)))
+            .min_by_key(|&(col, row)| (row, col))
+    }
+
+    // Cell styles
+
+    /// Change the style of the cells with content in an area. Returns how
+    /// many there were; empty cells have no style to change.
+    pub fn style_cells(&mut self, (c1, r1, c2, r2): (usize, usize, usize, usize), change: impl Fn(&mut CellStyle)) -> usize {
+        let mut count = 0;
+        for ((col, row), cell) in self.cells.iter_mut() {
+            if (c1..=c2).contains(col) && (r1..=r2).contains(row) {
+                change(&mut cell.style);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Set the display format of the cells with content in an area.
+    /// Returns how many there were.
+    pub fn format_cells(&mut self, (c1, r1, c2, r2): (usize, usize, usize, usize), format: &DisplayFormat) -> usize {
+        let mut count = 0;
+        for ((col, row), cell) in self.cells.iter_mut() {
+            if (c1..=c2).contains(col) && (r1..=r2).contains(row) {
+                cell.format = format.clone();
+                count += 1;
+            }
+        }
+        count
+    }
+
+    // Merged cells
+
+    pub fn merges(&self) -> &[(usize, usize, usize, usize)] {
+        &self.merges
+    }
+
+    /// Merge an area; merges it overlaps are undone first
+    pub fn merge(&mut self, area: (usize, usize, usize, usize)) {
+        self.unmerge(area);
+        self.merges.push(area);
+    }
+
+    /// Undo the merges an area overlaps. Returns how many there were.
+    pub fn unmerge(&mut self, (c1, r1, c2, r2): (usize, usize, usize, usize)) -> usize {
+        let before = self.merges.len();
+        self.merges.retain(|&(mc1, mr1, mc2, mr2)| mc1.max(c1) > mc2.min(c2) || mr1.max(r1) > mr2.min(r2));
+        before - self.merges.len()
+    }
+
+    /// The merged area a cell belongs to, if any
+    pub fn merge_at(&self, col: usize, row: usize) -> Option<(usize, usize, usize, usize)> {
+        self.merges.iter().copied().find(|&(c1, r1, c2, r2)| (c1..=c2).contains(&col) && (r1..=r2).contains(&row))
+    }
+
+    // Data validation
+
+    pub fn validations(&self) -> &[Validation] {
+        &self.validations
+    }
+
+    /// Add a rule; it replaces any rule on exactly the same range
+    pub fn add_validation(&mut self, validation: Validation) {
+        self.validations.retain(|v| {
+            (v.start_col, v.start_row, v.end_col, v.end_row)
+                != (validation.start_col, validation.start_row, validation.end_col, validation.end_row)
+        });
+        self.validations.push(validation);
+    }
+
+    /// Remove every rule overlapping the range. Returns how many were removed.
+    pub fn remove_validations(&mut self, start_col: usize, start_row: usize, end_col: usize, end_row: usize) -> usize {
+        let before = self.validations.len();
+        self.validations.retain(|v| !v.intersects(start_col, start_row, end_col, end_row));
+        before - self.validations.len()
+    }
+
+    /// The rule governing a cell (the most recently added one wins)
+    pub fn validation_at(&self, col: usize, row: usize) -> Option<&Validation> {
+        self.validations.iter().rev().find(|v| v.contains(col, row))
+    }
+
+    /// The rule that `input` would break if entered at (col, row)
+    pub fn check_input(&self, col: usize, row: usize, input: &str) -> Option<&Validation> {
+        let validation = self.validation_at(col, row)?;
+        let value = match self.typed_value(col, input) {
+            CellValue::Formula(_) => {
+                engine::evaluate_input(&self.cells, input).unwrap_or(CellValue::Error(cell::CellError::Value))
+            }
+            value => value,
+        };
+        (!validation.rule.check(&value)).then_some(validation)
+    }
+
+    /// Whether the current value of a cell breaks its validation rule or
+    /// its column type
+    pub fn is_invalid(&self, col: usize, row: usize) -> bool {
+        if self.type_mismatch(col, row).is_some() {
+            return true;
+        }
+        match self.validation_at(col, row) {
+            Some(validation) => !validation.rule.check(&self.value(col, row)),
+            None => false,
+        }
+    }
+
+    // Column types
+
+    pub fn col_type(&self, col: usize) -> Option<ColType> {
+        self.col_types.get(&col).copied()
+    }
+
+    /// Declared column types in column order
+    pub fn col_types(&self) -> impl Iterator<Item = (usize, ColType)> + '_ {
+        self.col_types.iter().map(|(&col, &ty)| (col, ty))
+    }
+
+    /// Declare (or with None, undeclare) a column's type. Values already in
+    /// the column are re-read, so numbers become text in a text column and
+    /// back again. Returns how many values do not fit the new type.
+    pub fn set_col_type(&mut self, col: usize, col_type: Option<ColType>) -> usize {
+        match col_type {
+            Some(ty) => self.col_types.insert(col, ty),
+            None => self.col_types.remove(&col),
+        };
+        self.cells_changed();
+        let rows: Vec<usize> = self.cells.keys().filter(|(c, _)| *c == col).map(|(_, r)| *r).collect();
+        for &row in &rows {
+            let input = self.cells[&(col, row)].raw_input.clone();
+            let value = self.parse_input(col, &input);
+            if let Some(cell) = self.cells.get_mut(&(col, row)) {
+                cell.value = value;
+            }
+        }
+        rows.into_iter().filter(|&row| self.type_mismatch(col, row).is_some()).count()
+    }
+
+    // Column formulas
+
+    pub fn col_formula(&self, col: usize) -> Option<&str> {
+        self.col_formulas.get(&col).map(String::as_str)
+    }
+
+    /// Column formulas in column order
+    pub fn col_formulas(&self) -> impl Iterator<Item = (usize, &str)> + '_ {
+        self.col_formulas.iter().map(|(&col, template)| (col, template.as_str()))
+    }
+
+    /// Declare (or with None, drop) the formula of a column, `@` standing
+    /// for the row. Cells already in the column are left as they are; see
+    /// `fill_col_formula`.
+    pub fn set_col_formula(&mut self, col: usize, template: Option<String>) {
+        match template {
+            Some(template) => self.col_formulas.insert(col, template),
+            None => self.col_formulas.remove(&col),
+        };
+    }
+
+    /// Write a column's formula into every data row: the rows below the
+    /// header with something in another column. Returns how many cells were
+    /// written.
+    pub fn fill_col_formula(&mut self, col: usize) -> usize {
+        let Some(template) = self.col_formulas.get(&col).cloned() else {
+            return 0;
+        };
+        let header = self.header_row();
+        let rows: BTreeSet<usize> =
+            self.cells.keys().filter(|&&(c, r)| c != col && Some(r) != header).map(|&(_, r)| r).collect();
+        for &row in &rows {
+            self.set_cell(col, row, formula::for_row(&template, row));
+        }
+        rows.len()
+    }
+
+    /// A row just got something in `col`: give it the formulas of the other
+    /// columns where it has none yet, unless it is the header
+    fn fill_col_formulas_at(&mut self, col: usize, row: usize) {
+        if self.col_formulas.keys().all(|&c| c == col || self.cells.contains_key(&(c, row))) {
+            return;
+        }
+        // The header is the first row; any cell above makes this a data row
+        if !self.cells.keys().any(|&(_, r)| r < row) {
+            return;
+        }
+        let missing: Vec<(usize, String)> = self
+            .col_formulas
+            .iter()
+            .filter(|&(&c, _)| c != col && !self.cells.contains_key(&(c, row)))
+            .map(|(&c, template)| (c, formula::for_row(template, row)))
+            .collect();
+        for (c, input) in missing {
+            let value = self.parse_input(c, &input);
+            self.cells.insert((c, row), Cell::new(input, value));
+        }
+    }
+
+    /// A cell was just typed in below the data of a table: give it the
+    /// look of the cell above, stretch validation rules that end on the row
+    /// above over its row, and fill in the formulas of calculated columns,
+    /// those whose last two rows hold the same formula. The table is the run
+    /// of filled cells in the row above around `col`, below the header.
+    pub fn extend_table(&mut self, col: usize, row: usize) {
+        let Some(above) = row.checked_sub(1) else {
+            return;
+        };
+        let filled = |sheet: &Sheet, c: usize| sheet.cells.get(&(c, above)).is_some_and(|cell| !cell.raw_input.is_empty());
+        if !filled(self, col) || self.header_row().is_none_or(|header| above <= header) {
+            return;
+        }
+        let first = (0..col).rev().take_while(|&c| filled(self, c)).last().unwrap_or(col);
+        let last = (col + 1..256).take_while(|&c| filled(self, c)).last().unwrap_or(col);
+
+        let source = &self.cells[&(col, above)];
+        let (format, style) = (source.format.clone(), source.style);
+        if let Some(cell) = self.cells.get_mut(&(col, row)) {
+            if cell.style.is_plain() {
+                cell.style = style;
+                cell.format = format;
+            }
+        }
+
+        for validation in &mut self.validations {
+            if validation.end_row == above && validation.start_row < above && validation.intersects(first, above, last, above) {
+                validation.end_row = row;
+            }
+        }
+
+        for c in first..=last {
+            if self.cells.contains_key(&(c, row)) {
+                continue;
+            }
+            let formula_at = |r: usize| self.cells.get(&(c, r)).map(|cell| cell.raw_input.as_str()).filter(|f| f.starts_with('='));
+            let (Some(previous), Some(formula)) = (above.checked_sub(1).and_then(formula_at), formula_at(above)) else {
+                continue;
+            };
+            if formula::adjust_formula(previous, 0, 1) != formula {
+                continue;
+            }
+            let input = formula::adjust_formula(formula, 0, 1);
+            let (format, style) = (self.cells[&(c, above)].format.clone(), self.cells[&(c, above)].style);
+            let value = self.parse_input(c, &input);
+            self.cells.insert((c, row), Cell { format, style, ..Cell::new(input, value) });
+        }
+        self.cells_changed();
+    }
+
+    /// The first non-empty row, taken to be the header: it is never filtered
+    /// out and may hold a column title whatever the column's type
+    pub fn header_row(&self) -> Option<usize> {
+        self.cells.keys().map(|(_, r)| *r).min()
+    }
+
+    /// Cells and ranges the formula in (col, row) refers to, as written and
+    /// as (min_col, min_row, max_col, max_row), in the order they appear;
+    /// empty for cells that are not formulas
+    pub fn references(&self, col: usize, row: usize) -> Vec<(String, (usize, usize, usize, usize))> {
+        match self.get_cell_ref(col, row).map(|cell| &cell.value) {
+            Some(CellValue::Formula(f)) => {
+                formula::find_references(f).into_iter().map(|r| (f[r.span].to_string(), r.area)).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Cells the formula in (col, row) depends on, directly or through other
+    /// formulas, each with the number of steps it is away (1 for the cells
+    /// the formula names). A range stands for the cells in it that have
+    /// content. Ordered by distance, then by row and column.
+    pub fn precedents(&self, col: usize, row: usize) -> Vec<((usize, usize), usize)> {
+        self.trace((col, row), |sheet, (col, row)| {
+            let mut cells = Vec::new();
+            for (_, (c1, r1, c2, r2)) in sheet.references(col, row) {
+                if (c1, r1) == (c2, r2) {
+                    cells.push((c1, r1));
+                } else {
+                    cells.extend(
+                        sheet
+                            .cells
+                            .iter()
+                            .filter(|(&(c, r), cell)| (c1..=c2).contains(&c) && (r1..=r2).contains(&r) && !cell.is_empty())
+                            .map(|(&pos, _)| pos),
+                    );
                 }
             }
+            cells
+        })
+    }
+
+    /// Cells whose formulas depend on (col, row), directly or through other
+    /// formulas, with their distance as for `precedents`
+    pub fn dependents(&self, col: usize, row: usize) -> Vec<((usize, usize), usize)> {
+        // Each formula cell with the areas it refers to
+        let formulas: Vec<_> = self
+            .cells
+            .keys()
+            .map(|&(c, r)| ((c, r), self.references(c, r).into_iter().map(|(_, area)| area).collect::<Vec<_>>()))
+            .filter(|(_, areas)| !areas.is_empty())
+            .collect();
+        self.trace((col, row), |_, (col, row)| {
+            formulas
+                .iter()
+                .filter(|(_, areas)| areas.iter().any(|&(c1, r1, c2, r2)| (c1..=c2).contains(&col) && (r1..=r2).contains(&row)))
+                .map(|&(pos, _)| pos)
+                .collect()
+        })
+    }
+
+    /// Breadth-first walk from `start` along `next`, each cell once
+    fn trace<F>(&self, start: (usize, usize), next: F) -> Vec<((usize, usize), usize)>
+    where
+        F: Fn(&Sheet, (usize, usize)) -> Vec<(usize, usize)>,
+    {
+        let mut seen = BTreeSet::from([start]);
+        let mut found = Vec::new();
+        let mut frontier = vec![start];
+        let mut depth = 0;
+        while !frontier.is_empty() {
+            depth += 1;
+            let mut level: Vec<(usize, usize)> = frontier
+                .iter()
+                .flat_map(|&pos| next(self, pos))
+                .filter(|&pos| seen.insert(pos))
+                .collect();
+            level.sort_by_key(|&(c, r)| (r, c));
+            found.extend(level.iter().map(|&pos| (pos, depth)));
+            frontier = level;
+        }
+        found
+    }
+
+    /// Text values elsewhere in column `col` that start with `typed` (ignoring
+    /// case), nearest to `row` first, for completing what is being typed.
+    /// The header is left out.
+    pub fn column_completions(&self, col: usize, row: usize, typed: &str) -> Vec<String> {
+        let header = self.header_row();
+        let typed = typed.to_lowercase();
+        let mut found: Vec<(usize, &str)> = self
+            .cells
+            .iter()
+            .filter(|(&(c, r), _)| c == col && r != row && Some(r) != header)
+            .filter_map(|(&(_, r), cell)| match &cell.value {
+                CellValue::Text(s) if s.to_lowercase().starts_with(&typed) && s.to_lowercase() != typed => Some((r, s.as_str())),
+                _ => None,
+            })
+            .collect();
+        // Nearest first, above before below at the same distance
+        found.sort_by_key(|&(r, _)| (r.abs_diff(row), r > row));
+        let mut completions: Vec<String> = Vec::new();
+        for (_, s) in found {
+            if !completions.iter().any(|c| c == s) {
+                completions.push(s.to_string());
+            }
+        }
+        completions
+    }
+
+    /// The column type a cell's current value breaks, if any
+    pub fn type_mismatch(&self, col: usize, row: usize) -> Option<ColType> {
+        let ty = self.col_type(col)?;
+        if self.header_row() == Some(row) {
+            return None;
+        }
+        (!ty.check(&self.value(col, row))).then_some(ty)
+    }
+
+    /// The column type that `input` would break if entered at (col, row).
+    /// Typing at or above the first row starts a header, which is not checked.
+    pub fn check_input_type(&self, col: usize, row: usize, input: &str) -> Option<ColType> {
+        let ty = self.col_type(col)?;
+        if self.header_row().is_none_or(|header| row <= header) || input.trim().is_empty() {
+            return None;
         }
+        let value = match self.typed_value(col, input) {
+            CellValue::Formula(_) => {
+                engine::evaluate_input(&self.cells, input).unwrap_or(CellValue::Error(cell::CellError::Value))
+            }
+            value => value,
+        };
+        (!ty.check(&value)).then_some(ty)
+    }
+
+    // Row filter
+
+    pub fn filter(&self) -> Option<&RowFilter> {
+        self.filter.as_ref()
+    }
+
+    /// Hide the rows below the header whose value in `col` does not match
+    /// `criteria`. The header is the first non-empty row of the sheet.
+    /// Returns the number of rows hidden.
+    pub fn set_filter(&mut self, col: usize, criteria: &str) -> Result<usize, String> {
+        let criteria = criteria.trim();
+        if criteria.is_empty() {
+            return Err("Empty filter criteria".to_string());
+        }
+        // A typed column may compare in its own way (dates)
+        let typed = self.col_type(col).filter(|ty| ty.matches("", criteria).is_some());
+        for op in [">=", "<=", "<>", "!=", ">", "<"] {
+            if let Some(target) = criteria.strip_prefix(op) {
+                if typed.is_none() && target.trim().parse::<f64>().is_err() {
+                    return Err(format!("Invalid filter criteria: {} (expected a number after {})", criteria, op));
+                }
+                break;
+            }
+        }
+
+        self.filtered_rows.clear();
+        if let (Some(header), Some(max_row)) = (self.header_row(), self.max_row()) {
+            let mut engine = Engine::new(&self.cells);
+            for row in header + 1..=max_row {
+                let matches = match typed {
+                    Some(ty) => ty.matches(&self.evaluate(col, row), criteria).unwrap_or(false),
+                    None => engine.matches_criteria(col, row, criteria).unwrap_or(false),
+                };
+                if !matches {
+                    self.filtered_rows.insert(row);
+                }
+            }
+        }
+        self.filter = Some(RowFilter { col, criteria: criteria.to_string() });
+        Ok(self.filtered_rows.len())
+    }
+
+    /// Remove the filter, showing its rows again. Returns the number of rows shown.
+    pub fn clear_filter(&mut self) -> usize {
+        self.filter = None;
+        let count = self.filtered_rows.len();
+        self.filtered_rows.clear();
+        count
+    }
+
+    // Hidden rows and columns
+
+    /// Whether a row is currently hidden from display and navigation
+    /// (by the filter or with :hide)
+    pub fn is_row_hidden(&self, row: usize) -> bool {
+        self.filtered_rows.contains(&row) || self.hidden_rows.contains(&row)
+    }
+
+    pub fn is_col_hidden(&self, col: usize) -> bool {
+        self.hidden_cols.contains(&col)
+    }
+
+    /// Rows hidden with :hide (not counting filtered rows)
+    pub fn hidden_rows(&self) -> impl Iterator<Item = usize> + '_ {
+        self.hidden_rows.iter().copied()
+    }
+
+    pub fn hidden_cols(&self) -> impl Iterator<Item = usize> + '_ {
+        self.hidden_cols.iter().copied()
+    }
+
+    /// Hide or show rows start..=end. Returns how many rows changed state.
+    pub fn set_rows_hidden(&mut self, start: usize, end: usize, hidden: bool) -> usize {
+        (start..=end.min(9999))
+            .filter(|&r| if hidden { self.hidden_rows.insert(r) } else { self.hidden_rows.remove(&r) })
+            .count()
+    }
+
+    /// Hide or show columns start..=end. Returns how many columns changed state.
+    pub fn set_cols_hidden(&mut self, start: usize, end: usize, hidden: bool) -> usize {
+        (start..=end.min(255))
+            .filter(|&c| if hidden { self.hidden_cols.insert(c) } else { self.hidden_cols.remove(&c) })
+            .count()
+    }
+
+    /// Show every hidden row and column. Returns (rows, columns) shown.
+    pub fn unhide_all(&mut self) -> (usize, usize) {
+        let counts = (self.hidden_rows.len(), self.hidden_cols.len());
+        self.hidden_rows.clear();
+        self.hidden_cols.clear();
+        counts
+    }
+
+    /// The nearest visible column to `col`, searching in direction `dir` first
+    pub fn nearest_visible_col(&self, col: usize, dir: isize) -> usize {
+        if !self.is_col_hidden(col) {
+            return col;
+        }
+        let right = (col..=255).find(|&c| !self.is_col_hidden(c));
+        let left = (0..col).rev().find(|&c| !self.is_col_hidden(c));
+        let found = if dir < 0 { left.or(right) } else { right.or(left) };
+        found.unwrap_or(col)
+    }
+
+    /// Up to `count` visible row indices starting at `start`
+    pub fn visible_rows(&self, start: usize, count: usize) -> Vec<usize> {
+        (start..=9999).filter(|&r| !self.is_row_hidden(r)).take(count).collect()
+    }
+
+    /// The nearest visible row to `row`, searching in direction `dir` first
+    pub fn nearest_visible_row(&self, row: usize, dir: isize) -> usize {
+        if !self.is_row_hidden(row) {
+            return row;
+        }
+        let below = (row..=9999).find(|&r| !self.is_row_hidden(r));
+        let above = (0..row).rev().find(|&r| !self.is_row_hidden(r));
+        let found = if dir < 0 { above.or(below) } else { below.or(above) };
+        found.unwrap_or(row)
     }
 
     pub fn max_row(&self) -> Option<usize> {
@@ -115,6 +1100,87 @@ impl Sheet {
             .max()
     }
 
+    /// Next cell after (col, row) in reading order, or before it when
+    /// searching backward, whose displayed value contains `term`
+    /// (case-insensitive). The search wraps around the grid; the bool is
+    /// true if it did.
+    pub fn find(&self, term: &str, col: usize, row: usize, forward: bool) -> Option<(usize, usize, bool)> {
+        self.find_in(&Pattern::text(term), col, row, forward, (0, 0, 255, 9999), SearchTarget::Value)
+    }
+
+    /// `find` for a pattern, limited to the (min_col, min_row, max_col,
+    /// max_row) area, wrapping around it, and looking at the values or the
+    /// formulas; (col, row) may be outside the area
+    pub fn find_in(
+        &self,
+        pattern: &Pattern,
+        col: usize,
+        row: usize,
+        forward: bool,
+        (c1, r1, c2, r2): (usize, usize, usize, usize),
+        target: SearchTarget,
+    ) -> Option<(usize, usize, bool)> {
+        // Only cells with something in them can match, taken in reading
+        // order from just past (col, row) and then around from the start
+        let cells = self.occupied_cells((c1, r1, c2, r2), target);
+        let split = cells.partition_point(|&(r, c)| (r, c) <= (row, col));
+        let at = cells.partition_point(|&(r, c)| (r, c) < (row, col));
+        let (before, after) = (&cells[..at], &cells[split..]);
+        let matches = |&&(r, c): &&(usize, usize)| self.matches(c, r, pattern, target);
+
+        let found = if forward {
+            after.iter().find(matches).map(|p| (p, false)).or_else(|| before.iter().find(matches).map(|p| (p, true)))
+        } else {
+            before.iter().rev().find(matches).map(|p| (p, false)).or_else(|| after.iter().rev().find(matches).map(|p| (p, true)))
+        };
+        found.map(|(&(r, c), wrapped)| (c, r, wrapped))
+    }
+
+    /// The cells of the area a search can find something in, as (row, col)
+    /// in reading order: those with contents, and for values also those an
+    /// array formula spills into
+    fn occupied_cells(&self, (c1, r1, c2, r2): (usize, usize, usize, usize), target: SearchTarget) -> Vec<(usize, usize)> {
+        let mut cells: Vec<(usize, usize)> = self.cells.keys().copied().collect();
+        if target == SearchTarget::Value {
+            match &self.calculated {
+                Some(calculated) => cells.extend(calculated.keys()),
+                None => cells.extend(self.spills().keys()),
+            }
+        }
+        let mut cells: Vec<(usize, usize)> = cells
+            .into_iter()
+            .filter(|&(c, r)| (c1..=c2).contains(&c) && (r1..=r2).contains(&r))
+            .map(|(c, r)| (r, c))
+            .collect();
+        cells.sort_unstable();
+        cells.dedup();
+        cells
+    }
+
+    /// Every cell in the area whose value (or formula) matches `pattern`,
+    /// in reading order
+    pub fn find_all(&self, pattern: &Pattern, area: (usize, usize, usize, usize), target: SearchTarget) -> Vec<(usize, usize)> {
+        self.occupied_cells(area, target)
+            .into_iter()
+            .filter(|&(r, c)| self.matches(c, r, pattern, target))
+            .map(|(r, c)| (c, r))
+            .collect()
+    }
+
+    /// Whether a cell's value (or formula) matches `pattern`; an empty
+    /// cell never does
+    pub fn matches(&self, col: usize, row: usize, pattern: &Pattern, target: SearchTarget) -> bool {
+        match target {
+            SearchTarget::Value => {
+                let value = self.evaluate(col, row);
+                !value.is_empty() && pattern.is_match(&value)
+            }
+            SearchTarget::Formula => {
+                self.cells.get(&(col, row)).is_some_and(|cell| !cell.raw_input.is_empty() && pattern.is_match(&cell.raw_input))
+            }
+        }
+    }
+
     pub fn first_non_empty_col_in_row(&self, row: usize) -> Option<usize> {
         self.cells.keys()
             .filter(|(_, r)| *r == row)
@@ -122,6 +1188,47 @@ impl Sheet {
             .min()
     }
 
+    /// Where a block motion from (col, row) goes along its row
+    /// (`along_row`) or column, moving between runs of filled cells; None
+    /// when there is nowhere to go
+    pub fn block_motion(&self, col: usize, row: usize, along_row: bool, motion: BlockMotion) -> Option<(usize, usize)> {
+        let (pos, last) = if along_row { (col, 255) } else { (row, 9999) };
+        let filled: BTreeSet<usize> = self
+            .cells
+            .keys()
+            .filter(|&&(c, r)| if along_row { r == row } else { c == col })
+            .map(|&(c, r)| if along_row { c } else { r })
+            .collect();
+        let is_filled = |p: usize| filled.contains(&p);
+        // Ends of the run of filled cells through p
+        let run_end = |mut p: usize| {
+            while p < last && is_filled(p + 1) {
+                p += 1;
+            }
+            p
+        };
+        let run_start = |mut p: usize| {
+            while p > 0 && is_filled(p - 1) {
+                p -= 1;
+            }
+            p
+        };
+
+        let target = match motion {
+            BlockMotion::NextStart => {
+                let from = if is_filled(pos) { run_end(pos) + 1 } else { pos };
+                filled.range(from..).next().copied()
+            }
+            BlockMotion::NextEnd => filled.range(pos + 1..).next().map(|&p| run_end(p)),
+            BlockMotion::PrevStart => filled.range(..pos).next_back().map(|&p| run_start(p)),
+            BlockMotion::EdgeNext if pos < last && is_filled(pos) && is_filled(pos + 1) => Some(run_end(pos)),
+            BlockMotion::EdgeNext => Some(filled.range(pos + 1..).next().copied().unwrap_or(last)),
+            BlockMotion::EdgePrev if pos > 0 && is_filled(pos) && is_filled(pos - 1) => Some(run_start(pos)),
+            BlockMotion::EdgePrev => Some(filled.range(..pos).next_back().copied().unwrap_or(0)),
+        };
+        target.filter(|&p| p != pos).map(|p| if along_row { (p, row) } else { (col, p) })
+    }
+
     pub fn first_non_empty_row_in_col(&self, col: usize) -> Option<usize> {
         self.cells.keys()
             .filter(|(c, _)| *c == col)
@@ -129,138 +1236,198 @@ impl Sheet {
             .min()
     }
 
-    // Row operations
-    pub fn delete_row(&mut self, row: usize) {
-        self.cells.retain(|(_, r), _| *r != row);
-        
-        let cells_to_move: Vec<_> = self.cells
-            .iter()
-            .filter(|((_, r), _)| *r > row)
-            .map(|((c, r), cell)| ((*c, *r), cell.clone()))
-            .collect();
+    // Row and column operations
 
-        for ((c, r), _) in &cells_to_move {
-            self.cells.remove(&(*c, *r));
+    /// Rearrange the rows first..=last: row `first + i` gets what was on
+    /// row `order[i]`, and the rows after the last one given are emptied.
+    /// Formulas move as written.
+    pub fn arrange_rows(&mut self, first: usize, last: usize, order: &[usize]) {
+        let new_row: HashMap<usize, usize> = order.iter().enumerate().map(|(i, &row)| (row, first + i)).collect();
+        let positions: Vec<(usize, usize)> =
+            self.cells.keys().filter(|&&(_, row)| (first..=last).contains(&row)).copied().collect();
+        // Take them all out first so none is overwritten before it moves
+        let moved: Vec<_> = positions.into_iter().filter_map(|pos| Some((pos, self.cells.remove(&pos)?))).collect();
+        for ((col, row), cell) in moved {
+            if let Some(&row) = new_row.get(&row) {
+                self.cells.insert((col, row), cell);
+            }
         }
+        self.cells_moved(|col, row| match (first..=last).contains(&row) {
+            true => new_row.get(&row).map(|&row| (col, row)),
+            false => Some((col, row)),
+        });
+    }
 
-        for ((c, r), cell) in cells_to_move {
-            self.cells.insert((c, r - 1), cell);
-        }
+    pub fn delete_row(&mut self, row: usize) {
+        self.delete_rows(row, 1);
     }
 
     pub fn insert_row(&mut self, row: usize) {
-        let cells_to_move: Vec<_> = self.cells
-            .iter()
-            .filter(|((_, r), _)| *r >= row)
-            .map(|((c, r), cell)| ((*c, *r), cell.clone()))
-            .collect();
+        self.insert_rows(row, 1);
+    }
 
-        for ((c, r), _) in &cells_to_move {
-            self.cells.remove(&(*c, *r));
-        }
+    pub fn delete_col(&mut self, col: usize) {
+        self.delete_cols(col, 1);
+    }
 
-        for ((c, r), cell) in cells_to_move {
-            self.cells.insert((c, r + 1), cell);
-        }
+    pub fn insert_col(&mut self, col: usize) {
+        self.insert_cols(col, 1);
     }
 
-    // Column operations
-    pub fn delete_col(&mut self, col: usize) {
-        self.cells.retain(|(c, _), _| *c != col);
-        
-        let cells_to_move: Vec<_> = self.cells
-            .iter()
-            .filter(|((c, _), _)| *c > col)
-            .map(|((c, r), cell)| ((*c, *r), cell.clone()))
-            .collect();
+    /// Delete `count` rows from `row` on, as one change
+    pub fn delete_rows(&mut self, row: usize, count: usize) {
+        self.apply_structure_change(StructureChange::RowDelete(row, count));
+    }
 
-        for ((c, r), _) in &cells_to_move {
-            self.cells.remove(&(*c, *r));
-        }
+    pub fn insert_rows(&mut self, row: usize, count: usize) {
+        self.apply_structure_change(StructureChange::RowInsert(row, count));
+    }
 
-        for ((c, r), cell) in cells_to_move {
-            self.cells.insert((c - 1, r), cell);
-        }
+    pub fn delete_cols(&mut self, col: usize, count: usize) {
+        self.apply_structure_change(StructureChange::ColDelete(col, count));
     }
 
-    pub fn insert_col(&mut self, col: usize) {
-        let cells_to_move: Vec<_> = self.cells
-            .iter()
-            .filter(|((c, _), _)| *c >= col)
-            .map(|((c, r), cell)| ((*c, *r), cell.clone()))
-            .collect();
+    pub fn insert_cols(&mut self, col: usize, count: usize) {
+        self.apply_structure_change(StructureChange::ColInsert(col, count));
+    }
 
-        for ((c, r), _) in &cells_to_move {
-            self.cells.remove(&(*c, *r));
-        }
+    /// Take out a row and put it back in at `to`, the rows in between
+    /// closing up
+    pub fn move_row(&mut self, from: usize, to: usize) {
+        self.apply_structure_change(StructureChange::RowMove(from, to));
+    }
 
-        for ((c, r), cell) in cells_to_move {
-            self.cells.insert((c + 1, r), cell);
-        }
+    pub fn move_col(&mut self, from: usize, to: usize) {
+        self.apply_structure_change(StructureChange::ColMove(from, to));
     }
 
-    /// Adjust all formulas in the sheet for a row insertion
-    pub fn adjust_formulas_for_row_insert(&mut self, inserted_row: usize) {
-        let keys: Vec<_> = self.cells.keys().cloned().collect();
-        for (col, row) in keys {
-            if let Some(cell) = self.cells.get(&(col, row)) {
-                if cell.raw_input.starts_with('=') {
-                    let adjusted = crate::formula::adjust_formula_for_row_insert(&cell.raw_input, inserted_row);
-                    if adjusted != cell.raw_input {
-                        let value = crate::cell::parse_input(&adjusted);
-                        self.cells.insert((col, row), Cell::new(adjusted, value));
-                    }
+    /// Insert, delete or move rows or columns. This is the one place
+    /// structural edits go through: cells, formula references, column widths
+    /// and types, hidden rows and columns, the filter and validation rules all
+    /// follow the change here, so anything new that is keyed by position
+    /// belongs here too.
+    pub fn apply_structure_change(&mut self, change: StructureChange) {
+        // Cells on a deleted row or column go; the rest move
+        self.cells = std::mem::take(&mut self.cells)
+            .into_iter()
+            .filter_map(|((col, row), cell)| Some(((change.col(col)?, change.row(row)?), cell)))
+            .collect();
+        for cell in self.cells.values_mut() {
+            if cell.raw_input.starts_with('=') {
+                let adjusted = formula::adjust_formula_for_structure_change(&cell.raw_input, change);
+                if adjusted != cell.raw_input {
+                    let value = cell::parse_input(&adjusted);
+                    *cell = Cell {
+                        format: std::mem::take(&mut cell.format),
+                        style: cell.style,
+                        ..Cell::new(adjusted, value)
+                    };
                 }
             }
         }
-    }
 
-    /// Adjust all formulas in the sheet for a row deletion
-    pub fn adjust_formulas_for_row_delete(&mut self, deleted_row: usize) {
-        let keys: Vec<_> = self.cells.keys().cloned().collect();
-        for (col, row) in keys {
-            if let Some(cell) = self.cells.get(&(col, row)) {
-                if cell.raw_input.starts_with('=') {
-                    let adjusted = crate::formula::adjust_formula_for_row_delete(&cell.raw_input, deleted_row);
-                    if adjusted != cell.raw_input {
-                        let value = crate::cell::parse_input(&adjusted);
-                        self.cells.insert((col, row), Cell::new(adjusted, value));
-                    }
+        self.col_widths = std::mem::take(&mut self.col_widths)
+            .into_iter()
+            .filter_map(|(col, width)| Some((change.col(col)?, width)))
+            .collect();
+        self.col_types = std::mem::take(&mut self.col_types)
+            .into_iter()
+            .filter_map(|(col, ty)| Some((change.col(col)?, ty)))
+            .collect();
+        self.col_formulas = std::mem::take(&mut self.col_formulas)
+            .into_iter()
+            .filter_map(|(col, template)| {
+                Some((change.col(col)?, formula::adjust_formula_for_structure_change(&template, change)))
+            })
+            .collect();
+        self.hidden_cols = self.hidden_cols.iter().filter_map(|&c| change.col(c)).collect();
+        self.hidden_rows = self.hidden_rows.iter().filter_map(|&r| change.row(r)).collect();
+        self.filtered_rows = self.filtered_rows.iter().filter_map(|&r| change.row(r)).collect();
+
+        // Deleting the filtered column removes the filter
+        if let Some(filter) = &mut self.filter {
+            match change.col(filter.col) {
+                Some(col) => filter.col = col,
+                None => {
+                    self.clear_filter();
                 }
             }
         }
+
+        self.validations.retain_mut(|v| v.apply_structure_change(change));
+        self.locks = self
+            .locks
+            .iter()
+            .filter_map(|&(c1, r1, c2, r2)| {
+                let ((c1, c2), (r1, r2)) = (change.cols(c1, c2)?, change.rows(r1, r2)?);
+                Some((c1, r1, c2, r2))
+            })
+            .collect();
+        // A merge that shrinks to one cell is no longer a merge
+        self.merges = self
+            .merges
+            .iter()
+            .filter_map(|&(c1, r1, c2, r2)| {
+                let ((c1, c2), (r1, r2)) = (change.cols(c1, c2)?, change.rows(r1, r2)?);
+                Some((c1, r1, c2, r2)).filter(|_| (c1, r1) != (c2, r2))
+            })
+            .collect();
+        self.cells_moved(|col, row| Some((change.col(col)?, change.row(row)?)));
     }
 
-    /// Adjust all formulas in the sheet for a column insertion
-    pub fn adjust_formulas_for_col_insert(&mut self, inserted_col: usize) {
-        let keys: Vec<_> = self.cells.keys().cloned().collect();
-        for (col, row) in keys {
-            if let Some(cell) = self.cells.get(&(col, row)) {
-                if cell.raw_input.starts_with('=') {
-                    let adjusted = crate::formula::adjust_formula_for_col_insert(&cell.raw_input, inserted_col);
-                    if adjusted != cell.raw_input {
-                        let value = crate::cell::parse_input(&adjusted);
-                        self.cells.insert((col, row), Cell::new(adjusted, value));
-                    }
-                }
-            }
+    /// Turn the cells of an area over about its top-left cell, rows becoming
+    /// columns, and fix up references to them as `move_cells` does. Refused
+    /// if the turned block would run off the sheet or over cells outside
+    /// the area. Returns the area the block covers now.
+    pub fn transpose(&mut self, area: (usize, usize, usize, usize)) -> Result<(usize, usize, usize, usize), String> {
+        let (c1, r1, c2, r2) = area;
+        let turned = (c1, r1, c1 + (r2 - r1), r1 + (c2 - c1));
+        if turned.2 > 255 || turned.3 > 9999 {
+            return Err(format!("{} turned over would run off the sheet", crate::validation::area_name(area)));
+        }
+        let inside = |(col, row): (usize, usize), (c1, r1, c2, r2): (usize, usize, usize, usize)| {
+            (c1..=c2).contains(&col) && (r1..=r2).contains(&row)
+        };
+        let covered = self
+            .cells
+            .iter()
+            .filter(|&(&pos, cell)| inside(pos, turned) && !inside(pos, area) && !cell.raw_input.is_empty())
+            .map(|(&pos, _)| pos)
+            .min_by_key(|&(col, row)| (row, col));
+        if let Some((col, row)) = covered {
+            return Err(format!(
+                "{} turned over would cover {}, which is not empty",
+                crate::validation::area_name(area),
+                formula::cell_name(col, row)
+            ));
         }
-    }
 
-    /// Adjust all formulas in the sheet for a column deletion
-    pub fn adjust_formulas_for_col_delete(&mut self, deleted_col: usize) {
-        let keys: Vec<_> = self.cells.keys().cloned().collect();
-        for (col, row) in keys {
-            if let Some(cell) = self.cells.get(&(col, row)) {
-                if cell.raw_input.starts_with('=') {
-                    let adjusted = crate::formula::adjust_formula_for_col_delete(&cell.raw_input, deleted_col);
-                    if adjusted != cell.raw_input {
-                        let value = crate::cell::parse_input(&adjusted);
-                        self.cells.insert((col, row), Cell::new(adjusted, value));
-                    }
+        let to = |col: usize, row: usize| (c1 + (row - r1), r1 + (col - c1));
+        for cell in self.cells.values_mut() {
+            if cell.raw_input.starts_with('=') {
+                let adjusted = formula::adjust_formula_for_transpose(&cell.raw_input, area);
+                if adjusted != cell.raw_input {
+                    let value = cell::parse_input(&adjusted);
+                    *cell = Cell {
+                        format: std::mem::take(&mut cell.format),
+                        style: cell.style,
+                        ..Cell::new(adjusted, value)
+                    };
                 }
             }
         }
+        let positions: Vec<(usize, usize)> = self.cells.keys().filter(|&&pos| inside(pos, area)).copied().collect();
+        let moved: Vec<_> = positions.into_iter().filter_map(|pos| Some((pos, self.cells.remove(&pos)?))).collect();
+        self.cells.retain(|&pos, _| !inside(pos, turned));
+        for ((col, row), cell) in moved {
+            self.cells.insert(to(col, row), cell);
+        }
+        self.cells_moved(|col, row| match (inside((col, row), area), inside((col, row), turned)) {
+            (true, _) => Some(to(col, row)),
+            (false, true) => None,
+            _ => Some((col, row)),
+        });
+        Ok(turned)
     }
 
     // Cell shift operations (within a row)
@@ -279,6 +1446,7 @@ impl Sheet {
         for ((c, r), cell) in cells_to_move {
             self.cells.insert((c + 1, r), cell);
         }
+        self.cells_moved(|c, r| Some(if r == row && c >= col { (c + 1, r) } else { (c, r) }));
     }
 
     // Cell shift operations (within a column)
@@ -297,5 +1465,643 @@ impl Sheet {
         for ((c, r), cell) in cells_to_move {
             self.cells.insert((c, r + 1), cell);
         }
+        self.cells_moved(|c, r| Some(if c == col && r >= row { (c, r + 1) } else { (c, r) }));
+    }
+
+    /// Move the cells of an area by (col_offset, row_offset), over whatever
+    /// is where they land, as a cut and paste would. References to the moved
+    /// cells anywhere in the sheet follow them; the caller keeps the area
+    /// on the sheet.
+    pub fn move_cells(&mut self, area: (usize, usize, usize, usize), col_offset: isize, row_offset: isize) {
+        let (c1, r1, c2, r2) = area;
+        let inside = |col: usize, row: usize| (c1..=c2).contains(&col) && (r1..=r2).contains(&row);
+        let to = |col: usize, row: usize| ((col as isize + col_offset) as usize, (row as isize + row_offset) as usize);
+        let (dc1, dr1) = to(c1, r1);
+        let (dc2, dr2) = to(c2, r2);
+
+        for cell in self.cells.values_mut() {
+            if cell.raw_input.starts_with('=') {
+                let adjusted = formula::adjust_formula_for_move(&cell.raw_input, area, col_offset, row_offset);
+                if adjusted != cell.raw_input {
+                    let value = cell::parse_input(&adjusted);
+                    *cell = Cell {
+                        format: std::mem::take(&mut cell.format),
+                        style: cell.style,
+                        ..Cell::new(adjusted, value)
+                    };
+                }
+            }
+        }
+        let positions: Vec<(usize, usize)> = self.cells.keys().filter(|&&(col, row)| inside(col, row)).copied().collect();
+        let moved: Vec<_> = positions.into_iter().filter_map(|pos| Some((pos, self.cells.remove(&pos)?))).collect();
+        self.cells.retain(|&(col, row), _| !((dc1..=dc2).contains(&col) && (dr1..=dr2).contains(&row)));
+        for ((col, row), cell) in moved {
+            self.cells.insert(to(col, row), cell);
+        }
+        self.cells_moved(|col, row| {
+            if inside(col, row) {
+                Some(to(col, row))
+            } else if (dc1..=dc2).contains(&col) && (dr1..=dr2).contains(&row) {
+                None
+            } else {
+                Some((col, row))
+            }
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell::{Align, Rgb};
+    use crate::validation;
+
+    #[test]
+    fn test_structure_change_moves_cells_and_formulas() {
+        let mut sheet = Sheet::new();
+        sheet.set_cell(0, 0, "1".to_string());
+        sheet.set_cell(0, 2, "2".to_string());
+        sheet.set_cell(1, 0, "=A1+A3".to_string());
+
+        sheet.insert_row(1);
+        assert_eq!(sheet.evaluate(0, 3), "2");
+        assert_eq!(sheet.get_cell(1, 0).raw_input, "=A1+A4");
+
+        sheet.delete_col(0);
+        assert_eq!(sheet.get_cell(0, 0).raw_input, "=#REF!+#REF!");
+        assert!(sheet.get_cell_ref(0, 3).is_none());
+    }
+
+    #[test]
+    fn test_structure_change_moves_widths_and_hidden() {
+        let mut sheet = Sheet::new();
+        sheet.set_col_width(2, 20);
+        sheet.set_cols_hidden(3, 3, true);
+        sheet.set_rows_hidden(5, 5, true);
+
+        sheet.insert_col(0);
+        assert_eq!(sheet.get_col_width(3), 20);
+        assert_eq!(sheet.get_col_width(2), DEFAULT_COL_WIDTH);
+        assert!(sheet.is_col_hidden(4) && !sheet.is_col_hidden(3));
+
+        sheet.delete_col(3);
+        assert_eq!(sheet.get_col_width(3), DEFAULT_COL_WIDTH);
+        assert!(sheet.is_col_hidden(3));
+
+        sheet.delete_row(0);
+        assert!(sheet.is_row_hidden(4) && !sheet.is_row_hidden(5));
+    }
+
+    #[test]
+    fn test_structure_change_moves_filter() {
+        let mut sheet = Sheet::new();
+        for (row, value) in ["Qty", "5", "50"].iter().enumerate() {
+            sheet.set_cell(1, row, value.to_string());
+        }
+        sheet.set_filter(1, ">10").unwrap();
+        assert!(sheet.is_row_hidden(1));
+
+        sheet.insert_col(0);
+        sheet.insert_row(0);
+        assert_eq!(sheet.filter().map(|f| f.col), Some(2));
+        assert!(sheet.is_row_hidden(2) && !sheet.is_row_hidden(3));
+
+        // Deleting the filtered column drops the filter and shows its rows
+        sheet.delete_col(2);
+        assert!(sheet.filter().is_none());
+        assert!(!sheet.is_row_hidden(2));
+    }
+
+    #[test]
+    fn test_structure_change_moves_validations() {
+        let mut sheet = Sheet::new();
+        sheet.add_validation(validation::parse("B2:B5 number").unwrap());
+        sheet.add_validation(validation::parse("D1 list \"a\"").unwrap());
+
+        sheet.insert_row(0);
+        assert_eq!(sheet.validations()[0].range_name(), "B3:B6");
+
+        sheet.delete_col(3);
+        assert_eq!(sheet.validations().len(), 1);
+        assert!(sheet.validation_at(1, 2).is_some());
+        assert!(sheet.validation_at(1, 1).is_none());
+    }
+
+    #[test]
+    fn test_default_col_width() {
+        let mut sheet = Sheet::new();
+        sheet.set_col_width(0, 20);
+        sheet.set_col_width(1, 12);
+        sheet.set_default_col_width(12);
+        assert_eq!(sheet.get_col_width(0), 20);
+        assert_eq!(sheet.get_col_width(5), 12);
+        // Set to what is now the default, so no longer stored
+        assert_eq!(sheet.col_widths().count(), 1);
+        sheet.set_col_width(2, DEFAULT_COL_WIDTH);
+        assert_eq!(sheet.get_col_width(2), DEFAULT_COL_WIDTH);
+    }
+
+    #[test]
+    fn test_array_formulas_spill() {
+        let mut sheet = Sheet::new();
+        for (row, (name, qty)) in [("pear", "5"), ("apple", "12"), ("Pear", "30"), ("fig", "")].iter().enumerate() {
+            sheet.set_cell(0, row, name.to_string());
+            sheet.set_cell(1, row, qty.to_string());
+        }
+        sheet.set_cell(3, 0, "=FILTER(A1:B4, B1:B4>=10)".to_string());
+        let column = |sheet: &Sheet, col: usize| (0..4).map(|row| sheet.evaluate(col, row)).collect::<Vec<_>>();
+        assert_eq!(column(&sheet, 3), vec!["apple", "Pear", "", ""]);
+        assert_eq!(column(&sheet, 4), vec!["12", "30", "", ""]);
+        assert_eq!(sheet.value(4, 1), CellValue::Number(30.0));
+
+        // Other formulas see the spilled values
+        sheet.set_cell(6, 0, "=SUM(E1:E4)".to_string());
+        assert_eq!(sheet.evaluate(6, 0), "42");
+
+        // Sorting the distinct names of a column, and a spill that is blocked
+        sheet.set_cell(3, 0, "=SORT(UNIQUE(A1:A4))".to_string());
+        assert_eq!(column(&sheet, 3), vec!["apple", "fig", "pear", ""]);
+        sheet.set_cell(3, 0, "=SORT(A1:B4, 2, FALSE)".to_string());
+        assert_eq!(column(&sheet, 4), vec!["30", "12", "5", ""]);
+        sheet.set_cell(4, 2, "x".to_string());
+        assert_eq!(sheet.evaluate(3, 0), "#SPILL!");
+        assert_eq!(sheet.evaluate(3, 1), "");
+
+        sheet.set_cell(3, 0, "=FILTER(A1:A4, B1:B4>100)".to_string());
+        assert_eq!(sheet.evaluate(3, 0), "#N/A");
+        sheet.set_cell(3, 0, "=FILTER(A1:A4, B1:B4>100, \"none\")".to_string());
+        assert_eq!(sheet.evaluate(3, 0), "none");
+    }
+
+    #[test]
+    fn test_lock_and_unlock() {
+        let mut sheet = Sheet::new();
+        sheet.lock((0, 0, 3, 0));
+        sheet.lock((1, 2, 2, 4));
+        assert!(sheet.is_locked(3, 0));
+        assert!(!sheet.is_locked(0, 1));
+        assert_eq!(sheet.locked_in((0, 1, 9, 9)), Some((1, 2)));
+        assert_eq!(sheet.locked_in((4, 0, 9, 9)), None);
+
+        // Unlocking the middle of a lock keeps the cells around it
+        assert_eq!(sheet.unlock((1, 0, 2, 0)), 2);
+        assert!(sheet.is_locked(0, 0) && sheet.is_locked(3, 0));
+        assert!(!sheet.is_locked(1, 0) && !sheet.is_locked(2, 0));
+        assert_eq!(sheet.unlock((2, 3, 9, 3)), 1);
+        assert!(sheet.is_locked(1, 3) && sheet.is_locked(2, 2) && sheet.is_locked(2, 4));
+        assert!(!sheet.is_locked(2, 3));
+
+        // Locks follow inserted rows
+        sheet.insert_row(0);
+        assert!(sheet.is_locked(0, 1) && !sheet.is_locked(0, 0));
+    }
+
+    #[test]
+    fn test_find_in() {
+        let mut sheet = Sheet::new();
+        for (col, row) in [(0, 0), (1, 2), (3, 2), (1, 5)] {
+            sheet.set_cell(col, row, "Total".to_string());
+        }
+        assert_eq!(sheet.find("total", 0, 0, true), Some((1, 2, false)));
+        let total = Pattern::text("total");
+        // Column B only
+        let (b, value) = ((1, 0, 1, 9999), SearchTarget::Value);
+        assert_eq!(sheet.find_in(&total, 1, 2, true, b, value), Some((1, 5, false)));
+        assert_eq!(sheet.find_in(&total, 1, 5, true, b, value), Some((1, 2, true)));
+        assert_eq!(sheet.find_in(&total, 1, 2, false, b, value), Some((1, 5, true)));
+        // From outside the area
+        assert_eq!(sheet.find_in(&total, 4, 3, true, b, value), Some((1, 5, false)));
+        assert_eq!(sheet.find_in(&total, 0, 0, false, (2, 2, 3, 2), value), Some((3, 2, true)));
+        assert_eq!(sheet.find_in(&total, 0, 0, true, (0, 3, 0, 9), value), None);
+
+        // Formulas as written rather than their values
+        sheet.set_cell(2, 7, "=SUM(B1:B6)".to_string());
+        assert_eq!(sheet.find("b6", 0, 0, true), None);
+        assert_eq!(sheet.find_in(&Pattern::text("b6"), 0, 0, true, (0, 0, 255, 9999), SearchTarget::Formula), Some((2, 7, false)));
+
+        // Values spilled by an array formula are found too
+        sheet.set_cell(5, 0, "=SORT(G1:G2)".to_string());
+        sheet.set_cell(6, 0, "total b".to_string());
+        sheet.set_cell(6, 1, "total a".to_string());
+        assert_eq!(sheet.find_in(&Pattern::text("total a"), 0, 0, true, (0, 0, 255, 9999), value), Some((5, 0, false)));
+        assert_eq!(sheet.find_in(&Pattern::text("total b"), 0, 0, false, (5, 0, 5, 9), value), Some((5, 1, true)));
+        // The cell searched from is not a match of its own
+        assert_eq!(sheet.find_in(&Pattern::text("=sum"), 2, 7, false, (0, 0, 255, 9999), SearchTarget::Formula), None);
+
+        assert_eq!(sheet.find_all(&total, (0, 0, 4, 9999), value), vec![(0, 0), (1, 2), (3, 2), (1, 5)]);
+        assert_eq!(sheet.find_all(&total, (1, 0, 3, 4), value), vec![(1, 2), (3, 2)]);
+        // Spilled values as well, as the search goes through them
+        assert_eq!(sheet.find_all(&Pattern::text("total a"), (0, 0, 255, 9999), value), vec![(5, 0), (6, 1)]);
+        assert_eq!(sheet.find_all(&Pattern::text("total a"), (0, 0, 255, 9999), SearchTarget::Formula), vec![(6, 1)]);
+
+        // Regular expressions, ignoring case; empty cells never match
+        sheet.set_cell(0, 8, "2024-05".to_string());
+        let month = Pattern::regex(r"\d{4}-\d{2}").unwrap();
+        assert_eq!(sheet.find_in(&month, 0, 0, true, (0, 0, 255, 9999), value), Some((0, 8, false)));
+        assert_eq!(sheet.find_all(&Pattern::regex("^$").unwrap(), (0, 0, 4, 9999), value), vec![]);
+        assert!(Pattern::regex("^tot").unwrap().is_match("Total"));
+        assert_eq!(Pattern::regex("(a").unwrap_err(), "unclosed group");
+    }
+
+    #[test]
+    fn test_block_motion() {
+        let mut sheet = Sheet::new();
+        // Row 1: A B C _ _ F G _ I
+        for col in [0, 1, 2, 5, 6, 8] {
+            sheet.set_cell(col, 0, "x".to_string());
+        }
+        let go = |col: usize, motion: BlockMotion| sheet.block_motion(col, 0, true, motion).map(|(c, _)| c);
+        assert_eq!(go(0, BlockMotion::NextStart), Some(5));
+        assert_eq!(go(3, BlockMotion::NextStart), Some(5));
+        assert_eq!(go(8, BlockMotion::NextStart), None);
+        assert_eq!(go(0, BlockMotion::NextEnd), Some(2));
+        assert_eq!(go(2, BlockMotion::NextEnd), Some(6));
+        assert_eq!(go(6, BlockMotion::PrevStart), Some(5));
+        assert_eq!(go(5, BlockMotion::PrevStart), Some(0));
+        assert_eq!(go(0, BlockMotion::EdgeNext), Some(2));
+        assert_eq!(go(2, BlockMotion::EdgeNext), Some(5));
+        assert_eq!(go(8, BlockMotion::EdgeNext), Some(255));
+        assert_eq!(go(4, BlockMotion::EdgePrev), Some(2));
+        assert_eq!(go(0, BlockMotion::EdgePrev), None);
+
+        // Down a column
+        sheet.set_cell(0, 1, "x".to_string());
+        assert_eq!(sheet.block_motion(0, 0, false, BlockMotion::EdgeNext), Some((0, 1)));
+        assert_eq!(sheet.block_motion(0, 1, false, BlockMotion::EdgeNext), Some((0, 9999)));
+    }
+
+    #[test]
+    fn test_arrange_rows() {
+        let mut sheet = Sheet::new();
+        for row in 0..4 {
+            sheet.set_cell(0, row, format!("{}", row));
+        }
+        sheet.set_cell(1, 2, "=A3*2".to_string());
+        sheet.arrange_rows(1, 3, &[2, 1]);
+        assert_eq!(sheet.evaluate(0, 0), "0");
+        assert_eq!(sheet.evaluate(0, 1), "2");
+        assert_eq!(sheet.get_cell(1, 1).raw_input, "=A3*2");
+        assert_eq!(sheet.evaluate(0, 2), "1");
+        assert!(sheet.get_cell_ref(0, 3).is_none());
+    }
+
+    #[test]
+    fn test_cell_styles() {
+        let mut sheet = Sheet::new();
+        sheet.set_cell(0, 0, "Total".to_string());
+        sheet.set_cell(1, 0, "=A2*2".to_string());
+        let styled = sheet.style_cells((0, 0, 2, 0), |style| {
+            style.bold = true;
+            style.align = Some(Align::Center);
+        });
+        assert_eq!(styled, 2);
+
+        // The style stays through edits and moves with the cell
+        sheet.set_cell(0, 0, "Sum".to_string());
+        sheet.insert_row(0);
+        assert!(sheet.get_cell(0, 1).style.bold);
+        assert_eq!(sheet.get_cell(1, 1).raw_input, "=A3*2");
+        assert_eq!(sheet.get_cell(1, 1).style.align, Some(Align::Center));
+        sheet.clear_cell(0, 1);
+        sheet.set_cell(0, 1, "New".to_string());
+        assert!(sheet.get_cell(0, 1).style.is_plain());
+
+        assert_eq!(Rgb::parse("#FF8000"), Some(Rgb(255, 128, 0)));
+        assert_eq!(Rgb::parse("red").map(String::from).as_deref(), Some("#e04040"));
+        assert_eq!(Rgb::parse("#12345"), None);
+    }
+
+    #[test]
+    fn test_merge_and_unmerge() {
+        let mut sheet = Sheet::new();
+        sheet.merge((0, 0, 2, 0));
+        sheet.merge((4, 0, 5, 1));
+        assert_eq!(sheet.merge_at(1, 0), Some((0, 0, 2, 0)));
+        assert_eq!(sheet.merge_at(3, 0), None);
+
+        // A new merge replaces those it overlaps
+        sheet.merge((2, 0, 4, 0));
+        assert_eq!(sheet.merges(), &[(2, 0, 4, 0)]);
+
+        // Merges follow inserted and deleted columns, and go once one cell is left
+        sheet.insert_col(0);
+        assert_eq!(sheet.merge_at(5, 0), Some((3, 0, 5, 0)));
+        sheet.delete_col(3);
+        sheet.delete_col(3);
+        assert!(sheet.merges().is_empty());
+        assert_eq!(sheet.unmerge((0, 0, 9, 9)), 0);
+    }
+
+    #[test]
+    fn test_references() {
+        let mut sheet = Sheet::new();
+        sheet.set_cell(2, 0, "=SUM(A1:A3)*B2".to_string());
+        sheet.set_cell(2, 1, "5".to_string());
+        let refs = sheet.references(2, 0);
+        assert_eq!(refs, vec![("A1:A3".to_string(), (0, 0, 0, 2)), ("B2".to_string(), (1, 1, 1, 1))]);
+        assert!(sheet.references(2, 1).is_empty());
+        assert!(sheet.references(9, 9).is_empty());
+    }
+
+    #[test]
+    fn test_precedents_and_dependents() {
+        let mut sheet = Sheet::new();
+        // A1, A2 -> B1 = SUM(A1:A3) -> C1 = B1*2 -> D1 = C1+B1
+        sheet.set_cell(0, 0, "1".to_string());
+        sheet.set_cell(0, 1, "2".to_string());
+        sheet.set_cell(1, 0, "=SUM(A1:A3)".to_string());
+        sheet.set_cell(2, 0, "=B1*2".to_string());
+        sheet.set_cell(3, 0, "=C1+B1".to_string());
+
+        assert_eq!(sheet.precedents(3, 0), vec![((1, 0), 1), ((2, 0), 1), ((0, 0), 2), ((0, 1), 2)]);
+        assert_eq!(sheet.dependents(0, 1), vec![((1, 0), 1), ((2, 0), 2), ((3, 0), 2)]);
+        assert!(sheet.precedents(0, 0).is_empty());
+        assert!(sheet.dependents(3, 0).is_empty());
+
+        // A cycle ends the walk instead of looping
+        sheet.set_cell(0, 0, "=D1".to_string());
+        let cells: Vec<_> = sheet.dependents(3, 0).into_iter().map(|(pos, _)| pos).collect();
+        assert_eq!(cells, vec![(0, 0), (1, 0), (2, 0)]);
+    }
+
+    #[test]
+    fn test_typed_in_number_style() {
+        let mut sheet = Sheet::new();
+        sheet.set_number_style(cell::NumberStyle { grouped: false, decimal_comma: true });
+        sheet.set_cell(0, 0, "Qty".to_string());
+        sheet.set_col_type(0, Some(ColType::Number));
+
+        sheet.set_typed(0, 1, "1,5");
+        assert_eq!(sheet.get_cell(0, 1).raw_input, "1.5");
+        assert_eq!(sheet.value(0, 1), CellValue::Number(1.5));
+
+        // A decimal point is no decimal mark, so these stay text
+        sheet.set_typed(0, 2, "1.234");
+        assert_eq!(sheet.value(0, 2), CellValue::Text("1.234".to_string()));
+        sheet.set_typed(0, 3, "1.5");
+        assert_eq!(sheet.value(0, 3), CellValue::Text("1.5".to_string()));
+        assert_eq!(sheet.check_input_type(0, 4, "1.234"), Some(ColType::Number));
+        assert!(sheet.check_input_type(0, 4, "1,234").is_none());
+    }
+
+    #[test]
+    fn test_column_completions() {
+        let mut sheet = Sheet::new();
+        for (row, input) in ["Category", "Food", "Fuel", "Books", "food", "Fun", "12"].iter().enumerate() {
+            sheet.set_cell(1, row, input.to_string());
+        }
+        // Typing in B5, between "Books" and "Fun"
+        assert_eq!(sheet.column_completions(1, 4, "f"), vec!["Fun", "Fuel", "Food"]);
+        assert_eq!(sheet.column_completions(1, 4, "FU"), vec!["Fun", "Fuel"]);
+        // The header and the value typed in full are not offered
+        assert!(sheet.column_completions(1, 9, "cat").is_empty());
+        assert_eq!(sheet.column_completions(1, 9, "books"), Vec::<String>::new());
+        assert_eq!(sheet.column_completions(0, 0, ""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_col_types() {
+        let mut sheet = Sheet::new();
+        for (row, (date, code)) in [("Date", "Code"), ("2024-01-05", "007"), ("2024-02-01", "12")].iter().enumerate() {
+            sheet.set_cell(0, row, date.to_string());
+            sheet.set_cell(1, row, code.to_string());
+        }
+        assert_eq!(sheet.set_col_type(0, Some(ColType::Date)), 0);
+        // Text columns keep numbers as typed
+        sheet.set_col_type(1, Some(ColType::Text));
+        assert_eq!(sheet.evaluate(1, 1), "007");
+
+        // The header is not checked; other rows are
+        assert!(sheet.check_input_type(0, 0, "When").is_none());
+        assert_eq!(sheet.check_input_type(0, 3, "soon"), Some(ColType::Date));
+        assert!(sheet.check_input_type(0, 3, "2024-03-01").is_none());
+        sheet.set_cell(0, 3, "soon".to_string());
+        assert!(sheet.is_invalid(0, 3));
+
+        // Filters on a date column compare dates
+        sheet.set_filter(0, ">=2024-1-31").unwrap();
+        assert!(sheet.is_row_hidden(1) && !sheet.is_row_hidden(2) && sheet.is_row_hidden(3));
+
+        sheet.insert_col(0);
+        assert_eq!(sheet.col_type(1), Some(ColType::Date));
+        assert_eq!(sheet.col_type(0), None);
+    }
+
+    #[test]
+    fn test_col_formulas() {
+        let mut sheet = Sheet::new();
+        for (row, (qty, price)) in [("Qty", "Price"), ("2", "3"), ("4", "5")].iter().enumerate() {
+            sheet.set_cell(0, row, qty.to_string());
+            sheet.set_cell(1, row, price.to_string());
+        }
+        sheet.set_cell(2, 0, "Total".to_string());
+        sheet.set_col_formula(2, Some("=A@*B@".to_string()));
+        assert_eq!(sheet.fill_col_formula(2), 2);
+        assert_eq!(sheet.evaluate(2, 0), "Total");
+        assert_eq!(sheet.evaluate(2, 2), "20");
+
+        // A new row gets the formula as soon as it has data
+        sheet.set_cell(0, 4, "10".to_string());
+        assert_eq!(sheet.get_cell(2, 4).raw_input, "=A5*B5");
+        sheet.set_cell(1, 4, "7".to_string());
+        assert_eq!(sheet.evaluate(2, 4), "70");
+
+        // Columns inserted before it move the formula and what it names
+        sheet.insert_col(0);
+        assert_eq!(sheet.col_formula(3), Some("=B@*C@"));
+        sheet.set_cell(1, 5, "1".to_string());
+        assert_eq!(sheet.get_cell(3, 5).raw_input, "=B6*C6");
+    }
+
+    #[test]
+    fn test_extend_table() {
+        let mut sheet = Sheet::new();
+        for (row, (item, qty, total)) in
+            [("Item", "Qty", "Total"), ("Pen", "2", "=B2*10"), ("Ink", "3", "=B3*10")].iter().enumerate()
+        {
+            sheet.set_cell(0, row, item.to_string());
+            sheet.set_cell(1, row, qty.to_string());
+            sheet.set_cell(2, row, total.to_string());
+        }
+        sheet.style_cells((1, 1, 1, 2), |style| style.bold = true);
+        sheet.add_validation(validation::parse("B2:B3 number").unwrap());
+
+        sheet.set_cell(1, 3, "4".to_string());
+        sheet.extend_table(1, 3);
+        assert!(sheet.get_cell(1, 3).style.bold);
+        assert_eq!(sheet.get_cell(2, 3).raw_input, "=B4*10");
+        assert_eq!(sheet.evaluate(2, 3), "40");
+        assert!(sheet.validation_at(1, 3).is_some());
+
+        // Not below the header alone, nor below an empty cell
+        sheet.set_cell(4, 0, "Note".to_string());
+        sheet.set_cell(4, 1, "x".to_string());
+        sheet.extend_table(4, 1);
+        assert!(!sheet.get_cell(4, 1).style.bold);
+        sheet.set_cell(3, 5, "y".to_string());
+        sheet.extend_table(3, 5);
+        assert!(!sheet.cells().contains_key(&(2, 5)));
+    }
+
+    #[test]
+    fn test_move_cells() {
+        let mut sheet = Sheet::new();
+        sheet.set_cell(0, 0, "2".to_string());
+        sheet.set_cell(0, 1, "=A1*10".to_string());
+        sheet.set_cell(1, 0, "=A1+A2".to_string());
+        sheet.set_cell(1, 1, "old".to_string());
+        sheet.style_cells((0, 0, 0, 0), |style| style.bold = true);
+
+        // A1:A2 one to the right, over B1:B2
+        sheet.move_cells((0, 0, 0, 1), 1, 0);
+        assert!(!sheet.cells().contains_key(&(0, 0)) && !sheet.cells().contains_key(&(0, 1)));
+        assert_eq!(sheet.get_cell(1, 0).raw_input, "2");
+        assert!(sheet.get_cell(1, 0).style.bold);
+        assert_eq!(sheet.get_cell(1, 1).raw_input, "=B1*10");
+        assert_eq!(sheet.evaluate(1, 1), "20");
+
+        // A formula outside the area keeps pointing at the moved cells
+        sheet.set_cell(3, 0, "=B1+B2".to_string());
+        sheet.move_cells((1, 0, 1, 1), 0, 2);
+        assert_eq!(sheet.get_cell(3, 0).raw_input, "=B3+B4");
+        assert_eq!(sheet.evaluate(3, 0), "22");
+    }
+
+    #[test]
+    fn test_transpose() {
+        let mut sheet = Sheet::new();
+        for (col, header) in ["Q1", "Q2", "Q3"].iter().enumerate() {
+            sheet.set_cell(col, 0, header.to_string());
+            sheet.set_cell(col, 1, (col * 10).to_string());
+        }
+        sheet.set_cell(0, 2, "=SUM(A2:C2)".to_string());
+        sheet.set_cell(4, 0, "=C2".to_string());
+
+        assert_eq!(sheet.transpose((0, 0, 2, 1)), Err("A1:C2 turned over would cover A3, which is not empty".to_string()));
+        sheet.clear_cell(0, 2);
+        assert_eq!(sheet.transpose((0, 0, 2, 1)), Ok((0, 0, 1, 2)));
+        assert_eq!(sheet.get_cell(0, 2).raw_input, "Q3");
+        assert_eq!(sheet.get_cell(1, 1).raw_input, "10");
+        assert!(!sheet.cells().contains_key(&(2, 0)));
+        assert_eq!(sheet.get_cell(4, 0).raw_input, "=B3");
+        assert_eq!(sheet.evaluate(4, 0), "20");
+        assert!(sheet.transpose((0, 9990, 20, 9999)).is_err());
+    }
+
+    #[test]
+    fn test_move_row() {
+        // :moverow 2 5 with a total over the rows moved among
+        let mut sheet = Sheet::new();
+        for row in 0..5 {
+            sheet.set_cell(0, row, (row + 1).to_string());
+        }
+        sheet.set_cell(2, 0, "=SUM(A1:A5)".to_string());
+        sheet.set_cell(3, 0, "=SUM(A1:A3)".to_string());
+        sheet.move_row(1, 4);
+        assert_eq!((0..5).map(|row| sheet.evaluate(0, row)).collect::<Vec<_>>(), ["1", "3", "4", "5", "2"]);
+        assert_eq!(sheet.get_cell(2, 0).raw_input, "=SUM(A1:A5)");
+        assert_eq!(sheet.evaluate(2, 0), "15");
+        // The moved row left A1:A3
+        assert_eq!(sheet.get_cell(3, 0).raw_input, "=SUM(A1:A2)");
+        assert_eq!(sheet.evaluate(3, 0), "4");
+    }
+
+    #[test]
+    fn test_tags() {
+        let mut sheet = Sheet::new();
+        sheet.set_cell(1, 1, "=A1".to_string());
+        assert!(sheet.add_tag(1, 1, "review"));
+        assert!(!sheet.add_tag(1, 1, "review"));
+        assert!(sheet.add_tag(0, 3, "todo"));
+        sheet.set_cell(1, 1, "=A1*2".to_string());
+        sheet.insert_row(0);
+        assert_eq!(sheet.tags(1, 2), ["review"]);
+        assert_eq!(sheet.get_cell(1, 2).raw_input, "=A2*2");
+        assert_eq!(sheet.tagged_cells().iter().map(|&(pos, _)| pos).collect::<Vec<_>>(), vec![(1, 2), (0, 4)]);
+
+        assert_eq!(sheet.remove_tags(0, 4, None), 1);
+        assert!(!sheet.cells().contains_key(&(0, 4)));
+        assert_eq!(sheet.remove_tags(1, 2, Some("q3")), 0);
+        assert_eq!(sheet.tags(1, 2).len(), 1);
+
+        // Tagging an empty cell does not make it part of the used area
+        assert!(sheet.add_tag(5, 20, "later"));
+        assert!(sheet.get_cell_ref(5, 20).is_none());
+        assert_eq!((sheet.max_col(), sheet.max_row()), (Some(1), Some(2)));
+
+        // Clearing a cell keeps its tags, and they follow the cell
+        sheet.set_cell(1, 2, String::new());
+        assert!(sheet.get_cell_ref(1, 2).is_none());
+        assert_eq!(sheet.tags(1, 2), ["review"]);
+        sheet.delete_row(0);
+        assert_eq!(sheet.tags(1, 1), ["review"]);
+        sheet.arrange_rows(0, 1, &[1, 0]);
+        assert_eq!(sheet.tags(1, 0), ["review"]);
+        assert_eq!(sheet.tags(5, 19), ["later"]);
+    }
+
+    #[test]
+    fn test_typed_formats() {
+        let mut sheet = Sheet::new();
+        sheet.set_cell(0, 0, "$1,234.50".to_string());
+        sheet.set_cell(1, 0, "45%".to_string());
+        sheet.set_cell(2, 0, "=A1*B1".to_string());
+        assert_eq!(sheet.evaluate(0, 0), "$1,234.50");
+        assert_eq!(sheet.evaluate(1, 0), "45%");
+        assert_eq!(sheet.evaluate(2, 0), "555.525");
+        assert_eq!(sheet.get_cell(0, 0).raw_input, "$1,234.50");
+
+        // A plain number typed over keeps the format
+        sheet.set_cell(0, 0, "2000".to_string());
+        assert_eq!(sheet.evaluate(0, 0), "$2,000.00");
+        sheet.set_col_type(3, Some(ColType::Text));
+        sheet.set_cell(3, 0, "$5".to_string());
+        assert_eq!(sheet.get_cell(3, 0).format, DisplayFormat::General);
+
+        // Values put in as they are stay what they are
+        sheet.set_value(0, 0, CellValue::Text("00123".to_string()));
+        sheet.set_value(1, 0, CellValue::Number(0.5));
+        assert_eq!(sheet.get_cell(0, 0).value, CellValue::Text("00123".to_string()));
+        assert_eq!(sheet.evaluate(1, 0), "50%");
+        sheet.set_value(1, 0, CellValue::Empty);
+        assert!(sheet.get_cell_ref(1, 0).is_none());
+    }
+
+    #[test]
+    fn test_deferred_calc() {
+        let mut sheet = Sheet::new();
+        sheet.set_cell(0, 0, "2".to_string());
+        sheet.set_cell(1, 0, "=A1*10".to_string());
+        sheet.set_cell(2, 0, "=SORT(A1:B1)".to_string());
+        sheet.set_deferred_calc(true);
+        assert!(!sheet.is_stale());
+
+        // Formulas keep their values until recalculated
+        sheet.set_cell(0, 0, "3".to_string());
+        assert!(sheet.is_stale());
+        assert_eq!(sheet.evaluate(1, 0), "20");
+        assert_eq!(sheet.evaluate(3, 0), "20");
+        // A new formula is worked out on its own
+        sheet.set_cell(0, 1, "=A1+1".to_string());
+        assert_eq!(sheet.evaluate(0, 1), "4");
+        sheet.recalculate();
+        assert!(!sheet.is_stale());
+        assert_eq!(sheet.evaluate(1, 0), "30");
+
+        // Values move with their cells
+        sheet.insert_row(0);
+        assert_eq!(sheet.evaluate(1, 1), "30");
+        assert_eq!(sheet.evaluate(3, 1), "30");
+
+        // Values worked out elsewhere are taken only for the same cells
+        let (version, calculated) = (sheet.version(), calculate(sheet.cells()));
+        assert!(sheet.finish_recalculation(version, calculated.clone()));
+        sheet.set_cell(0, 1, "5".to_string());
+        assert!(!sheet.finish_recalculation(version, calculated));
+        assert_eq!(sheet.evaluate(1, 1), "30");
+
+        sheet.set_deferred_calc(false);
+        assert_eq!(sheet.evaluate(1, 1), "50");
     }
 }
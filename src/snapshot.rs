@@ -0,0 +1,164 @@
+//! Named copies of the sheet to compare against later.
+//!
+//! `:snapshot before-cleanup` keeps a copy of the sheet as it is now under
+//! that name (taking one again under the same name replaces it), and
+//! `:snapshots` lists them. `:diff before-cleanup` highlights every cell
+//! whose contents differ from the copy and lists them in the quickfix panel
+//! with the old and new contents, to be gone through with `]q` and `[q`;
+//! Esc or `:diff off` clears the highlight. Snapshots last until vicalc is
+//! quit.
+//!
+//! `:changed` lists the cells changed since the file was last loaded or
+//! saved the same way, to look over before writing; the grid marks them
+//! too, unless `:set changemarks` is off.
+
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+use crate::quickfix::Entry;
+use crate::sheet::Sheet;
+use crate::App;
+
+pub struct Snapshot {
+    pub name: String,
+    pub sheet: Sheet,
+    // File shown when it was taken
+    pub file: Option<String>,
+    pub time: SystemTime,
+}
+
+/// The cells whose contents differ between two sheets, in reading order.
+/// A cell left empty counts the same as one never typed in.
+pub fn changed_cells(before: &Sheet, after: &Sheet) -> Vec<(usize, usize)> {
+    fn raw(sheet: &Sheet, (col, row): (usize, usize)) -> &str {
+        sheet.get_cell_ref(col, row).map_or("", |cell| cell.raw_input.as_str())
+    }
+    let positions: HashSet<(usize, usize)> = before.cells().keys().chain(after.cells().keys()).copied().collect();
+    let mut changed: Vec<(usize, usize)> =
+        positions.into_iter().filter(|&pos| raw(before, pos) != raw(after, pos)).collect();
+    changed.sort_by_key(|&(col, row)| (row, col));
+    changed
+}
+
+/// `:snapshot <name>`
+pub fn take(app: &mut App, args: &str) {
+    let name = args.trim();
+    if name.is_empty() {
+        app.warn("Usage: :snapshot <name>");
+        return;
+    }
+    let snapshot = Snapshot {
+        name: name.to_string(),
+        sheet: app.sheet.clone(),
+        file: app.current_file.clone(),
+        time: SystemTime::now(),
+    };
+    match app.snapshots.iter_mut().find(|s| s.name == name) {
+        Some(old) => {
+            *old = snapshot;
+            app.info(format!("Snapshot {} replaced", name));
+        }
+        None => {
+            app.snapshots.push(snapshot);
+            app.info(format!("Snapshot {} taken", name));
+        }
+    }
+}
+
+/// `:snapshots`: the snapshots taken, oldest first, with what has changed
+/// since each
+pub fn list(app: &mut App) {
+    if app.snapshots.is_empty() {
+        app.info("No snapshots (:snapshot <name> to take one)");
+        return;
+    }
+    let mut lines = vec![format!("{:<20}  {:>8}  {:>8}  {}", "name", "taken", "changed", "file")];
+    for snapshot in &app.snapshots {
+        lines.push(format!(
+            "{:<20}  {:>8}  {:>8}  {}",
+            snapshot.name,
+            crate::history::age(snapshot.time),
+            changed_cells(&snapshot.sheet, &app.sheet).len(),
+            snapshot.file.as_deref().unwrap_or("(unnamed)")
+        ));
+    }
+    let title = format!("Snapshots: {}", app.snapshots.len());
+    app.list = Some(crate::ListView { title, lines, scroll: 0 });
+    app.mode = crate::Mode::List;
+}
+
+/// `:diff <name>`, or `:diff off` to clear the highlight
+pub fn diff(app: &mut App, args: &str) {
+    let name = args.trim();
+    if name.is_empty() {
+        app.warn("Usage: :diff <snapshot>|off");
+        return;
+    }
+    if name == "off" && !app.snapshots.iter().any(|s| s.name == name) {
+        app.diff = None;
+        return;
+    }
+    let Some(snapshot) = app.snapshots.iter().find(|s| s.name == name) else {
+        app.error(format!("No snapshot named {}", name));
+        return;
+    };
+    let changed = changed_cells(&snapshot.sheet, &app.sheet);
+    if changed.is_empty() {
+        app.diff = None;
+        app.info(format!("No changes since {}", name));
+        return;
+    }
+    let entries = entries(&snapshot.sheet, &app.sheet, &changed);
+    let title = format!("Diff against {}: {}", name, count(changed.len()));
+    app.diff = Some(changed.into_iter().collect());
+    crate::quickfix::set(app, title, entries);
+}
+
+/// `:changed`: the cells changed since the file was loaded or saved
+pub fn changed(app: &mut App) {
+    let changed = changed_cells(&app.saved_sheet, &app.sheet);
+    if changed.is_empty() {
+        app.info("No changes since the last save");
+        return;
+    }
+    let entries = entries(&app.saved_sheet, &app.sheet, &changed);
+    let title = format!("Changed since the last save: {}", count(changed.len()));
+    crate::quickfix::set(app, title, entries);
+}
+
+/// Quickfix entries reading `old → new` for the changed cells
+fn entries(before: &Sheet, after: &Sheet, changed: &[(usize, usize)]) -> Vec<Entry> {
+    let shown = |sheet: &Sheet, col, row| {
+        let raw = sheet.get_cell_ref(col, row).map_or(String::new(), |cell| cell.raw_input.clone());
+        if raw.is_empty() { "(empty)".to_string() } else { raw }
+    };
+    changed
+        .iter()
+        .map(|&(col, row)| Entry { col, row, text: format!("{} → {}", shown(before, col, row), shown(after, col, row)) })
+        .collect()
+}
+
+fn count(cells: usize) -> String {
+    format!("{} cell{} changed", cells, if cells == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_cells() {
+        let mut before = Sheet::new();
+        before.set_cell(0, 0, "Item".to_string());
+        before.set_cell(1, 1, "5".to_string());
+        before.set_cell(2, 2, "gone".to_string());
+        let mut after = before.clone();
+        after.set_cell(1, 1, "6".to_string());
+        after.set_cell(2, 2, String::new());
+        after.set_cell(0, 3, "new".to_string());
+        after.style_cells((3, 0, 3, 0), |style| style.bold = true);
+
+        assert_eq!(changed_cells(&before, &after), vec![(1, 1), (2, 2), (0, 3)]);
+        assert!(changed_cells(&after, &after).is_empty());
+    }
+}
@@ -0,0 +1,76 @@
+//! Snippets: short triggers expanded into longer input with Tab while
+//! editing a cell, defined in the `[snippets]` table of the config file:
+//!
+//! ```toml
+//! [snippets]
+//! ";vl" = "=VLOOKUP(|, |, |, FALSE)"
+//! ```
+//!
+//! Each `|` in a snippet marks a place to fill in. The edit cursor goes to
+//! the first one, and Tab jumps on to the next.
+
+use std::collections::BTreeMap;
+
+/// Mark for a place to fill in
+pub const PLACEHOLDER: char = '|';
+
+#[derive(Default)]
+pub struct Snippets(BTreeMap<String, String>);
+
+impl Snippets {
+    pub fn insert(&mut self, trigger: String, text: String) {
+        self.0.insert(trigger, text);
+    }
+
+    /// The longest trigger the text before the cursor ends with, and its
+    /// snippet. A trigger must start the text or follow a character that is
+    /// not a letter or digit, so that ";vl" is not found inside "a;vl".
+    pub fn find(&self, before: &str) -> Option<(&str, &str)> {
+        self.0
+            .iter()
+            .filter(|(trigger, _)| {
+                before
+                    .strip_suffix(trigger.as_str())
+                    .is_some_and(|rest| !rest.ends_with(char::is_alphanumeric))
+            })
+            .max_by_key(|(trigger, _)| trigger.len())
+            .map(|(trigger, text)| (trigger.as_str(), text.as_str()))
+    }
+}
+
+/// The snippet's text without its placeholders, and where they were (in
+/// characters)
+pub fn expand(text: &str) -> (String, Vec<usize>) {
+    let mut expanded = String::new();
+    let mut stops = Vec::new();
+    for c in text.chars() {
+        if c == PLACEHOLDER {
+            stops.push(expanded.chars().count());
+        } else {
+            expanded.push(c);
+        }
+    }
+    (expanded, stops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_and_expand() {
+        let mut snippets = Snippets::default();
+        snippets.insert(";vl".to_string(), "=VLOOKUP(|, |, |, FALSE)".to_string());
+        snippets.insert("l".to_string(), "=LEN(|)".to_string());
+
+        assert_eq!(snippets.find(";vl").map(|(t, _)| t), Some(";vl"));
+        assert_eq!(snippets.find("=1+;vl").map(|(t, _)| t), Some(";vl"));
+        assert!(snippets.find("a;vl").is_none());
+        assert!(snippets.find("vl").is_none());
+        assert_eq!(snippets.find("=1+l").map(|(t, _)| t), Some("l"));
+
+        let (text, stops) = expand("=VLOOKUP(|, |, |, FALSE)");
+        assert_eq!(text, "=VLOOKUP(, , , FALSE)");
+        assert_eq!(stops, vec![9, 11, 13]);
+    }
+}
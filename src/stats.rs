@@ -0,0 +1,71 @@
+//! Session statistics for the status bar.
+//!
+//! `:set stats=on` shows how long vicalc has been running and how many
+//! cells have been entered from Edit mode since it started, as a running
+//! tally for long data-entry sessions. Counting goes on while it is hidden.
+
+use std::time::{Duration, Instant};
+
+pub struct Stats {
+    started: Instant,
+    // Cells committed from Edit mode
+    entered: usize,
+    // Whole seconds shown at the last draw
+    drawn_secs: u64,
+    pub shown: bool,
+}
+
+impl Stats {
+    pub fn new(now: Instant) -> Self {
+        Stats { started: now, entered: 0, drawn_secs: 0, shown: false }
+    }
+
+    /// Count a cell entered from Edit mode
+    pub fn record_entry(&mut self) {
+        self.entered += 1;
+    }
+
+    /// Status bar text: elapsed time and cells entered
+    pub fn label(&self, now: Instant) -> String {
+        let secs = now.saturating_duration_since(self.started).as_secs();
+        let cells = if self.entered == 1 { "cell" } else { "cells" };
+        format!("{}:{:02}:{:02} {} {}", secs / 3600, secs / 60 % 60, secs % 60, self.entered, cells)
+    }
+
+    /// When the clock next ticks over, while it is shown
+    pub fn deadline(&self, now: Instant) -> Option<Instant> {
+        let elapsed = now.saturating_duration_since(self.started).as_secs();
+        self.shown.then(|| self.started + Duration::from_secs(elapsed + 1))
+    }
+
+    /// Whether the clock shows a new second since it was last drawn
+    pub fn tick(&mut self, now: Instant) -> bool {
+        let secs = now.saturating_duration_since(self.started).as_secs();
+        let changed = self.shown && secs != self.drawn_secs;
+        self.drawn_secs = secs;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label() {
+        let start = Instant::now();
+        let mut stats = Stats::new(start);
+        stats.record_entry();
+        assert_eq!(stats.label(start + Duration::from_secs(3725)), "1:02:05 1 cell");
+        stats.record_entry();
+        assert_eq!(stats.label(start), "0:00:00 2 cells");
+
+        // The clock only ticks while it is shown
+        assert_eq!(stats.deadline(start), None);
+        assert!(!stats.tick(start + Duration::from_secs(2)));
+        stats.shown = true;
+        assert_eq!(stats.deadline(start + Duration::from_millis(2500)), Some(start + Duration::from_secs(3)));
+        assert!(stats.tick(start + Duration::from_secs(3)));
+        assert!(!stats.tick(start + Duration::from_millis(3500)));
+    }
+}
@@ -0,0 +1,181 @@
+//! Swap files for crash recovery.
+//!
+//! Like vim, vicalc keeps a copy of a file's unsaved changes next to it as
+//! `.name.swp`, written a few seconds after editing stops. Saving or closing
+//! normally removes it, so a swap file found on startup means the previous
+//! session ended without either (a crash, a closed terminal, a power cut)
+//! and its work can be recovered. Unnamed sheets have no swap file.
+
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::commands;
+use crate::history;
+use crate::App;
+
+/// How long editing has to pause before the swap file is written, unless
+/// changed with `:set autosave`
+pub const UPDATE_TIME: Duration = Duration::from_secs(4);
+
+/// Swap file of `file`: `.name.swp` in the same directory
+pub fn path_for(file: &str) -> PathBuf {
+    let path = Path::new(file);
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    path.with_file_name(format!(".{}.swp", name))
+}
+
+pub struct Swap {
+    // Swap file on disk, if any
+    written: Option<PathBuf>,
+    // History generation the swap or the saved file matches
+    synced: u64,
+    // When the pending changes are due to be written
+    due: Option<Instant>,
+    // Pause before writing, None for no swap file
+    update_time: Option<Duration>,
+}
+
+impl Default for Swap {
+    fn default() -> Self {
+        Swap { written: None, synced: 0, due: None, update_time: Some(UPDATE_TIME) }
+    }
+}
+
+impl Swap {
+    pub fn update_time(&self) -> Option<Duration> {
+        self.update_time
+    }
+
+    /// Write the swap file `secs` seconds after editing stops, or never if 0
+    pub fn set_update_time(&mut self, secs: u64) {
+        self.update_time = (secs > 0).then(|| Duration::from_secs(secs));
+        if self.update_time.is_none() {
+            self.remove();
+        } else {
+            self.due = None;
+        }
+    }
+
+    /// When the next write is due, None if there is nothing to write
+    pub fn deadline(&self) -> Option<Instant> {
+        self.due
+    }
+
+    /// The sheet matches its file (just saved or loaded): remove the swap
+    /// file until the next change
+    pub fn clean(&mut self, generation: u64) {
+        self.remove();
+        self.synced = generation;
+    }
+
+    /// Delete the swap file (on a normal exit)
+    pub fn remove(&mut self) {
+        if let Some(path) = self.written.take() {
+            let _ = std::fs::remove_file(path);
+        }
+        self.due = None;
+    }
+}
+
+/// Schedule a swap write after a change and write it once editing has
+/// paused for the update time
+pub fn update(app: &mut App, now: Instant) {
+    let generation = app.history.generation();
+    let Some(update_time) = app.swap.update_time else {
+        return;
+    };
+    if generation == app.swap.synced {
+        app.swap.due = None;
+        return;
+    }
+    match app.swap.due {
+        None => app.swap.due = Some(now + update_time),
+        Some(due) if due <= now => write(app, generation),
+        Some(_) => {}
+    }
+}
+
+fn write(app: &mut App, generation: u64) {
+    app.swap.due = None;
+    app.swap.synced = generation;
+    // The swap file belongs to the session holding the file
+    let Some(file) = app.current_file.as_ref().filter(|_| !app.read_only) else {
+        return;
+    };
+    let path = path_for(file);
+    if app.swap.written.as_ref().is_some_and(|old| *old != path) {
+        // Saved under a new name since the last write
+        app.swap.remove();
+    }
+    match commands::save_json(app, &path.to_string_lossy()) {
+        Ok(()) => app.swap.written = Some(path),
+        Err(e) => app.warn(format!("Cannot write swap file {}: {}", path.display(), e)),
+    }
+}
+
+/// What to do about a swap file found on startup
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Recovery {
+    Recover,
+    Delete,
+    Edit,
+    Quit,
+}
+
+/// Tell the user about the swap file of `file` and ask what to do.
+/// Runs before the terminal is put into raw mode.
+pub fn ask(file: &str, swap: &Path) -> Recovery {
+    let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    let swap_time = modified(swap);
+
+    eprintln!("Found a swap file: {}", swap.display());
+    if let Some(time) = swap_time {
+        eprintln!("  last written {}", history::age(time));
+    }
+    if modified(Path::new(file)).zip(swap_time).is_some_and(|(f, s)| f > s) {
+        eprintln!("  {} has been saved since: the swap file may be out of date", file);
+    }
+    eprintln!("vicalc may have crashed while editing {}, or it is open in another vicalc.", file);
+
+    let stdin = io::stdin();
+    loop {
+        eprint!("[r]ecover, [d]elete swap file, [e]dit anyway, [q]uit: ");
+        let _ = io::stderr().flush();
+        let mut answer = String::new();
+        if stdin.lock().read_line(&mut answer).unwrap_or(0) == 0 {
+            return Recovery::Quit;
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "r" => return Recovery::Recover,
+            "d" => return Recovery::Delete,
+            "e" => return Recovery::Edit,
+            "q" => return Recovery::Quit,
+            _ => {}
+        }
+    }
+}
+
+/// Replace the sheet with the swap file's copy. The loaded file stays one
+/// undo step away, and the recovered work is written to a fresh swap file.
+pub fn recover(app: &mut App, swap: &Path) {
+    app.save_undo_as("recover");
+    match commands::load_json(app, &swap.to_string_lossy()) {
+        Ok(()) => {
+            app.swap.written = Some(swap.to_path_buf());
+            app.info(format!("Recovered from {} (:w to keep, u to undo)", swap.display()));
+        }
+        Err(e) => app.error(format!("Cannot recover {}: {}", swap.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_for() {
+        assert_eq!(path_for("data.csv"), PathBuf::from(".data.csv.swp"));
+        assert_eq!(path_for("dir/sub/book.json"), PathBuf::from("dir/sub/.book.json.swp"));
+    }
+}
@@ -0,0 +1,94 @@
+//! Tags on cells, for keeping track of work inside a sheet.
+//!
+//! `:tag review` tags the cursor's cell, or from Visual mode every cell of
+//! the selection; several tags can be given at once, and `:tag` alone shows
+//! the cell's tags. `:untag review` takes a tag off again and `:untag` takes
+//! them all off. `:tags` lists the tagged cells in the quickfix panel, to be
+//! gone through with `]q` and `[q`, and `:tags review` only those with that
+//! tag. Rows with tagged cells are marked beside their number, and the tags
+//! are saved with the file's settings.
+
+use crate::formula::cell_name;
+use crate::quickfix::Entry;
+use crate::validation::area_name;
+use crate::App;
+
+/// The cells of the range, or the cursor's cell
+fn cells(app: &App, range: Option<(usize, usize, usize, usize)>) -> Vec<(usize, usize)> {
+    let (c1, r1, c2, r2) = range.unwrap_or((app.cursor_col, app.cursor_row, app.cursor_col, app.cursor_row));
+    (r1..=r2).flat_map(|row| (c1..=c2).map(move |col| (col, row))).collect()
+}
+
+fn place(app: &App, range: Option<(usize, usize, usize, usize)>) -> String {
+    range.map_or(cell_name(app.cursor_col, app.cursor_row), area_name)
+}
+
+/// `:tag [tags]`
+pub fn tag(app: &mut App, args: &str, range: Option<(usize, usize, usize, usize)>) {
+    let tags: Vec<&str> = args.split_whitespace().collect();
+    if tags.is_empty() {
+        let (col, row) = (app.cursor_col, app.cursor_row);
+        let message = match app.sheet.tags(col, row) {
+            [] => format!("{} has no tags", cell_name(col, row)),
+            tags => format!("{}: {}", cell_name(col, row), tags.join(" ")),
+        };
+        app.info(message);
+        return;
+    }
+    let cells = cells(app, range);
+    if cells.iter().all(|&(col, row)| tags.iter().all(|tag| app.sheet.tags(col, row).iter().any(|t| t == tag))) {
+        app.info(format!("{} already tagged {}", place(app, range), tags.join(" ")));
+        return;
+    }
+    app.save_undo();
+    for &(col, row) in &cells {
+        for tag in &tags {
+            app.sheet.add_tag(col, row, tag);
+        }
+    }
+    app.info(format!("Tagged {} {}", place(app, range), tags.join(" ")));
+}
+
+/// `:untag [tag]`
+pub fn untag(app: &mut App, args: &str, range: Option<(usize, usize, usize, usize)>) {
+    let tag = Some(args.trim()).filter(|tag| !tag.is_empty());
+    let cells = cells(app, range);
+    let has = |app: &App, (col, row): (usize, usize)| {
+        app.sheet.tags(col, row).iter().any(|t| tag.is_none_or(|tag| t == tag))
+    };
+    if !cells.iter().any(|&pos| has(app, pos)) {
+        app.warn(format!("{} has no tag {}", place(app, range), tag.unwrap_or("")).trim_end().to_string());
+        return;
+    }
+    app.save_undo();
+    let removed: usize = cells.iter().map(|&(col, row)| app.sheet.remove_tags(col, row, tag)).sum();
+    let plural = if removed == 1 { "" } else { "s" };
+    app.info(format!("Removed {} tag{} from {}", removed, plural, place(app, range)));
+}
+
+/// `:tags [tag]`: the tagged cells in the quickfix panel
+pub fn list(app: &mut App, args: &str) {
+    let wanted = Some(args.trim()).filter(|tag| !tag.is_empty());
+    let entries: Vec<Entry> = app
+        .sheet
+        .tagged_cells()
+        .into_iter()
+        .filter(|(_, tags)| wanted.is_none_or(|wanted| tags.iter().any(|t| t == wanted)))
+        .map(|((col, row), tags)| {
+            let raw = app.sheet.get_cell(col, row).raw_input;
+            let raw = if raw.is_empty() { "(empty)".to_string() } else { raw };
+            Entry { col, row, text: format!("[{}] {}", tags.join(" "), raw) }
+        })
+        .collect();
+    if entries.is_empty() {
+        app.quickfix = None;
+        app.info(wanted.map_or("No tagged cells".to_string(), |tag| format!("No cells tagged {}", tag)));
+        return;
+    }
+    let plural = if entries.len() == 1 { "" } else { "s" };
+    let title = match wanted {
+        Some(tag) => format!("Tagged {}: {} cell{}", tag, entries.len(), plural),
+        None => format!("Tagged: {} cell{}", entries.len(), plural),
+    };
+    crate::quickfix::set(app, title, entries);
+}
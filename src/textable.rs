@@ -0,0 +1,142 @@
+//! Plain-text tables for pasting into documentation
+//! (`:export table.txt --box`).
+//!
+//! Cells show their displayed values. Numbers are right-aligned and text
+//! left-aligned; a header row at the top of the range is centered and ruled
+//! off from the data. Hidden rows and columns are left out.
+
+use unicode_width::UnicodeWidthStr;
+
+use crate::cell::CellValue;
+use crate::coltype::ColType;
+use crate::sheet::Sheet;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Border {
+    // ┌─┬─┐
+    Unicode,
+    // +-+-+
+    Ascii,
+}
+
+/// Characters for the corners and joints of one horizontal rule: left,
+/// line, joint, right
+type Rule = [char; 4];
+
+impl Border {
+    fn rules(self) -> (Rule, Rule, Rule, char) {
+        match self {
+            Border::Unicode => (['┌', '─', '┬', '┐'], ['├', '─', '┼', '┤'], ['└', '─', '┴', '┘'], '│'),
+            Border::Ascii => (['+', '-', '+', '+'], ['+', '-', '+', '+'], ['+', '-', '+', '+'], '|'),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+/// Draw the (min_col, min_row, max_col, max_row) area of the sheet as a table
+pub fn render(sheet: &Sheet, (c1, r1, c2, r2): (usize, usize, usize, usize), border: Border) -> String {
+    let cols: Vec<usize> = (c1..=c2).filter(|&c| !sheet.is_col_hidden(c)).collect();
+    let rows: Vec<usize> = (r1..=r2).filter(|&r| !sheet.is_row_hidden(r)).collect();
+    let header = sheet.header_row().filter(|&h| rows.first() == Some(&h) && rows.len() > 1);
+
+    let cells: Vec<Vec<(String, Align)>> = rows
+        .iter()
+        .map(|&row| {
+            cols.iter()
+                .map(|&col| {
+                    let value = sheet.evaluate(col, row);
+                    let align = if Some(row) == header {
+                        Align::Center
+                    } else if is_number(sheet, col, row, &value) {
+                        Align::Right
+                    } else {
+                        Align::Left
+                    };
+                    (value, align)
+                })
+                .collect()
+        })
+        .collect();
+    let widths: Vec<usize> = (0..cols.len())
+        .map(|i| cells.iter().map(|row| row[i].0.width()).max().unwrap_or(0))
+        .collect();
+
+    let (top, middle, bottom, vertical) = border.rules();
+    let rule = |[left, line, joint, right]: Rule| {
+        let segments: Vec<String> = widths.iter().map(|&w| line.to_string().repeat(w + 2)).collect();
+        format!("{}{}{}\n", left, segments.join(&joint.to_string()), right)
+    };
+
+    let mut out = rule(top);
+    for (i, row) in cells.iter().enumerate() {
+        let fields: Vec<String> = row.iter().zip(&widths).map(|((value, align), &w)| pad(value, w, *align)).collect();
+        let separator = format!(" {} ", vertical);
+        out.push_str(&format!("{} {} {}\n", vertical, fields.join(&separator), vertical));
+        if Some(rows[i]) == header {
+            out.push_str(&rule(middle));
+        }
+    }
+    out.push_str(&rule(bottom));
+    out
+}
+
+/// Whether a cell holds a number: typed as one, computed as one, or in a
+/// number column
+fn is_number(sheet: &Sheet, col: usize, row: usize, value: &str) -> bool {
+    match sheet.get_cell_ref(col, row).map(|cell| &cell.value) {
+        Some(CellValue::Number(_)) => true,
+        Some(CellValue::Formula(_)) => value.parse::<f64>().is_ok(),
+        _ => sheet.col_type(col) == Some(ColType::Number) && !value.is_empty(),
+    }
+}
+
+fn pad(value: &str, width: usize, align: Align) -> String {
+    let space = width.saturating_sub(value.width());
+    match align {
+        Align::Left => format!("{}{}", value, " ".repeat(space)),
+        Align::Right => format!("{}{}", " ".repeat(space), value),
+        Align::Center => format!("{}{}{}", " ".repeat(space / 2), value, " ".repeat(space - space / 2)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sheet() -> Sheet {
+        let mut sheet = Sheet::new();
+        for (row, (item, qty)) in [("Item", "Qty"), ("Apple", "3"), ("Pear", "=B2*4")].iter().enumerate() {
+            sheet.set_cell(0, row, item.to_string());
+            sheet.set_cell(1, row, qty.to_string());
+        }
+        sheet
+    }
+
+    #[test]
+    fn test_unicode_table() {
+        let table = render(&sheet(), (0, 0, 1, 2), Border::Unicode);
+        let expected = "\
+┌───────┬─────┐
+│ Item  │ Qty │
+├───────┼─────┤
+│ Apple │   3 │
+│ Pear  │  12 │
+└───────┴─────┘
+";
+        assert_eq!(table, expected);
+    }
+
+    #[test]
+    fn test_ascii_table_without_header() {
+        let mut sheet = sheet();
+        sheet.set_rows_hidden(2, 2, true);
+        let table = render(&sheet, (0, 1, 1, 2), Border::Ascii);
+        assert_eq!(table, "+-------+---+\n| Apple | 3 |\n+-------+---+\n");
+    }
+}
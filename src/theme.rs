@@ -0,0 +1,300 @@
+//! Color themes.
+//!
+//! Everything the UI draws takes its colors from the current `Theme`. There
+//! are built-in themes for dark and light terminals, a monochrome one, one
+//! safe for red-green color blindness and a high-contrast one, chosen with
+//! `:colorscheme`, `:set theme=` or the config file, whose `[colors]` table
+//! can then change single colors. Following https://no-color.org, a
+//! non-empty `NO_COLOR` in the environment starts vicalc in the monochrome
+//! theme.
+
+use crossterm::style::Color;
+
+/// Names accepted by `:set theme=`
+pub const NAMES: [&str; 5] = ["dark", "light", "monochrome", "deuteranopia", "highcontrast"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub name: &'static str,
+    pub background: Color,
+    pub text: Color,
+    // Text on the cursor's neighbours: selection, references, error messages
+    pub highlight: Color,
+    // Cursor while editing
+    pub edit: Color,
+    // Cursor in Visual mode
+    pub visual: Color,
+    pub selection: Color,
+    // Background of search matches (until :noh)
+    pub search: Color,
+    pub frame: Color,
+    pub invalid: Color,
+    pub warning: Color,
+    // Background of error messages
+    pub error: Color,
+    // Chart series, cycled in order
+    pub series: [Color; 6],
+    // Backgrounds for cells referenced by the formula being edited
+    pub refs: [Color; 6],
+    // Mark states with more than color: the cursor bold, invalid cells
+    // underlined, and warnings and errors named in their messages
+    pub cues: bool,
+}
+
+const fn rgb(r: u8, g: u8, b: u8) -> Color {
+    Color::Rgb { r, g, b }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    /// Green on black, for dark terminals
+    pub fn dark() -> Self {
+        Theme {
+            name: "dark",
+            background: Color::Black,
+            text: rgb(0, 170, 0),
+            highlight: Color::White,
+            edit: rgb(255, 136, 0),
+            visual: rgb(100, 100, 255),
+            selection: rgb(60, 60, 120),
+            search: rgb(120, 95, 0),
+            frame: rgb(180, 180, 180),
+            invalid: rgb(255, 80, 80),
+            warning: rgb(255, 200, 0),
+            error: rgb(170, 0, 0),
+            series: [
+                rgb(80, 160, 255),
+                rgb(255, 110, 90),
+                rgb(120, 220, 120),
+                rgb(255, 200, 60),
+                rgb(200, 120, 255),
+                rgb(60, 210, 210),
+            ],
+            refs: [
+                rgb(40, 90, 200),
+                rgb(190, 50, 50),
+                rgb(140, 60, 170),
+                rgb(30, 140, 90),
+                rgb(190, 110, 20),
+                rgb(20, 140, 160),
+            ],
+            cues: false,
+        }
+    }
+
+    /// Dark green on white, for light terminals
+    pub fn light() -> Self {
+        Theme {
+            name: "light",
+            background: rgb(255, 255, 255),
+            text: rgb(0, 110, 0),
+            highlight: Color::Black,
+            edit: rgb(230, 120, 0),
+            visual: rgb(70, 70, 220),
+            selection: rgb(190, 200, 245),
+            search: rgb(255, 225, 110),
+            frame: rgb(110, 110, 110),
+            invalid: rgb(200, 0, 0),
+            warning: rgb(170, 110, 0),
+            error: rgb(255, 185, 185),
+            series: [
+                rgb(0, 90, 200),
+                rgb(200, 60, 40),
+                rgb(0, 140, 0),
+                rgb(180, 120, 0),
+                rgb(140, 60, 200),
+                rgb(0, 140, 140),
+            ],
+            refs: [
+                rgb(170, 200, 255),
+                rgb(255, 180, 180),
+                rgb(220, 180, 240),
+                rgb(170, 230, 190),
+                rgb(250, 210, 150),
+                rgb(170, 225, 235),
+            ],
+            cues: false,
+        }
+    }
+
+    /// Black, white and two greys of the basic palette only
+    pub fn monochrome() -> Self {
+        Theme {
+            name: "monochrome",
+            background: Color::Black,
+            text: Color::Grey,
+            highlight: Color::White,
+            edit: Color::White,
+            visual: Color::White,
+            selection: Color::DarkGrey,
+            search: Color::DarkGrey,
+            frame: Color::DarkGrey,
+            invalid: Color::White,
+            warning: Color::White,
+            error: Color::DarkGrey,
+            series: [Color::White, Color::Grey, Color::DarkGrey, Color::White, Color::Grey, Color::DarkGrey],
+            refs: [Color::DarkGrey; 6],
+            cues: true,
+        }
+    }
+
+    /// Blues, oranges and yellows (the Okabe-Ito palette) that stay apart
+    /// for red-green color blindness, on black
+    pub fn deuteranopia() -> Self {
+        Theme {
+            name: "deuteranopia",
+            background: Color::Black,
+            text: rgb(86, 180, 233),
+            highlight: Color::White,
+            edit: rgb(230, 159, 0),
+            visual: rgb(204, 121, 167),
+            selection: rgb(0, 70, 120),
+            search: rgb(150, 100, 0),
+            frame: rgb(180, 180, 180),
+            invalid: rgb(240, 228, 66),
+            warning: rgb(230, 159, 0),
+            error: rgb(160, 70, 0),
+            series: [
+                rgb(230, 159, 0),
+                rgb(86, 180, 233),
+                rgb(240, 228, 66),
+                rgb(0, 114, 178),
+                rgb(213, 94, 0),
+                rgb(204, 121, 167),
+            ],
+            refs: [
+                rgb(0, 90, 150),
+                rgb(150, 100, 0),
+                rgb(130, 70, 110),
+                rgb(40, 110, 150),
+                rgb(150, 60, 0),
+                rgb(90, 90, 30),
+            ],
+            cues: true,
+        }
+    }
+
+    /// White on black, with the brightest colors for everything else
+    pub fn high_contrast() -> Self {
+        Theme {
+            name: "highcontrast",
+            background: Color::Black,
+            text: Color::White,
+            highlight: Color::Black,
+            edit: rgb(255, 255, 0),
+            visual: rgb(0, 255, 255),
+            selection: Color::White,
+            search: rgb(255, 255, 0),
+            frame: Color::White,
+            invalid: rgb(255, 255, 0),
+            warning: rgb(255, 255, 0),
+            error: rgb(255, 0, 255),
+            series: [
+                Color::White,
+                rgb(255, 255, 0),
+                rgb(0, 255, 255),
+                rgb(255, 0, 255),
+                rgb(255, 160, 0),
+                rgb(120, 170, 255),
+            ],
+            refs: [
+                rgb(0, 255, 255),
+                rgb(255, 255, 0),
+                rgb(255, 0, 255),
+                rgb(255, 160, 0),
+                rgb(120, 170, 255),
+                rgb(200, 200, 200),
+            ],
+            cues: true,
+        }
+    }
+
+    pub fn named(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            "monochrome" | "mono" => Some(Self::monochrome()),
+            "deuteranopia" | "colorblind" => Some(Self::deuteranopia()),
+            "highcontrast" | "hc" => Some(Self::high_contrast()),
+            _ => None,
+        }
+    }
+
+    /// Theme to start with: monochrome when NO_COLOR is set, else dark
+    pub fn initial() -> Self {
+        match std::env::var_os("NO_COLOR") {
+            Some(value) if !value.is_empty() => Self::monochrome(),
+            _ => Self::dark(),
+        }
+    }
+
+    /// Set one color by name to "#rrggbb" or a color name such as "red"
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let color = parse_color(value).ok_or_else(|| format!("invalid color for {}: {}", name, value))?;
+        let slot = match name {
+            "background" => &mut self.background,
+            "text" => &mut self.text,
+            "highlight" => &mut self.highlight,
+            "edit" => &mut self.edit,
+            "visual" => &mut self.visual,
+            "selection" => &mut self.selection,
+            "search" => &mut self.search,
+            "frame" => &mut self.frame,
+            "invalid" => &mut self.invalid,
+            "warning" => &mut self.warning,
+            "error" => &mut self.error,
+            _ => return Err(format!("unknown color: {}", name)),
+        };
+        *slot = color;
+        Ok(())
+    }
+
+    pub fn series_color(&self, i: usize) -> Color {
+        self.series[i % self.series.len()]
+    }
+
+    pub fn ref_color(&self, i: usize) -> Color {
+        self.refs[i % self.refs.len()]
+    }
+}
+
+/// "#rrggbb", or one of the names crossterm knows ("red", "dark_grey", ...)
+pub fn parse_color(s: &str) -> Option<Color> {
+    if let Some(hex) = s.strip_prefix('#') {
+        let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+        return match hex.len() {
+            6 => Some(rgb(channel(0)?, channel(2)?, channel(4)?)),
+            _ => None,
+        };
+    }
+    Color::try_from(s.to_lowercase().as_str()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_named_and_set() {
+        for name in NAMES {
+            assert_eq!(Theme::named(name).unwrap().name, name);
+        }
+        assert!(Theme::named("solarized").is_none());
+        // Themes for color blindness and low vision do not rely on color alone
+        assert!(Theme::named("deuteranopia").unwrap().cues && Theme::named("hc").unwrap().cues);
+        assert!(!Theme::dark().cues);
+
+        let mut theme = Theme::light();
+        theme.set("text", "#102030").unwrap();
+        assert_eq!(theme.text, rgb(0x10, 0x20, 0x30));
+        theme.set("frame", "dark_grey").unwrap();
+        assert_eq!(theme.frame, Color::DarkGrey);
+        assert!(theme.set("text", "#12345").is_err());
+        assert!(theme.set("nosuch", "red").is_err());
+    }
+}
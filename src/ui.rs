@@ -1,26 +1,30 @@
-use crossterm::{
-    cursor::{Hide, MoveTo, Show},
-    queue,
-    style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor},
-    terminal,
-};
+use crossterm::style::Color;
 use std::io::{stdout, Result, Write};
+use std::time::Instant;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::queue;
 use unicode_width::UnicodeWidthStr;
 
-use crate::{App, Mode, EditAxis};
+use crate::{App, Mode, EditAxis, ListView, Pane, SplitDir, Window};
 use crate::cell::CellValue;
+use crate::chart::Chart;
 use crate::formula;
+use crate::funcmenu::{FuncMenu, CATEGORIES};
+use crate::message::Severity;
+use crate::quickfix::Quickfix;
+use crate::render;
+use crate::screen::{Attrs, Screen};
 
 const ROW_LABEL_WIDTH: usize = 5;
 
-// Colors
-const GREEN: Color = Color::Rgb { r: 0, g: 170, b: 0 };
-const ORANGE: Color = Color::Rgb { r: 255, g: 136, b: 0 };
-const FRAME_COLOR: Color = Color::Rgb { r: 180, g: 180, b: 180 };
-
 // Box drawing characters
 const BOX_VERTICAL: char = '│';
 
+// Drawn in the right padding of cells changed since the last save
+const CHANGE_MARK: &str = "·";
+// Drawn in the row label of rows with tagged cells (:tag)
+const TAG_MARK: &str = "*";
+
 /// Truncate string to fit within max_width (display width) - keeps left side
 fn truncate_to_width(s: &str, max_width: usize) -> String {
     let mut result = String::new();
@@ -78,28 +82,82 @@ fn display_width(s: &str) -> usize {
     UnicodeWidthStr::width(s)
 }
 
-pub struct UI;
+/// Name of a mode as shown in the status line
+pub fn mode_name(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Normal => "NORMAL",
+        Mode::EditSingle => "EDIT",
+        Mode::EditContinuous => "EDIT+",
+        Mode::EditPreserve => "EDIT",
+        Mode::Command => "COMMAND",
+        Mode::Visual => "VISUAL",
+        Mode::Chart => "CHART",
+        Mode::List => "LIST",
+    }
+}
+
+pub struct UI {
+    screen: Screen,
+    // The terminal cursor is shown for screen readers (:set a11y)
+    cursor_shown: bool,
+}
 
 impl UI {
-    fn cursor_color(mode: Mode) -> Color {
-        match mode {
-            Mode::Normal => GREEN,
-            Mode::EditSingle | Mode::EditContinuous | Mode::EditPreserve => ORANGE,
-            Mode::Command => GREEN,
-            Mode::Visual => Color::Rgb { r: 100, g: 100, b: 255 },
+    pub fn new(app: &App) -> Self {
+        UI { screen: Screen::new(app.term_size.0, app.term_size.1), cursor_shown: false }
+    }
+
+    /// Repaint everything on the next draw (the terminal contents were lost)
+    pub fn invalidate(&mut self) {
+        self.screen.invalidate();
+    }
+
+    fn cursor_color(app: &App) -> Color {
+        match app.mode {
+            Mode::Normal => app.theme.text,
+            Mode::EditSingle | Mode::EditContinuous | Mode::EditPreserve => app.theme.edit,
+            Mode::Command => app.theme.text,
+            Mode::Visual => app.theme.visual,
+            Mode::Chart | Mode::List => app.theme.text,
         }
     }
 
-    const SELECTION_BG: Color = Color::Rgb { r: 60, g: 60, b: 120 };
+    /// References in the formula being edited, paired with their highlight color
+    fn edit_references(app: &App) -> Vec<(formula::FormulaRef, Color)> {
+        let editing = matches!(app.mode, Mode::EditSingle | Mode::EditContinuous | Mode::EditPreserve);
+        if !editing || !app.input_buffer.starts_with('=') {
+            return Vec::new();
+        }
+        formula::find_references(&app.input_buffer)
+            .into_iter()
+            .enumerate()
+            .map(|(i, r)| (r, app.theme.ref_color(i)))
+            .collect()
+    }
 
-    /// Calculate how many columns fit in the terminal and their positions
-    fn calc_visible_cols(app: &App, term_width: usize) -> Vec<(usize, usize)> {
+    /// Highlight for a cell found by :precedents or :dependents
+    fn trace_color(app: &App, col: usize, row: usize) -> Option<Color> {
+        let trace = app.trace.as_ref()?;
+        trace.cells.contains(&(col, row)).then(|| app.theme.ref_color(if trace.dependents { 1 } else { 0 }))
+    }
+
+    /// Background for a cell changed since the snapshot given to :diff
+    fn diff_color(app: &App, col: usize, row: usize) -> Option<Color> {
+        app.diff.as_ref()?.contains(&(col, row)).then(|| app.theme.ref_color(2))
+    }
+
+    /// Calculate how many columns fit in a window and their positions
+    fn calc_visible_cols(app: &App, view_col: usize, term_width: usize) -> Vec<(usize, usize)> {
         // Returns Vec of (col_index, col_width)
         let mut cols = Vec::new();
         let mut used_width = ROW_LABEL_WIDTH;
-        let mut col = app.view_col;
+        let mut col = view_col;
         
         while used_width < term_width && col <= 255 {
+            if app.sheet.is_col_hidden(col) {
+                col += 1;
+                continue;
+            }
             let col_width = app.sheet.get_col_width(col);
             if used_width + col_width > term_width {
                 break;
@@ -112,35 +170,157 @@ impl UI {
         cols
     }
 
-    pub fn draw(app: &App) -> Result<()> {
-        let mut stdout = stdout();
-        let (term_width, term_height) = terminal::size()?;
-        let grid_height = (term_height as usize).saturating_sub(4);
-        let visible_cols = Self::calc_visible_cols(app, term_width as usize);
+    /// Draw a frame into the screen buffer and send the changes to the terminal
+    pub fn draw(&mut self, app: &App) -> Result<()> {
+        self.draw_to(app, &mut stdout())
+    }
 
-        let cursor_color = Self::cursor_color(app.mode);
+    /// Draw a frame and write the changes to `out`
+    pub fn draw_to<W: Write>(&mut self, app: &App, out: &mut W) -> Result<()> {
+        let (term_width, term_height) = app.term_size;
 
-        queue!(stdout, Hide)?;
-        queue!(stdout, MoveTo(0, 0))?;
+        self.screen.resize(term_width, term_height);
+        let screen = &mut self.screen;
 
-        Self::draw_status_bar(&mut stdout, app, term_width)?;
-        Self::draw_column_headers(&mut stdout, app, &visible_cols, term_width)?;
-        Self::draw_grid(&mut stdout, app, grid_height, &visible_cols, term_width, cursor_color)?;
-        Self::draw_formula_bar(&mut stdout, app, term_height, term_width)?;
+        if let (Mode::Chart, Some(chart)) = (app.mode, &app.chart) {
+            Self::draw_chart(screen, app, chart, term_width, term_height);
+            return self.screen.flush(out);
+        }
+        if let (Mode::List, Some(list)) = (app.mode, &app.list) {
+            Self::draw_list(screen, app, list, term_width, term_height);
+            return self.screen.flush(out);
+        }
 
-        queue!(stdout, Show)?;
-        
-        stdout.flush()?;
-        Ok(())
+        Self::draw_status_bar(screen, app, term_width);
+        let (active, other) = app.panes();
+        Self::draw_window(screen, app, &app.window(), active, true);
+        if let (Some(pane), Some(split)) = (other, &app.split) {
+            Self::draw_window(screen, app, &split.other, pane, false);
+            if split.dir == SplitDir::Vertical {
+                // Separator between the left and right windows
+                let left = if active.x < pane.x { active } else { pane };
+                let x = left.x + left.width;
+                screen.set_colors(app.theme.background, app.theme.frame);
+                for y in active.y..active.y + active.height {
+                    screen.move_to(x, y);
+                    screen.print(&BOX_VERTICAL.to_string());
+                }
+                screen.reset_colors();
+            }
+        }
+        if let (Some(pane), Some(quickfix)) = (app.quickfix_pane(), &app.quickfix) {
+            Self::draw_quickfix(screen, app, quickfix, pane);
+        }
+        if let Some(menu) = &app.func_menu {
+            Self::draw_func_menu(screen, app, menu, term_height, term_width);
+        }
+        Self::draw_formula_bar(screen, app, term_height, term_width);
+
+        self.screen.flush(out)?;
+        self.place_cursor(app, out)
     }
 
-    fn draw_status_bar(stdout: &mut std::io::Stdout, app: &App, term_width: u16) -> Result<()> {
-        queue!(
-            stdout,
-            MoveTo(0, 0),
-            SetBackgroundColor(GREEN),
-            SetForegroundColor(Color::Black),
-        )?;
+    /// Leave the terminal cursor on the announcement for screen readers,
+    /// and hidden otherwise
+    fn place_cursor<W: Write>(&mut self, app: &App, out: &mut W) -> Result<()> {
+        if app.a11y.enabled {
+            queue!(out, MoveTo(0, 0))?;
+            if !self.cursor_shown {
+                queue!(out, Show)?;
+            }
+        } else if self.cursor_shown {
+            queue!(out, Hide)?;
+        }
+        self.cursor_shown = app.a11y.enabled;
+        out.flush()
+    }
+
+    /// Function menu over the bottom of the grid: the categories, then the
+    /// functions of the one shown with the picked one highlighted
+    fn draw_func_menu(screen: &mut Screen, app: &App, menu: &FuncMenu, term_height: u16, term_width: u16) {
+        let functions = menu.functions();
+        let width = CATEGORIES
+            .iter()
+            .flat_map(|(_, functions)| functions.iter())
+            .map(|(name, _, help)| name.len() + display_width(help) + 4)
+            .max()
+            .unwrap_or(0)
+            .max(CATEGORIES.iter().map(|(name, _)| name.len() + 2).sum())
+            .min(term_width as usize);
+        let top = (term_height as usize).saturating_sub(3 + functions.len()) as u16;
+
+        screen.move_to(0, top);
+        let mut used = 0;
+        for (i, (name, _)) in CATEGORIES.iter().enumerate() {
+            if i == menu.category {
+                screen.set_colors(app.theme.selection, app.theme.highlight);
+            } else {
+                screen.set_colors(app.theme.frame, app.theme.background);
+            }
+            screen.print(&format!(" {} ", name));
+            used += name.len() + 2;
+        }
+        screen.set_colors(app.theme.frame, app.theme.background);
+        screen.print(&" ".repeat(width.saturating_sub(used)));
+
+        for (i, (name, _, help)) in functions.iter().enumerate() {
+            if i == menu.index {
+                screen.set_colors(app.theme.selection, app.theme.highlight);
+            } else {
+                screen.set_colors(app.theme.background, app.theme.text);
+            }
+            screen.move_to(0, top + 1 + i as u16);
+            screen.print(&pad_to_width(&format!(" {:<8} {}", name, help), width, false));
+        }
+        screen.reset_colors();
+    }
+
+    /// Quickfix panel: a title line, then a page of entries with the
+    /// current one highlighted
+    fn draw_quickfix(screen: &mut Screen, app: &App, quickfix: &Quickfix, pane: Pane) {
+        let width = pane.width as usize;
+        screen.move_to(pane.x, pane.y);
+        screen.set_colors(app.theme.frame, app.theme.background);
+        let title = format!(" {} ", quickfix.title);
+        let hint = format!(" {}/{}  ]q [q  :cclose ", quickfix.current + 1, quickfix.entries.len());
+        let padding = width.saturating_sub(display_width(&title) + display_width(&hint));
+        screen.print(&format!("{}{:width$}{}", title, "", hint, width = padding));
+
+        for y in 1..pane.height {
+            let i = quickfix.scroll + y as usize - 1;
+            if i == quickfix.current {
+                screen.set_colors(app.theme.selection, app.theme.highlight);
+            } else {
+                screen.set_colors(app.theme.background, app.theme.text);
+            }
+            let line = quickfix
+                .entries
+                .get(i)
+                .map(|e| format!(" {:<7} {}", formula::cell_name(e.col, e.row), e.text))
+                .unwrap_or_default();
+            screen.move_to(pane.x, pane.y + y);
+            screen.print(&pad_to_width(&line, width, false));
+        }
+        screen.reset_colors();
+    }
+
+    /// Column headers and grid of one window. Only the active window shows
+    /// the edit cursor; the other marks its cursor in the frame color.
+    fn draw_window(screen: &mut Screen, app: &App, win: &Window, pane: Pane, active: bool) {
+        let visible_cols = Self::calc_visible_cols(app, win.view_col, pane.width as usize);
+        Self::draw_column_headers(screen, app, &visible_cols, pane, active);
+        Self::draw_grid(screen, app, win, pane, active, &visible_cols);
+    }
+
+    fn draw_status_bar(screen: &mut Screen, app: &App, term_width: u16) {
+        screen.move_to(0, 0);
+        if app.a11y.enabled {
+            // A plain sentence in the terminal's colors, for screen readers
+            screen.reset_colors();
+            screen.print(&pad_to_width(app.a11y.text(), term_width as usize, false));
+            return;
+        }
+        screen.set_colors(app.theme.text, app.theme.background);
 
         let cell_name = formula::cell_name(app.cursor_col, app.cursor_row);
         let cell = app.sheet.get_cell(app.cursor_col, app.cursor_row);
@@ -153,225 +333,394 @@ impl UI {
             _ => app.sheet.evaluate(app.cursor_col, app.cursor_row),
         };
 
-        let mode_str = match app.mode {
-            Mode::Normal => "NORMAL",
-            Mode::EditSingle => "EDIT",
-            Mode::EditContinuous => "EDIT+",
-            Mode::EditPreserve => "EDIT",
-            Mode::Command => "COMMAND",
-            Mode::Visual => "VISUAL",
-        };
+        let mode_str = mode_name(app.mode);
 
         let axis_str = match app.axis {
             EditAxis::Row => "Row",
             EditAxis::Column => "Col",
         };
 
-        let file_str = app.current_file.as_deref().unwrap_or("[New]");
+        let file_str = Self::file_label(app);
 
         let left = format!(" {} | {} ", cell_name, value_display);
-        let right = format!(" {} | {} | {} ", axis_str, mode_str, file_str);
+        let mut right = format!(" {} | {} | {} ", axis_str, mode_str, file_str);
+        if app.calc.busy() {
+            right = format!(" calculating… |{}", right);
+        } else if app.calc.mode == crate::calc::Mode::Manual {
+            // CALC: formulas may be out of date until F9
+            right = format!(" {} |{}", if app.sheet.is_stale() { "CALC" } else { "MANUAL" }, right);
+        }
+        if let Some(register) = app.macros.recording() {
+            right = format!(" recording @{} |{}", register, right);
+        }
+        if app.stats.shown {
+            right = format!(" {} |{}", app.stats.label(Instant::now()), right);
+        }
         
         // Use display width for proper padding calculation
         let left_width = display_width(&left);
         let right_width = display_width(&right);
         let padding = (term_width as usize).saturating_sub(left_width + right_width);
 
-        write!(stdout, "{}{:width$}{}", left, "", right, width = padding)?;
-        queue!(stdout, ResetColor)?;
-        Ok(())
+        screen.print(&format!("{}{:width$}{}", left, "", right, width = padding));
+        screen.reset_colors();
     }
 
-    fn draw_column_headers(stdout: &mut std::io::Stdout, _app: &App, visible_cols: &[(usize, usize)], term_width: u16) -> Result<()> {
-        queue!(
-            stdout,
-            MoveTo(0, 1),
-            SetBackgroundColor(GREEN),
-            SetForegroundColor(Color::Black),
-        )?;
+    fn draw_column_headers(screen: &mut Screen, app: &App, visible_cols: &[(usize, usize)], pane: Pane, active: bool) {
+        screen.move_to(pane.x, pane.y);
+        // Dim the headers of the inactive window
+        screen.set_colors(if active { app.theme.text } else { app.theme.frame }, app.theme.background);
 
-        write!(stdout, "{:width$}", "", width = ROW_LABEL_WIDTH)?;
+        screen.print(&format!("{:width$}", "", width = ROW_LABEL_WIDTH));
+
+        let filter_col = app.sheet.filter().map(|f| f.col);
 
         let mut used = ROW_LABEL_WIDTH;
         for &(col, col_width) in visible_cols {
-            let col_name = formula::col_to_name(col);
-            write!(stdout, "{:^width$}", col_name, width = col_width)?;
+            let mut col_name = formula::col_to_name(col);
+            if filter_col == Some(col) {
+                // Mark the column the rows are filtered on
+                col_name.push('▾');
+            }
+            screen.print(&format!("{:^width$}", col_name, width = col_width));
             used += col_width;
         }
 
-        let remaining = (term_width as usize).saturating_sub(used);
-        write!(stdout, "{:width$}", "", width = remaining)?;
+        let remaining = (pane.width as usize).saturating_sub(used);
+        screen.print(&format!("{:width$}", "", width = remaining));
 
-        queue!(stdout, ResetColor)?;
-        Ok(())
+        screen.reset_colors();
     }
 
-    fn draw_grid(stdout: &mut std::io::Stdout, app: &App, grid_height: usize, visible_cols: &[(usize, usize)], term_width: u16, cursor_color: Color) -> Result<()> {
+    fn draw_grid(screen: &mut Screen, app: &App, win: &Window, pane: Pane, active: bool, visible_cols: &[(usize, usize)]) {
+        let term_width = pane.width;
+        let grid_height = (pane.height as usize).saturating_sub(1);
+        let cursor_color = if active { Self::cursor_color(app) } else { app.theme.frame };
+        let edit_refs = Self::edit_references(app);
+        let rows = app.sheet.visible_rows(win.view_row, grid_height);
+        let changed: std::collections::HashSet<(usize, usize)> = if app.change_marks && app.modified {
+            crate::snapshot::changed_cells(&app.saved_sheet, &app.sheet).into_iter().collect()
+        } else {
+            Default::default()
+        };
+        let tagged_rows: std::collections::HashSet<usize> =
+            app.sheet.tagged_cells().into_iter().map(|((_, row), _)| row).collect();
+
         for row in 0..grid_height {
-            let actual_row = app.view_row + row;
-            
-            queue!(stdout, MoveTo(0, (row + 2) as u16))?;
+            screen.move_to(pane.x, pane.y + 1 + row as u16);
+
+            // Past the last row: blank line
+            let Some(&actual_row) = rows.get(row) else {
+                screen.set_bg(app.theme.background);
+                screen.print(&format!("{:width$}", "", width = term_width as usize));
+                screen.reset_colors();
+                continue;
+            };
 
-            // Row label
-            queue!(
-                stdout,
-                SetBackgroundColor(GREEN),
-                SetForegroundColor(Color::Black),
-            )?;
-            write!(stdout, "{:>width$}", actual_row + 1, width = ROW_LABEL_WIDTH)?;
-            queue!(stdout, ResetColor)?;
+            // Row label; with :set relativenumber the distance from the
+            // cursor row, which shows its own number on the left as in vim
+            screen.set_colors(app.theme.text, app.theme.background);
+            let label = if !app.relative_number {
+                format!("{:>width$}", actual_row + 1, width = ROW_LABEL_WIDTH)
+            } else if actual_row == win.cursor_row {
+                format!("{:<width$}", actual_row + 1, width = ROW_LABEL_WIDTH)
+            } else {
+                format!("{:>width$}", actual_row.abs_diff(win.cursor_row), width = ROW_LABEL_WIDTH)
+            };
+            // A row with tagged cells is marked in the blank first column
+            let label = match label.strip_prefix(' ') {
+                Some(rest) if tagged_rows.contains(&actual_row) => format!("{}{}", TAG_MARK, rest),
+                _ => label,
+            };
+            screen.print(&label);
+            screen.reset_colors();
 
             let mut used = ROW_LABEL_WIDTH;
 
-            // Get selection bounds for Visual mode
+            // Get selection bounds for Visual mode, or of the cells being
+            // typed into at once
             let (sel_min_col, sel_min_row, sel_max_col, sel_max_row) = if app.mode == Mode::Visual {
                 app.get_selection_bounds()
+            } else if let Some(area) = app.fill_area {
+                area
             } else {
                 (usize::MAX, usize::MAX, 0, 0)  // No selection
             };
 
             // Cells
             for &(actual_col, col_width) in visible_cols {
-                let is_cursor = actual_col == app.cursor_col && actual_row == app.cursor_row;
-                let is_current_col = actual_col == app.cursor_col;
-                let is_selected = app.mode == Mode::Visual 
+                // A merged area is drawn once, across its visible columns,
+                // from the first of them
+                let merge = app.sheet.merge_at(actual_col, actual_row);
+                let mut col_width = col_width;
+                if let Some((c1, _, c2, _)) = merge {
+                    if visible_cols.iter().any(|&(c, _)| c >= c1 && c < actual_col) {
+                        continue;
+                    }
+                    col_width = visible_cols.iter().filter(|&&(c, _)| c >= actual_col && c <= c2).map(|&(_, w)| w).sum();
+                }
+                let contains = |col: usize, row: usize| match merge {
+                    Some((c1, r1, c2, r2)) => (c1..=c2).contains(&col) && (r1..=r2).contains(&row),
+                    None => (col, row) == (actual_col, actual_row),
+                };
+                let is_cursor = contains(win.cursor_col, win.cursor_row);
+                let is_current_col = active && actual_col == win.cursor_col;
+                let is_selected = (app.mode == Mode::Visual || app.fill_area.is_some())
                     && actual_col >= sel_min_col && actual_col <= sel_max_col
                     && actual_row >= sel_min_row && actual_row <= sel_max_row;
 
                 // Flag for edit mode cursor
-                let is_editing = is_cursor && matches!(app.mode, Mode::EditSingle | Mode::EditContinuous | Mode::EditPreserve);
-
-                // Get cell value and type
-                let cell = app.sheet.get_cell(actual_col, actual_row);
-                let is_number = matches!(cell.value, CellValue::Number(_) | CellValue::Formula(_));
+                let is_editing = active && is_cursor && matches!(app.mode, Mode::EditSingle | Mode::EditContinuous | Mode::EditPreserve);
+
+                // Highlight color if the formula being edited references this cell
+                let ref_color = edit_refs.iter()
+                    .find(|(r, _)| r.contains(actual_col, actual_row))
+                    .map(|(_, color)| *color)
+                    .or_else(|| Self::trace_color(app, actual_col, actual_row))
+                    .or_else(|| Self::diff_color(app, actual_col, actual_row));
+
+                // Flag values that break the cell's validation rule
+                let is_invalid = app.sheet.is_invalid(actual_col, actual_row);
+                // Locked cells (:lock) are drawn in the frame color
+                let is_locked = app.sheet.is_locked(actual_col, actual_row);
+
+                // Get cell value and type; a merged area shows its top-left
+                // cell on its first row
+                let (value_col, value_row) = merge.map_or((actual_col, actual_row), |(c1, r1, _, _)| (c1, r1));
+                // An area being moved shows where it would land
+                let (value_col, value_row) = match app.grab {
+                    Some((from_col, from_row, _, _)) if is_selected => {
+                        ((value_col + from_col).saturating_sub(sel_min_col), (value_row + from_row).saturating_sub(sel_min_row))
+                    }
+                    _ => (value_col, value_row),
+                };
+                let cell = app.sheet.get_cell(value_col, value_row);
+                let value = match merge {
+                    Some((_, r1, _, _)) if actual_row != r1 => String::new(),
+                    _ => app.sheet.evaluate(value_col, value_row),
+                };
+                // Matches of the last search, until :noh
+                let is_match = app.hlsearch.as_ref().is_some_and(|pattern| {
+                    app.search_scope.is_none_or(|(c1, r1, c2, r2)| {
+                        (c1..=c2).contains(&value_col) && (r1..=r2).contains(&value_row)
+                    }) && app.sheet.matches(value_col, value_row, pattern, app.search_target)
+                });
+                let is_number = render::is_number(&app.sheet, value_col, value_row);
+
+                // Alignment and look set with :align and :style; merged
+                // cells are centered unless aligned otherwise
+                let style = cell.style;
+                let align = render::cell_align(&app.sheet, value_col, value_row, merge.is_some());
+                let attrs = Attrs {
+                    bold: style.bold || (app.theme.cues && is_cursor && active),
+                    underline: style.underline || (app.theme.cues && is_invalid),
+                };
+                let style_fg = style.color.map(|rgb| Color::Rgb { r: rgb.0, g: rgb.1, b: rgb.2 });
 
                 // Column mode: draw with frame
-                if app.axis == EditAxis::Column && is_current_col && !is_cursor && !is_selected {
+                if app.axis == EditAxis::Column
+                    && is_current_col
+                    && !is_cursor
+                    && !is_selected
+                    && !is_match
+                    && ref_color.is_none()
+                    && merge.is_none()
+                {
                     // Inner width = col_width - 2 (for borders)
                     let inner_width = col_width.saturating_sub(2);
                     
-                    // Get content
-                    let value = app.sheet.evaluate(actual_col, actual_row);
-                    let content = if display_width(&value) > inner_width {
-                        if is_number {
-                            "#".repeat(inner_width)
-                        } else {
-                            let truncated = truncate_to_width(&value, inner_width.saturating_sub(1));
-                            format!("{}…", truncated)
-                        }
-                    } else {
-                        value
-                    };
-                    
                     // Format with proper width
-                    let formatted = if is_number {
-                        pad_to_width(&content, inner_width, true)
-                    } else {
-                        pad_to_width(&content, inner_width, false)
-                    };
+                    let formatted = render::fit(&value, inner_width, align, is_number);
                     
                     // Left border
-                    queue!(stdout, SetBackgroundColor(Color::Black), SetForegroundColor(FRAME_COLOR))?;
-                    write!(stdout, "{}", BOX_VERTICAL)?;
+                    screen.set_colors(app.theme.background, app.theme.frame);
+                    screen.print(&BOX_VERTICAL.to_string());
                     
                     // Content
-                    queue!(stdout, SetForegroundColor(GREEN))?;
-                    write!(stdout, "{}", formatted)?;
+                    screen.set_fg(if is_invalid {
+                        app.theme.invalid
+                    } else if is_locked {
+                        app.theme.frame
+                    } else {
+                        style_fg.unwrap_or(app.theme.text)
+                    });
+                    screen.set_attrs(attrs);
+                    screen.print(&formatted);
+                    screen.set_attrs(Attrs::default());
                     
                     // Right border
-                    queue!(stdout, SetForegroundColor(FRAME_COLOR))?;
-                    write!(stdout, "{}", BOX_VERTICAL)?;
+                    screen.set_fg(app.theme.frame);
+                    screen.print(&BOX_VERTICAL.to_string());
                 } else {
                     // Content width = col_width - 1 (right padding)
                     let content_width = col_width.saturating_sub(1);
                     
                     // Get content
                     let content = if is_editing {
-                        let input = &app.input_buffer;
-                        let input_width = display_width(input);
+                        let (before, after) = app.input_split();
                         // Reserve 1 char for cursor indicator
                         let available_width = content_width.saturating_sub(1);
-                        if input_width > available_width {
-                            // Show end of input (slide to cursor position)
-                            let truncated = truncate_from_end(input, available_width);
-                            format!("{}▏", truncated)
+                        if display_width(before) + display_width(after) > available_width {
+                            // Slide the window so the cursor stays visible
+                            let shown_before = truncate_from_end(before, available_width);
+                            let remaining = available_width.saturating_sub(display_width(&shown_before));
+                            format!("{}▏{}", shown_before, truncate_to_width(after, remaining))
                         } else {
-                            format!("{}▏", input)
+                            format!("{}▏{}", before, after)
                         }
                     } else {
-                        let value = app.sheet.evaluate(actual_col, actual_row);
-                        if display_width(&value) > content_width {
-                            if is_number {
-                                "#".repeat(content_width)
-                            } else {
-                                let truncated = truncate_to_width(&value, content_width.saturating_sub(1));
-                                format!("{}…", truncated)
-                            }
-                        } else {
-                            value
-                        }
+                        value
                     };
                     
                     // Set colors based on cell type
                     let (bg, fg) = if is_cursor {
-                        (cursor_color, Color::Black)
+                        (cursor_color, app.theme.background)
+                    } else if let Some(color) = ref_color {
+                        (color, app.theme.highlight)
                     } else if is_selected {
-                        (Self::SELECTION_BG, Color::White)
+                        (app.theme.selection, app.theme.highlight)
+                    } else if is_match {
+                        (app.theme.search, app.theme.highlight)
+                    } else if is_invalid {
+                        (app.theme.background, app.theme.invalid)
+                    } else if is_locked {
+                        (app.theme.background, app.theme.frame)
                     } else {
-                        (Color::Black, GREEN)
+                        (app.theme.background, style_fg.unwrap_or(app.theme.text))
                     };
                     
-                    queue!(stdout, SetBackgroundColor(bg), SetForegroundColor(fg))?;
+                    screen.set_colors(bg, fg);
                     
-                    // Format and write
-                    let formatted = if is_number && !is_editing {
-                        pad_to_width(&content, content_width, true)
-                    } else {
+                    // Format and write; while editing, the input reads from the left
+                    let formatted = if is_editing {
                         pad_to_width(&content, content_width, false)
+                    } else {
+                        render::fit(&content, content_width, align, is_number)
                     };
-                    write!(stdout, "{} ", formatted)?;  // +1 for right padding
+                    screen.set_attrs(attrs);
+                    screen.print(&formatted);
+                    screen.set_attrs(Attrs::default());
+                    // Right padding, marking a cell changed since the last save
+                    if changed.contains(&(actual_col, actual_row)) {
+                        if !is_cursor {
+                            screen.set_fg(app.theme.frame);
+                        }
+                        screen.print(CHANGE_MARK);
+                    } else {
+                        screen.print(" ");
+                    }
                 }
 
-                queue!(stdout, ResetColor)?;
+                screen.reset_colors();
                 used += col_width;
             }
             
             // Clear rest of line
             let remaining = (term_width as usize).saturating_sub(used);
             if remaining > 0 {
-                queue!(stdout, SetBackgroundColor(Color::Black))?;
-                write!(stdout, "{:width$}", "", width = remaining)?;
-                queue!(stdout, ResetColor)?;
+                screen.set_bg(app.theme.background);
+                screen.print(&format!("{:width$}", "", width = remaining));
+                screen.reset_colors();
+            }
+        }
+    }
+
+    /// Chart mode: title, legend and the chart over the whole screen
+    fn draw_chart(screen: &mut Screen, app: &App, chart: &Chart, term_width: u16, term_height: u16) {
+        let width = term_width as usize;
+
+        screen.move_to(0, 0);
+        screen.set_colors(app.theme.text, app.theme.background);
+        let title = match chart.kind {
+            crate::chart::Kind::Histogram => format!(" histogram of {} ({} bins) ", chart.range, chart.labels.len()),
+            kind => format!(" {} chart of {} ", kind.name(), chart.range),
+        };
+        let hint = " Esc: close ";
+        let padding = width.saturating_sub(display_width(&title) + display_width(hint));
+        screen.print(&format!("{}{:width$}{}", title, "", hint, width = padding));
+
+        screen.move_to(0, 1);
+        screen.set_colors(app.theme.background, app.theme.frame);
+        screen.print(" ");
+        for (i, series) in chart.series.iter().enumerate() {
+            screen.set_fg(app.theme.series_color(i));
+            screen.print(&format!("■ {}  ", series.name));
+        }
+        screen.print(&" ".repeat(width));
+
+        let rows = chart.render(width, (term_height as usize).saturating_sub(3));
+        for (y, line) in rows.iter().enumerate() {
+            screen.move_to(0, (y + 2) as u16);
+            for glyph in line {
+                let fg = glyph.series.map_or(app.theme.frame, |s| app.theme.series_color(s));
+                screen.set_colors(app.theme.background, fg);
+                screen.print(glyph.ch.encode_utf8(&mut [0; 4]));
             }
         }
+        screen.reset_colors();
+
+        Self::draw_status_line(screen, app, term_height, term_width);
+    }
+
+    /// List mode: a title bar and a page of lines over the whole screen
+    fn draw_list(screen: &mut Screen, app: &App, list: &ListView, term_width: u16, term_height: u16) {
+        let width = term_width as usize;
+        let page = (term_height as usize).saturating_sub(2);
+
+        screen.move_to(0, 0);
+        screen.set_colors(app.theme.text, app.theme.background);
+        let title = format!(" {} ", list.title);
+        let position = if list.lines.len() > page {
+            format!(" {}-{}/{} ", list.scroll + 1, (list.scroll + page).min(list.lines.len()), list.lines.len())
+        } else {
+            String::new()
+        };
+        let hint = format!("{} Esc: close ", position);
+        let padding = width.saturating_sub(display_width(&title) + display_width(&hint));
+        screen.print(&format!("{}{:width$}{}", title, "", hint, width = padding));
+
+        screen.set_colors(app.theme.background, app.theme.frame);
+        for y in 0..page {
+            screen.move_to(0, (y + 1) as u16);
+            let line = list.lines.get(list.scroll + y).map(String::as_str).unwrap_or("");
+            screen.print(&pad_to_width(line, width, false));
+        }
+        screen.reset_colors();
 
-        Ok(())
+        Self::draw_status_line(screen, app, term_height, term_width);
     }
 
-    fn draw_formula_bar(stdout: &mut std::io::Stdout, app: &App, term_height: u16, term_width: u16) -> Result<()> {
-        queue!(
-            stdout,
-            MoveTo(0, term_height - 2),
-            SetBackgroundColor(GREEN),
-            SetForegroundColor(Color::Black),
-        )?;
+    fn draw_formula_bar(screen: &mut Screen, app: &App, term_height: u16, term_width: u16) {
+        screen.move_to(0, term_height - 2);
+        screen.set_colors(app.theme.text, app.theme.background);
 
         let content = match app.mode {
             Mode::EditSingle | Mode::EditContinuous | Mode::EditPreserve => {
-                format!(" fx: {}_ ", app.input_buffer)
+                let (before, after) = app.input_split();
+                match &app.completion {
+                    Some(crate::Completion { matches, index: Some(i), .. }) => {
+                        format!(" fx: {}▏{}  [{}/{}] ", before, after, i + 1, matches.len())
+                    }
+                    _ => format!(" fx: {}▏{} ", before, after),
+                }
             }
             Mode::Command => {
                 format!(" :{}_ ", app.command_buffer)
             }
+            Mode::Visual if app.grab.is_some() => {
+                let from = app.grab.unwrap_or_default();
+                let to = app.get_selection_bounds();
+                format!(" Moving {} to {} ", crate::validation::area_name(from), crate::validation::area_name(to))
+            }
             Mode::Visual => {
                 let (min_col, min_row, max_col, max_row) = app.get_selection_bounds();
                 let start = crate::formula::cell_name(min_col, min_row);
                 let end = crate::formula::cell_name(max_col, max_row);
-                format!(" Selection: {}:{} ", start, end)
+                format!(" Selection: {}:{} ({}x{}) ", start, end, max_col - min_col + 1, max_row - min_row + 1)
             }
-            Mode::Normal => {
+            Mode::Normal | Mode::Chart | Mode::List => {
                 let cell = app.sheet.get_cell(app.cursor_col, app.cursor_row);
-                format!(" fx: {} ", cell.raw_input)
+                format!(" fx: {} ", app.number_style.typed_input(&cell))
             }
         };
 
@@ -383,37 +732,72 @@ impl UI {
             pad_to_width(&content, term_width as usize, false)
         };
         
-        write!(stdout, "{}", display)?;
-        queue!(stdout, ResetColor)?;
-
-        // Status line - generate real-time instead of using cached status_message
-        queue!(
-            stdout,
-            MoveTo(0, term_height - 1),
-            SetBackgroundColor(Color::Black),
-            SetForegroundColor(GREEN),
-        )?;
-        
-        let mode_str = match app.mode {
-            Mode::Normal => "NORMAL",
-            Mode::EditSingle => "EDIT",
-            Mode::EditContinuous => "EDIT+",
-            Mode::EditPreserve => "EDIT",
-            Mode::Command => "COMMAND",
-            Mode::Visual => "VISUAL",
+        screen.print(&display);
+        screen.reset_colors();
+
+        Self::draw_status_line(screen, app, term_height, term_width);
+    }
+
+    /// Bottom line: the latest notification while it is shown, otherwise the
+    /// mode and position, with a badge counting unacknowledged errors
+    fn draw_status_line(screen: &mut Screen, app: &App, term_height: u16, term_width: u16) {
+        let errors = app.messages.errors().len();
+        let badge = if errors > 0 { format!(" E:{} ", errors) } else { String::new() };
+        let width = (term_width as usize).saturating_sub(display_width(&badge));
+
+        screen.move_to(0, term_height - 1);
+
+        if let Some(typed) = &app.goto_buffer {
+            screen.set_colors(app.theme.background, app.theme.text);
+            screen.print(&pad_to_width(&format!("Go to: {}▏", typed), width, false));
+        } else if let Some(message) = app.messages.current() {
+            let (bg, fg, label) = match message.severity {
+                Severity::Info => (app.theme.background, app.theme.text, ""),
+                Severity::Warning => (app.theme.background, app.theme.warning, "Warning: "),
+                Severity::Error => (app.theme.error, app.theme.highlight, "Error: "),
+            };
+            screen.set_colors(bg, fg);
+            let label = if app.theme.cues { label } else { "" };
+            screen.print(&pad_to_width(&format!("{}{}", label, message.text), width, false));
+        } else {
+            screen.set_colors(app.theme.background, app.theme.text);
+            screen.print(&pad_to_width(&Self::status_text(app), width, false));
+        }
+
+        if !badge.is_empty() {
+            screen.set_colors(app.theme.error, app.theme.highlight);
+            screen.print(&badge);
+        }
+        screen.reset_colors();
+    }
+
+    /// File name, with [RO] when another vicalc has it open and [+] when
+    /// there are unsaved changes
+    fn file_label(app: &App) -> String {
+        let mut label = match (&app.current_file, &app.template) {
+            (Some(file), _) => file.clone(),
+            (None, Some(template)) => format!("[New from {}]", template),
+            (None, None) => "[New]".to_string(),
         };
+        if app.read_only {
+            label.push_str(" [RO]");
+        }
+        if app.modified {
+            label.push_str(" [+]");
+        }
+        label
+    }
+
+    /// Normal status: mode, cell, axis and file
+    fn status_text(app: &App) -> String {
+        let mode_str = mode_name(app.mode);
         let axis_str = match app.axis {
             crate::EditAxis::Row => "Row",
             crate::EditAxis::Column => "Col",
         };
         let cell_name = crate::formula::cell_name(app.cursor_col, app.cursor_row);
-        let file_str = app.current_file.as_deref().unwrap_or("[New]");
+        let file_str = Self::file_label(app);
         
-        let status = format!("{} | {} | {} | {}", mode_str, cell_name, axis_str, file_str);
-        let status_display = pad_to_width(&status, term_width as usize, false);
-        write!(stdout, "{}", status_display)?;
-        queue!(stdout, ResetColor)?;
-
-        Ok(())
+        format!("{} | {} | {} | {}", mode_str, cell_name, axis_str, file_str)
     }
 }
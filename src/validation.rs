@@ -0,0 +1,259 @@
+//! Data validation rules attached to cell ranges.
+//!
+//! A rule is checked when a value is typed into a covered cell (rejecting or
+//! just warning about bad input) and when the grid is drawn, so cells that
+//! became invalid by other means are still flagged.
+
+use serde::{Deserialize, Serialize};
+
+use crate::cell::CellValue;
+use crate::formula::{self, StructureChange};
+
+/// What a value has to satisfy
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Rule {
+    /// A number, optionally bounded (inclusive)
+    Number {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max: Option<f64>,
+    },
+    /// One of a fixed set of values (case-insensitive)
+    List { items: Vec<String> },
+}
+
+/// What happens when invalid input is entered
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Reject,
+    Warn,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Validation {
+    pub start_col: usize,
+    pub start_row: usize,
+    pub end_col: usize,
+    pub end_row: usize,
+    pub rule: Rule,
+    #[serde(default)]
+    pub action: Action,
+}
+
+impl Rule {
+    /// Whether `value` satisfies the rule. Empty cells always do.
+    pub fn check(&self, value: &CellValue) -> bool {
+        match (self, value) {
+            (_, CellValue::Empty) => true,
+            (Rule::Number { min, max }, CellValue::Number(n)) => {
+                min.is_none_or(|min| *n >= min) && max.is_none_or(|max| *n <= max)
+            }
+            (Rule::Number { .. }, _) => false,
+            (Rule::List { items }, value) => {
+                let text = match value {
+                    CellValue::Number(n) => n.to_string(),
+                    CellValue::Text(s) => s.clone(),
+                    CellValue::Boolean(b) => if *b { "TRUE" } else { "FALSE" }.to_string(),
+                    _ => return false,
+                };
+                items.iter().any(|item| item.eq_ignore_ascii_case(&text))
+            }
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Rule::Number { min: None, max: None } => "number".to_string(),
+            Rule::Number { min, max } => format!(
+                "number {}..{}",
+                min.map(|n| n.to_string()).unwrap_or_default(),
+                max.map(|n| n.to_string()).unwrap_or_default()
+            ),
+            Rule::List { items } => format!("list \"{}\"", items.join(",")),
+        }
+    }
+}
+
+impl Validation {
+    pub fn contains(&self, col: usize, row: usize) -> bool {
+        col >= self.start_col && col <= self.end_col && row >= self.start_row && row <= self.end_row
+    }
+
+    pub fn intersects(&self, start_col: usize, start_row: usize, end_col: usize, end_row: usize) -> bool {
+        self.start_col <= end_col && start_col <= self.end_col && self.start_row <= end_row && start_row <= self.end_row
+    }
+
+    /// Range in A1 notation ("B2:B100", or "B2" for a single cell)
+    pub fn range_name(&self) -> String {
+        area_name((self.start_col, self.start_row, self.end_col, self.end_row))
+    }
+
+    /// Follow an inserted or deleted row or column. Returns false if the
+    /// whole range was deleted and the rule should go.
+    pub fn apply_structure_change(&mut self, change: StructureChange) -> bool {
+        let (Some((start_col, end_col)), Some((start_row, end_row))) =
+            (change.cols(self.start_col, self.end_col), change.rows(self.start_row, self.end_row))
+        else {
+            return false;
+        };
+        (self.start_col, self.start_row, self.end_col, self.end_row) = (start_col, start_row, end_col, end_row);
+        true
+    }
+
+    pub fn describe(&self) -> String {
+        let action = match self.action {
+            Action::Reject => "",
+            Action::Warn => " warn",
+        };
+        format!("{} {}{}", self.range_name(), self.rule.describe(), action)
+    }
+}
+
+/// Parse a range in A1 notation ("B2:B100" or "B2") into normalized bounds
+pub fn parse_range(range: &str) -> Option<(usize, usize, usize, usize)> {
+    let (start, end) = range.split_once(':').unwrap_or((range, range));
+    let (c1, r1, _, _) = formula::parse_cell_ref(start.trim())?;
+    let (c2, r2, _, _) = formula::parse_cell_ref(end.trim())?;
+    Some((c1.min(c2), r1.min(r2), c1.max(c2), r1.max(r2)))
+}
+
+/// A1 notation for normalized bounds: "B2:B100", or "B2" for one cell
+pub fn area_name((c1, r1, c2, r2): (usize, usize, usize, usize)) -> String {
+    let start = formula::cell_name(c1, r1);
+    if (c1, r1) == (c2, r2) {
+        start
+    } else {
+        format!("{}:{}", start, formula::cell_name(c2, r2))
+    }
+}
+
+/// Parse the arguments of `:validate`, e.g. `B2:B100 number 0..100` or
+/// `C2:C100 list "Yes,No,Maybe" warn`
+pub fn parse(args: &str) -> Result<Validation, String> {
+    const USAGE: &str = "Usage: :validate <range> number [min..max] | list \"a,b,c\" [warn]";
+
+    let args = args.trim();
+    let (range, rest) = args.split_once(char::is_whitespace).ok_or(USAGE)?;
+    let (start_col, start_row, end_col, end_row) =
+        parse_range(range).ok_or_else(|| format!("Invalid range: {}", range))?;
+
+    let mut rest = rest.trim();
+    let mut action = Action::Reject;
+    for (word, act) in [("warn", Action::Warn), ("reject", Action::Reject)] {
+        if let Some(stripped) = rest.strip_suffix(word) {
+            if stripped.is_empty() || stripped.ends_with(char::is_whitespace) {
+                rest = stripped.trim_end();
+                action = act;
+                break;
+            }
+        }
+    }
+
+    let (kind, spec) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let spec = spec.trim();
+    let rule = match kind.to_lowercase().as_str() {
+        "number" | "num" => {
+            let (min, max) = if spec.is_empty() {
+                (None, None)
+            } else {
+                let (lo, hi) = spec
+                    .split_once("..")
+                    .ok_or_else(|| format!("Invalid bounds: {} (expected min..max)", spec))?;
+                (parse_bound(lo)?, parse_bound(hi)?)
+            };
+            if let (Some(lo), Some(hi)) = (min, max) {
+                if lo > hi {
+                    return Err(format!("Invalid bounds: {} is greater than {}", lo, hi));
+                }
+            }
+            Rule::Number { min, max }
+        }
+        "list" => {
+            let spec = spec
+                .strip_prefix('"')
+                .and_then(|s| s.strip_suffix('"'))
+                .unwrap_or(spec);
+            let items: Vec<String> = spec
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect();
+            if items.is_empty() {
+                return Err("List rule needs at least one value".to_string());
+            }
+            Rule::List { items }
+        }
+        _ => return Err(USAGE.to_string()),
+    };
+
+    Ok(Validation { start_col, start_row, end_col, end_row, rule, action })
+}
+
+fn parse_bound(s: &str) -> Result<Option<f64>, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(None);
+    }
+    s.parse::<f64>()
+        .map(Some)
+        .map_err(|_| format!("Invalid number: {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_number_rule() {
+        let v = parse("B2:B100 number 0..100").unwrap();
+        assert_eq!((v.start_col, v.start_row, v.end_col, v.end_row), (1, 1, 1, 99));
+        assert_eq!(v.rule, Rule::Number { min: Some(0.0), max: Some(100.0) });
+        assert_eq!(v.action, Action::Reject);
+
+        let v = parse("A1 number 5.. warn").unwrap();
+        assert_eq!(v.rule, Rule::Number { min: Some(5.0), max: None });
+        assert_eq!(v.action, Action::Warn);
+
+        assert!(parse("A1 number 10..1").is_err());
+        assert!(parse("A1 number x..1").is_err());
+    }
+
+    #[test]
+    fn test_parse_list_rule() {
+        let v = parse("C2:C100 list \"Yes,No,Maybe\"").unwrap();
+        assert_eq!(v.rule, Rule::List { items: vec!["Yes".into(), "No".into(), "Maybe".into()] });
+        assert_eq!(v.describe(), "C2:C100 list \"Yes,No,Maybe\"");
+        assert!(parse("C2 list").is_err());
+        assert!(parse("C2").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let number = Rule::Number { min: Some(0.0), max: Some(100.0) };
+        assert!(number.check(&CellValue::Number(100.0)));
+        assert!(!number.check(&CellValue::Number(100.5)));
+        assert!(!number.check(&CellValue::Text("abc".into())));
+        assert!(number.check(&CellValue::Empty));
+
+        let list = Rule::List { items: vec!["Yes".into(), "No".into(), "1".into()] };
+        assert!(list.check(&CellValue::Text("yes".into())));
+        assert!(list.check(&CellValue::Number(1.0)));
+        assert!(!list.check(&CellValue::Text("Maybe".into())));
+    }
+
+    #[test]
+    fn test_structure_change() {
+        let mut v = parse("B2:B10 number").unwrap();
+        assert!(v.apply_structure_change(StructureChange::RowInsert(0, 1)));
+        assert_eq!(v.range_name(), "B3:B11");
+        assert!(v.apply_structure_change(StructureChange::RowDelete(4, 1)));
+        assert_eq!(v.range_name(), "B3:B10");
+        assert!(v.apply_structure_change(StructureChange::ColInsert(1, 1)));
+        assert_eq!(v.range_name(), "C3:C10");
+        assert!(!v.apply_structure_change(StructureChange::ColDelete(2, 1)));
+    }
+}
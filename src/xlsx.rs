@@ -1,7 +1,8 @@
 use calamine::{open_workbook, Reader, Xlsx, Data};
-use rust_xlsxwriter::Workbook;
+use rust_xlsxwriter::{Color, Format, FormatAlign, FormatUnderline, Workbook};
 use std::path::Path;
 
+use crate::cell::{Align, CellStyle, Rgb};
 use crate::sheet::Sheet;
 
 /// Read xlsx file and return Sheet
@@ -79,20 +80,20 @@ pub fn write_xlsx<P: AsRef<Path>>(sheet: &Sheet, path: P) -> Result<(), String>
                 continue;
             }
             
+            let format = cell_format(&cell.style);
+            let (row, col) = (row as u32, col as u16);
+            
             // Try to parse as number
-            if let Ok(num) = cell.raw_input.parse::<f64>() {
-                worksheet.write_number(row as u32, col as u16, num)
-                    .map_err(|e| format!("Failed to write cell: {}", e))?;
+            let written = if let Ok(num) = cell.raw_input.parse::<f64>() {
+                worksheet.write_number_with_format(row, col, num, &format)
             } else if cell.raw_input.eq_ignore_ascii_case("TRUE") {
-                worksheet.write_boolean(row as u32, col as u16, true)
-                    .map_err(|e| format!("Failed to write cell: {}", e))?;
+                worksheet.write_boolean_with_format(row, col, true, &format)
             } else if cell.raw_input.eq_ignore_ascii_case("FALSE") {
-                worksheet.write_boolean(row as u32, col as u16, false)
-                    .map_err(|e| format!("Failed to write cell: {}", e))?;
+                worksheet.write_boolean_with_format(row, col, false, &format)
             } else {
-                worksheet.write_string(row as u32, col as u16, &cell.raw_input)
-                    .map_err(|e| format!("Failed to write cell: {}", e))?;
-            }
+                worksheet.write_string_with_format(row, col, &cell.raw_input, &format)
+            };
+            written.map_err(|e| format!("Failed to write cell: {}", e))?;
         }
     }
     
@@ -102,3 +103,25 @@ pub fn write_xlsx<P: AsRef<Path>>(sheet: &Sheet, path: P) -> Result<(), String>
     
     Ok(())
 }
+
+/// The xlsx format for a cell's :align and :style settings
+fn cell_format(style: &CellStyle) -> Format {
+    let mut format = Format::new();
+    if style.bold {
+        format = format.set_bold();
+    }
+    if style.underline {
+        format = format.set_underline(FormatUnderline::Single);
+    }
+    if let Some(align) = style.align {
+        format = format.set_align(match align {
+            Align::Left => FormatAlign::Left,
+            Align::Right => FormatAlign::Right,
+            Align::Center => FormatAlign::Center,
+        });
+    }
+    if let Some(Rgb(r, g, b)) = style.color {
+        format = format.set_font_color(Color::RGB(((r as u32) << 16) | ((g as u32) << 8) | b as u32));
+    }
+    format
+}